@@ -1,14 +1,25 @@
 mod parser;
 mod password;
+mod progress;
+#[cfg(unix)]
+mod ssh_agent;
 mod vault;
 
 use crate::{
     password::{password_confirmation, request_password},
-    vault::Vault,
+    vault::{open_database, write_diff, Vault},
+};
+use pwm_db::{
+    db_base::error::DatabaseError,
+    db_encrypted::{attachment::DEFAULT_MAX_ATTACHMENT_SIZE, trash::DEFAULT_TRASH_DAYS},
+};
+use pwm_lib::{
+    atomic_file::DEFAULT_BACKUPS,
+    crypt_dir::{decrypt_dir, encrypt_dir},
+    crypt_file::{decrypt_file, encrypt_file},
 };
-use pwm_lib::crypt_file::{decrypt_file, encrypt_file};
 
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 #[command(arg_required_else_help = true)]
@@ -22,6 +33,14 @@ struct Args {
     #[arg(short, long, value_name = "file")]
     decrypt: Option<String>,
 
+    /// Directory to encrypt into an archive
+    #[arg(long, value_name = "directory")]
+    encrypt_dir: Option<String>,
+
+    /// Archive to decrypt into a directory
+    #[arg(long, value_name = "file")]
+    decrypt_dir: Option<String>,
+
     /// Vault to open
     #[arg(short, long, value_name = "file")]
     vault: Option<String>,
@@ -33,12 +52,140 @@ struct Args {
     /// Output file
     #[arg(short, long, value_name = "file")]
     out: Option<String>,
+
+    /// Number of backups kept when saving a vault
+    #[arg(long, value_name = "count", default_value_t = DEFAULT_BACKUPS)]
+    backups: usize,
+
+    /// Days removed entries stay in the recycle bin, 0 keeps them until purged
+    #[arg(long, value_name = "days", default_value_t = DEFAULT_TRASH_DAYS)]
+    trash_days: u32,
+
+    /// Largest file that can be attached to an entry, in bytes
+    #[arg(long, value_name = "bytes", default_value_t = DEFAULT_MAX_ATTACHMENT_SIZE)]
+    max_attachment_size: u64,
+
+    /// Serve the SSH keys stored in the vault as an ssh-agent on this socket
+    #[cfg(unix)]
+    #[arg(long, value_name = "socket", requires = "vault")]
+    ssh_agent: Option<String>,
+
+    /// Ask before every signature made by the ssh-agent
+    #[cfg(unix)]
+    #[arg(long, value_name = "boolean", action = ArgAction::SetTrue, requires = "ssh_agent")]
+    confirm: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Merge two copies of a vault that were changed separately, then open the result
+    Merge {
+        /// Vault the changes are merged into
+        a: String,
+
+        /// Vault the changes are taken from
+        b: String,
+
+        /// Copy both vaults were made from
+        #[arg(long, value_name = "file")]
+        base: String,
+    },
+    /// Show what changed from one vault to another
+    Diff {
+        /// Older vault
+        old: String,
+
+        /// Newer vault
+        new: String,
+
+        /// Show changed secret values
+        #[arg(long, action = ArgAction::SetTrue)]
+        reveal: bool,
+    },
 }
 
 fn main() -> Result<(), std::io::Error> {
     let args = Args::parse();
 
-    if args.decrypt.is_none() && args.vault.is_none() && !args.create {
+    if let Some(Command::Diff { old, new, reveal }) = &args.command {
+        let password = request_password("Enter the master password")?;
+        let mut opened = Vec::new();
+        for file in [old, new] {
+            let result = std::fs::read(file)
+                .map_err(DatabaseError::from)
+                .and_then(|contents| open_database(file, contents, password.as_bytes(), true));
+            match result {
+                Ok((db, _recovered)) => opened.push(db),
+                Err(error) => {
+                    println!("Error: {}", error);
+                    return Ok(());
+                }
+            }
+        }
+
+        match opened[1].diff(&opened[0], password.as_bytes(), *reveal) {
+            Ok(diff) => write_diff(&mut std::io::stdout(), &diff)?,
+            Err(error) => println!("Error: {}", error),
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Merge { a, b, base }) = args.command {
+        println!("Merging the vault {} into {}", b, a);
+        let mut vault =
+            match Vault::<std::io::BufReader<std::io::Stdin>, std::io::Stdout>::new_from_file(
+                a.as_str(),
+            ) {
+                Ok(vault) => vault,
+                Err(error) => {
+                    println!("Error: {}", error);
+                    return Ok(());
+                }
+            };
+
+        if let Err(error) = vault.merge(&b, &base) {
+            println!("Error: {}", error);
+            return Ok(());
+        }
+        vault.set_backups(args.backups);
+        vault.set_trash_days(args.trash_days);
+        vault.set_max_attachment_size(args.max_attachment_size);
+        vault.run()?;
+        return Ok(());
+    }
+
+    let file_mode = args.encrypt.is_some() || args.decrypt.is_some();
+    let vault_mode = args.vault.is_some() || args.create;
+    if args.encrypt_dir.is_some() || args.decrypt_dir.is_some() {
+        if file_mode || vault_mode || (args.encrypt_dir.is_some() && args.decrypt_dir.is_some()) {
+            println!("to many arguments provided, only provide encrypt-dir or decrypt-dir");
+            return Ok(());
+        }
+
+        if let Some(name) = args.encrypt_dir {
+            // Encrypt directory
+            println!("Encrypting directory {}", name);
+            let password = password_confirmation()?;
+            match encrypt_dir(name, args.out, password.as_bytes()) {
+                Ok(skipped) => {
+                    for path in skipped {
+                        println!("Skipped {}, only files and directories are encrypted", path);
+                    }
+                }
+                Err(error) => println!("Error: {}", error),
+            }
+        } else if let Some(name) = args.decrypt_dir {
+            // Decrypt directory
+            println!("Decrypting directory {}", name);
+            let password = request_password("Enter your password")?;
+            if let Err(error) = decrypt_dir(name, args.out, password.as_bytes()) {
+                println!("Error: {}", error);
+            }
+        }
+    } else if args.decrypt.is_none() && args.vault.is_none() && !args.create {
         // Encrypt
         if let Some(name) = args.encrypt {
             println!("Encrypting file {}", name);
@@ -61,6 +208,15 @@ fn main() -> Result<(), std::io::Error> {
         if args.out.is_some() {
             println!("ignoring out parameter for vault");
         }
+        #[cfg(unix)]
+        if let (Some(name), Some(socket)) = (&args.vault, &args.ssh_agent) {
+            println!("Starting an ssh-agent for the vault {}", name);
+            if let Err(error) = ssh_agent::run_ssh_agent(name, socket, args.confirm) {
+                println!("Error: {}", error);
+            }
+            return Ok(());
+        }
+
         if let Some(name) = args.vault {
             println!("Loading a vault from the file {}", name);
             let mut vault =
@@ -74,6 +230,9 @@ fn main() -> Result<(), std::io::Error> {
                     }
                 };
 
+            vault.set_backups(args.backups);
+            vault.set_trash_days(args.trash_days);
+            vault.set_max_attachment_size(args.max_attachment_size);
             vault.run()?;
         }
     } else if args.encrypt.is_none() && args.decrypt.is_none() && args.vault.is_none() {
@@ -92,6 +251,9 @@ fn main() -> Result<(), std::io::Error> {
                     }
                 };
 
+            vault.set_backups(args.backups);
+            vault.set_trash_days(args.trash_days);
+            vault.set_max_attachment_size(args.max_attachment_size);
             vault.run()?;
         }
     } else {
@@ -0,0 +1,81 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use pwm_lib::progress::Progress;
+
+static REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+static SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+// Runs job while redrawing a progress line on stderr, stdout is left alone so
+// the line never ends up in piped output
+pub fn with_progress_line<T>(label: &str, enabled: bool, job: impl FnOnce(&Progress) -> T) -> T {
+    let progress = Progress::new();
+    if !enabled {
+        return job(&progress);
+    }
+
+    let done = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut frame = 0;
+            while !done.load(Ordering::SeqCst) {
+                draw(label, &progress, SPINNER[frame % SPINNER.len()]);
+                frame += 1;
+                std::thread::sleep(REDRAW_INTERVAL);
+            }
+            clear();
+        });
+
+        let result = job(&progress);
+        done.store(true, Ordering::SeqCst);
+        result
+    })
+}
+
+// Redraws the line from the progress callback instead of a timer, used for
+// bulk operations where every finished record is worth showing
+pub fn with_record_progress_line<T>(
+    label: &str,
+    enabled: bool,
+    job: impl FnOnce(&Progress) -> T,
+) -> T {
+    if !enabled {
+        return job(&Progress::new());
+    }
+
+    let owned_label = label.to_string();
+    let progress = Progress::with_callback(move |completed, total| {
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "\r{} {}/{}", owned_label, completed, total);
+        let _ = stderr.flush();
+    });
+
+    let result = job(&progress);
+    clear();
+    result
+}
+
+fn draw(label: &str, progress: &Progress, spinner: char) {
+    let mut stderr = std::io::stderr();
+    let _ = match progress.fraction() {
+        Some(fraction) => write!(
+            stderr,
+            "\r{} {} {}/{} ({:.0}%)",
+            label,
+            spinner,
+            progress.completed(),
+            progress.total(),
+            fraction * 100.0
+        ),
+        None => write!(stderr, "\r{} {}", label, spinner),
+    };
+    let _ = stderr.flush();
+}
+
+fn clear() {
+    let mut stderr = std::io::stderr();
+    // Erase the whole line so a shorter message can be written over it
+    let _ = write!(stderr, "\r\x1b[2K");
+    let _ = stderr.flush();
+}
@@ -0,0 +1,119 @@
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+use pwm_db::{
+    db_base::error::DatabaseError,
+    db_encrypted::{db_interface::DatabaseInterface, DatabaseEncrypted},
+};
+use pwm_lib::{
+    encryption::EncryptionResult,
+    ssh::{
+        agent::{bind, Agent},
+        SshKey,
+    },
+};
+
+use crate::{
+    password::request_password,
+    progress::{with_progress_line, with_record_progress_line},
+};
+
+// Unlocks the vault, loads every entry holding an SSH key and answers
+// ssh-agent requests on the socket until the process is stopped
+pub fn run_ssh_agent(file: &str, socket: &str, confirm: bool) -> Result<(), DatabaseError> {
+    let contents = match std::fs::read(file) {
+        Ok(contents) => match EncryptionResult::new(contents) {
+            Ok(contents) => contents,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        },
+        Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+    };
+
+    let password = match request_password("Enter master password") {
+        Ok(password) => password,
+        Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+    };
+
+    let db = with_progress_line("Unlocking vault", true, |progress| {
+        DatabaseEncrypted::new_deserialize_encrypted_with_progress(
+            &contents,
+            password.as_bytes(),
+            progress,
+        )
+    })?;
+
+    let entries = with_record_progress_line("Loading SSH keys", true, |progress| {
+        db.get_all_with_progress(password.as_bytes(), progress)
+    })?;
+
+    let mut keys = Vec::new();
+    for (name, data) in entries.iter() {
+        if !SshKey::is_ssh_key(data.as_slice()) {
+            continue;
+        }
+
+        match SshKey::from_entry(name, data.as_slice()) {
+            Ok(key) => keys.push(key),
+            Err(error) => eprintln!("Skipping \"{}\": {}", name, error),
+        }
+    }
+    drop(entries);
+
+    if keys.is_empty() {
+        return Err(DatabaseError::InputError(String::from(
+            "The vault doesn't contain any SSH keys",
+        )));
+    }
+
+    for key in keys.iter() {
+        println!("{} {} {}", key.algorithm(), key.fingerprint(), key.name());
+    }
+
+    let agent = if confirm {
+        // Prompts from different connections would interleave on the terminal
+        let terminal = Mutex::new(());
+        Agent::with_confirmation(keys, move |key| {
+            let _lock = match terminal.lock() {
+                Ok(lock) => lock,
+                Err(_error) => return false,
+            };
+            confirm_use(key)
+        })
+    } else {
+        Agent::new(keys)
+    };
+
+    let listener = match bind(socket) {
+        Ok(listener) => listener,
+        Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+    };
+
+    println!("SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;", socket);
+    let result = Arc::new(agent).serve(listener, |error| {
+        eprintln!("ssh-agent client error: {}", error);
+    });
+    let _ = std::fs::remove_file(socket);
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(error) => Err(DatabaseError::InputError(error.to_string())),
+    }
+}
+
+fn confirm_use(key: &SshKey) -> bool {
+    print!(
+        "Allow a signature with \"{}\" ({})? (Y, N) ",
+        key.name(),
+        key.fingerprint()
+    );
+    if std::io::stdout().flush().is_err() {
+        return false;
+    }
+
+    let mut input = String::new();
+    if std::io::stdin().lock().read_line(&mut input).is_err() {
+        return false;
+    }
+
+    matches!(input.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
@@ -1,10 +1,42 @@
+use std::path::Path;
+
 use pwm_db::{
-    db_base::error::DatabaseError,
-    db_encrypted::{db_interface::DatabaseInterface, DatabaseEncrypted},
+    audit::{
+        pwned::{check_pwned_with_progress, PwnedPasswords},
+        report::{audit_with_progress, AuditReport, DEFAULT_MAX_AGE_DAYS},
+    },
+    db_base::{base_name, error::DatabaseError, is_in_group, join_group, GROUP_SEPARATOR},
+    db_encrypted::{
+        attachment::{attachment_file_name, DEFAULT_MAX_ATTACHMENT_SIZE},
+        bitwarden::{is_bitwarden_file, is_password_protected},
+        db_interface::DatabaseInterface,
+        diff::{FieldChange, VaultDiff},
+        entry::{
+            CustomField, Entry, FIELD_NOTES, FIELD_PASSWORD, FIELD_TITLE, FIELD_URL, FIELD_USERNAME,
+        },
+        kdbx::is_kdbx_file,
+        merge::MergeSide,
+        trash::DEFAULT_TRASH_DAYS,
+        DatabaseEncrypted,
+    },
+    lock::VaultFile,
+    timestamp::format_timestamp,
+};
+use pwm_lib::{
+    atomic_file::{write_atomic, DEFAULT_BACKUPS},
+    encryption::EncryptionResult,
+    random::{
+        random_password,
+        token::{random_token, TokenKind, DEFAULT_TOKEN_BYTES},
+    },
+    ssh::SshKey,
+    zeroize::Zeroizing,
 };
-use pwm_lib::{encryption::EncryptionResult, random::random_password, zeroize::Zeroizing};
 
-use crate::parser::Parser;
+use crate::{
+    parser::Parser,
+    progress::{with_progress_line, with_record_progress_line},
+};
 
 pub struct Vault<I, O>
 where
@@ -17,6 +49,24 @@ where
     writer: O,
     test_mode: bool,
     clipboard: Option<arboard::Clipboard>,
+    // Number of backups kept when saving
+    backups: usize,
+    // Days removed entries stay in the recycle bin, 0 keeps them
+    trash_days: u32,
+    // Largest file that can be attached, in bytes
+    max_attachment_size: u64,
+    // The group names are relative to, "" is the root
+    group: String,
+    // The file the vault was opened from or last saved to and its lock
+    vault_file: VaultFile,
+}
+
+// How a file is imported and the password it is opened with
+enum ImportSource {
+    Csv,
+    Kdbx(Zeroizing<String>),
+    // None for exports without a password
+    Bitwarden(Option<Zeroizing<String>>),
 }
 
 impl<I, O> Vault<I, O>
@@ -63,6 +113,11 @@ where
             writer,
             test_mode,
             clipboard,
+            backups: DEFAULT_BACKUPS,
+            trash_days: DEFAULT_TRASH_DAYS,
+            max_attachment_size: DEFAULT_MAX_ATTACHMENT_SIZE,
+            group: String::new(),
+            vault_file: VaultFile::new(),
         })
     }
 
@@ -76,18 +131,26 @@ where
     fn new_from_file_internal<In, Out>(
         file: &str,
         mut reader: In,
-        writer: Out,
+        mut writer: Out,
         test_mode: bool,
     ) -> Result<Vault<In, Out>, DatabaseError>
     where
         In: std::io::BufRead,
         Out: std::io::Write,
     {
-        let contents = match std::fs::read(file) {
-            Ok(contents) => match EncryptionResult::new(contents) {
-                Ok(contents) => contents,
-                Err(error) => return Err(DatabaseError::InputError(error.to_string())),
-            },
+        let (vault_file, contents) = match VaultFile::open(file, false) {
+            Ok(opened) => opened,
+            Err(DatabaseError::Locked(owner)) => {
+                let question =
+                    format!("{} is in use by {}, open it read-only? (Y, N)", file, owner);
+                if !confirm(&mut reader, &mut writer, &question)? {
+                    return Err(DatabaseError::Locked(owner));
+                }
+                match VaultFile::open(file, true) {
+                    Ok(opened) => opened,
+                    Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+                }
+            }
             Err(error) => return Err(DatabaseError::InputError(error.to_string())),
         };
 
@@ -103,7 +166,13 @@ where
             }
         };
 
-        let db = DatabaseEncrypted::new_deserialize_encrypted(&contents, password.as_bytes())?;
+        let (db, recovered) = open_database(file, contents, password.as_bytes(), !test_mode)?;
+        if recovered {
+            writeln!(
+                writer,
+                "Warning: the log was damaged, changes after the last complete save were lost"
+            )?;
+        }
 
         let clipboard = if test_mode {
             None
@@ -121,6 +190,11 @@ where
             writer,
             test_mode,
             clipboard,
+            backups: DEFAULT_BACKUPS,
+            trash_days: DEFAULT_TRASH_DAYS,
+            max_attachment_size: DEFAULT_MAX_ATTACHMENT_SIZE,
+            group: String::new(),
+            vault_file,
         })
     }
 
@@ -132,6 +206,18 @@ where
         Self::new_from_file_internal(file, reader, writer, false)
     }
 
+    pub fn set_backups(&mut self, backups: usize) {
+        self.backups = backups;
+    }
+
+    pub fn set_trash_days(&mut self, days: u32) {
+        self.trash_days = days;
+    }
+
+    pub fn set_max_attachment_size(&mut self, size: u64) {
+        self.max_attachment_size = size;
+    }
+
     pub fn run(&mut self) -> std::io::Result<()> {
         self.help()?;
         Ok(self.run_without_help()?)
@@ -183,8 +269,28 @@ where
                     }
                 }
                 "import" | "im" => {
-                    if let Some(name) = itr.next() {
-                        match self.import(name) {
+                    let mut dry_run = false;
+                    let mut key_file = None;
+                    let mut file = None;
+                    let mut missing_key_file = false;
+                    while let Some(arg) = itr.next() {
+                        match arg {
+                            "--dry-run" | "-n" => dry_run = true,
+                            "--key-file" | "-k" => {
+                                key_file = itr.next();
+                                missing_key_file = key_file.is_none();
+                            }
+                            _ => file = Some(arg),
+                        }
+                    }
+                    if missing_key_file {
+                        writeln!(self.writer, "Expected a key file")?;
+                    } else if let Some(name) = file {
+                        let result = match dry_run {
+                            true => self.import_dry_run(name, key_file),
+                            false => self.import(name, key_file),
+                        };
+                        match result {
                             Ok(()) => {}
                             Err(error) => {
                                 writeln!(self.writer, "{}", error.to_string())?;
@@ -194,6 +300,31 @@ where
                         writeln!(self.writer, "Expected a file")?;
                     }
                 }
+                "diff" => {
+                    let args: Vec<&str> = itr.collect();
+                    let reveal = args.contains(&"--reveal");
+                    let file = args.into_iter().find(|arg| *arg != "--reveal");
+                    match self.diff(file, reveal) {
+                        Ok(()) => {}
+                        Err(error) => {
+                            writeln!(self.writer, "Failed to diff: {}", error)?;
+                        }
+                    }
+                }
+                "merge" => {
+                    let args: Vec<&str> = itr.collect();
+                    match args.as_slice() {
+                        [theirs, "--base", base] => match self.merge(theirs, base) {
+                            Ok(()) => {}
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to merge: {}", error)?;
+                            }
+                        },
+                        _ => {
+                            writeln!(self.writer, "Expected merge <file> --base <file>")?;
+                        }
+                    }
+                }
                 "export" | "ex" => {
                     if let Some(name) = itr.next() {
                         match self.export(name) {
@@ -244,43 +375,36 @@ where
                 }
                 "get" | "g" => {
                     if let Some(data) = itr.next() {
-                        match self.get(data) {
-                            Ok(result) => {
-                                let pass = match String::from_utf8(result.as_slice().to_vec()) {
-                                    Ok(val) => Zeroizing::new(val),
+                        let field = match (itr.next(), itr.next()) {
+                            (Some("--field" | "-f"), Some(field)) => Some(field),
+                            (None, _) => None,
+                            _ => {
+                                writeln!(self.writer, "Expected get <key> --field <name>")?;
+                                return Ok(false);
+                            }
+                        };
+
+                        let result = match field {
+                            Some(field) => self.get_field(data, field),
+                            None => match self.get(data) {
+                                Ok(result) => match String::from_utf8(result.as_slice().to_vec()) {
+                                    Ok(val) => Ok(Zeroizing::new(val)),
                                     Err(error) => {
                                         writeln!(
                                             self.writer,
                                             "Failed to convert data to String: {}",
                                             error.to_string()
                                         )?;
-                                        Zeroizing::new("".to_string())
+                                        Ok(Zeroizing::new("".to_string()))
                                     }
-                                };
-
-                                if self.test_mode {
-                                    writeln!(self.writer, "{}", pass.as_str())?;
-                                } else {
-                                    if let Some(clipboard) = &mut self.clipboard {
-                                        match clipboard.set_text(pass.as_str()) {
-                                            Ok(()) => {
-                                                writeln!(self.writer, "copied to clipboard")?;
-                                            }
-                                            Err(error) => {
-                                                writeln!(
-                                                    self.writer,
-                                                    "failed to copy to clipboard: {}",
-                                                    error.to_string()
-                                                )?;
-                                            }
-                                        };
-                                    } else {
-                                        return Err(std::io::Error::new(
-                                            std::io::ErrorKind::Other,
-                                            "Expected clipboard to be Some() when not in test mode",
-                                        ));
-                                    }
-                                }
+                                },
+                                Err(error) => Err(error),
+                            },
+                        };
+
+                        match result {
+                            Ok(pass) => {
+                                self.output_secret(pass.as_str())?;
                             }
                             Err(error) => {
                                 writeln!(self.writer, "Failed to get: {}", error.to_string())?;
@@ -290,8 +414,84 @@ where
                         writeln!(self.writer, "Expected a key")?;
                     }
                 }
+                "set" => {
+                    let args: Vec<&str> = itr.collect();
+                    let secret = args.contains(&"--secret");
+                    let args: Vec<&str> =
+                        args.into_iter().filter(|arg| *arg != "--secret").collect();
+                    if let [name, field, value @ ..] = args.as_slice() {
+                        match self.set_field(name, field, value.first().copied(), secret) {
+                            Ok(()) => (),
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to set: {}", error)?;
+                            }
+                        }
+                    } else {
+                        writeln!(self.writer, "Expected set <key> <field> <value?>")?;
+                    }
+                }
+                "unset" => {
+                    if let (Some(name), Some(field)) = (itr.next(), itr.next()) {
+                        match self.unset_field(name, field) {
+                            Ok(()) => (),
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to unset: {}", error)?;
+                            }
+                        }
+                    } else {
+                        writeln!(self.writer, "Expected unset <key> <field>")?;
+                    }
+                }
+                "history" => {
+                    if let Some(name) = itr.next() {
+                        let result = match itr.next() {
+                            Some(index) => self.get_history(name, index),
+                            None => self.history(name),
+                        };
+                        match result {
+                            Ok(()) => (),
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to get history: {}", error)?;
+                            }
+                        }
+                    } else {
+                        writeln!(self.writer, "Expected a key")?;
+                    }
+                }
+                "restore" => {
+                    if let Some(name) = itr.next() {
+                        // A removed entry is put back when there is no entry
+                        // with the name and no n is given
+                        let index = itr.next();
+                        let result = match index.is_none() && self.is_trashed(name) {
+                            true => self.restore_trashed(name),
+                            false => self.restore(name, index.unwrap_or("1")),
+                        };
+                        match result {
+                            Ok(()) => (),
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to restore: {}", error)?;
+                            }
+                        }
+                    } else {
+                        writeln!(self.writer, "Expected a key")?;
+                    }
+                }
+                "info" => {
+                    if let Some(name) = itr.next() {
+                        match self.info(name) {
+                            Ok(()) => (),
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to get: {}", error)?;
+                            }
+                        }
+                    } else {
+                        writeln!(self.writer, "Expected a key")?;
+                    }
+                }
                 "list" | "ls" => {
-                    match self.list(itr.next()) {
+                    let (query, tags) = Self::parse_tag_filters(itr);
+                    match self.list(query.as_deref(), &tags) {
                         Ok(()) => (),
                         Err(error) => {
                             writeln!(self.writer, "Failed to list: {}", error.to_string())?;
@@ -299,16 +499,131 @@ where
                     };
                 }
                 "search" => {
-                    match self.list(itr.next()) {
+                    let (query, tags) = Self::parse_tag_filters(itr);
+                    match self.list(query.as_deref(), &tags) {
                         Ok(()) => (),
                         Err(error) => {
                             writeln!(self.writer, "Failed to search: {}", error.to_string())?;
                         }
                     };
                 }
+                "tag" | "untag" => {
+                    let name = itr.next();
+                    let tags: Vec<&str> = itr.collect();
+                    if let (Some(name), false) = (name, tags.is_empty()) {
+                        let result = match value {
+                            "tag" => self.add_tags(name, &tags),
+                            _ => self.remove_tags(name, &tags),
+                        };
+                        match result {
+                            Ok(()) => (),
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to {}: {}", value, error)?;
+                            }
+                        }
+                    } else {
+                        writeln!(self.writer, "Expected {} <key> <tag...>", value)?;
+                    }
+                }
+                "trash" => {
+                    self.trash()?;
+                }
+                "purge" => match self.purge_trash() {
+                    Ok(()) => (),
+                    Err(error) => {
+                        writeln!(self.writer, "Failed to purge: {}", error)?;
+                    }
+                },
+                "undo" | "redo" => {
+                    let result = match value {
+                        "undo" => self.undo(),
+                        _ => self.redo(),
+                    };
+                    match result {
+                        Ok(()) => (),
+                        Err(error) => {
+                            writeln!(self.writer, "Failed to {}: {}", value, error)?;
+                        }
+                    }
+                }
+                "begin" => {
+                    self.db.begin();
+                    writeln!(self.writer, "Changes are kept until commit or rollback")?;
+                }
+                "commit" => {
+                    let description = itr.collect::<Vec<&str>>().join(" ");
+                    let description = match description.is_empty() {
+                        true => "transaction",
+                        false => description.as_str(),
+                    };
+                    if let Err(error) = self.db.commit(description) {
+                        writeln!(self.writer, "Failed to commit: {}", error)?;
+                    }
+                }
+                "rollback" => {
+                    if let Err(error) = self.db.rollback() {
+                        writeln!(self.writer, "Failed to rollback: {}", error)?;
+                    }
+                }
+                "tags" => match self.list_tags() {
+                    Ok(()) => (),
+                    Err(error) => {
+                        writeln!(self.writer, "Failed to list tags: {}", error)?;
+                    }
+                },
+                "cd" => {
+                    match self.change_group(itr.next().unwrap_or("/")) {
+                        Ok(()) => (),
+                        Err(error) => {
+                            writeln!(self.writer, "Failed to change group: {}", error)?;
+                        }
+                    };
+                }
+                "pwd" => {
+                    writeln!(self.writer, "{}{}", GROUP_SEPARATOR, self.group)?;
+                }
+                "mkdir" => {
+                    if let Some(group) = itr.next() {
+                        match self.create_group(group) {
+                            Ok(()) => (),
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to create group: {}", error)?;
+                            }
+                        }
+                    } else {
+                        writeln!(self.writer, "Expected a group")?;
+                    }
+                }
+                "rmdir" => {
+                    let args: Vec<&str> = itr.collect();
+                    let recursive = args.iter().any(|arg| matches!(*arg, "-r" | "--recursive"));
+                    let group = args.iter().find(|arg| !arg.starts_with('-'));
+                    if let Some(group) = group {
+                        match self.remove_group(group, recursive) {
+                            Ok(()) => (),
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to remove group: {}", error)?;
+                            }
+                        }
+                    } else {
+                        writeln!(self.writer, "Expected rmdir <group> [-r]")?;
+                    }
+                }
+                "mv" | "move" => {
+                    if let (Some(name), Some(target)) = (itr.next(), itr.next()) {
+                        match self.move_to(name, target) {
+                            Ok(()) => (),
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to move: {}", error)?;
+                            }
+                        }
+                    } else {
+                        writeln!(self.writer, "Expected mv <name> <group>")?;
+                    }
+                }
                 "save" | "s" => {
                     if let Some(value) = itr.next() {
-                        self.serialize_and_save(value)?;
+                        self.serialize_and_save(value, itr.next())?;
                     } else {
                         writeln!(self.writer, "Expected a filename")?;
                     }
@@ -320,6 +635,99 @@ where
                         writeln!(self.writer, "Expected a length")?;
                     }
                 }
+                "token" => {
+                    if let Some(kind) = itr.next() {
+                        let args: Vec<&str> = itr.collect();
+                        self.generate_token(kind, &args)?;
+                    } else {
+                        writeln!(
+                            self.writer,
+                            "Expected a token kind: {}",
+                            TokenKind::names().join(", ")
+                        )?;
+                    }
+                }
+                "audit" => {
+                    let args: Vec<&str> = itr.collect();
+                    match self.audit(&args) {
+                        Ok(()) => (),
+                        Err(error) => {
+                            writeln!(self.writer, "Failed to audit: {}", error)?;
+                        }
+                    }
+                }
+                "pwned" => {
+                    if let Some(file) = itr.next() {
+                        match self.pwned(file) {
+                            Ok(()) => (),
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to check: {}", error)?;
+                            }
+                        }
+                    } else {
+                        writeln!(self.writer, "Expected a file or directory")?;
+                    }
+                }
+                "ssh-add" => {
+                    if let (Some(name), Some(file)) = (itr.next(), itr.next()) {
+                        match self.ssh_add(name, file) {
+                            Ok(()) => (),
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to add SSH key: {}", error)?;
+                            }
+                        }
+                    } else {
+                        writeln!(self.writer, "Expected ssh-add <key> <file>")?;
+                    }
+                }
+                "attach" => {
+                    if let (Some(name), Some(file)) = (itr.next(), itr.next()) {
+                        match self.attach(name, file, itr.next()) {
+                            Ok(()) => (),
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to attach: {}", error)?;
+                            }
+                        }
+                    } else {
+                        writeln!(self.writer, "Expected attach <key> <file> <name?>")?;
+                    }
+                }
+                "attachments" => {
+                    if let Some(name) = itr.next() {
+                        match self.attachments(name) {
+                            Ok(()) => (),
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to list attachments: {}", error)?;
+                            }
+                        }
+                    } else {
+                        writeln!(self.writer, "Expected a key")?;
+                    }
+                }
+                "extract" => {
+                    if let (Some(name), Some(attachment)) = (itr.next(), itr.next()) {
+                        match self.extract(name, attachment, itr.next()) {
+                            Ok(()) => (),
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to extract: {}", error)?;
+                            }
+                        }
+                    } else {
+                        writeln!(self.writer, "Expected extract <key> <name> <file?>")?;
+                    }
+                }
+                "detach" => {
+                    if let (Some(name), Some(attachment)) = (itr.next(), itr.next()) {
+                        match self.detach(name, attachment) {
+                            Ok(()) => (),
+                            Err(error) => {
+                                writeln!(self.writer, "Failed to detach: {}", error)?;
+                            }
+                        }
+                    } else {
+                        writeln!(self.writer, "Expected detach <key> <name>")?;
+                    }
+                }
                 "exit" | "quit" | "q" => {
                     return Ok(true);
                 }
@@ -363,7 +771,7 @@ where
 
                         let mut itr = input.split_whitespace();
                         if let Some(value) = itr.next() {
-                            self.serialize_and_save(value)?;
+                            self.serialize_and_save(value, None)?;
                         }
                         break 'a;
                     }
@@ -379,6 +787,7 @@ where
     }
 
     fn insert(&mut self, name: &str, data: Option<&str>) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
         let password = match self.request_password("Enter the master password") {
             Ok(password) => password,
             Err(error) => return Err(DatabaseError::InputError(error.to_string())),
@@ -404,379 +813,2241 @@ where
         Ok(())
     }
 
-    fn import(&mut self, file: &str) -> Result<(), DatabaseError> {
+    // audit [--json] [--days <days>]
+    fn audit(&mut self, args: &[&str]) -> Result<(), DatabaseError> {
+        let mut json = false;
+        let mut days = DEFAULT_MAX_AGE_DAYS;
+
+        let mut itr = args.iter();
+        while let Some(arg) = itr.next() {
+            match *arg {
+                "--json" | "-j" => json = true,
+                "--days" | "-d" => match itr.next().map(|value| value.parse()) {
+                    Some(Ok(value)) => days = value,
+                    _ => {
+                        return Err(DatabaseError::InputError(String::from(
+                            "Expected a number of days",
+                        )))
+                    }
+                },
+                value => {
+                    return Err(DatabaseError::InputError(format!(
+                        "Unknown option \"{}\"",
+                        value
+                    )))
+                }
+            }
+        }
+
         let password = match self.request_password("Enter the master password") {
             Ok(password) => password,
             Err(error) => return Err(DatabaseError::InputError(error.to_string())),
         };
-        self.db.insert_from_csv(file, password.as_bytes())?;
-        self.changed = true;
+
+        let db = &self.db;
+        let report = with_record_progress_line("Auditing", !self.test_mode, |progress| {
+            audit_with_progress(db, days, password.as_bytes(), progress)
+        })?;
+
+        let lines = if json {
+            vec![audit_to_json(&report, days).pretty(2)]
+        } else {
+            audit_to_lines(&report, days)
+        };
+
+        for line in lines {
+            if let Err(error) = writeln!(self.writer, "{}", line) {
+                return Err(DatabaseError::OutputError(error.to_string()));
+            }
+        }
 
         Ok(())
     }
 
-    fn export(&mut self, file: &str) -> Result<(), DatabaseError> {
+    fn pwned(&mut self, file: &str) -> Result<(), DatabaseError> {
+        let pwned = PwnedPasswords::open(file)?;
         let password = match self.request_password("Enter the master password") {
             Ok(password) => password,
             Err(error) => return Err(DatabaseError::InputError(error.to_string())),
         };
-        self.db.export_to_csv(file, password.as_bytes())?;
 
-        Ok(())
+        let db = &self.db;
+        let found = with_record_progress_line("Checking", !self.test_mode, |progress| {
+            check_pwned_with_progress(db, &pwned, password.as_bytes(), progress)
+        })?;
+
+        let result = if found.is_empty() {
+            writeln!(self.writer, "No stored passwords were found in breaches")
+        } else {
+            found.iter().try_for_each(|entry| {
+                writeln!(
+                    self.writer,
+                    "\"{}\" was seen {} times in breaches",
+                    entry.name, entry.count
+                )
+            })
+        };
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(error) => Err(DatabaseError::OutputError(error.to_string())),
+        }
     }
 
-    fn remove(&mut self, name: &str) -> Result<(), DatabaseError> {
-        let password = match self.request_password("Enter the master password") {
-            Ok(password) => password,
+    // Checks the file holds a key the ssh-agent can use before storing it
+    fn ssh_add(&mut self, name: &str, file: &str) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let contents = match std::fs::read(file) {
+            Ok(contents) => Zeroizing::new(contents),
             Err(error) => return Err(DatabaseError::InputError(error.to_string())),
         };
-        self.db.remove(name, password.as_bytes())?;
-        self.changed = true;
 
-        Ok(())
-    }
+        let key = match SshKey::from_entry(name, contents.as_slice()) {
+            Ok(key) => key,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
 
-    fn edit(&mut self, name: &str, new_data: Option<&str>) -> Result<(), DatabaseError> {
         let password = match self.request_password("Enter the master password") {
             Ok(password) => password,
             Err(error) => return Err(DatabaseError::InputError(error.to_string())),
         };
 
-        let new_data_password: Zeroizing<String>;
-        let new_data = if let Some(new_data) = new_data {
-            new_data
-        } else {
-            match self.request_password("Enter new password") {
-                Ok(password) => {
-                    new_data_password = password;
-                    new_data_password.as_str()
-                }
-                Err(error) => return Err(DatabaseError::InputError(error.to_string())),
-            }
-        };
-        self.db
-            .replace(name, new_data.as_bytes(), password.as_bytes())?;
+        self.db.insert(name, contents.as_slice(), password.as_bytes())?;
         self.changed = true;
 
+        if let Err(error) = writeln!(
+            self.writer,
+            "Added {} key {}",
+            key.algorithm(),
+            key.fingerprint()
+        ) {
+            return Err(DatabaseError::OutputError(error.to_string()));
+        }
+
         Ok(())
     }
 
-    fn rename(&mut self, name: &str, new_name: &str) -> Result<(), DatabaseError> {
-        if name == new_name {
-            return Ok(())
-        }
+    // Shows what changed since the vault was saved to a file, the file it
+    // was opened from or last saved to by default
+    fn diff(&mut self, file: Option<&str>, reveal: bool) -> Result<(), DatabaseError> {
+        let own_file = self
+            .vault_file
+            .path()
+            .map(|path| path.display().to_string());
+        let file = match file.map(String::from).or(own_file) {
+            Some(file) => file,
+            None => {
+                return Err(DatabaseError::InputError(String::from(
+                    "The vault wasn't saved yet, expected a file",
+                )))
+            }
+        };
+        let contents = match std::fs::read(&file) {
+            Ok(contents) => contents,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
         let password = match self.request_password("Enter the master password") {
             Ok(password) => password,
             Err(error) => return Err(DatabaseError::InputError(error.to_string())),
         };
-        self.db.rename(name, new_name, password.as_bytes())?;
-        self.changed = true;
+        let (saved, _recovered) =
+            open_database(&file, contents, password.as_bytes(), !self.test_mode)?;
+
+        let diff = self.db.diff(&saved, password.as_bytes(), reveal)?;
+        write_diff(&mut self.writer, &diff)?;
 
         Ok(())
     }
 
-    fn get(&mut self, name: &str) -> Result<EncryptionResult, DatabaseError> {
+    // Merges the changes made to another copy of the vault since base, asks
+    // which copy to keep for every entry both copies changed
+    pub fn merge(&mut self, theirs: &str, base: &str) -> Result<(), DatabaseError> {
         let password = match self.request_password("Enter the master password") {
             Ok(password) => password,
             Err(error) => return Err(DatabaseError::InputError(error.to_string())),
         };
-        self.db.get(name, password.as_bytes())
-    }
 
-    fn list(&mut self, pattern: Option<&str>) -> Result<(), DatabaseError> {
-        let list_lifetime: Vec<String>;
+        let mut opened = Vec::new();
+        for file in [theirs, base] {
+            let contents = match std::fs::read(file) {
+                Ok(contents) => contents,
+                Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+            };
+            let (db, recovered) =
+                open_database(file, contents, password.as_bytes(), !self.test_mode)?;
+            if recovered {
+                writeln!(
+                    self.writer,
+                    "Warning: the log {} was damaged, changes after its last complete save are missing",
+                    file
+                )?;
+            }
+            opened.push(db);
+        }
 
-        let list = if let Some(pattern) = pattern {
-            self.db.list_fuzzy_match(pattern)?
+        let report = self.db.merge(&opened[1], &opened[0], password.as_bytes())?;
+        for name in report.merged.iter() {
+            writeln!(self.writer, "Merged {}", name)?;
+        }
+        if !report.merged.is_empty() {
+            self.changed = true;
+        }
+
+        for conflict in report.conflicts.iter() {
+            writeln!(self.writer, "Conflict in {}", conflict.name)?;
+            for (label, entry) in [("this vault", &conflict.ours), (theirs, &conflict.theirs)] {
+                match entry {
+                    Some(entry) => writeln!(
+                        self.writer,
+                        "    {}: modified {}",
+                        label,
+                        format_timestamp(entry.modified())
+                    )?,
+                    None => writeln!(self.writer, "    {}: removed", label)?,
+                }
+            }
+
+            let side = loop {
+                writeln!(self.writer, "Keep this vault (A) or {} (B)?", theirs)?;
+                let mut input = String::new();
+                if self.reader.read_line(&mut input)? == 0 {
+                    return Err(DatabaseError::InputError(String::from(
+                        "Merge stopped before every conflict was resolved",
+                    )));
+                }
+                match input.trim().to_ascii_lowercase().as_str() {
+                    "a" => break MergeSide::Ours,
+                    "b" => break MergeSide::Theirs,
+                    _ => (),
+                }
+            };
+            self.db.resolve_conflict(conflict, side)?;
+            self.changed = true;
+        }
+
+        Ok(())
+    }
+
+    // KeePass databases are recognized by their signature and Bitwarden
+    // exports by their extension, anything else is read as csv. Their
+    // passwords are asked for before the master password.
+    fn request_import_source(
+        &mut self,
+        file: &str,
+        key_file: Option<&str>,
+    ) -> Result<ImportSource, DatabaseError> {
+        let kdbx = is_kdbx_file(file);
+        if !kdbx && key_file.is_some() {
+            return Err(DatabaseError::InputError(String::from(
+                "Key files are only used by KeePass databases",
+            )));
+        }
+
+        let prompt = if kdbx {
+            "Enter the KeePass password"
+        } else if !is_bitwarden_file(file) {
+            return Ok(ImportSource::Csv);
+        } else if !is_password_protected(file)? {
+            return Ok(ImportSource::Bitwarden(None));
         } else {
-            list_lifetime = self.db.list()?;
-            &list_lifetime
+            "Enter the export password"
+        };
+        let password = match self.request_password(prompt) {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
         };
 
-        let mut list_string = String::new();
-        let last_value = list.len() - 1;
-        let mut itr_number = 0;
-        for value in list {
-            if itr_number != last_value {
-                list_string += format!("{}, ", value).as_str();
-            } else {
-                list_string += format!("{}", value).as_str();
-            }
-            itr_number += 1;
+        match kdbx {
+            true => Ok(ImportSource::Kdbx(password)),
+            false => Ok(ImportSource::Bitwarden(Some(password))),
+        }
+    }
+
+    fn import(&mut self, file: &str, key_file: Option<&str>) -> Result<(), DatabaseError> {
+        let source = self.request_import_source(file, key_file)?;
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+        let db = &mut self.db;
+        let unmapped = with_record_progress_line(
+            "Importing",
+            !self.test_mode,
+            |progress| -> Result<Vec<String>, DatabaseError> {
+                match &source {
+                    ImportSource::Csv => {
+                        db.insert_from_csv_with_progress(file, password.as_bytes(), progress)?
+                    }
+                    ImportSource::Kdbx(kdbx_password) => db.insert_from_kdbx_with_progress(
+                        file,
+                        kdbx_password.as_bytes(),
+                        key_file,
+                        password.as_bytes(),
+                        progress,
+                    )?,
+                    ImportSource::Bitwarden(export_password) => {
+                        let report = db.insert_from_bitwarden_with_progress(
+                            file,
+                            export_password.as_ref().map(|password| password.as_bytes()),
+                            password.as_bytes(),
+                            progress,
+                        )?;
+                        return Ok(report.unmapped);
+                    }
+                }
+                Ok(Vec::new())
+            },
+        )?;
+        self.changed = true;
+
+        for line in unmapped.iter() {
+            writeln!(self.writer, "Not imported: {}", line)?;
         }
-        writeln!(self.writer, "{}", list_string)?;
 
         Ok(())
     }
 
-    fn serialize_and_save(&mut self, file: &str) -> std::io::Result<()> {
-        let password = match self.request_password("Enter master password") {
-            Ok(pass) => pass,
-            Err(error) => {
-                writeln!(self.writer, "Error failed to get user input {}", error)?;
-                return Ok(());
-            }
+    fn import_dry_run(&mut self, file: &str, key_file: Option<&str>) -> Result<(), DatabaseError> {
+        let source = self.request_import_source(file, key_file)?;
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+        let report = match &source {
+            ImportSource::Csv => self.db.insert_from_csv_dry_run(file, password.as_bytes())?,
+            ImportSource::Kdbx(kdbx_password) => self.db.insert_from_kdbx_dry_run(
+                file,
+                kdbx_password.as_bytes(),
+                key_file,
+                password.as_bytes(),
+            )?,
+            ImportSource::Bitwarden(export_password) => self.db.insert_from_bitwarden_dry_run(
+                file,
+                export_password.as_ref().map(|password| password.as_bytes()),
+                password.as_bytes(),
+            )?,
         };
 
-        let ciphertext = match self.db.serialize_encrypted(password.as_bytes()) {
-            Ok(data) => data,
-            Err(error) => {
-                writeln!(self.writer, "Error failed to serialize database: {}", error)?;
-                return Ok(());
-            }
+        for name in report.added.iter() {
+            writeln!(self.writer, "Would add {}", name)?;
+        }
+        for (name, error) in report.conflicts.iter() {
+            writeln!(self.writer, "Can't add {}: {}", name, error)?;
+        }
+        for line in report.unmapped.iter() {
+            writeln!(self.writer, "Not imported: {}", line)?;
+        }
+        if report.skipped > 0 {
+            writeln!(self.writer, "Skipped records: {}", report.skipped)?;
+        }
+        if !report.conflicts.is_empty() {
+            writeln!(self.writer, "Nothing would be imported")?;
+        }
+
+        Ok(())
+    }
+
+    fn export(&mut self, file: &str) -> Result<(), DatabaseError> {
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
         };
+        let db = &mut self.db;
+        with_record_progress_line("Exporting", !self.test_mode, |progress| {
+            db.export_to_csv_with_progress(file, password.as_bytes(), progress)
+        })?;
 
-        writeln!(self.writer, "Writing to file \"{}\"", file)?;
-        match std::fs::write(file, ciphertext.as_ref()) {
-            Ok(()) => (),
-            Err(error) => {
-                writeln!(self.writer, "Error failed to write to file: {}", error)?;
-                return Ok(());
-            }
+        Ok(())
+    }
+
+    fn remove(&mut self, name: &str) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
         };
-        self.changed = false;
+        self.db.remove(name, password.as_bytes())?;
+        self.changed = true;
 
         Ok(())
     }
 
-    fn generate_password(&mut self, length: &str) -> std::io::Result<()> {
-        let length = match length.parse::<usize>() {
-            Ok(length) => length,
-            Err(_error) => {
-                writeln!(self.writer, "Invalid length input")?;
-                return Ok(());
+    fn edit(&mut self, name: &str, new_data: Option<&str>) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        let new_data_password: Zeroizing<String>;
+        let new_data = if let Some(new_data) = new_data {
+            new_data
+        } else {
+            match self.request_password("Enter new password") {
+                Ok(password) => {
+                    new_data_password = password;
+                    new_data_password.as_str()
+                }
+                Err(error) => return Err(DatabaseError::InputError(error.to_string())),
             }
         };
-        let password = match random_password(length) {
+        self.db
+            .replace(name, new_data.as_bytes(), password.as_bytes())?;
+        self.changed = true;
+
+        Ok(())
+    }
+
+    fn rename(&mut self, name: &str, new_name: &str) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let new_name = &self.resolve(new_name);
+        if name == new_name {
+            return Ok(())
+        }
+        let password = match self.request_password("Enter the master password") {
             Ok(password) => password,
-            Err(error) => {
-                writeln!(self.writer, "Failed to generate password: {}", error)?;
-                return Ok(());
-            }
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
         };
-        writeln!(self.writer, "Generated: \"{}\"", password)?;
+        self.db.rename(name, new_name, password.as_bytes())?;
+        self.changed = true;
+
         Ok(())
     }
 
-    fn help(&mut self) -> std::io::Result<()> {
-        writeln!(
-            self.writer,
-            "Vault:
-    help                  - this menu 
-    insert  <key> <data?> - insert an element 
-    remove  <key>         - remove an element
-    edit    <key> <data?> - remove an element
-    rename  <name> <name> - rename an entry
-    get     <key>         - retrieve an element
-    save    <file>        - save to a file
-    list    <pattern?>    - list all keys
-    search  <pattern?>    - search all keys
-    import  <file>        - import key/value pairs from csv
-    export  <file>        - export key/value pairs to csv
-    pw      <length>      - generate a password
-    exit                  - exit the program"
-        )?;
+    fn get(&mut self, name: &str) -> Result<EncryptionResult, DatabaseError> {
+        let name = &self.resolve(name);
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+        let result = self.db.get(name, password.as_bytes())?;
+        self.db.mark_accessed(name)?;
+        Ok(result)
+    }
+
+    // Times of the entry and when each previous password was replaced
+    fn history(&mut self, name: &str) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let info = self.db.get_info(name)?;
+
+        let mut lines = vec![
+            format!("created:  {}", format_timestamp(info.created())),
+            format!("modified: {}", format_timestamp(info.modified())),
+            format!("accessed: {}", format_timestamp(info.accessed())),
+        ];
+        for (index, replaced) in info.history_times().into_iter().enumerate() {
+            lines.push(format!(
+                "{}: replaced {}",
+                index + 1,
+                format_timestamp(replaced)
+            ));
+        }
+
+        for line in lines {
+            writeln!(self.writer, "{}", line)?;
+        }
+
         Ok(())
     }
 
-    fn request_password(&mut self, prompt: &str) -> std::io::Result<Zeroizing<String>> {
-        if self.test_mode {
-            crate::password::request_password_test(&mut self.reader, prompt)
-        } else {
-            crate::password::request_password(prompt)
+    // Previous passwords are numbered from 1, the newest
+    fn history_index(index: &str) -> Result<usize, DatabaseError> {
+        match index.parse::<usize>() {
+            Ok(index) if index > 0 => Ok(index - 1),
+            _ => Err(DatabaseError::InputError(format!(
+                "\"{}\" is not a history number",
+                index
+            ))),
         }
     }
 
-    #[allow(dead_code)]
-    fn password_confirmation(&mut self) -> std::io::Result<Zeroizing<String>> {
-        if self.test_mode {
-            crate::password::password_confirmation_test(&mut self.reader)
-        } else {
-            crate::password::password_confirmation()
+    fn get_history(&mut self, name: &str, index: &str) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let index = Self::history_index(index)?;
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        let history = self.db.get_history(name, password.as_bytes())?;
+        let previous = match history.get(index) {
+            Some(previous) => previous,
+            None => return Err(DatabaseError::NotFound),
+        };
+        let previous = match String::from_utf8(previous.password.to_vec()) {
+            Ok(previous) => Zeroizing::new(previous),
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        self.output_secret(previous.as_str())?;
+        Ok(())
+    }
+
+    fn restore(&mut self, name: &str, index: &str) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let index = Self::history_index(index)?;
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        self.db.restore_history(name, index, password.as_bytes())?;
+        self.changed = true;
+
+        Ok(())
+    }
+
+    // Stored under the file's name unless another is given
+    fn attach(
+        &mut self,
+        name: &str,
+        file: &str,
+        attachment: Option<&str>,
+    ) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let attachment = match attachment {
+            Some(attachment) => attachment.to_string(),
+            None => match Path::new(file).file_name() {
+                Some(file_name) => file_name.to_string_lossy().to_string(),
+                None => return Err(DatabaseError::InputError(format!("{} is not a file", file))),
+            },
+        };
+        let contents = match std::fs::read(file) {
+            Ok(contents) => Zeroizing::new(contents),
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        let max_size = self.max_attachment_size;
+        self.db
+            .add_attachment(name, &attachment, &contents, max_size, password.as_bytes())?;
+        self.changed = true;
+
+        Ok(())
+    }
+
+    fn attachments(&mut self, name: &str) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let info = self.db.get_info(name)?;
+
+        let mut lines = Vec::new();
+        for attachment in info.attachments() {
+            lines.push(format!(
+                "{}  {} bytes  added {}",
+                attachment.name,
+                attachment.size,
+                format_timestamp(attachment.added)
+            ));
         }
+
+        for line in lines {
+            if let Err(error) = writeln!(self.writer, "{}", line) {
+                return Err(DatabaseError::OutputError(error.to_string()));
+            }
+        }
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::Vault;
-    use std::io::{BufRead, BufReader, Cursor, Write};
+    // Written to the attachment's name in the current directory unless a file
+    // is given, an existing file is never overwritten
+    fn extract(
+        &mut self,
+        name: &str,
+        attachment: &str,
+        file: Option<&str>,
+    ) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let file = match file.or(attachment_file_name(attachment)) {
+            Some(file) => file,
+            None => {
+                return Err(DatabaseError::InputError(format!(
+                    "{} isn't a file name, give a file to extract it to",
+                    attachment
+                )))
+            }
+        };
+        if Path::new(file).exists() {
+            return Err(DatabaseError::OutputError(format!(
+                "{} already exists",
+                file
+            )));
+        }
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
 
-    fn new_vault(text: &str) -> Vault<BufReader<Cursor<&[u8]>>, Cursor<Vec<u8>>> {
-        let input = BufReader::new(Cursor::new(text.as_bytes()));
-        let output = Cursor::new(Vec::<u8>::new());
+        let contents = self
+            .db
+            .get_attachment(name, attachment, password.as_bytes())?;
+        write_atomic(file, contents.as_slice(), 0)?;
 
-        let vault =
-            Vault::<BufReader<Cursor<&[u8]>>, Cursor<Vec<u8>>>::new_internal(input, output, true)
-                .unwrap();
+        if let Err(error) = writeln!(self.writer, "Extracted {} to {}", attachment, file) {
+            return Err(DatabaseError::OutputError(error.to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn detach(&mut self, name: &str, attachment: &str) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        self.db
+            .remove_attachment(name, attachment, password.as_bytes())?;
+        self.changed = true;
+
+        Ok(())
+    }
+
+    fn is_trashed(&self, name: &str) -> bool {
+        let name = self.resolve(name);
+        self.db.get_info(&name).is_err() && self.db.trash().iter().any(|t| t.name == name)
+    }
+
+    fn restore_trashed(&mut self, name: &str) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        self.db.restore_trashed(name, password.as_bytes())?;
+        self.changed = true;
+
+        Ok(())
+    }
+
+    fn trash(&mut self) -> std::io::Result<()> {
+        if self.db.trash().is_empty() {
+            writeln!(self.writer, "The recycle bin is empty")?;
+            return Ok(());
+        }
+
+        for trashed in self.db.trash() {
+            writeln!(
+                self.writer,
+                "{}  removed {}",
+                trashed.name,
+                format_timestamp(trashed.removed)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn purge_trash(&mut self) -> Result<(), DatabaseError> {
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        let purged = self.db.purge_trash(None, password.as_bytes())?;
+        if !purged.is_empty() {
+            self.changed = true;
+        }
+        if let Err(error) = writeln!(self.writer, "Purged {} entries", purged.len()) {
+            return Err(DatabaseError::OutputError(error.to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn get_field(&mut self, name: &str, field: &str) -> Result<Zeroizing<String>, DatabaseError> {
+        let name = &self.resolve(name);
+        // Plain fields are readable without the master password
+        if let Some(value) = self.db.get_info(name)?.plain_field(name, field) {
+            self.db.mark_accessed(name)?;
+            return Ok(Zeroizing::new(value));
+        }
+
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+        let value = self.db.get_field(name, field, password.as_bytes())?;
+        self.db.mark_accessed(name)?;
+        Ok(value)
+    }
+
+    // The value is asked for when missing, hidden for the password and secret
+    // fields. Setting "url" replaces every URL of the entry.
+    fn set_field(
+        &mut self,
+        name: &str,
+        field: &str,
+        value: Option<&str>,
+        secret: bool,
+    ) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        if field.eq_ignore_ascii_case(FIELD_TITLE) {
+            return Err(DatabaseError::InputError(String::from(
+                "Use rename to change the title",
+            )));
+        }
+        if secret && Entry::is_builtin_field(field) {
+            return Err(DatabaseError::InputError(format!(
+                "\"{}\" is a built in field",
+                field
+            )));
+        }
+
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+        let mut entry = self.db.get_entry(name, password.as_bytes())?;
+
+        let value_not_entered: Zeroizing<String>;
+        let value = match value {
+            Some(value) => value,
+            None => {
+                let hidden = secret || field.eq_ignore_ascii_case(FIELD_PASSWORD);
+                let result = if hidden {
+                    self.request_password("Enter the value")
+                } else {
+                    self.request_line("Enter the value")
+                };
+                value_not_entered = match result {
+                    Ok(value) => value,
+                    Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+                };
+                value_not_entered.as_str()
+            }
+        };
+
+        if field.eq_ignore_ascii_case(FIELD_USERNAME) {
+            entry.username = value.to_string();
+        } else if field.eq_ignore_ascii_case(FIELD_PASSWORD) {
+            entry.password = Zeroizing::new(value.as_bytes().to_vec());
+        } else if field.eq_ignore_ascii_case(FIELD_URL) {
+            entry.urls = vec![value.to_string()];
+        } else if field.eq_ignore_ascii_case(FIELD_NOTES) {
+            entry.notes = value.to_string();
+        } else {
+            match entry.fields.iter_mut().find(|custom| custom.name == field) {
+                Some(custom) => {
+                    custom.value = Zeroizing::new(value.to_string());
+                    custom.secret = secret;
+                }
+                None => entry.fields.push(CustomField::new(field, value, secret)),
+            }
+        }
+
+        self.db.replace_entry(&entry, password.as_bytes())?;
+        self.changed = true;
+
+        Ok(())
+    }
+
+    fn unset_field(&mut self, name: &str, field: &str) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+        let mut entry = self.db.get_entry(name, password.as_bytes())?;
+
+        if field.eq_ignore_ascii_case(FIELD_USERNAME) {
+            entry.username.clear();
+        } else if field.eq_ignore_ascii_case(FIELD_URL) {
+            entry.urls.clear();
+        } else if field.eq_ignore_ascii_case(FIELD_NOTES) {
+            entry.notes.clear();
+        } else if Entry::is_builtin_field(field) {
+            return Err(DatabaseError::InputError(format!(
+                "\"{}\" can't be removed",
+                field
+            )));
+        } else {
+            let count = entry.fields.len();
+            entry.fields.retain(|custom| custom.name != field);
+            if entry.fields.len() == count {
+                return Err(DatabaseError::NotFound);
+            }
+        }
+
+        self.db.replace_entry(&entry, password.as_bytes())?;
+        self.changed = true;
+
+        Ok(())
+    }
+
+    // Everything stored in plain text, secret fields only by name
+    fn info(&mut self, name: &str) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let info = self.db.get_info(name)?;
+
+        let mut lines = Vec::new();
+        if !info.username.is_empty() {
+            lines.push(format!("username: {}", info.username));
+        }
+        for url in info.urls.iter() {
+            lines.push(format!("url: {}", url));
+        }
+        if !info.notes.is_empty() {
+            lines.push(format!("notes: {}", info.notes));
+        }
+        if !info.tags().is_empty() {
+            let tags: Vec<&str> = info.tags().iter().map(String::as_str).collect();
+            lines.push(format!("tags: {}", tags.join(", ")));
+        }
+        for field in info.field_names() {
+            match info.plain_field(name, field) {
+                Some(value) => lines.push(format!("{}: {}", field, value)),
+                None => lines.push(format!("{}: (secret)", field)),
+            }
+        }
+
+        for line in lines {
+            writeln!(self.writer, "{}", line)?;
+        }
+
+        Ok(())
+    }
+
+    // Without a query or tags the direct children of the current group,
+    // groups ending in "/". Otherwise a search of everything below it.
+    fn list(&mut self, query: Option<&str>, tags: &[String]) -> Result<(), DatabaseError> {
+        let group = self.group.clone();
+
+        let list: Vec<String> = if query.is_some() || !tags.is_empty() {
+            self.db
+                .list_group_fuzzy_match(&group, query.unwrap_or(""), tags)?
+        } else {
+            let (groups, entries) = self.db.list_group(&group)?;
+            groups
+                .into_iter()
+                .map(|name| format!("{}{}", name, GROUP_SEPARATOR))
+                .chain(entries)
+                .collect()
+        };
+
+        let list: Vec<&str> = list
+            .iter()
+            .map(|name| match group.is_empty() {
+                true => name.as_str(),
+                false => &name[group.len() + 1..],
+            })
+            .collect();
+        writeln!(self.writer, "{}", list.join(", "))?;
+
+        Ok(())
+    }
+
+    // The search query and every "--tag <tag>" of list and search. The
+    // query is every other argument, the parser removed the quotes of
+    // phrases so they are quoted again.
+    fn parse_tag_filters<'b>(
+        mut itr: impl Iterator<Item = &'b str>,
+    ) -> (Option<String>, Vec<String>) {
+        let mut query = Vec::new();
+        let mut tags = Vec::new();
+        while let Some(arg) = itr.next() {
+            match arg {
+                "--tag" | "-t" => {
+                    if let Some(tag) = itr.next() {
+                        tags.push(tag.to_string());
+                    }
+                }
+                arg if arg.contains(char::is_whitespace) => query.push(format!("\"{}\"", arg)),
+                arg => query.push(arg.to_string()),
+            }
+        }
+
+        let query = match query.is_empty() {
+            true => None,
+            false => Some(query.join(" ")),
+        };
+        (query, tags)
+    }
+
+    fn add_tags(&mut self, name: &str, tags: &[&str]) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        for tag in tags {
+            if self.db.add_tag(name, tag, password.as_bytes())? {
+                self.changed = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_tags(&mut self, name: &str, tags: &[&str]) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        for tag in tags {
+            if !self.db.remove_tag(name, tag, password.as_bytes())? {
+                return Err(DatabaseError::NotFound);
+            }
+            self.changed = true;
+        }
+
+        Ok(())
+    }
+
+    fn undo(&mut self) -> Result<(), DatabaseError> {
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        let description = self.db.undo(password.as_bytes())?;
+        self.changed = true;
+        writeln!(self.writer, "Undid {}", description.as_str())?;
+
+        Ok(())
+    }
+
+    fn redo(&mut self) -> Result<(), DatabaseError> {
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        let description = self.db.redo(password.as_bytes())?;
+        self.changed = true;
+        writeln!(self.writer, "Redid {}", description.as_str())?;
+
+        Ok(())
+    }
+
+    fn list_tags(&mut self) -> Result<(), DatabaseError> {
+        let tags: Vec<String> = self
+            .db
+            .tags()?
+            .iter()
+            .map(|(tag, count)| format!("{} ({})", tag, count))
+            .collect();
+        writeln!(self.writer, "{}", tags.join(", "))?;
+
+        Ok(())
+    }
+
+    // Names starting with "/" are from the root, anything else is relative to
+    // the current group. ".." is the parent group.
+    fn resolve(&self, name: &str) -> String {
+        let mut parts: Vec<&str> = match name.starts_with(GROUP_SEPARATOR) {
+            true => Vec::new(),
+            false => self.group.split(GROUP_SEPARATOR).collect(),
+        };
+
+        for part in name.split(GROUP_SEPARATOR) {
+            match part {
+                "" | "." => (),
+                ".." => {
+                    parts.pop();
+                }
+                part => parts.push(part),
+            }
+        }
+
+        parts.retain(|part| !part.is_empty());
+        parts.join(&GROUP_SEPARATOR.to_string())
+    }
+
+    fn change_group(&mut self, group: &str) -> Result<(), DatabaseError> {
+        let group = self.resolve(group);
+        if !self.db.contains_group(&group) {
+            return Err(DatabaseError::NotFound);
+        }
+
+        self.group = group;
+        Ok(())
+    }
+
+    fn create_group(&mut self, group: &str) -> Result<(), DatabaseError> {
+        let group = &self.resolve(group);
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        self.db.create_group(group, password.as_bytes())?;
+        self.changed = true;
+
+        Ok(())
+    }
+
+    fn remove_group(&mut self, group: &str, recursive: bool) -> Result<(), DatabaseError> {
+        let group = &self.resolve(group);
+        if is_in_group(&self.group, group) || self.group == *group {
+            return Err(DatabaseError::InputError(String::from(
+                "Can't remove the current group",
+            )));
+        }
+
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        let removed = self
+            .db
+            .remove_group(group, recursive, password.as_bytes())?;
+        self.changed = true;
+        if !removed.is_empty() {
+            writeln!(self.writer, "Removed {} entries", removed.len())?;
+        }
+
+        Ok(())
+    }
+
+    // Moves an entry or group into a group when the target is one, otherwise
+    // renames it
+    fn move_to(&mut self, name: &str, target: &str) -> Result<(), DatabaseError> {
+        let name = &self.resolve(name);
+        let mut target = self.resolve(target);
+        if self.db.contains_group(&target) {
+            target = join_group(&target, base_name(name));
+        }
+        if is_in_group(&self.group, name) || self.group == *name {
+            return Err(DatabaseError::InputError(String::from(
+                "Can't move the current group",
+            )));
+        }
+
+        let password = match self.request_password("Enter the master password") {
+            Ok(password) => password,
+            Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+        };
+
+        if self.db.contains_group(name) {
+            self.db.rename_group(name, &target, password.as_bytes())?;
+        } else {
+            self.db.rename(name, &target, password.as_bytes())?;
+        }
+        self.changed = true;
+
+        Ok(())
+    }
+
+    // Vaults opened from a log or saved with --log append their changes to
+    // the log, --vault goes back to writing the whole vault
+    fn serialize_and_save(&mut self, file: &str, mode: Option<&str>) -> std::io::Result<()> {
+        match mode {
+            Some("--log") | None => (),
+            Some("--vault") => self.db.stop_log(),
+            Some(mode) => {
+                writeln!(self.writer, "Unknown save option {}", mode)?;
+                return Ok(());
+            }
+        }
+
+        // Another program wrote to the file since it was loaded or saved
+        match self.vault_file.changed_on_disk(file) {
+            Ok(false) => (),
+            Ok(true) => {
+                let question = format!(
+                    "{} was changed on disk since it was loaded, overwrite it? (Y, N)",
+                    file
+                );
+                if !confirm(&mut self.reader, &mut self.writer, &question)? {
+                    writeln!(self.writer, "Not saved")?;
+                    return Ok(());
+                }
+            }
+            Err(error) => {
+                writeln!(self.writer, "Error failed to check the file: {}", error)?;
+                return Ok(());
+            }
+        }
+
+        let password = match self.request_password("Enter master password") {
+            Ok(pass) => pass,
+            Err(error) => {
+                writeln!(self.writer, "Error failed to get user input {}", error)?;
+                return Ok(());
+            }
+        };
+
+        // Entries removed longer ago than the retention are dropped for good
+        if self.trash_days > 0 {
+            if let Err(error) = self
+                .db
+                .purge_trash(Some(self.trash_days), password.as_bytes())
+            {
+                writeln!(
+                    self.writer,
+                    "Error failed to purge the recycle bin: {}",
+                    error
+                )?;
+                return Ok(());
+            }
+        }
+
+        if mode == Some("--log") || self.db.uses_log() {
+            let backups = self.backups;
+            let db = &mut self.db;
+            let show_progress = !self.test_mode;
+            match self.vault_file.save(file, || {
+                with_progress_line("Saving vault", show_progress, |progress| {
+                    db.save_log(file, password.as_bytes(), backups, progress)
+                })
+            }) {
+                Ok(()) => (),
+                Err(error) => {
+                    writeln!(self.writer, "Error failed to save log: {}", error)?;
+                    return Ok(());
+                }
+            };
+            writeln!(self.writer, "Saved changes to log \"{}\"", file)?;
+            self.changed = false;
+            return Ok(());
+        }
+
+        let db = &self.db;
+        let ciphertext = match with_progress_line("Saving vault", !self.test_mode, |progress| {
+            db.serialize_encrypted_with_progress(password.as_bytes(), progress)
+        }) {
+            Ok(data) => data,
+            Err(error) => {
+                writeln!(self.writer, "Error failed to serialize database: {}", error)?;
+                return Ok(());
+            }
+        };
+
+        writeln!(self.writer, "Writing to file \"{}\"", file)?;
+        let backups = self.backups;
+        match self.vault_file.save(file, || {
+            Ok(write_atomic(file, ciphertext.as_ref(), backups)?)
+        }) {
+            Ok(()) => (),
+            Err(error) => {
+                writeln!(self.writer, "Error failed to write to file: {}", error)?;
+                return Ok(());
+            }
+        };
+        self.changed = false;
+
+        Ok(())
+    }
+
+    fn generate_password(&mut self, length: &str) -> std::io::Result<()> {
+        let length = match length.parse::<usize>() {
+            Ok(length) => length,
+            Err(_error) => {
+                writeln!(self.writer, "Invalid length input")?;
+                return Ok(());
+            }
+        };
+        let password = match random_password(length) {
+            Ok(password) => password,
+            Err(error) => {
+                writeln!(self.writer, "Failed to generate password: {}", error)?;
+                return Ok(());
+            }
+        };
+        writeln!(self.writer, "Generated: \"{}\"", password)?;
+        Ok(())
+    }
+
+    // token <kind> <length?> [--prefix <prefix>] [--store <key>]
+    fn generate_token(&mut self, kind: &str, args: &[&str]) -> std::io::Result<()> {
+        let mut length = None;
+        let mut prefix = None;
+        let mut store = None;
+
+        let mut itr = args.iter();
+        while let Some(arg) = itr.next() {
+            match *arg {
+                "--prefix" | "-p" => prefix = itr.next().copied(),
+                "--store" | "-s" => store = itr.next().copied(),
+                value => match value.parse::<usize>() {
+                    Ok(value) => length = Some(value),
+                    Err(_error) => {
+                        writeln!(self.writer, "Invalid length input")?;
+                        return Ok(());
+                    }
+                },
+            }
+        }
+
+        let kind = match TokenKind::from_name(kind, prefix) {
+            Some(kind) => kind,
+            None => {
+                writeln!(
+                    self.writer,
+                    "Invalid token kind, expected one of: {}",
+                    TokenKind::names().join(", ")
+                )?;
+                return Ok(());
+            }
+        };
+
+        let token = match random_token(&kind, length.unwrap_or(DEFAULT_TOKEN_BYTES)) {
+            Ok(token) => token,
+            Err(error) => {
+                writeln!(self.writer, "Failed to generate token: {}", error)?;
+                return Ok(());
+            }
+        };
+
+        if let Some(name) = store {
+            match self.insert(name, Some(token.as_str())) {
+                Ok(()) => writeln!(self.writer, "Stored {} token as \"{}\"", kind.name(), name)?,
+                Err(error) => writeln!(self.writer, "Failed to insert: {}", error)?,
+            }
+        } else {
+            writeln!(self.writer, "Generated: \"{}\"", token.as_str())?;
+        }
+
+        Ok(())
+    }
+
+    // Printed in test mode, copied to the clipboard otherwise
+    fn output_secret(&mut self, value: &str) -> std::io::Result<()> {
+        if self.test_mode {
+            writeln!(self.writer, "{}", value)?;
+        } else if let Some(clipboard) = &mut self.clipboard {
+            match clipboard.set_text(value) {
+                Ok(()) => {
+                    writeln!(self.writer, "copied to clipboard")?;
+                }
+                Err(error) => {
+                    writeln!(self.writer, "failed to copy to clipboard: {}", error)?;
+                }
+            };
+        } else {
+            return Err(std::io::Error::other(
+                "Expected clipboard to be Some() when not in test mode",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn help(&mut self) -> std::io::Result<()> {
+        writeln!(
+            self.writer,
+            "Vault:
+    help                  - this menu 
+    insert  <key> <data?> - insert an element 
+    remove  <key>         - remove an element
+    edit    <key> <data?> - remove an element
+    rename  <name> <name> - rename an entry
+    get     <key>         - retrieve an element
+            --field <f>   - retrieve a field instead of the password
+    set     <key> <field> <value?> [--secret]
+                          - set username, password, url, notes or a custom field
+    unset   <key> <field> - clear a field
+    info    <key>         - show the fields that aren't secret
+    history <key> <n?>    - show when the password changed, or get the nth
+                            previous password
+    restore <key> <n?>    - make the nth previous password current again, or
+                            put back a removed entry
+    trash                 - list the removed entries in the recycle bin
+    purge                 - empty the recycle bin for good
+    begin                 - start a transaction
+    commit  <desc?>       - keep the changes since begin as one change
+    rollback              - put back every change since begin
+    undo                  - undo the last change
+    redo                  - redo the last undone change
+    save    <file>        - save to a file
+            --log         - keep saving to the file as an append-only log
+            --vault       - save as a single encrypted vault again
+    list    <query?>      - list the current group, or search everything below it
+            --tag <t>     - only entries with the tag, can be repeated
+    search  <query?>      - search all entries below the current group, words
+                            match names and fields that aren't secret, also
+                            tag:, url:, user:, name:, notes:, group:,
+                            \"phrases\", -term, AND, OR, NOT and (...)
+    tag     <key> <tags>  - add tags to an entry
+    untag   <key> <tags>  - remove tags from an entry
+    tags                  - list every tag and how many entries have it
+    cd      <group?>      - change the current group, keys are relative to it
+    pwd                   - show the current group
+    mkdir   <group>       - create a group
+    rmdir   <group> [-r]  - remove an empty group, or with -r everything in it
+    mv      <name> <dest> - move an entry or group into a group, or rename it
+    import  <file>        - import key/value pairs from csv, a KeePass (KDBX 4)
+                            database or a Bitwarden .json export, all or nothing
+            --dry-run, -n - only show what would be imported
+            --key-file, -k <f>
+                          - the key file of the KeePass database
+    export  <file>        - export key/value pairs to csv
+    diff    <file?>       - show what changed since the vault was saved
+            --reveal      - show changed secret values
+    merge   <file> --base <file>
+                          - merge another copy of the vault changed since base
+    pw      <length>      - generate a password
+    token   <kind> <len?> - generate a token (hex, base64, base64url, base32, uuid, apikey)
+            --prefix <p>  - prefix for apikey tokens
+            --store <key> - insert the token as a new element
+    ssh-add <key> <file>  - insert an OpenSSH private key for the ssh-agent
+    attach  <key> <file> <name?>
+                          - store an encrypted copy of a file with an entry
+    attachments <key>     - list the files attached to an entry
+    extract <key> <name> <file?>
+                          - write an attachment to a file
+    detach  <key> <name>  - remove an attachment
+    audit                 - report weak, reused, old and empty passwords and
+                            logins without 2FA
+            --json        - print the report as JSON
+            --days <n>    - passwords older than this are old, default 365
+    pwned   <file>        - check passwords against a local Pwned Passwords
+                            SHA-1 file or range directory
+    exit                  - exit the program"
+        )?;
+        Ok(())
+    }
+
+    fn request_password(&mut self, prompt: &str) -> std::io::Result<Zeroizing<String>> {
+        if self.test_mode {
+            crate::password::request_password_test(&mut self.reader, prompt)
+        } else {
+            crate::password::request_password(prompt)
+        }
+    }
+
+    // Visible input for values that aren't secret
+    fn request_line(&mut self, prompt: &str) -> std::io::Result<Zeroizing<String>> {
+        print!("{}", prompt);
+        std::io::Write::flush(&mut std::io::stdout())?;
+
+        let mut line = Zeroizing::new(String::new());
+        self.reader.read_line(&mut line)?;
+        let length = line.trim_end_matches(['\r', '\n']).len();
+        line.truncate(length);
+        Ok(line)
+    }
+
+    #[allow(dead_code)]
+    fn password_confirmation(&mut self) -> std::io::Result<Zeroizing<String>> {
+        if self.test_mode {
+            crate::password::password_confirmation_test(&mut self.reader)
+        } else {
+            crate::password::password_confirmation()
+        }
+    }
+}
+
+// Vaults saved as a change log are replayed up to the last complete save,
+fn audit_to_lines(report: &AuditReport, days: u32) -> Vec<String> {
+    if report.is_clean() {
+        return vec![format!(
+            "Audited {} entries, no issues were found",
+            report.entries
+        )];
+    }
+
+    let mut lines = vec![format!(
+        "Audited {} entries, {} need attention",
+        report.entries,
+        report.affected()
+    )];
+    if !report.weak.is_empty() {
+        lines.push(String::from("Weak passwords:"));
+        for entry in report.weak.iter() {
+            lines.push(format!("    {}  score {}", entry.name, entry.score));
+        }
+    }
+    if !report.reused.is_empty() {
+        lines.push(String::from("Reused passwords:"));
+        for names in report.reused.iter() {
+            lines.push(format!("    {}", names.join(", ")));
+        }
+    }
+    if !report.old.is_empty() {
+        lines.push(format!("Passwords not changed in {} days:", days));
+        for entry in report.old.iter() {
+            lines.push(format!(
+                "    {}  modified {}",
+                entry.name,
+                format_timestamp(entry.modified)
+            ));
+        }
+    }
+    if !report.empty.is_empty() {
+        lines.push(String::from("Empty values:"));
+        for entry in report.empty.iter() {
+            lines.push(format!("    {}  {}", entry.name, entry.fields.join(", ")));
+        }
+    }
+    if !report.missing_second_factor.is_empty() {
+        lines.push(String::from("Logins without 2FA:"));
+        for name in report.missing_second_factor.iter() {
+            lines.push(format!("    {}", name));
+        }
+    }
+
+    lines
+}
+
+fn audit_to_json(report: &AuditReport, days: u32) -> json::JsonValue {
+    let weak: Vec<json::JsonValue> = report
+        .weak
+        .iter()
+        .map(|entry| json::object! { name: entry.name.as_str(), score: entry.score })
+        .collect();
+    let old: Vec<json::JsonValue> = report
+        .old
+        .iter()
+        .map(|entry| json::object! { name: entry.name.as_str(), modified: entry.modified })
+        .collect();
+    let empty: Vec<json::JsonValue> = report
+        .empty
+        .iter()
+        .map(|entry| json::object! { name: entry.name.as_str(), fields: entry.fields.clone() })
+        .collect();
+
+    json::object! {
+        entries: report.entries,
+        affected: report.affected(),
+        max_age_days: days,
+        weak: weak,
+        reused: report.reused.clone(),
+        old: old,
+        empty: empty,
+        missing_2fa: report.missing_second_factor.clone(),
+    }
+}
+
+// the flag is set when a damaged log was recovered
+pub(crate) fn open_database(
+    file: &str,
+    contents: Vec<u8>,
+    password: &[u8],
+    show_progress: bool,
+) -> Result<(DatabaseEncrypted, bool), DatabaseError> {
+    if DatabaseEncrypted::is_log_file(&contents) {
+        let (db, report) = with_progress_line("Unlocking vault", show_progress, |progress| {
+            DatabaseEncrypted::open_log(file, password, progress)
+        })?;
+        return Ok((db, report.recovered));
+    }
+
+    let contents = match EncryptionResult::new(contents) {
+        Ok(contents) => contents,
+        Err(error) => return Err(DatabaseError::InputError(error.to_string())),
+    };
+    let db = with_progress_line("Unlocking vault", show_progress, |progress| {
+        DatabaseEncrypted::new_deserialize_encrypted_with_progress(&contents, password, progress)
+    })?;
+    Ok((db, false))
+}
+
+// Asks until the question is answered, no if the input ends
+fn confirm(
+    reader: &mut impl std::io::BufRead,
+    writer: &mut impl std::io::Write,
+    question: &str,
+) -> std::io::Result<bool> {
+    let mut input = String::new();
+    loop {
+        writeln!(writer, "{}", question)?;
+        input.clear();
+        if reader.read_line(&mut input)? == 0 {
+            return Ok(false);
+        }
+        match input.trim().to_ascii_lowercase().as_str() {
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => (),
+        }
+    }
+}
+
+// One line per change, secret values that weren't revealed are only shown
+// as changed
+pub(crate) fn write_diff(
+    writer: &mut impl std::io::Write,
+    diff: &VaultDiff,
+) -> std::io::Result<()> {
+    if diff.is_empty() {
+        return writeln!(writer, "No changes");
+    }
+
+    for name in diff.added.iter() {
+        writeln!(writer, "Added {}", name)?;
+    }
+    for name in diff.removed.iter() {
+        writeln!(writer, "Removed {}", name)?;
+    }
+    for entry in diff.changed.iter() {
+        match &entry.renamed_from {
+            Some(old_name) => writeln!(writer, "Renamed {} to {}", old_name, entry.name)?,
+            None => writeln!(writer, "Changed {}", entry.name)?,
+        }
+        for field in entry.fields.iter() {
+            let change = Zeroizing::new(match &field.change {
+                FieldChange::Added(Some(value)) => format!("added {}", value.as_str()),
+                FieldChange::Added(None) => String::from("added"),
+                FieldChange::Removed(_value) => String::from("removed"),
+                FieldChange::Changed(Some(old), Some(new)) => {
+                    format!("{} -> {}", old.as_str(), new.as_str())
+                }
+                FieldChange::Changed(_old, _new) => String::from("changed"),
+            });
+            writeln!(writer, "    {}: {}", field.name, change.as_str())?;
+        }
+    }
+    for group in diff.groups_added.iter() {
+        writeln!(writer, "Added group {}", group)?;
+    }
+    for group in diff.groups_removed.iter() {
+        writeln!(writer, "Removed group {}", group)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Vault;
+    use std::io::{BufRead, BufReader, Cursor, Write};
+
+    fn new_vault(text: &str) -> Vault<BufReader<Cursor<&[u8]>>, Cursor<Vec<u8>>> {
+        let input = BufReader::new(Cursor::new(text.as_bytes()));
+        let output = Cursor::new(Vec::<u8>::new());
+
+        let vault =
+            Vault::<BufReader<Cursor<&[u8]>>, Cursor<Vec<u8>>>::new_internal(input, output, true)
+                .unwrap();
+
+        vault
+    }
+
+    fn new_vault_from_file<'a, 'b>(
+        file: &'a str,
+        text: &'b str,
+    ) -> Vault<BufReader<Cursor<&'b [u8]>>, Cursor<Vec<u8>>> {
+        let input = BufReader::new(Cursor::new(text.as_bytes()));
+        let output = Cursor::new(Vec::<u8>::new());
+
+        let vault = Vault::<BufReader<Cursor<&[u8]>>, Cursor<Vec<u8>>>::new_from_file_internal(
+            file, input, output, true,
+        )
+        .unwrap();
+
+        vault
+    }
+
+    fn reset_cursors(
+        vault: &mut Vault<BufReader<Cursor<&[u8]>>, Cursor<Vec<u8>>>,
+        input: &'static str,
+    ) {
+        let input = BufReader::new(Cursor::new(input.as_bytes()));
+        let output = Cursor::new(Vec::<u8>::new());
+        vault.reader = input;
+        vault.writer = output;
+    }
+
+    fn run_command(
+        vault: &mut Vault<BufReader<Cursor<&[u8]>>, Cursor<Vec<u8>>>,
+    ) -> std::io::Result<bool> {
+        let mut input = String::new();
+        match vault.reader.read_line(&mut input) {
+            Ok(count) => count,
+            Err(error) => {
+                writeln!(vault.writer, "User input error: {}", error.to_string())?;
+                0
+            }
+        };
+
+        if vault.handle_input(&mut input)? {
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    fn output_to_string(vault: &mut Vault<BufReader<Cursor<&[u8]>>, Cursor<Vec<u8>>>) -> String {
+        let bytes = vault.writer.get_ref();
+        let string = String::from_utf8(bytes.to_vec()).unwrap();
+        return string;
+    }
+
+    #[test]
+    fn test_insert_get() {
+        let mut vault = new_vault("12\n12\n");
+        reset_cursors(&mut vault, "insert test 123\n12\nget test\n12\n");
+
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
+
+        let string = output_to_string(&mut vault);
+
+        assert_eq!(string, "123\n");
+    }
+
+    #[test]
+    fn test_insert_get_extended_name() {
+        let mut vault = new_vault("12\n12\n");
+        reset_cursors(
+            &mut vault,
+            "insert \"test 123\" 123\n12\nget \"test 123\"\n12\n",
+        );
+
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
+
+        let string = output_to_string(&mut vault);
+
+        assert_eq!(string, "123\n");
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut vault = new_vault("12\n12\n");
+        reset_cursors(
+            &mut vault,
+            "insert test 123\n12\nedit test 1234\n12\nget test\n12\n",
+        );
+
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
+
+        let string = output_to_string(&mut vault);
+
+        assert_eq!(string, "1234\n");
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut vault = new_vault("12\n12\n");
+        reset_cursors(
+            &mut vault,
+            "insert test 123\n12\nrename test test2\n12\nget test2\n12\n",
+        );
+
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
+
+        let string = output_to_string(&mut vault);
+
+        assert_eq!(string, "123\n");
+    }
+
+    #[test]
+    fn test_list_search() {
+        let mut vault = new_vault("12\n12\n");
+        reset_cursors(
+            &mut vault,
+            "insert user1 123\n12\ninsert user2 123\n12\ninsert user3 123\n12\n",
+        );
+
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
+
+        let string = output_to_string(&mut vault);
+        assert_eq!(string, "");
+
+        reset_cursors(&mut vault, "list\n");
+        run_command(&mut vault).unwrap();
+
+        let string = output_to_string(&mut vault);
+        assert_eq!(string, "user1, user2, user3\n");
+
+        reset_cursors(&mut vault, "search\n");
+        run_command(&mut vault).unwrap();
+
+        let string = output_to_string(&mut vault);
+        assert_eq!(string, "user1, user2, user3\n");
+
+        reset_cursors(&mut vault, "list 1\n");
+        run_command(&mut vault).unwrap();
+
+        let string = output_to_string(&mut vault);
+        assert_eq!(string, "user1\n");
+
+        reset_cursors(&mut vault, "search 1\n");
+        run_command(&mut vault).unwrap();
+
+        let string = output_to_string(&mut vault);
+        assert_eq!(string, "user1\n");
+    }
+
+    #[test]
+    fn test_token() {
+        let mut vault = new_vault("12\n12\n");
+        reset_cursors(&mut vault, "token hex 4\n");
+        run_command(&mut vault).unwrap();
+
+        let string = output_to_string(&mut vault);
+        assert_eq!(string.len(), "Generated: \"\"\n".len() + 8);
+
+        reset_cursors(
+            &mut vault,
+            "token apikey 12 --prefix sk --store key\n12\nget key\n12\n",
+        );
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
+
+        let string = output_to_string(&mut vault);
+        let mut lines = string.lines();
+        assert_eq!(lines.next().unwrap(), "Stored apikey token as \"key\"");
+        let key = lines.next().unwrap();
+        assert!(key.starts_with("sk_"));
+        assert_eq!(key.len(), 15);
+    }
+
+    #[test]
+    fn test_audit() {
+        let mut vault = new_vault("12\n12\n");
+        reset_cursors(&mut vault, "insert a password\n12\ninsert b password\n12\n");
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
+        reset_cursors(&mut vault, "audit\n12\n");
+        run_command(&mut vault).unwrap();
+
+        let string = output_to_string(&mut vault);
+        assert_eq!(
+            string,
+            "Audited 2 entries, 2 need attention\nWeak passwords:\n    a  score 56\n    b  score 56\n\
+             Reused passwords:\n    a, b\n"
+        );
+
+        reset_cursors(&mut vault, "audit --json --days 0\n12\n");
+        run_command(&mut vault).unwrap();
+        let report = json::parse(&output_to_string(&mut vault)).unwrap();
+        assert_eq!(report["entries"], 2);
+        assert_eq!(report["max_age_days"], 0);
+        assert_eq!(report["weak"][1]["name"], "b");
+        assert_eq!(report["reused"][0][1], "b");
+        assert_eq!(report["old"].len(), 2);
+        assert!(report["missing_2fa"].is_empty());
+
+        reset_cursors(&mut vault, "audit --days\n");
+        run_command(&mut vault).unwrap();
+        assert_eq!(
+            output_to_string(&mut vault),
+            "Failed to audit: Input error: Expected a number of days\n"
+        );
+    }
+
+    #[test]
+    fn test_pwned() {
+        let file = std::env::temp_dir().join(format!("pwm-cli-pwned-{}", std::process::id()));
+        // SHA-1 of "password"
+        std::fs::write(&file, "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3\n").unwrap();
+        let input = format!(
+            "12\n12\ninsert a password\n12\ninsert b other\n12\npwned {}\n12\n",
+            file.display()
+        );
+
+        let mut vault = new_vault(&input);
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        let string = output_to_string(&mut vault);
+        assert_eq!(string, "\"a\" was seen 3 times in breaches\n");
+    }
+
+    #[test]
+    fn test_ssh_add() {
+        let mut vault = new_vault("12\n12\n");
+        reset_cursors(
+            &mut vault,
+            "ssh-add key tests/id_ed25519\n12\nssh-add password tests/users.csv\n",
+        );
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
+
+        let string = output_to_string(&mut vault);
+        assert_eq!(
+            string,
+            "Added ssh-ed25519 key SHA256:Xh0mOyroREZf9JDdKKdik4zYrMT4gOdbAIb+qDOik48\n\
+             Failed to add SSH key: Input error: Not an OpenSSH private key\n"
+        );
+    }
+
+    #[test]
+    fn test_attachments() {
+        let _ = std::fs::remove_file("tests/extracted.csv");
+        let mut vault = new_vault("12\n12\n");
+        vault.set_max_attachment_size(200);
+        reset_cursors(
+            &mut vault,
+            "insert mail 1\n12\nattach mail tests/users.csv\n12\nattach mail tests/entries.csv list.csv\n12\nattach mail tests/id_ed25519\n12\nextract mail users.csv tests/extracted.csv\n12\nextract mail users.csv tests/extracted.csv\ndetach mail list.csv\n12\ndetach mail list.csv\n12\n",
+        );
+        for _ in 0..8 {
+            run_command(&mut vault).unwrap();
+        }
+
+        let string = output_to_string(&mut vault);
+        let lines: Vec<&str> = string.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("Failed to attach: Input error: id_ed25519 is "));
+        assert_eq!(lines[1], "Extracted users.csv to tests/extracted.csv");
+        assert_eq!(
+            lines[2],
+            "Failed to extract: Output error: tests/extracted.csv already exists"
+        );
+        assert_eq!(lines[3], "Failed to detach: Not found");
+        assert_eq!(
+            std::fs::read("tests/extracted.csv").unwrap(),
+            std::fs::read("tests/users.csv").unwrap()
+        );
+        std::fs::remove_file("tests/extracted.csv").unwrap();
+
+        reset_cursors(&mut vault, "attachments mail\nattachments missing\n");
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
 
-        vault
+        let string = output_to_string(&mut vault);
+        let lines: Vec<&str> = string.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("users.csv  "));
+        assert!(lines[0].contains(" bytes  added 2"));
+        assert_eq!(lines[1], "Failed to list attachments: Not found");
     }
 
-    fn new_vault_from_file<'a, 'b>(
-        file: &'a str,
-        text: &'b str,
-    ) -> Vault<BufReader<Cursor<&'b [u8]>>, Cursor<Vec<u8>>> {
-        let input = BufReader::new(Cursor::new(text.as_bytes()));
-        let output = Cursor::new(Vec::<u8>::new());
+    #[test]
+    fn test_import() {
+        let mut vault = new_vault("12\n12\n");
+        reset_cursors(&mut vault, "import tests/users.csv\n12\nget user1\n12\n");
 
-        let vault = Vault::<BufReader<Cursor<&[u8]>>, Cursor<Vec<u8>>>::new_from_file_internal(
-            file, input, output, true,
-        )
-        .unwrap();
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
 
-        vault
-    }
+        let string = output_to_string(&mut vault);
 
-    fn reset_cursors(
-        vault: &mut Vault<BufReader<Cursor<&[u8]>>, Cursor<Vec<u8>>>,
-        input: &'static str,
-    ) {
-        let input = BufReader::new(Cursor::new(input.as_bytes()));
-        let output = Cursor::new(Vec::<u8>::new());
-        vault.reader = input;
-        vault.writer = output;
+        assert_eq!(string, "password1\n");
     }
 
-    fn run_command(
-        vault: &mut Vault<BufReader<Cursor<&[u8]>>, Cursor<Vec<u8>>>,
-    ) -> std::io::Result<bool> {
-        let mut input = String::new();
-        match vault.reader.read_line(&mut input) {
-            Ok(count) => count,
-            Err(error) => {
-                writeln!(vault.writer, "User input error: {}", error.to_string())?;
-                0
-            }
-        };
-
-        if vault.handle_input(&mut input)? {
-            return Ok(true);
+    #[test]
+    fn test_import_dry_run() {
+        let mut vault = new_vault("12\n12\n");
+        reset_cursors(
+            &mut vault,
+            "insert bank 1\n12\nimport tests/entries.csv --dry-run\n12\nimport tests/entries.csv\n12\nls\n",
+        );
+        for _ in 0..4 {
+            run_command(&mut vault).unwrap();
         }
 
-        Ok(false)
+        let string = output_to_string(&mut vault);
+        assert_eq!(
+            string,
+            "Would add mail\n\
+             Can't add bank: Already exists\n\
+             Nothing would be imported\n\
+             Failed to import: bank\n\
+             bank\n"
+        );
     }
 
-    fn output_to_string(vault: &mut Vault<BufReader<Cursor<&[u8]>>, Cursor<Vec<u8>>>) -> String {
-        let bytes = vault.writer.get_ref();
-        let string = String::from_utf8(bytes.to_vec()).unwrap();
-        return string;
+    #[test]
+    fn test_import_kdbx() {
+        let mut vault = new_vault("12\n12\n");
+        reset_cursors(
+            &mut vault,
+            "import ../pwm-db/tests/kdbx/aes-aeskdf.kdbx\nwrong\n12\n\
+             import ../pwm-db/tests/kdbx/chacha20-argon2id-keyfile.kdbx -n -k ../pwm-db/tests/kdbx/keyfile.keyx\ntest\n12\n\
+             import tests/users.csv -k ../pwm-db/tests/kdbx/keyfile.keyx\n\
+             import ../pwm-db/tests/kdbx/aes-aeskdf.kdbx -k\n\
+             import ../pwm-db/tests/kdbx/aes-aeskdf.kdbx\ntest\n12\n\
+             ls\nget Work/Server\n12\n",
+        );
+        for _ in 0..7 {
+            run_command(&mut vault).unwrap();
+        }
+
+        let string = output_to_string(&mut vault);
+        assert_eq!(
+            string,
+            "Failed to import: Wrong KeePass password or key file\n\
+             Would add Mail\n\
+             Would add Mail (2)\n\
+             Would add Work/Server\n\
+             Would add Work/Untitled\n\
+             Skipped records: 1\n\
+             Input error: Key files are only used by KeePass databases\n\
+             Expected a key file\n\
+             Work/, Mail, Mail (2)\n\
+             Srv&<pass>\n"
+        );
     }
 
     #[test]
-    fn test_insert_get() {
+    fn test_import_bitwarden() {
         let mut vault = new_vault("12\n12\n");
-        reset_cursors(&mut vault, "insert test 123\n12\nget test\n12\n");
-
-        run_command(&mut vault).unwrap();
-        run_command(&mut vault).unwrap();
+        reset_cursors(
+            &mut vault,
+            "import ../pwm-db/tests/bitwarden/pbkdf2.json\nwrong\n12\n\
+             import ../pwm-db/tests/bitwarden/plain.json -n\n12\n\
+             import ../pwm-db/tests/bitwarden/argon2id.json -k ../pwm-db/tests/kdbx/keyfile.keyx\n\
+             import ../pwm-db/tests/bitwarden/argon2id.json\ntest\n12\n\
+             ls\nget Personal/Mail --field otp\n12\n",
+        );
+        for _ in 0..6 {
+            run_command(&mut vault).unwrap();
+        }
 
         let string = output_to_string(&mut vault);
+        assert_eq!(
+            string,
+            "Failed to import: Wrong export password\n\
+             Would add Personal/Mail\n\
+             Would add Personal/Mail (2)\n\
+             Would add Home/Network/Wifi\n\
+             Would add Visa\n\
+             Would add Me\n\
+             Would add Server key\n\
+             Would add Untitled\n\
+             Not imported: Personal/Mail: passkey\n\
+             Not imported: Personal/Mail: linked field \"Linked\"\n\
+             Not imported: Visa: master password re-prompt\n\
+             Not imported: From the future: unknown item type 9\n\
+             Skipped records: 2\n\
+             Input error: Key files are only used by KeePass databases\n\
+             Not imported: Personal/Mail: passkey\n\
+             Not imported: Personal/Mail: linked field \"Linked\"\n\
+             Not imported: Visa: master password re-prompt\n\
+             Not imported: From the future: unknown item type 9\n\
+             Empty/, Home/, Personal/, Me, Server key, Untitled, Visa\n\
+             otpauth://totp/Mail?secret=JBSWY3DPEHPK3PXP\n"
+        );
+    }
 
-        assert_eq!(string, "123\n");
+    #[test]
+    fn test_transaction() {
+        let mut vault = new_vault("12\n12\n");
+        reset_cursors(
+            &mut vault,
+            "begin\ninsert mail 1\n12\ninsert chat 2\n12\nrollback\nls\nbegin\ninsert mail 1\n12\nmv mail work/mail\n12\ncommit add mail\nundo\n12\nls\ncommit\n",
+        );
+        for _ in 0..13 {
+            run_command(&mut vault).unwrap();
+        }
+
+        let string = output_to_string(&mut vault);
+        assert_eq!(
+            string,
+            "Changes are kept until commit or rollback\n\
+             \n\
+             Changes are kept until commit or rollback\n\
+             Undid add mail\n\
+             \n\
+             Failed to commit: No transaction in progress\n"
+        );
     }
 
     #[test]
-    fn test_insert_get_extended_name() {
+    fn test_fields() {
         let mut vault = new_vault("12\n12\n");
         reset_cursors(
             &mut vault,
-            "insert \"test 123\" 123\n12\nget \"test 123\"\n12\n",
+            "import tests/entries.csv\n12\nget mail --field username\nget mail --field pin\n12\nget mail -f url\n",
+        );
+        for _ in 0..4 {
+            run_command(&mut vault).unwrap();
+        }
+
+        let string = output_to_string(&mut vault);
+        assert_eq!(
+            string,
+            "someone\n1234\nhttps://a.example\nhttps://b.example\n"
+        );
+
+        reset_cursors(
+            &mut vault,
+            "set bank username new\n12\nset bank token abc --secret\n12\nunset mail question\n12\nset mail title x\ninfo bank\ninfo mail\n",
+        );
+        for _ in 0..6 {
+            run_command(&mut vault).unwrap();
+        }
+
+        let string = output_to_string(&mut vault);
+        assert_eq!(
+            string,
+            "Failed to set: Input error: Use rename to change the title\n\
+             username: new\n\
+             token: (secret)\n\
+             username: someone\n\
+             url: https://a.example\n\
+             url: https://b.example\n\
+             notes: some notes\n\
+             pin: (secret)\n"
         );
 
+        reset_cursors(
+            &mut vault,
+            "get bank --field token\n12\nget bank --field pin\n12\n",
+        );
         run_command(&mut vault).unwrap();
         run_command(&mut vault).unwrap();
 
         let string = output_to_string(&mut vault);
-
-        assert_eq!(string, "123\n");
+        assert_eq!(string, "abc\nFailed to get: Not found\n");
     }
 
     #[test]
-    fn test_edit() {
+    fn test_history() {
         let mut vault = new_vault("12\n12\n");
         reset_cursors(
             &mut vault,
-            "insert test 123\n12\nedit test 1234\n12\nget test\n12\n",
+            "insert test 1\n12\nedit test 2\n12\nedit test 3\n12\nhistory test 2\n12\nrestore test 2\n12\nget test\n12\nhistory test 0\n",
         );
+        for _ in 0..7 {
+            run_command(&mut vault).unwrap();
+        }
 
-        run_command(&mut vault).unwrap();
-        run_command(&mut vault).unwrap();
+        let string = output_to_string(&mut vault);
+        assert_eq!(
+            string,
+            "1\n1\nFailed to get history: Input error: \"0\" is not a history number\n"
+        );
+
+        reset_cursors(&mut vault, "history test\n");
         run_command(&mut vault).unwrap();
 
         let string = output_to_string(&mut vault);
-
-        assert_eq!(string, "1234\n");
+        let lines: Vec<&str> = string.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("created:  2"));
+        assert!(lines[2].starts_with("accessed: 2"));
+        assert!(lines[3].starts_with("1: replaced 2"));
+        assert!(lines[4].starts_with("2: replaced 2"));
     }
 
     #[test]
-    fn test_rename() {
+    fn test_groups() {
         let mut vault = new_vault("12\n12\n");
         reset_cursors(
             &mut vault,
-            "insert test 123\n12\nrename test test2\n12\nget test2\n12\n",
+            "mkdir work\n12\ncd work\ninsert mail 1\n12\nmkdir chat\n12\ninsert chat/team 2\n12\ninsert /home 3\n12\nls\npwd\ncd chat\nget ../mail\n12\nls\ncd /\nls\nsearch team\ncd missing\n",
         );
-
-        run_command(&mut vault).unwrap();
-        run_command(&mut vault).unwrap();
-        run_command(&mut vault).unwrap();
+        for _ in 0..15 {
+            run_command(&mut vault).unwrap();
+        }
 
         let string = output_to_string(&mut vault);
+        assert_eq!(
+            string,
+            "chat/, mail\n\
+             /work\n\
+             1\n\
+             team\n\
+             work/, home\n\
+             work/chat/team\n\
+             Failed to change group: Not found\n"
+        );
 
-        assert_eq!(string, "123\n");
+        reset_cursors(
+            &mut vault,
+            "mv work/mail work/chat\n12\nrmdir work\n12\nmv work/chat /\n12\nls\nrmdir work\n12\nrmdir chat\n12\nrmdir chat -r\n12\nls\n",
+        );
+        for _ in 0..8 {
+            run_command(&mut vault).unwrap();
+        }
+
+        let string = output_to_string(&mut vault);
+        assert_eq!(
+            string,
+            "Failed to remove group: Not empty\n\
+             chat/, work/, home\n\
+             Failed to remove group: Not empty\n\
+             Removed 2 entries\n\
+             home\n"
+        );
     }
 
     #[test]
-    fn test_list_search() {
+    fn test_tags() {
         let mut vault = new_vault("12\n12\n");
         reset_cursors(
             &mut vault,
-            "insert user1 123\n12\ninsert user2 123\n12\ninsert user3 123\n12\n",
+            "insert web 1\n12\ninsert work/db 2\n12\ntag web shared\n12\ntag work/db prod Shared\n12\ntag web two,words\n12\nls --tag shared\nls --tag shared --tag prod\nls -t missing\ncd work\nls d --tag prod\ninfo db\ntags\nuntag db prod\n12\nuntag db prod\n12\nls --tag prod\n",
+        );
+        for _ in 0..15 {
+            run_command(&mut vault).unwrap();
+        }
+
+        let string = output_to_string(&mut vault);
+        assert_eq!(
+            string,
+            "Failed to tag: Input error: Invalid tag \"two,words\"\n\
+             web, work/db\n\
+             work/db\n\
+             \n\
+             db\n\
+             tags: prod, shared\n\
+             prod (1), shared (2)\n\
+             Failed to untag: Not found\n\
+             \n"
         );
+    }
 
-        run_command(&mut vault).unwrap();
-        run_command(&mut vault).unwrap();
-        run_command(&mut vault).unwrap();
+    #[test]
+    fn test_search() {
+        let mut vault = new_vault("12\n12\n");
+        reset_cursors(
+            &mut vault,
+            "import tests/entries.csv\n12\ntag mail prod\n12\ntag bank prod old\n12\nsearch tag:prod -tag:old\nsearch user:someone OR bnk\nsearch \"a.example\"\nsearch NOT tag:prod\nls tag:prod --tag old\n",
+        );
+        for _ in 0..8 {
+            run_command(&mut vault).unwrap();
+        }
 
         let string = output_to_string(&mut vault);
-        assert_eq!(string, "");
+        assert_eq!(string, "mail\nbank, mail\nmail\n\nbank\n");
+    }
 
-        reset_cursors(&mut vault, "list\n");
-        run_command(&mut vault).unwrap();
+    #[test]
+    fn test_undo() {
+        let mut vault = new_vault("12\n12\n");
+        reset_cursors(
+            &mut vault,
+            "insert mail 1\n12\nremove mail\n12\nls\nundo\n12\nget mail\n12\nredo\n12\nredo\n12\nls\nundo\n12\nundo\n12\nundo\n12\n",
+        );
+        for _ in 0..11 {
+            run_command(&mut vault).unwrap();
+        }
 
         let string = output_to_string(&mut vault);
-        assert_eq!(string, "user1, user2, user3\n");
+        assert_eq!(
+            string,
+            "\n\
+             Undid remove mail\n\
+             1\n\
+             Redid remove mail\n\
+             Failed to redo: Nothing to redo\n\
+             \n\
+             Undid remove mail\n\
+             Undid insert mail\n\
+             Failed to undo: Nothing to undo\n"
+        );
+    }
 
-        reset_cursors(&mut vault, "search\n");
-        run_command(&mut vault).unwrap();
+    #[test]
+    fn test_trash() {
+        let mut vault = new_vault("12\n12\n");
+        reset_cursors(
+            &mut vault,
+            "trash\ninsert mail 1\n12\nedit mail 2\n12\nremove mail\n12\nrestore mail\n12\nget mail\n12\nrestore mail\n12\nget mail\n12\n",
+        );
+        for _ in 0..8 {
+            run_command(&mut vault).unwrap();
+        }
 
+        // Restoring an entry that exists goes back through its history
         let string = output_to_string(&mut vault);
-        assert_eq!(string, "user1, user2, user3\n");
+        assert_eq!(
+            string,
+            "The recycle bin is empty\n\
+             2\n\
+             1\n"
+        );
 
-        reset_cursors(&mut vault, "list 1\n");
-        run_command(&mut vault).unwrap();
+        reset_cursors(
+            &mut vault,
+            "remove mail\n12\ninsert mail 3\n12\nremove mail\n12\ntrash\npurge\n12\ntrash\n",
+        );
+        for _ in 0..6 {
+            run_command(&mut vault).unwrap();
+        }
 
         let string = output_to_string(&mut vault);
-        assert_eq!(string, "user1\n");
+        let lines: Vec<&str> = string.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("mail  removed 2"));
+        assert!(lines[1].starts_with("mail  removed 2"));
+        assert_eq!(lines[2], "Purged 2 entries");
+        assert_eq!(lines[3], "The recycle bin is empty");
+    }
 
-        reset_cursors(&mut vault, "search 1\n");
-        run_command(&mut vault).unwrap();
+    #[test]
+    fn test_diff() {
+        let mut vault = new_vault("12\n12\n");
+        vault.set_backups(0);
+        reset_cursors(
+            &mut vault,
+            "diff\ninsert mail 1\n12\nsave tests/DiffVault\n12\ndiff\n12\n",
+        );
+        for _ in 0..4 {
+            run_command(&mut vault).unwrap();
+        }
+        let string = output_to_string(&mut vault);
+        assert!(string.starts_with(
+            "Failed to diff: Input error: The vault wasn't saved yet, expected a file\n"
+        ));
+        assert!(string.ends_with("No changes\n"));
 
+        reset_cursors(
+            &mut vault,
+            "insert bank 2\n12\nset mail pin 1234 --secret\n12\ndiff\n12\ndiff tests/DiffVault --reveal\n12\n",
+        );
+        for _ in 0..4 {
+            run_command(&mut vault).unwrap();
+        }
         let string = output_to_string(&mut vault);
-        assert_eq!(string, "user1\n");
+        std::fs::remove_file("tests/DiffVault").unwrap();
+
+        assert_eq!(
+            string,
+            "Added bank\n\
+             Changed mail\n    pin: added\n\
+             Added bank\n\
+             Changed mail\n    pin: added 1234\n"
+        );
     }
 
     #[test]
-    fn test_import() {
+    fn test_merge() {
         let mut vault = new_vault("12\n12\n");
-        reset_cursors(&mut vault, "import tests/users.csv\n12\nget user1\n12\n");
+        vault.set_backups(0);
+        reset_cursors(
+            &mut vault,
+            "insert mail 1\n12\ninsert bank 2\n12\nsave tests/MergeBase\n12\n",
+        );
+        for _ in 0..3 {
+            run_command(&mut vault).unwrap();
+        }
+        std::fs::copy("tests/MergeBase", "tests/MergeTheirs").unwrap();
 
-        run_command(&mut vault).unwrap();
-        run_command(&mut vault).unwrap();
+        let mut theirs = new_vault_from_file("tests/MergeTheirs", "12\n");
+        theirs.set_backups(0);
+        reset_cursors(
+            &mut theirs,
+            "set mail username theirs\n12\ninsert new 3\n12\nsave tests/MergeTheirs\n12\n",
+        );
+        for _ in 0..3 {
+            run_command(&mut theirs).unwrap();
+        }
 
+        reset_cursors(
+            &mut vault,
+            "set mail username ours\n12\nremove bank\n12\nmerge tests/MergeTheirs --base tests/MergeBase\n12\nc\nb\n",
+        );
+        for _ in 0..3 {
+            run_command(&mut vault).unwrap();
+        }
         let string = output_to_string(&mut vault);
-
-        assert_eq!(string, "password1\n");
+        std::fs::remove_file("tests/MergeBase").unwrap();
+        std::fs::remove_file("tests/MergeTheirs").unwrap();
+
+        assert!(string.contains("Merged new\nConflict in mail\n"));
+        assert!(string.ends_with(
+            "Keep this vault (A) or tests/MergeTheirs (B)?\n\
+             Keep this vault (A) or tests/MergeTheirs (B)?\n"
+        ));
+        assert_eq!(vault.db.list().unwrap(), vec!["mail", "new"]);
+        assert_eq!(vault.db.get_info("mail").unwrap().username, "theirs");
     }
 
     #[test]
@@ -790,7 +3061,8 @@ mod tests {
         run_command(&mut vault).unwrap();
         run_command(&mut vault).unwrap();
 
-        let imported = std::fs::read("tests/users.csv").unwrap();
+        // Exports always use the structured layout
+        let imported = std::fs::read("tests/users_export.csv").unwrap();
         let exported = std::fs::read("tests/users_test.csv").unwrap();
         std::fs::remove_file("tests/users_test.csv").unwrap();
 
@@ -820,7 +3092,7 @@ mod tests {
         let string = output_to_string(&mut vault);
         assert_eq!(string, "password0\n");
 
-        let imported = std::fs::read("tests/users.csv").unwrap();
+        let imported = std::fs::read("tests/users_export.csv").unwrap();
         let exported = std::fs::read("tests/save_load_test_users.csv").unwrap();
         std::fs::remove_file("tests/save_load_test_users.csv").unwrap();
         std::fs::remove_file("tests/Vault").unwrap();
@@ -833,4 +3105,120 @@ mod tests {
         let string = output_to_string(&mut vault);
         assert_eq!(string, "");
     }
+
+    #[test]
+    fn test_vault_save_backups() {
+        let mut vault = new_vault("12\n12\n");
+        vault.set_backups(1);
+        reset_cursors(
+            &mut vault,
+            "save tests/BackupVault\n12\ninsert a b\n12\nsave tests/BackupVault\n12\nsave tests/BackupVault\n12\n",
+        );
+
+        for _ in 0..4 {
+            run_command(&mut vault).unwrap();
+        }
+
+        // The backup is the previous save, which already held "a"
+        let mut vault = new_vault_from_file("tests/BackupVault.bak.1", "12\n");
+        reset_cursors(&mut vault, "get a\n12\n");
+        run_command(&mut vault).unwrap();
+        let string = output_to_string(&mut vault);
+
+        let older = std::path::Path::new("tests/BackupVault.bak.2").exists();
+        std::fs::remove_file("tests/BackupVault").unwrap();
+        std::fs::remove_file("tests/BackupVault.bak.1").unwrap();
+
+        assert_eq!(string, "b\n");
+        assert!(!older);
+    }
+
+    #[test]
+    fn test_vault_save_log() {
+        let mut vault = new_vault("12\n12\n");
+        vault.set_backups(0);
+        reset_cursors(
+            &mut vault,
+            "insert a b\n12\nsave tests/LogVault --log\n12\ninsert c d\n12\nsave tests/LogVault\n12\n",
+        );
+        for _ in 0..4 {
+            run_command(&mut vault).unwrap();
+        }
+        let string = output_to_string(&mut vault);
+        assert_eq!(
+            string
+                .matches("Saved changes to log \"tests/LogVault\"")
+                .count(),
+            2
+        );
+
+        let mut vault = new_vault_from_file("tests/LogVault", "12\n");
+        vault.set_backups(0);
+        reset_cursors(&mut vault, "get c\n12\nsave tests/LogVault --vault\n12\n");
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
+        let string = output_to_string(&mut vault);
+        let contents = std::fs::read("tests/LogVault").unwrap();
+        std::fs::remove_file("tests/LogVault").unwrap();
+
+        assert!(string.starts_with("d\n"));
+        assert!(!pwm_db::db_encrypted::DatabaseEncrypted::is_log_file(
+            &contents
+        ));
+    }
+
+    #[test]
+    fn test_vault_lock() {
+        let mut vault = new_vault("12\n12\n");
+        vault.set_backups(0);
+        reset_cursors(&mut vault, "insert a b\n12\nsave tests/LockVault\n12\n");
+        run_command(&mut vault).unwrap();
+        run_command(&mut vault).unwrap();
+
+        // Another copy writes the file, this one has to confirm overwriting it
+        let mut other = new_vault_from_file("tests/LockVault", "12\n");
+        other.set_backups(0);
+        reset_cursors(&mut other, "insert c d\n12\nsave tests/LockVault\n12\n");
+        run_command(&mut other).unwrap();
+        run_command(&mut other).unwrap();
+        reset_cursors(&mut vault, "save tests/LockVault\nmaybe\nn\n");
+        run_command(&mut vault).unwrap();
+        let string = output_to_string(&mut vault);
+        assert!(string.ends_with(
+            "tests/LockVault was changed on disk since it was loaded, overwrite it? (Y, N)\n\
+             Not saved\n"
+        ));
+
+        // A lock held by a process on another host
+        std::fs::write("tests/LockVault.lock", "1\nother-host\n0011\n").unwrap();
+        let input = BufReader::new(Cursor::new("n\n".as_bytes()));
+        let output = Cursor::new(Vec::<u8>::new());
+        let error = Vault::<BufReader<Cursor<&[u8]>>, Cursor<Vec<u8>>>::new_from_file_internal(
+            "tests/LockVault",
+            input,
+            output,
+            true,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "The vault is in use by process 1 on other-host"
+        );
+
+        let mut read_only = new_vault_from_file("tests/LockVault", "y\n12\n");
+        reset_cursors(&mut read_only, "get c\n12\nsave tests/LockVault\n12\n");
+        run_command(&mut read_only).unwrap();
+        run_command(&mut read_only).unwrap();
+        let string = output_to_string(&mut read_only);
+        std::fs::remove_file("tests/LockVault.lock").unwrap();
+        std::fs::remove_file("tests/LockVault").unwrap();
+
+        assert_eq!(
+            string,
+            "d\n\
+             Writing to file \"tests/LockVault\"\n\
+             Error failed to write to file: The vault is in use by process 1 on other-host\n"
+        );
+    }
 }
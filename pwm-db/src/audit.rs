@@ -0,0 +1,4 @@
+// Checks over the decrypted contents of a vault, nothing here leaves the
+// machine
+pub mod pwned;
+pub mod report;
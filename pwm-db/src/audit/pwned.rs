@@ -0,0 +1,237 @@
+use std::{
+    cmp::Ordering,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use memmap2::Mmap;
+use pwm_lib::{
+    hash::sha_wrapper::sha1_hash, progress::Progress, random::token::encode_hex, zeroize::Zeroizing,
+};
+
+use crate::{
+    db_base::error::DatabaseError,
+    db_encrypted::{db_interface::DatabaseInterface, DatabaseEncrypted},
+};
+
+// Length of the hash prefix used to name the files of a range download
+static RANGE_PREFIX_LENGTH: usize = 5;
+
+// A local copy of the Pwned Passwords SHA-1 list, either
+//  - one file of "HASH:COUNT" lines ordered by hash, or
+//  - a directory of range files as written by the official downloader, one
+//    "PREFIX.txt" per 5 character prefix holding "SUFFIX:COUNT" lines
+pub enum PwnedPasswords {
+    Ordered(Option<Mmap>),
+    Range(PathBuf),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PwnedEntry {
+    pub name: String,
+    pub count: u64,
+}
+
+impl PwnedPasswords {
+    pub fn open(path: &str) -> Result<Self, DatabaseError> {
+        let path = Path::new(path);
+        if path.is_dir() {
+            return Ok(Self::Range(path.to_path_buf()));
+        }
+
+        Ok(Self::Ordered(map_file(path)?))
+    }
+
+    // Number of times the password was seen in a breach, 0 if never
+    pub fn count(&self, password: &[u8]) -> Result<u64, DatabaseError> {
+        let mut hash = Zeroizing::new([0u8; 20]);
+        sha1_hash(password, hash.as_mut_slice())?;
+        let hash = encode_hex(hash.as_slice());
+        let hash = Zeroizing::new(hash.to_ascii_uppercase());
+
+        self.count_hash(hash.as_str())
+    }
+
+    // Takes the uppercase hex SHA-1 of the password
+    pub fn count_hash(&self, hash: &str) -> Result<u64, DatabaseError> {
+        match self {
+            Self::Ordered(map) => match map {
+                Some(map) => Ok(search(map, hash.as_bytes())),
+                None => Ok(0),
+            },
+            Self::Range(directory) => {
+                let (prefix, suffix) = hash.split_at(RANGE_PREFIX_LENGTH);
+                let file = directory.join(format!("{}.txt", prefix));
+                if !file.exists() {
+                    return Err(DatabaseError::InputError(format!(
+                        "Missing range file {}",
+                        file.display()
+                    )));
+                }
+
+                match map_file(&file)? {
+                    Some(map) => Ok(search(&map, suffix.as_bytes())),
+                    None => Ok(0),
+                }
+            }
+        }
+    }
+}
+
+// Decrypts every entry and returns the ones found in the list, in the same
+// order as list
+pub fn check_pwned_with_progress(
+    db: &DatabaseEncrypted,
+    pwned: &PwnedPasswords,
+    password: &[u8],
+    progress: &Progress,
+) -> Result<Vec<PwnedEntry>, DatabaseError> {
+    let entries = db.get_all_with_progress(password, progress)?;
+
+    let mut result = Vec::new();
+    for (name, data) in entries.iter() {
+        let count = pwned.count(data.as_slice())?;
+        if count > 0 {
+            result.push(PwnedEntry {
+                name: name.clone(),
+                count,
+            });
+        }
+    }
+
+    Ok(result)
+}
+
+// Empty files can't be mapped, they are returned as None
+fn map_file(path: &Path) -> Result<Option<Mmap>, DatabaseError> {
+    let file = File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        return Ok(None);
+    }
+
+    // The dump is tens of gigabytes so it is mapped instead of read. Safe as
+    // long as nothing truncates the file while it is open, which would only
+    // happen if the user replaced the dump during a check.
+    let map = unsafe { Mmap::map(&file)? };
+    Ok(Some(map))
+}
+
+// Binary search over the byte range of the file, every probe is moved back to
+// the start of its line. Lines are "KEY:COUNT" ending in \n or \r\n.
+fn search(data: &[u8], key: &[u8]) -> u64 {
+    let mut low = 0;
+    let mut high = data.len();
+
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let start = match data[low..middle].iter().rposition(|&byte| byte == b'\n') {
+            Some(position) => low + position + 1,
+            None => low,
+        };
+        let end = match data[start..high].iter().position(|&byte| byte == b'\n') {
+            Some(position) => start + position,
+            None => high,
+        };
+
+        let line = &data[start..end];
+        let (line_key, count) = match line.iter().position(|&byte| byte == b':') {
+            Some(position) => (&line[..position], &line[position + 1..]),
+            None => (line, &line[line.len()..]),
+        };
+
+        match compare_ignore_case(line_key, key) {
+            Ordering::Equal => return parse_count(count),
+            Ordering::Less => low = end + 1,
+            Ordering::Greater => high = start,
+        }
+    }
+
+    0
+}
+
+fn compare_ignore_case(a: &[u8], b: &[u8]) -> Ordering {
+    let a = a.iter().map(|byte| byte.to_ascii_uppercase());
+    let b = b.iter().map(|byte| byte.to_ascii_uppercase());
+    a.cmp(b)
+}
+
+// A hash without a count still counts as seen once
+fn parse_count(count: &[u8]) -> u64 {
+    let count = match std::str::from_utf8(count) {
+        Ok(count) => count.trim(),
+        Err(_error) => return 1,
+    };
+
+    count.parse().unwrap_or(1)
+}
+
+#[cfg(test)]
+mod test {
+    use pwm_lib::progress::Progress;
+
+    use super::*;
+
+    // SHA-1 of "password" and "123456"
+    static PASSWORD_HASH: &str = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8";
+    static NUMBERS_HASH: &str = "7C4A8D09CA3762AF61E59520943DC26494F8941B";
+
+    fn ordered_dump() -> String {
+        let mut lines = [
+            format!("{}:9545824", PASSWORD_HASH),
+            format!("{}:37359195", NUMBERS_HASH),
+            String::from("0000000000000000000000000000000000000001:2"),
+            String::from("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF:3"),
+        ];
+        lines.sort();
+        lines.join("\r\n") + "\r\n"
+    }
+
+    #[test]
+    fn test_search() {
+        let dump = ordered_dump();
+        let data = dump.as_bytes();
+
+        assert_eq!(search(data, PASSWORD_HASH.as_bytes()), 9545824);
+        assert_eq!(search(data, NUMBERS_HASH.as_bytes()), 37359195);
+        assert_eq!(search(data, b"0000000000000000000000000000000000000001"), 2);
+        assert_eq!(search(data, b"ffffffffffffffffffffffffffffffffffffffff"), 3);
+        assert_eq!(search(data, b"0000000000000000000000000000000000000000"), 0);
+        assert_eq!(search(data, b"8000000000000000000000000000000000000000"), 0);
+        assert_eq!(search(b"", PASSWORD_HASH.as_bytes()), 0);
+    }
+
+    #[test]
+    fn test_pwned_layouts() {
+        let directory = std::env::temp_dir().join(format!("pwm-pwned-{}", std::process::id()));
+        let range = directory.join("range");
+        std::fs::create_dir_all(&range).unwrap();
+
+        let ordered = directory.join("ordered.txt");
+        std::fs::write(&ordered, ordered_dump()).unwrap();
+        std::fs::write(
+            range.join("5BAA6.txt"),
+            "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n",
+        )
+        .unwrap();
+
+        let mut db = DatabaseEncrypted::new(b"12").unwrap();
+        db.insert("weak", b"password", b"12").unwrap();
+        db.insert("strong", b"T6n#q9@Lr2!vXw", b"12").unwrap();
+
+        let expected = vec![PwnedEntry {
+            name: String::from("weak"),
+            count: 9545824,
+        }];
+
+        let pwned = PwnedPasswords::open(ordered.to_str().unwrap()).unwrap();
+        let result = check_pwned_with_progress(&db, &pwned, b"12", &Progress::new());
+        assert_eq!(result.unwrap(), expected);
+
+        // Only the range file for "password" exists
+        let pwned = PwnedPasswords::open(range.to_str().unwrap()).unwrap();
+        assert_eq!(pwned.count(b"password").unwrap(), 9545824);
+        assert!(pwned.count(b"T6n#q9@Lr2!vXw").is_err());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use pwm_lib::{
+    hash::{randomize_slice, sha_wrapper::hmac_sha256},
+    progress::Progress,
+    random::password_score,
+    zeroize::Zeroizing,
+};
+
+use crate::{
+    db_base::error::DatabaseError,
+    db_encrypted::{db_interface::DatabaseInterface, DatabaseEncrypted},
+    timestamp::now,
+};
+
+// Passwords scoring under this are reported as weak
+pub static WEAK_SCORE: f64 = 80.0;
+
+// Passwords not modified for longer than this are reported as old
+pub static DEFAULT_MAX_AGE_DAYS: u32 = 365;
+
+// Custom field names and the tag that mark an entry as using a second factor
+static SECOND_FACTOR_FIELDS: [&str; 3] = ["otp", "2fa", "mfa"];
+pub static SECOND_FACTOR_TAG: &str = "2fa";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AuditReport {
+    // Number of entries checked
+    pub entries: usize,
+    pub weak: Vec<WeakEntry>,
+    // Every group holds the entries that share one password, in list order
+    pub reused: Vec<Vec<String>>,
+    pub old: Vec<OldEntry>,
+    pub empty: Vec<EmptyEntry>,
+    // Entries with a URL but no one time password field or 2fa tag
+    pub missing_second_factor: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeakEntry {
+    pub name: String,
+    pub score: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OldEntry {
+    pub name: String,
+    pub modified: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmptyEntry {
+    pub name: String,
+    // "password" or the names of empty custom fields
+    pub fields: Vec<String>,
+}
+
+impl AuditReport {
+    pub fn is_clean(&self) -> bool {
+        self.weak.is_empty()
+            && self.reused.is_empty()
+            && self.old.is_empty()
+            && self.empty.is_empty()
+            && self.missing_second_factor.is_empty()
+    }
+
+    // Number of entries with at least one issue
+    pub fn affected(&self) -> usize {
+        let mut names: Vec<&str> = self.weak.iter().map(|entry| entry.name.as_str()).collect();
+        names.extend(self.reused.iter().flatten().map(|name| name.as_str()));
+        names.extend(self.old.iter().map(|entry| entry.name.as_str()));
+        names.extend(self.empty.iter().map(|entry| entry.name.as_str()));
+        names.extend(self.missing_second_factor.iter().map(|name| name.as_str()));
+        names.sort_unstable();
+        names.dedup();
+        names.len()
+    }
+}
+
+// Decrypts every entry and checks it, entries are reported in list order.
+// Reused passwords are found by comparing HMACs under a key that only lives
+// for the audit, so no list of passwords is built.
+pub fn audit_with_progress(
+    db: &DatabaseEncrypted,
+    max_age_days: u32,
+    password: &[u8],
+    progress: &Progress,
+) -> Result<AuditReport, DatabaseError> {
+    let entries = db.get_all_entries_with_progress(password, progress)?;
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    randomize_slice(key.as_mut_slice());
+
+    let oldest = now() - i64::from(max_age_days) * 86400;
+    let mut report = AuditReport {
+        entries: entries.len(),
+        ..AuditReport::default()
+    };
+    let mut passwords: HashMap<[u8; 32], Vec<String>> = HashMap::new();
+    let mut reused_order = Vec::new();
+
+    for entry in entries.iter() {
+        let name = &entry.title;
+        let info = db.get_info(name)?;
+
+        let mut empty = Vec::new();
+        if entry.password.is_empty() {
+            empty.push(String::from("password"));
+        } else {
+            let text = Zeroizing::new(String::from_utf8_lossy(&entry.password).to_string());
+            let score = password_score(&text);
+            if score < WEAK_SCORE {
+                report.weak.push(WeakEntry {
+                    name: name.clone(),
+                    score: score as u32,
+                });
+            }
+
+            let mut hash = [0u8; 32];
+            hmac_sha256(key.as_slice(), &entry.password, &mut hash)?;
+            let names = passwords.entry(hash).or_default();
+            if names.len() == 1 {
+                reused_order.push(hash);
+            }
+            names.push(name.clone());
+        }
+        empty.extend(
+            entry
+                .fields
+                .iter()
+                .filter(|field| field.value.is_empty())
+                .map(|field| field.name.clone()),
+        );
+        if !empty.is_empty() {
+            report.empty.push(EmptyEntry {
+                name: name.clone(),
+                fields: empty,
+            });
+        }
+
+        // 0 means the time is unknown, such entries aren't guessed to be old
+        let modified = info.modified();
+        if modified != 0 && modified <= oldest {
+            report.old.push(OldEntry {
+                name: name.clone(),
+                modified,
+            });
+        }
+
+        let has_second_factor = info.tags().contains(SECOND_FACTOR_TAG)
+            || entry.fields.iter().any(|field| {
+                let field = field.name.to_lowercase();
+                SECOND_FACTOR_FIELDS.iter().any(|name| field.contains(name))
+            });
+        if !entry.urls.is_empty() && !has_second_factor {
+            report.missing_second_factor.push(name.clone());
+        }
+    }
+
+    report.reused = reused_order
+        .iter()
+        .filter_map(|hash| passwords.remove(hash))
+        .collect();
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use pwm_lib::progress::Progress;
+
+    use super::*;
+    use crate::db_encrypted::entry::{CustomField, Entry};
+
+    #[test]
+    fn test_audit() {
+        let pw = b"12";
+        let strong = b"T6n#q9@Lr2!vXw";
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert("a", strong, pw).unwrap();
+        db.insert("b", b"password", pw).unwrap();
+        db.insert("c", strong, pw).unwrap();
+        db.insert("d", b"", pw).unwrap();
+
+        let mut site = Entry::new("site", b"x9$Kp2!mQz7#Wd");
+        site.urls.push(String::from("https://example.com"));
+        site.fields.push(CustomField::new("pin", "", true));
+        db.insert_entry(&site, pw).unwrap();
+
+        let mut otp = site.clone();
+        otp.title = String::from("site otp");
+        otp.password = Zeroizing::new(b"Vb4&nR8*eLq1^Ts".to_vec());
+        otp.fields = vec![CustomField::new("TOTP secret", "JBSWY3DP", true)];
+        db.insert_entry(&otp, pw).unwrap();
+
+        let mut tagged = site.clone();
+        tagged.title = String::from("site tagged");
+        tagged.password = Zeroizing::new(b"Hy7!cW3@uPz5%Ka".to_vec());
+        tagged.fields.clear();
+        db.insert_entry(&tagged, pw).unwrap();
+        db.add_tag("site tagged", "2FA", pw).unwrap();
+
+        let report = audit_with_progress(&db, DEFAULT_MAX_AGE_DAYS, pw, &Progress::new()).unwrap();
+        assert_eq!(report.entries, 7);
+        let weak: Vec<&str> = report
+            .weak
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(weak, vec!["b"]);
+        assert_eq!(
+            report.reused,
+            vec![vec![String::from("a"), String::from("c")]]
+        );
+        assert!(report.old.is_empty());
+        assert_eq!(
+            report.empty,
+            vec![
+                EmptyEntry {
+                    name: String::from("d"),
+                    fields: vec![String::from("password")],
+                },
+                EmptyEntry {
+                    name: String::from("site"),
+                    fields: vec![String::from("pin")],
+                },
+            ]
+        );
+        assert_eq!(report.missing_second_factor, vec![String::from("site")]);
+        assert_eq!(report.affected(), 5);
+        assert!(!report.is_clean());
+
+        // Everything was just modified, so a limit of 0 days reports all of it
+        let report = audit_with_progress(&db, 0, pw, &Progress::new()).unwrap();
+        assert_eq!(report.old.len(), 7);
+
+        assert!(audit_with_progress(&db, 0, b"wrong", &Progress::new()).is_err());
+    }
+}
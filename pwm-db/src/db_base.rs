@@ -1,31 +1,176 @@
 pub mod error;
 
-use std::collections::btree_map::BTreeMap;
+use std::collections::{btree_map::BTreeMap, BTreeSet};
 
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
 use serde::{Deserialize, Serialize};
 
 use self::error::DatabaseError;
+use crate::db_encrypted::tlv::UnknownFields;
+use crate::timestamp::now;
 
-#[derive(Serialize, Deserialize, Debug)]
+// Names are paths, "work/mail/personal" is the entry "personal" in the group
+// "mail" inside the group "work". The root group is "".
+pub static GROUP_SEPARATOR: char = '/';
+
+// Only data, groups and the recycle bin are saved, see FORMAT.md. The rest is cached search
+// state.
+#[derive(Debug)]
 pub struct Database<V> 
 {
     data: BTreeMap<String, V>,
+    // Groups that were created or emptied, groups holding entries exist
+    // without being listed here
+    groups: BTreeSet<String>,
+    // Removed entries, oldest first
+    trash: Vec<Trashed<V>>,
+    // Vault fields of a newer version, written back when saving
+    unknown: UnknownFields,
     prev_list_changed: bool,
     prev_list: Vec<String>,
     prev_pattern: String,
+    // Bumped on every change, including values borrowed mutably, so caches
+    // built from the values know when they are stale
+    revision: u64,
+}
+
+// A removed entry in the recycle bin, until it is restored or purged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trashed<V> {
+    pub name: String,
+    // Seconds since the unix epoch
+    pub removed: i64,
+    pub value: V,
+    pub(crate) unknown: UnknownFields,
+}
+
+// The bincode layout of the released version, format version 0, which saved
+// the search cache
+#[derive(Serialize, Deserialize)]
+pub(crate) struct DatabaseV0<V> {
+    data: BTreeMap<String, V>,
+    prev_list_changed: bool,
+    prev_list: Vec<String>,
+    prev_pattern: String,
+}
+
+impl<V> From<DatabaseV0<V>> for Database<V> {
+    fn from(db: DatabaseV0<V>) -> Self {
+        Database::from_parts(
+            db.data,
+            BTreeSet::new(),
+            Vec::new(),
+            UnknownFields::default(),
+        )
+    }
+}
+
+impl<V> DatabaseV0<V> {
+    pub(crate) fn map<T>(self, mut f: impl FnMut(V) -> T) -> DatabaseV0<T> {
+        DatabaseV0 {
+            data: self
+                .data
+                .into_iter()
+                .map(|(key, value)| (key, f(value)))
+                .collect(),
+            prev_list_changed: self.prev_list_changed,
+            prev_list: self.prev_list,
+            prev_pattern: self.prev_pattern,
+        }
+    }
+}
+
+#[cfg(test)]
+impl<V> From<Database<V>> for DatabaseV0<V> {
+    fn from(db: Database<V>) -> Self {
+        Self {
+            data: db.data,
+            prev_list_changed: true,
+            prev_list: Vec::new(),
+            prev_pattern: String::new(),
+        }
+    }
+}
+
+// The group an entry or group is in
+pub fn parent_group(name: &str) -> &str {
+    match name.rfind(GROUP_SEPARATOR) {
+        Some(index) => &name[..index],
+        None => "",
+    }
+}
+
+// The name without its group
+pub fn base_name(name: &str) -> &str {
+    match name.rfind(GROUP_SEPARATOR) {
+        Some(index) => &name[index + 1..],
+        None => name,
+    }
+}
+
+pub fn join_group(group: &str, name: &str) -> String {
+    if group.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}{}{}", group, GROUP_SEPARATOR, name)
+    }
+}
+
+// True for everything below the group, not only its direct children
+pub fn is_in_group(name: &str, group: &str) -> bool {
+    group.is_empty()
+        || (name.len() > group.len()
+            && name.starts_with(group)
+            && name[group.len()..].starts_with(GROUP_SEPARATOR))
+}
+
+// Every part of a path needs a name, so no leading, trailing or doubled
+// separators
+pub fn validate_name(name: &str) -> Result<(), DatabaseError> {
+    if name.split(GROUP_SEPARATOR).any(|part| part.is_empty()) {
+        return Err(DatabaseError::InputError(format!(
+            "\"{}\" is not a valid name, groups are separated by a single \"{}\"",
+            name, GROUP_SEPARATOR
+        )));
+    }
+    Ok(())
 }
 
 impl<V> Database<V> {
     pub fn new() -> Self {
         Self {
             data: BTreeMap::new(),
+            groups: BTreeSet::new(),
+            trash: Vec::new(),
+            unknown: UnknownFields::default(),
             prev_list_changed: true,
             prev_list: Vec::new(),
             prev_pattern: String::new(),
+            revision: 0,
+        }
+    }
+
+    // Entries, groups and the recycle bin as they were saved, names aren't
+    // checked
+    pub(crate) fn from_parts(
+        data: BTreeMap<String, V>,
+        groups: BTreeSet<String>,
+        trash: Vec<Trashed<V>>,
+        unknown: UnknownFields,
+    ) -> Self {
+        Self {
+            data,
+            groups,
+            trash,
+            unknown,
+            ..Self::new()
         }
     }
 
+    pub(crate) fn unknown_fields(&self) -> &UnknownFields {
+        &self.unknown
+    }
+
     pub fn as_ref(&self) -> &Self {
         &self
     }
@@ -35,23 +180,37 @@ impl<V> Database<V> {
     }
 
     pub fn insert(&mut self, name: &str, value: V) -> Result<(), DatabaseError> {
-        if self.data.contains_key(name) {
+        validate_name(name)?;
+        if self.data.contains_key(name) || self.contains_group(name) {
             return Err(DatabaseError::AlreadyExists);
         }
 
         self.data.insert(name.to_string(), value);
         self.prev_list_changed = true;
+        self.revision = self.revision.wrapping_add(1);
         Ok(())
     }
 
+    // Also moves entries between groups
     pub fn rename(&mut self, name: &str, new_name: &str) -> Result<(), DatabaseError> {
+        if !self.data.contains_key(name) {
+            return Err(DatabaseError::NotFound);
+        }
+        if name == new_name {
+            return Ok(());
+        }
+        validate_name(new_name)?;
+        if self.data.contains_key(new_name) || self.contains_group(new_name) {
+            return Err(DatabaseError::AlreadyExists);
+        }
+
         if let Some(data) = self.data.remove(name) {
-            self.insert(new_name, data)?;
-        } else {
-            return Err(DatabaseError::NotFound)
+            self.keep_group(parent_group(name));
+            self.data.insert(new_name.to_string(), data);
         }
 
         self.prev_list_changed = true;
+        self.revision = self.revision.wrapping_add(1);
         Ok(())
     }
 
@@ -61,20 +220,71 @@ impl<V> Database<V> {
         }
 
         self.prev_list_changed = true;
+        self.revision = self.revision.wrapping_add(1);
         self.data.insert(name.to_string(), new_data);
         Ok(())
     }
 
+    // Moves the entry to the recycle bin
     pub fn remove(&mut self, name: &str) -> Result<(), DatabaseError> {
         if !self.data.contains_key(name) {
             return Err(DatabaseError::NotFound);
         }
 
         self.prev_list_changed = true;
-        self.data.remove(name);
+        self.revision = self.revision.wrapping_add(1);
+        self.move_to_trash(name);
+        self.keep_group(parent_group(name));
+        Ok(())
+    }
+
+    fn move_to_trash(&mut self, name: &str) {
+        if let Some(value) = self.data.remove(name) {
+            self.trash.push(Trashed {
+                name: name.to_string(),
+                removed: now(),
+                value,
+                unknown: UnknownFields::default(),
+            });
+        }
+    }
+
+    // Removed entries, oldest first
+    pub fn trash(&self) -> &[Trashed<V>] {
+        &self.trash
+    }
+
+    // Puts back the entry removed last under the name
+    pub fn restore_trashed(&mut self, name: &str) -> Result<(), DatabaseError> {
+        let index = match self.trash.iter().rposition(|trashed| trashed.name == name) {
+            Some(index) => index,
+            None => return Err(DatabaseError::NotFound),
+        };
+        if self.data.contains_key(name) || self.contains_group(name) {
+            return Err(DatabaseError::AlreadyExists);
+        }
+
+        let trashed = self.trash.remove(index);
+        self.data.insert(trashed.name, trashed.value);
+        self.prev_list_changed = true;
+        self.revision = self.revision.wrapping_add(1);
         Ok(())
     }
 
+    // Removes entries from the recycle bin for good, with a time only the
+    // ones removed before it. Returns their names.
+    pub fn purge_trash(&mut self, before: Option<i64>) -> Vec<String> {
+        let (purged, kept): (Vec<Trashed<V>>, Vec<Trashed<V>>) = std::mem::take(&mut self.trash)
+            .into_iter()
+            .partition(|trashed| before.is_none_or(|before| trashed.removed < before));
+        self.trash = kept;
+
+        if !purged.is_empty() {
+            self.revision = self.revision.wrapping_add(1);
+        }
+        purged.into_iter().map(|trashed| trashed.name).collect()
+    }
+
     pub fn get(&self, name: &str) -> Result<&V, DatabaseError> {
         let value = self.data.get(name);
         return match value {
@@ -83,6 +293,278 @@ impl<V> Database<V> {
         };
     }
 
+    // Changing values in place doesn't change the list
+    pub fn get_mut(&mut self, name: &str) -> Result<&mut V, DatabaseError> {
+        match self.data.get_mut(name) {
+            Some(value) => {
+                self.revision = self.revision.wrapping_add(1);
+                Ok(value)
+            }
+            None => Err(DatabaseError::NotFound),
+        }
+    }
+
+    // Puts back a value as it was, None removes it. Nothing is checked, this
+    // is only for undoing changes that were checked when they were made.
+    pub(crate) fn restore(&mut self, name: &str, value: Option<V>) {
+        match value {
+            Some(value) => self.data.insert(name.to_string(), value),
+            None => self.data.remove(name),
+        };
+        self.prev_list_changed = true;
+        self.revision = self.revision.wrapping_add(1);
+    }
+
+    // The groups kept without entries, see groups for every group
+    pub(crate) fn stored_groups(&self) -> &BTreeSet<String> {
+        &self.groups
+    }
+
+    pub(crate) fn restore_groups(&mut self, groups: BTreeSet<String>) {
+        self.groups = groups;
+        self.prev_list_changed = true;
+        self.revision = self.revision.wrapping_add(1);
+    }
+
+    pub(crate) fn restore_trash(&mut self, trash: Vec<Trashed<V>>) {
+        self.trash = trash;
+        self.revision = self.revision.wrapping_add(1);
+    }
+
+    // Converts every value, the cached search results stay valid
+    pub fn map<T>(self, mut f: impl FnMut(V) -> T) -> Database<T> {
+        Database {
+            data: self
+                .data
+                .into_iter()
+                .map(|(key, value)| (key, f(value)))
+                .collect(),
+            groups: self.groups,
+            trash: self
+                .trash
+                .into_iter()
+                .map(|trashed| Trashed {
+                    name: trashed.name,
+                    removed: trashed.removed,
+                    value: f(trashed.value),
+                    unknown: trashed.unknown,
+                })
+                .collect(),
+            unknown: self.unknown,
+            prev_list_changed: self.prev_list_changed,
+            prev_list: self.prev_list,
+            prev_pattern: self.prev_pattern,
+            revision: self.revision,
+        }
+    }
+
+    // The names and groups without the values, for trying out changes
+    pub(crate) fn names_only(&self) -> Database<()> {
+        Database {
+            data: self.data.keys().map(|name| (name.clone(), ())).collect(),
+            groups: self.groups.clone(),
+            trash: Vec::new(),
+            unknown: UnknownFields::default(),
+            prev_list_changed: true,
+            prev_list: Vec::new(),
+            prev_pattern: String::new(),
+            revision: 0,
+        }
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    // Every entry with its name, sorted by name
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+        self.data.iter()
+    }
+
+    // A group doesn't disappear when its last entry is moved or removed
+    fn keep_group(&mut self, group: &str) {
+        if !group.is_empty() {
+            self.groups.insert(group.to_string());
+        }
+    }
+
+    pub fn contains_group(&self, group: &str) -> bool {
+        if group.is_empty() || self.groups.contains(group) {
+            return true;
+        }
+
+        // Anything stored below it, the first key after the prefix is enough
+        let prefix = format!("{}{}", group, GROUP_SEPARATOR);
+        let below = |key: &String| key.starts_with(&prefix);
+        self.data
+            .range(prefix.clone()..)
+            .next()
+            .is_some_and(|(key, _)| below(key))
+            || self
+                .groups
+                .range(prefix.clone()..)
+                .next()
+                .is_some_and(below)
+    }
+
+    // Every group including the ones only holding entries, sorted
+    pub fn groups(&self) -> Vec<String> {
+        let mut groups = BTreeSet::new();
+        for name in self.groups.iter() {
+            groups.insert(name.clone());
+        }
+        for name in self.groups.iter().chain(self.data.keys()) {
+            let mut parent = parent_group(name);
+            while !parent.is_empty() && groups.insert(parent.to_string()) {
+                parent = parent_group(parent);
+            }
+        }
+        groups.into_iter().collect()
+    }
+
+    // The direct children of a group as (groups, entries), full names
+    pub fn list_group(&self, group: &str) -> Result<(Vec<String>, Vec<String>), DatabaseError> {
+        if !self.contains_group(group) {
+            return Err(DatabaseError::NotFound);
+        }
+
+        let groups = self
+            .groups()
+            .into_iter()
+            .filter(|name| parent_group(name) == group)
+            .collect();
+        let entries = self
+            .data
+            .keys()
+            .filter(|name| parent_group(name) == group)
+            .cloned()
+            .collect();
+
+        Ok((groups, entries))
+    }
+
+    // Fuzzy search of every entry below the group
+    pub fn list_group_fuzzy_match(
+        &mut self,
+        group: &str,
+        pattern: &str,
+    ) -> Result<Vec<String>, DatabaseError> {
+        if !self.contains_group(group) {
+            return Err(DatabaseError::NotFound);
+        }
+
+        Ok(self
+            .list_fuzzy_match(pattern)?
+            .iter()
+            .filter(|name| is_in_group(name, group))
+            .cloned()
+            .collect())
+    }
+
+    pub fn create_group(&mut self, group: &str) -> Result<(), DatabaseError> {
+        validate_name(group)?;
+        if self.data.contains_key(group) || self.contains_group(group) {
+            return Err(DatabaseError::AlreadyExists);
+        }
+
+        self.groups.insert(group.to_string());
+        self.revision = self.revision.wrapping_add(1);
+        Ok(())
+    }
+
+    // Renames or moves a group with everything in it
+    pub fn rename_group(&mut self, group: &str, new_group: &str) -> Result<(), DatabaseError> {
+        if group.is_empty() || !self.contains_group(group) {
+            return Err(DatabaseError::NotFound);
+        }
+        if group == new_group {
+            return Ok(());
+        }
+        validate_name(new_group)?;
+        if is_in_group(new_group, group) {
+            return Err(DatabaseError::InputError(format!(
+                "Can't move \"{}\" into itself",
+                group
+            )));
+        }
+        if self.data.contains_key(new_group) || self.contains_group(new_group) {
+            return Err(DatabaseError::AlreadyExists);
+        }
+
+        let renamed = |name: &str| format!("{}{}", new_group, &name[group.len()..]);
+
+        let entries: Vec<String> = self
+            .data
+            .keys()
+            .filter(|name| is_in_group(name, group))
+            .cloned()
+            .collect();
+        for name in entries {
+            if let Some(value) = self.data.remove(&name) {
+                self.data.insert(renamed(&name), value);
+            }
+        }
+
+        let groups: Vec<String> = self
+            .groups
+            .iter()
+            .filter(|name| *name == group || is_in_group(name, group))
+            .cloned()
+            .collect();
+        for name in groups {
+            self.groups.remove(&name);
+            self.groups.insert(renamed(&name));
+        }
+        self.groups.insert(new_group.to_string());
+        self.keep_group(parent_group(group));
+
+        self.prev_list_changed = true;
+        self.revision = self.revision.wrapping_add(1);
+        Ok(())
+    }
+
+    // Without recursive only empty groups are removed. Returns the removed
+    // entries.
+    pub fn remove_group(
+        &mut self,
+        group: &str,
+        recursive: bool,
+    ) -> Result<Vec<String>, DatabaseError> {
+        if group.is_empty() || !self.contains_group(group) {
+            return Err(DatabaseError::NotFound);
+        }
+
+        let entries: Vec<String> = self
+            .data
+            .keys()
+            .filter(|name| is_in_group(name, group))
+            .cloned()
+            .collect();
+        let groups: Vec<String> = self
+            .groups
+            .iter()
+            .filter(|name| *name == group || is_in_group(name, group))
+            .cloned()
+            .collect();
+
+        let empty = entries.is_empty() && groups.iter().all(|name| name == group);
+        if !recursive && !empty {
+            return Err(DatabaseError::NotEmpty);
+        }
+
+        for name in entries.iter() {
+            self.move_to_trash(name);
+        }
+        for name in groups.iter() {
+            self.groups.remove(name);
+        }
+        self.keep_group(parent_group(group));
+
+        self.prev_list_changed = true;
+        self.revision = self.revision.wrapping_add(1);
+        Ok(entries)
+    }
+
     pub fn list(&self) -> Result<Vec<String>, DatabaseError> {
         let keys = self.data.iter().map(|(key, _)| key.clone()).collect();
         Ok(keys)
@@ -187,4 +669,103 @@ mod test {
         assert!(list.len() == 1);
         assert_eq!(list.contains(&"Hello2".to_string()), true);
     }
+
+    #[test]
+    fn db_groups() {
+        let mut db = Database::<i32>::new();
+        db.insert("work/mail/personal", 1).unwrap();
+        db.insert("work/chat", 2).unwrap();
+        db.insert("home", 3).unwrap();
+        db.create_group("empty").unwrap();
+
+        assert_eq!(db.groups(), vec!["empty", "work", "work/mail"]);
+        assert!(db.contains_group("work/mail"));
+        assert!(!db.contains_group("work/chat"));
+        assert!(!db.contains_group("wor"));
+
+        let (groups, entries) = db.list_group("").unwrap();
+        assert_eq!(groups, vec!["empty", "work"]);
+        assert_eq!(entries, vec!["home"]);
+        let (groups, entries) = db.list_group("work").unwrap();
+        assert_eq!(groups, vec!["work/mail"]);
+        assert_eq!(entries, vec!["work/chat"]);
+        assert_eq!(
+            db.list_group("missing").unwrap_err(),
+            DatabaseError::NotFound
+        );
+
+        // Names can't clash with groups or be empty paths
+        assert_eq!(
+            db.insert("work", 4).unwrap_err(),
+            DatabaseError::AlreadyExists
+        );
+        assert_eq!(
+            db.create_group("home").unwrap_err(),
+            DatabaseError::AlreadyExists
+        );
+        assert!(db.insert("work//x", 4).is_err());
+        assert!(db.insert("/x", 4).is_err());
+        assert!(db.create_group("x/").is_err());
+
+        let list = db.list_group_fuzzy_match("work", "").unwrap();
+        assert_eq!(list, vec!["work/chat", "work/mail/personal"]);
+        let list = db.list_group_fuzzy_match("work/mail", "chat").unwrap();
+        assert_eq!(list.len(), 0);
+    }
+
+    #[test]
+    fn db_groups_move() {
+        let mut db = Database::<i32>::new();
+        db.insert("work/mail/personal", 1).unwrap();
+        db.insert("work/chat", 2).unwrap();
+        db.create_group("archive").unwrap();
+
+        assert!(db.rename_group("work", "work/mail/old").is_err());
+        assert_eq!(
+            db.rename_group("work", "archive").unwrap_err(),
+            DatabaseError::AlreadyExists
+        );
+        db.rename_group("work", "archive/work").unwrap();
+        assert_eq!(*db.get("archive/work/mail/personal").unwrap(), 1);
+        assert_eq!(*db.get("archive/work/chat").unwrap(), 2);
+        assert!(!db.contains_group("work"));
+        assert_eq!(
+            db.groups(),
+            vec!["archive", "archive/work", "archive/work/mail"]
+        );
+
+        // Moving the last entry out keeps the group
+        db.rename("archive/work/chat", "chat").unwrap();
+        db.rename("archive/work/mail/personal", "personal").unwrap();
+        assert!(db.contains_group("archive/work/mail"));
+    }
+
+    #[test]
+    fn db_groups_remove() {
+        let mut db = Database::<i32>::new();
+        db.insert("work/mail/personal", 1).unwrap();
+        db.insert("work/chat", 2).unwrap();
+        db.insert("home", 3).unwrap();
+
+        assert_eq!(
+            db.remove_group("work", false).unwrap_err(),
+            DatabaseError::NotEmpty
+        );
+        let removed = db.remove_group("work", true).unwrap();
+        assert_eq!(removed, vec!["work/chat", "work/mail/personal"]);
+        assert_eq!(db.list().unwrap(), vec!["home"]);
+        assert_eq!(db.groups().len(), 0);
+
+        db.create_group("empty/nested").unwrap();
+        assert_eq!(
+            db.remove_group("empty", false).unwrap_err(),
+            DatabaseError::NotEmpty
+        );
+        db.remove_group("empty/nested", false).unwrap();
+        db.remove_group("empty", false).unwrap();
+        assert_eq!(
+            db.remove_group("", true).unwrap_err(),
+            DatabaseError::NotFound
+        );
+    }
 }
@@ -1,9 +1,14 @@
-use pwm_lib::encryption::EncryptionError;
+use pwm_lib::{encryption::EncryptionError, hash::HashError};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum DatabaseError {
     NotFound,
     AlreadyExists,
+    NotEmpty,
+    NothingToUndo,
+    NothingToRedo,
+    TransactionInProgress,
+    NoTransaction,
     FailedHash(String),
     FailedEncryption(String),
     LockError,
@@ -13,10 +18,13 @@ pub enum DatabaseError {
     ImportError(String),
     FailedSerialize,
     FailedDeserialize,
+    UnsupportedVersion(u8),
     InvalidCsv(String),
     IoError(String),
     CompressionError(String),
     ClipboardError(String),
+    Cancelled,
+    Locked(String),
 }
 
 impl std::fmt::Display for DatabaseError {
@@ -24,6 +32,11 @@ impl std::fmt::Display for DatabaseError {
         return match self {
             Self::NotFound => f.write_str("Not found"),
             Self::AlreadyExists => f.write_str("Already exists"),
+            Self::NotEmpty => f.write_str("Not empty"),
+            Self::NothingToUndo => f.write_str("Nothing to undo"),
+            Self::NothingToRedo => f.write_str("Nothing to redo"),
+            Self::TransactionInProgress => f.write_str("A transaction is in progress"),
+            Self::NoTransaction => f.write_str("No transaction in progress"),
             Self::FailedHash(msg) => f.write_fmt(std::format_args!("Failed hash: {}", msg)),
             Self::FailedEncryption(msg) => f.write_fmt(std::format_args!("{}", msg)),
             Self::LockError => f.write_str("Failed to get mutex lock on db"),
@@ -33,10 +46,20 @@ impl std::fmt::Display for DatabaseError {
             Self::ImportError(msg) => f.write_fmt(std::format_args!("Failed to import: {}", msg)),
             Self::FailedSerialize => f.write_str("Failed to serialize"),
             Self::FailedDeserialize => f.write_str("Failed to deserialize"),
+            Self::UnsupportedVersion(version) => f.write_fmt(std::format_args!(
+                "The vault has format version {}, which is newer than this version of pwm supports",
+                version
+            )),
             Self::InvalidCsv(msg) => f.write_fmt(std::format_args!("Csv error: {}", msg)),
             Self::IoError(msg) => f.write_fmt(std::format_args!("Io error: {}", msg)),
-            Self::CompressionError(msg) => f.write_fmt(std::format_args!("Compression error: {}", msg)),
+            Self::CompressionError(msg) => {
+                f.write_fmt(std::format_args!("Compression error: {}", msg))
+            }
             Self::ClipboardError(msg) => f.write_fmt(std::format_args!("Clipboard error: {}", msg)),
+            Self::Cancelled => f.write_str("Cancelled"),
+            Self::Locked(owner) => {
+                f.write_fmt(std::format_args!("The vault is in use by {}", owner))
+            }
         };
     }
 }
@@ -55,6 +78,16 @@ impl From<EncryptionError> for DatabaseError {
     }
 }
 
+impl From<HashError> for DatabaseError {
+    fn from(value: HashError) -> Self {
+        if value.is_cancelled() {
+            Self::Cancelled
+        } else {
+            Self::FailedHash(value.to_string())
+        }
+    }
+}
+
 impl From<std::io::Error> for DatabaseError {
     fn from(value: std::io::Error) -> Self {
         Self::IoError(value.to_string())
@@ -1,20 +1,36 @@
-use crate::db_base::{error::DatabaseError, Database};
+use crate::db_base::{error::DatabaseError, is_in_group, Database};
+use crate::db_encrypted::entry::{normalize_tag, EncryptedEntry};
+use crate::db_encrypted::journal::Journal;
+use crate::db_encrypted::log::LogState;
+use crate::db_encrypted::query::SearchIndex;
 #[cfg(feature = "use-compression")]
 use lz4_flex::decompress_size_prepended;
 use pwm_lib::{
     encryption::{default::decrypt, EncryptionResult},
     hash::{
-        argon2_wrapper::{argon2_hash_password, argon2_hash_password_with_salt},
+        argon2_wrapper::{
+            argon2_hash_password, argon2_hash_password_cancellable,
+            argon2_hash_password_with_salt, argon2_hash_password_with_salt_cancellable,
+        },
         compare_hash,
-        pbkdf2_wrapper::{pbkdf2_hash_password, pbkdf2_hash_password_with_salt},
+        pbkdf2_wrapper::{
+            pbkdf2_hash_password, pbkdf2_hash_password_with_salt,
+            pbkdf2_hash_password_with_salt_cancellable,
+        },
         HashResult,
     },
+    progress::Progress,
     zeroize::Zeroizing,
 };
+use std::collections::BTreeMap;
 
 pub struct DatabaseEncrypted {
-    db: Database<EncryptionResult>,
+    db: Database<EncryptedEntry>,
     confirmation_hash: HashResult,
+    index: SearchIndex,
+    journal: Journal,
+    // Set while saves append to a change log, see log.rs
+    log: Option<LogState>,
 }
 
 impl DatabaseEncrypted {
@@ -25,12 +41,19 @@ impl DatabaseEncrypted {
         let db = Self {
             db: Database::new(),
             confirmation_hash: hash,
+            index: SearchIndex::default(),
+            journal: Journal::default(),
+            log: None,
         };
 
         Ok(db)
     }
 
-    fn new_deserialize(serialized: &[u8], password: &[u8]) -> Result<Self, DatabaseError> {
+    fn new_deserialize(
+        serialized: &[u8],
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<Self, DatabaseError> {
         let hash = match HashResult::new_with_salt_and_hash(
             &serialized[serialized.len() - 32..],
             &serialized[serialized.len() - 64..serialized.len() - 32],
@@ -39,27 +62,33 @@ impl DatabaseEncrypted {
             Err(_error) => return Err(DatabaseError::FailedDeserialize),
         };
 
-        if !Self::hash_password_and_compare_internal(&hash, password) {
+        if !Self::hash_password_and_compare_cancellable_internal(&hash, password, progress)? {
             return Err(DatabaseError::InvalidPassword);
         }
 
-        let db: Database<EncryptionResult> =
-            match bincode::deserialize(&serialized[..serialized.len() - 64]) {
-                Ok(db) => db,
-                Err(_error) => return Err(DatabaseError::FailedDeserialize),
-            };
+        let db = Self::deserialize_database(&serialized[..serialized.len() - 64])?;
 
         Ok(Self {
             db,
             confirmation_hash: hash,
+            index: SearchIndex::default(),
+            journal: Journal::default(),
+            log: None,
         })
     }
 
+    // Two steps of progress, one for decrypting and one for the confirmation hash
     fn new_deserialize_encrypted_internal(
         serialized: &EncryptionResult,
         password: &[u8],
+        progress: &Progress,
     ) -> Result<(Self, HashResult), DatabaseError> {
-        let hash = Self::hash_password_argon2_with_salt(password, serialized.get_salt_slice())?;
+        progress.add_total(2);
+        let hash = argon2_hash_password_with_salt_cancellable(
+            password,
+            serialized.get_salt_slice(),
+            progress,
+        )?;
 
         let plaintext = match decrypt(serialized, &hash) {
             Ok(plaintext) => plaintext,
@@ -72,17 +101,18 @@ impl DatabaseEncrypted {
             Err(error) => return Err(DatabaseError::CompressionError(error.to_string())),
         };
 
-        let result = Self::new_deserialize(plaintext.as_slice(), password)?;
+        let result = Self::new_deserialize(plaintext.as_slice(), password, progress)?;
 
         Ok((result, hash))
     }
 
-    fn serialize(&self) -> Result<Zeroizing<Vec<u8>>, DatabaseError> {
-        let mut data = match bincode::serialize(self.db.as_ref()) {
-            Ok(data) => Zeroizing::new(data),
-            Err(_err) => return Err(DatabaseError::FailedDeserialize),
-        };
+    // Vaults of older versions are migrated to the current layout
+    fn deserialize_database(serialized: &[u8]) -> Result<Database<EncryptedEntry>, DatabaseError> {
+        migration::deserialize(serialized)
+    }
 
+    fn serialize(&self) -> Result<Zeroizing<Vec<u8>>, DatabaseError> {
+        let mut data = migration::serialize(&self.db);
         data.extend_from_slice(self.confirmation_hash.get_hash());
         data.extend_from_slice(self.confirmation_hash.get_salt());
 
@@ -93,8 +123,161 @@ impl DatabaseEncrypted {
         self.db.list()
     }
 
-    pub fn list_fuzzy_match(&mut self, pattern: &str) -> Result<&Vec<String>, DatabaseError> {
-        self.db.list_fuzzy_match(pattern)
+    // The pattern is a search query, see query::Query. Only entries with
+    // every one of the tags are listed.
+    pub fn list_fuzzy_match(
+        &mut self,
+        pattern: &str,
+        tags: &[String],
+    ) -> Result<Vec<String>, DatabaseError> {
+        let list = self.index.search(&self.db, pattern).clone();
+        Ok(self.filter_tags(list, tags))
+    }
+
+    pub fn groups(&self) -> Vec<String> {
+        self.db.groups()
+    }
+
+    pub fn contains_group(&self, group: &str) -> bool {
+        self.db.contains_group(group)
+    }
+
+    pub fn list_group(&self, group: &str) -> Result<(Vec<String>, Vec<String>), DatabaseError> {
+        self.db.list_group(group)
+    }
+
+    pub fn list_group_fuzzy_match(
+        &mut self,
+        group: &str,
+        pattern: &str,
+        tags: &[String],
+    ) -> Result<Vec<String>, DatabaseError> {
+        if !self.db.contains_group(group) {
+            return Err(DatabaseError::NotFound);
+        }
+
+        let list = self
+            .index
+            .search(&self.db, pattern)
+            .iter()
+            .filter(|name| is_in_group(name, group))
+            .cloned()
+            .collect();
+        Ok(self.filter_tags(list, tags))
+    }
+
+    fn filter_tags(&self, list: Vec<String>, tags: &[String]) -> Vec<String> {
+        if tags.is_empty() {
+            return list;
+        }
+
+        list.into_iter()
+            .filter(|name| match self.db.get(name) {
+                Ok(entry) => entry.has_tags(tags),
+                Err(_error) => false,
+            })
+            .collect()
+    }
+
+    pub fn list_by_tag(&self, tag: &str) -> Result<Vec<String>, DatabaseError> {
+        let tags = [normalize_tag(tag)?];
+        Ok(self.filter_tags(self.db.list()?, &tags))
+    }
+
+    // Every tag in use and how many entries have it
+    pub fn tags(&self) -> Result<BTreeMap<String, usize>, DatabaseError> {
+        let mut tags = BTreeMap::new();
+        for name in self.db.list()? {
+            for tag in self.db.get(&name)?.tags() {
+                *tags.entry(tag.clone()).or_insert(0) += 1;
+            }
+        }
+        Ok(tags)
+    }
+
+    // The stored entry, its plain text fields don't need the master password
+    pub fn get_info(&self, name: &str) -> Result<&EncryptedEntry, DatabaseError> {
+        self.db.get(name)
+    }
+
+    // Only the time changes, so callers don't need to treat the vault as
+    // modified for it
+    pub fn mark_accessed(&mut self, name: &str) -> Result<(), DatabaseError> {
+        self.db.get_mut(name)?.set_accessed();
+        Ok(())
+    }
+
+    // Changes until the matching commit or rollback are kept together, they
+    // are either all kept or all put back and are undone as one change.
+    // Transactions can be nested, only the outermost is recorded.
+    pub fn begin(&mut self) {
+        self.journal.begin_transaction(&self.db);
+    }
+
+    pub fn commit(&mut self, description: &str) -> Result<(), DatabaseError> {
+        self.journal
+            .commit_transaction(&self.db, description.to_string())
+    }
+
+    pub fn rollback(&mut self) -> Result<(), DatabaseError> {
+        self.journal.rollback_transaction(&mut self.db)
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.journal.in_transaction()
+    }
+
+    // Commits if the operation succeeds and rolls back if it fails
+    pub fn transaction<T>(
+        &mut self,
+        description: &str,
+        operation: impl FnOnce(&mut Self) -> Result<T, DatabaseError>,
+    ) -> Result<T, DatabaseError> {
+        self.begin();
+        match operation(self) {
+            Ok(value) => {
+                self.commit(description)?;
+                Ok(value)
+            }
+            Err(error) => {
+                self.rollback()?;
+                Err(error)
+            }
+        }
+    }
+
+    // Runs an operation that can touch the named entries, recording it so
+    // it can be undone
+    fn journaled<T>(
+        &mut self,
+        description: String,
+        names: Vec<String>,
+        operation: impl FnOnce(&mut Database<EncryptedEntry>) -> Result<T, DatabaseError>,
+    ) -> Result<T, DatabaseError> {
+        let pending = Journal::begin(&self.db, &names);
+        let result = operation(&mut self.db);
+        self.journal.record(&self.db, pending, &names, description);
+        result
+    }
+
+    // Every entry below the group, and the names they would have below
+    // new_group
+    fn group_entries(&self, group: &str, new_group: Option<&str>) -> Vec<String> {
+        let entries: Vec<String> = self
+            .db
+            .iter()
+            .map(|(name, _entry)| name)
+            .filter(|name| is_in_group(name, group))
+            .cloned()
+            .collect();
+        match new_group {
+            Some(new_group) => entries
+                .iter()
+                .map(|name| format!("{}{}", new_group, &name[group.len()..]))
+                .chain(entries.iter().cloned())
+                .collect(),
+            None => entries,
+        }
     }
 
     // Common end
@@ -142,6 +325,13 @@ impl DatabaseEncrypted {
         Ok(hash)
     }
 
+    fn hash_password_argon2_cancellable(
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<HashResult, DatabaseError> {
+        Ok(argon2_hash_password_cancellable(password, progress)?)
+    }
+
     fn hash_password_and_compare_internal(hash: &HashResult, password: &[u8]) -> bool {
         let result = match Self::hash_password_pbkdf2_with_salt(password, hash.get_salt()) {
             Ok(hash) => hash,
@@ -151,14 +341,54 @@ impl DatabaseEncrypted {
         compare_hash(result.get_hash(), hash.get_hash())
     }
 
+    // Unlike hash_password_and_compare_internal a cancellation is returned as an error
+    fn hash_password_and_compare_cancellable_internal(
+        hash: &HashResult,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<bool, DatabaseError> {
+        let result =
+            pbkdf2_hash_password_with_salt_cancellable(password, hash.get_salt(), progress)?;
+
+        Ok(compare_hash(result.get_hash(), hash.get_hash()))
+    }
+
     // Returns true if the hash matches
     fn hash_password_and_compare(&self, password: &[u8]) -> bool {
         Self::hash_password_and_compare_internal(&self.confirmation_hash, password)
     }
+
+    // One step of progress
+    fn hash_password_and_compare_cancellable(
+        &self,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<bool, DatabaseError> {
+        progress.add_total(1);
+        Self::hash_password_and_compare_cancellable_internal(
+            &self.confirmation_hash,
+            password,
+            progress,
+        )
+    }
     // End Utility
 }
 
+pub mod attachment;
+pub mod bitwarden;
 pub mod db_interface;
+pub mod diff;
+pub mod entry;
+mod import;
+pub mod journal;
+pub mod kdbx;
+pub mod log;
+pub mod merge;
+mod migration;
+mod parallel;
+pub mod query;
+pub(crate) mod tlv;
+pub mod trash;
 
 #[cfg(test)]
 mod test_forget {
@@ -234,6 +464,599 @@ mod test_forget {
         assert_eq!(b"password,,,broken?", pass.as_slice());
     }
 
+    #[test]
+    fn test_csv_cancelled() {
+        use crate::db_base::error::DatabaseError;
+        use pwm_lib::progress::Progress;
+
+        let pw = b"test";
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert("user", b"password", pw).unwrap();
+        db.export_to_csv("db_encrypted_csv_test00000001.csv", pw)
+            .unwrap();
+
+        let progress = Progress::new();
+        progress.cancel();
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        let result =
+            db.insert_from_csv_with_progress("db_encrypted_csv_test00000001.csv", pw, &progress);
+        std::fs::remove_file("db_encrypted_csv_test00000001.csv").unwrap();
+
+        assert_eq!(result.unwrap_err(), DatabaseError::Cancelled);
+        assert_eq!(db.list().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_entry() {
+        use crate::db_encrypted::entry::{CustomField, Entry};
+
+        let pw = b"test";
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        let mut entry = Entry::new("mail", b"password");
+        entry.username = String::from("someone");
+        entry.urls = vec![
+            String::from("https://a.example"),
+            String::from("https://b.example"),
+        ];
+        entry.notes = String::from("some notes");
+        entry.fields.push(CustomField::new("pin", "1234", true));
+        entry
+            .fields
+            .push(CustomField::new("question", "blue", false));
+        db.insert_entry(&entry, pw).unwrap();
+
+        let serialized = db.serialize_encrypted(pw).unwrap();
+        let db = DatabaseEncrypted::new_deserialize_encrypted(&serialized, pw).unwrap();
+        assert_eq!(db.get_entry("mail", pw).unwrap(), entry);
+        assert_eq!(db.get("mail", pw).unwrap().as_slice(), b"password");
+
+        assert_eq!(
+            db.get_field("mail", "Username", pw).unwrap().as_str(),
+            "someone"
+        );
+        assert_eq!(db.get_field("mail", "pin", pw).unwrap().as_str(), "1234");
+        assert_eq!(
+            db.get_field("mail", "password", pw).unwrap().as_str(),
+            "password"
+        );
+        assert!(db.get_field("mail", "missing", pw).is_err());
+
+        // Secret fields are not readable from the stored entry
+        let info = db.get_info("mail").unwrap();
+        assert_eq!(info.plain_field("mail", "question").unwrap(), "blue");
+        assert_eq!(info.plain_field("mail", "pin"), None);
+    }
+
+    #[test]
+    fn test_entry_invalid() {
+        use crate::db_encrypted::entry::{CustomField, Entry};
+
+        let pw = b"test";
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        let mut entry = Entry::new("mail", b"password");
+        entry
+            .fields
+            .push(CustomField::new("Username", "someone", false));
+        assert!(db.insert_entry(&entry, pw).is_err());
+
+        entry.fields = vec![
+            CustomField::new("pin", "1", false),
+            CustomField::new("pin", "2", true),
+        ];
+        assert!(db.insert_entry(&entry, pw).is_err());
+        assert_eq!(db.list().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_legacy_format() {
+        use crate::db_base::DatabaseV0;
+        use pwm_lib::encryption::EncryptionResult;
+
+        let pw = b"test";
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert("user", b"password", pw).unwrap();
+
+        // Vaults written before entries only stored the encrypted password
+        let legacy: DatabaseV0<EncryptionResult> =
+            db.db.map(|entry| entry.password().clone()).into();
+        let serialized = bincode::serialize(&legacy).unwrap();
+        let db = DatabaseEncrypted {
+            db: DatabaseEncrypted::deserialize_database(&serialized).unwrap(),
+            confirmation_hash: db.confirmation_hash,
+            index: Default::default(),
+            journal: Default::default(),
+            log: None,
+        };
+
+        let entry = db.get_entry("user", pw).unwrap();
+        assert_eq!(entry.password.as_slice(), b"password");
+        assert_eq!(entry.username, "");
+        assert_eq!(entry.fields.len(), 0);
+    }
+
+    #[test]
+    fn test_history() {
+        use crate::db_encrypted::entry::MAX_HISTORY;
+
+        let pw = b"test";
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert("user", b"password1", pw).unwrap();
+        db.replace("user", b"password2", pw).unwrap();
+        db.replace("user", b"password3", pw).unwrap();
+
+        let history = db.get_history("user", pw).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].password.as_slice(), b"password2");
+        assert_eq!(history[1].password.as_slice(), b"password1");
+
+        // The restored password leaves the history, the replaced one joins it
+        db.restore_history("user", 1, pw).unwrap();
+        assert_eq!(db.get("user", pw).unwrap().as_slice(), b"password1");
+        let history = db.get_history("user", pw).unwrap();
+        assert_eq!(history[0].password.as_slice(), b"password3");
+        assert_eq!(history[1].password.as_slice(), b"password2");
+        assert!(db.restore_history("user", 2, pw).is_err());
+
+        // Editing other fields doesn't add to the history
+        let mut entry = db.get_entry("user", pw).unwrap();
+        entry.username = String::from("someone");
+        db.replace_entry(&entry, pw).unwrap();
+        assert_eq!(db.get_history("user", pw).unwrap().len(), 2);
+
+        for index in 0..MAX_HISTORY {
+            db.replace("user", format!("new{}", index).as_bytes(), pw)
+                .unwrap();
+        }
+        let history = db.get_history("user", pw).unwrap();
+        assert_eq!(history.len(), MAX_HISTORY);
+        assert_eq!(history[MAX_HISTORY - 1].password.as_slice(), b"password1");
+
+        let serialized = db.serialize_encrypted(pw).unwrap();
+        let db = DatabaseEncrypted::new_deserialize_encrypted(&serialized, pw).unwrap();
+        assert_eq!(db.get_history("user", pw).unwrap(), history);
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let pw = b"test";
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert("user", b"password", pw).unwrap();
+
+        let info = db.get_info("user").unwrap();
+        assert!(info.created() > 0);
+        assert_eq!(info.modified(), info.created());
+        assert_eq!(info.accessed(), 0);
+
+        db.mark_accessed("user").unwrap();
+        db.rename("user", "user2", pw).unwrap();
+        let info = db.get_info("user2").unwrap();
+        assert!(info.accessed() >= info.created());
+        assert!(info.modified() >= info.created());
+        assert!(db.mark_accessed("user").is_err());
+    }
+
+    #[test]
+    fn test_tags() {
+        let pw = b"test";
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert("web", b"password", pw).unwrap();
+        db.insert("work/server", b"password", pw).unwrap();
+        db.insert("work/mail", b"password", pw).unwrap();
+
+        assert!(db.add_tag("web", "Shared", pw).unwrap());
+        assert!(!db.add_tag("web", "shared", pw).unwrap());
+        db.add_tag("work/server", "prod", pw).unwrap();
+        db.add_tag("work/server", "shared", pw).unwrap();
+        db.add_tag("work/mail", "rotate-q3", pw).unwrap();
+        assert!(db.add_tag("web", "two words", pw).is_err());
+        assert!(db.add_tag("web", "", pw).is_err());
+        assert!(db.add_tag("missing", "prod", pw).is_err());
+        assert!(db.add_tag("web", "prod", b"wrong").is_err());
+
+        assert_eq!(
+            db.list_by_tag("shared").unwrap(),
+            vec!["web", "work/server"]
+        );
+        assert_eq!(db.list_by_tag("SHARED").unwrap().len(), 2);
+        let tags = [String::from("shared"), String::from("prod")];
+        assert_eq!(db.list_fuzzy_match("", &tags).unwrap(), vec!["work/server"]);
+        assert_eq!(db.list_fuzzy_match("web", &tags).unwrap().len(), 0);
+        let tags = [String::from("shared")];
+        assert_eq!(
+            db.list_group_fuzzy_match("work", "", &tags).unwrap(),
+            vec!["work/server"]
+        );
+        assert_eq!(db.list_fuzzy_match("", &[]).unwrap().len(), 3);
+
+        let tags = db.tags().unwrap();
+        assert_eq!(tags.get("shared"), Some(&2));
+        assert_eq!(tags.get("rotate-q3"), Some(&1));
+
+        // Editing and renaming keep the tags
+        let mut entry = db.get_entry("web", pw).unwrap();
+        entry.username = String::from("someone");
+        db.replace_entry(&entry, pw).unwrap();
+        db.replace("web", b"password2", pw).unwrap();
+        db.rename("web", "site", pw).unwrap();
+        assert_eq!(
+            db.list_by_tag("shared").unwrap(),
+            vec!["site", "work/server"]
+        );
+
+        assert!(db.remove_tag("site", "Shared", pw).unwrap());
+        assert!(!db.remove_tag("site", "shared", pw).unwrap());
+
+        let serialized = db.serialize_encrypted(pw).unwrap();
+        let db = DatabaseEncrypted::new_deserialize_encrypted(&serialized, pw).unwrap();
+        assert_eq!(db.list_by_tag("shared").unwrap(), vec!["work/server"]);
+        assert_eq!(db.tags().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_search() {
+        use crate::db_encrypted::entry::{CustomField, Entry};
+
+        let pw = b"test";
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        let mut entry = Entry::new("work/github", b"password");
+        entry.username = String::from("Alice");
+        entry.urls = vec![String::from("https://GitHub.com/login")];
+        entry.notes = String::from("The exact phrase is here");
+        entry
+            .fields
+            .push(CustomField::new("team", "platform", false));
+        entry
+            .fields
+            .push(CustomField::new("pin", "secretvalue", true));
+        db.insert_entry(&entry, pw).unwrap();
+        let mut entry = Entry::new("work/gitlab", b"password");
+        entry.username = String::from("bob");
+        entry.urls = vec![String::from("https://gitlab.com")];
+        db.insert_entry(&entry, pw).unwrap();
+        db.insert("home/mail", b"password", pw).unwrap();
+        db.add_tag("work/github", "prod", pw).unwrap();
+        db.add_tag("work/gitlab", "prod", pw).unwrap();
+        db.add_tag("work/gitlab", "old", pw).unwrap();
+
+        let mut search = |query: &str| db.list_fuzzy_match(query, &[]).unwrap();
+        assert_eq!(search("").len(), 3);
+        assert_eq!(search("tag:prod -tag:old"), vec!["work/github"]);
+        assert_eq!(
+            search("tag:prod url:github.com user:alice"),
+            vec!["work/github"]
+        );
+        assert_eq!(search("\"EXACT PHRASE\""), vec!["work/github"]);
+        assert_eq!(search("platform"), vec!["work/github"]);
+        assert_eq!(search("secretvalue").len(), 0);
+        assert_eq!(search("user:bob OR mail"), vec!["home/mail", "work/gitlab"]);
+        assert_eq!(search("NOT (tag:prod)"), vec!["home/mail"]);
+        assert_eq!(search("group:work AND NOT user:alice"), vec!["work/gitlab"]);
+        // Names are still fuzzy matched, best match first
+        assert_eq!(search("gitlb"), vec!["work/gitlab"]);
+        assert_eq!(search("gthub")[0], "work/github");
+
+        // Changes made in place are searchable right away
+        db.remove_tag("work/gitlab", "old", pw).unwrap();
+        assert_eq!(
+            db.list_fuzzy_match("tag:prod -tag:old", &[]).unwrap().len(),
+            2
+        );
+        assert_eq!(
+            db.list_group_fuzzy_match("home", "tag:prod OR mail", &[])
+                .unwrap(),
+            vec!["home/mail"]
+        );
+    }
+
+    #[test]
+    fn test_undo() {
+        use crate::db_base::error::DatabaseError;
+        use crate::db_encrypted::journal::MAX_UNDO;
+
+        let pw = b"test";
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        assert_eq!(db.undo(pw), Err(DatabaseError::NothingToUndo));
+
+        db.insert("work/mail", b"password1", pw).unwrap();
+        db.insert("work/chat", b"password2", pw).unwrap();
+        db.create_group("empty", pw).unwrap();
+        db.replace("work/mail", b"password3", pw).unwrap();
+        db.rename("work/chat", "chat", pw).unwrap();
+        db.remove("chat", pw).unwrap();
+        // Failed changes aren't recorded
+        assert!(db.remove("chat", pw).is_err());
+        assert!(db.undo(b"wrong").is_err());
+
+        assert_eq!(db.undo(pw).unwrap().as_str(), "remove chat");
+        assert_eq!(db.get("chat", pw).unwrap().as_slice(), b"password2");
+        assert_eq!(db.undo(pw).unwrap().as_str(), "rename work/chat to chat");
+        assert_eq!(db.list().unwrap(), vec!["work/chat", "work/mail"]);
+        db.undo(pw).unwrap();
+        assert_eq!(db.get("work/mail", pw).unwrap().as_slice(), b"password1");
+        assert_eq!(db.get_history("work/mail", pw).unwrap().len(), 0);
+
+        assert_eq!(db.redo(pw).unwrap().as_str(), "edit work/mail");
+        assert_eq!(db.get("work/mail", pw).unwrap().as_slice(), b"password3");
+        db.redo(pw).unwrap();
+        db.redo(pw).unwrap();
+        assert_eq!(db.redo(pw), Err(DatabaseError::NothingToRedo));
+        assert_eq!(db.list().unwrap(), vec!["work/mail"]);
+
+        // A recursive removal comes back with its groups
+        db.create_group("work/sub", pw).unwrap();
+        db.add_tag("work/mail", "prod", pw).unwrap();
+        assert_eq!(
+            db.remove_group("work", true, pw).unwrap(),
+            vec!["work/mail"]
+        );
+        assert!(!db.contains_group("work/sub"));
+        db.undo(pw).unwrap();
+        assert!(db.contains_group("work/sub"));
+        assert_eq!(db.list_by_tag("prod").unwrap(), vec!["work/mail"]);
+        db.undo(pw).unwrap();
+        assert_eq!(db.list_by_tag("prod").unwrap().len(), 0);
+
+        db.rename_group("work", "office", pw).unwrap();
+        assert_eq!(db.list().unwrap(), vec!["office/mail"]);
+        db.undo(pw).unwrap();
+        assert_eq!(db.list().unwrap(), vec!["work/mail"]);
+        assert!(db.contains_group("work/sub"));
+        assert!(db.contains_group("empty"));
+
+        // Anything new can't be redone over
+        db.insert("new", b"password", pw).unwrap();
+        assert_eq!(db.redo(pw), Err(DatabaseError::NothingToRedo));
+
+        // Without checking the password every time
+        for index in 0..MAX_UNDO + 5 {
+            let group = format!("many{}", index);
+            db.journaled(group.clone(), Vec::new(), |db| db.create_group(&group))
+                .unwrap();
+        }
+        for _ in 0..MAX_UNDO {
+            db.journal.undo(&mut db.db).unwrap();
+        }
+        assert_eq!(db.undo(pw), Err(DatabaseError::NothingToUndo));
+        assert!(db.contains_group("many4"));
+        assert!(!db.contains_group("many5"));
+    }
+
+    #[test]
+    fn test_transaction() {
+        use crate::db_base::error::DatabaseError;
+
+        let pw = b"test";
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert("mail", b"password1", pw).unwrap();
+        assert_eq!(db.commit("nothing"), Err(DatabaseError::NoTransaction));
+
+        db.begin();
+        db.insert("chat", b"password2", pw).unwrap();
+        db.replace("mail", b"password3", pw).unwrap();
+        db.create_group("work", pw).unwrap();
+        assert_eq!(db.undo(pw), Err(DatabaseError::TransactionInProgress));
+        assert!(db.serialize_encrypted(pw).is_err());
+        db.rollback().unwrap();
+        assert_eq!(db.list().unwrap(), vec!["mail"]);
+        assert_eq!(db.get("mail", pw).unwrap().as_slice(), b"password1");
+        assert!(!db.contains_group("work"));
+
+        // Nested transactions are undone with the outermost
+        db.begin();
+        db.insert("chat", b"password2", pw).unwrap();
+        db.begin();
+        db.rename("mail", "work/mail", pw).unwrap();
+        db.commit("move").unwrap();
+        db.begin();
+        db.remove("chat", pw).unwrap();
+        db.rollback().unwrap();
+        db.commit("chat and move").unwrap();
+        assert_eq!(db.list().unwrap(), vec!["chat", "work/mail"]);
+        assert_eq!(db.undo(pw).unwrap().as_str(), "chat and move");
+        assert_eq!(db.list().unwrap(), vec!["mail"]);
+        assert_eq!(db.undo(pw).unwrap().as_str(), "insert mail");
+
+        let result: Result<(), DatabaseError> = db.transaction("failed", |db| {
+            db.insert("mail", b"password1", pw)?;
+            db.insert("mail", b"password2", pw)
+        });
+        assert_eq!(result, Err(DatabaseError::AlreadyExists));
+        assert_eq!(db.list().unwrap().len(), 0);
+        assert_eq!(db.undo(pw), Err(DatabaseError::NothingToUndo));
+    }
+
+    #[test]
+    fn test_csv_all_or_nothing() {
+        use crate::db_base::error::DatabaseError;
+
+        let pw = b"test";
+        let file = "db_encrypted_csv_test00000003.csv";
+        std::fs::write(
+            file,
+            "Title,Password\nmail,password1\nchat,password2\nbank,password3\nchat,password4\n,password5\n",
+        )
+        .unwrap();
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert("bank", b"password", pw).unwrap();
+
+        let report = db.insert_from_csv_dry_run(file, pw).unwrap();
+        assert_eq!(report.added, vec!["mail", "chat"]);
+        assert_eq!(
+            report.conflicts,
+            vec![
+                (String::from("bank"), DatabaseError::AlreadyExists),
+                (String::from("chat"), DatabaseError::AlreadyExists),
+            ]
+        );
+        assert_eq!(report.skipped, 1);
+
+        let result = db.insert_from_csv(file, pw);
+        assert_eq!(
+            result,
+            Err(DatabaseError::ImportError(String::from("bank, chat")))
+        );
+        assert_eq!(db.list().unwrap(), vec!["bank"]);
+
+        db.remove("bank", pw).unwrap();
+        std::fs::write(
+            file,
+            "Title,Password\nmail,password1\nwork/chat,password2\n",
+        )
+        .unwrap();
+        assert_eq!(
+            db.insert_from_csv_dry_run(file, pw)
+                .unwrap()
+                .conflicts
+                .len(),
+            0
+        );
+        db.insert_from_csv(file, pw).unwrap();
+        std::fs::remove_file(file).unwrap();
+        assert_eq!(db.list().unwrap(), vec!["mail", "work/chat"]);
+        assert_eq!(db.undo(pw).unwrap().as_str(), format!("import {}", file));
+        assert_eq!(db.list().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_csv_fields() {
+        use crate::db_encrypted::entry::{CustomField, Entry};
+
+        let pw = b"test";
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        let mut entry = Entry::new("mail", b"pass,word");
+        entry.username = String::from("someone");
+        entry.urls = vec![
+            String::from("https://a.example"),
+            String::from("https://b.example"),
+        ];
+        entry.fields.push(CustomField::new("pin", "1234", true));
+        db.insert_entry(&entry, pw).unwrap();
+        let mut other = Entry::new("other", b"password");
+        other
+            .fields
+            .push(CustomField::new("question", "blue", false));
+        db.insert_entry(&other, pw).unwrap();
+
+        db.export_to_csv("db_encrypted_csv_test00000002.csv", pw)
+            .unwrap();
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert_from_csv("db_encrypted_csv_test00000002.csv", pw)
+            .unwrap();
+        std::fs::remove_file("db_encrypted_csv_test00000002.csv").unwrap();
+
+        assert_eq!(db.get_entry("mail", pw).unwrap(), entry);
+        assert_eq!(db.get_entry("other", pw).unwrap(), other);
+    }
+
+    #[test]
+    fn test_kdbx() {
+        use crate::db_base::error::DatabaseError;
+
+        let pw = b"12";
+        let file = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/kdbx/aes-argon2d.kdbx");
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert("Mail", b"password", pw).unwrap();
+
+        let report = db
+            .insert_from_kdbx_dry_run(file, b"test", None, pw)
+            .unwrap();
+        assert_eq!(
+            report.added,
+            vec!["Mail (2)", "Work/Server", "Work/Untitled"]
+        );
+        assert_eq!(
+            report.conflicts,
+            vec![(String::from("Mail"), DatabaseError::AlreadyExists)]
+        );
+        assert_eq!(report.skipped, 1);
+        assert!(db.insert_from_kdbx(file, b"test", None, pw).is_err());
+        assert!(db.insert_from_kdbx(file, b"wrong", None, pw).is_err());
+        assert!(db.insert_from_kdbx(file, b"test", None, b"wrong").is_err());
+        assert_eq!(db.list().unwrap(), vec!["Mail"]);
+
+        db.remove("Mail", pw).unwrap();
+        db.insert_from_kdbx(file, b"test", None, pw).unwrap();
+        assert_eq!(
+            db.list().unwrap(),
+            vec!["Mail", "Mail (2)", "Work/Server", "Work/Untitled"]
+        );
+        assert!(db.contains_group("Work/Empty"));
+
+        let mail = db.get_info("Mail").unwrap();
+        assert_eq!(mail.created(), 1600000000);
+        assert_eq!(mail.modified(), 1700000000);
+        let tags: Vec<&str> = mail.tags().iter().map(|tag| tag.as_str()).collect();
+        assert_eq!(tags, vec!["important", "personal"]);
+        assert_eq!(mail.history_times(), vec![1700000000, 1650000000]);
+        let history = db.get_history("Mail", pw).unwrap();
+        assert_eq!(history[0].password.as_slice(), b"second-Pass2");
+        assert_eq!(
+            db.get_attachment("Mail", "codes.txt", pw)
+                .unwrap()
+                .as_slice(),
+            b"1111-2222\n3333-4444\n"
+        );
+        let entry = db.get_entry("Mail", pw).unwrap();
+        assert_eq!(entry.password.as_slice(), b"current-Pass3");
+        assert_eq!(
+            entry.field("otp").unwrap().as_str(),
+            "otpauth://totp/Mail?secret=JBSWY3DPEHPK3PXP"
+        );
+
+        assert_eq!(db.undo(pw).unwrap().as_str(), format!("import {}", file));
+        assert_eq!(db.list().unwrap().len(), 0);
+        assert!(!db.contains_group("Work/Empty"));
+    }
+
+    #[test]
+    fn test_bitwarden() {
+        use crate::db_base::error::DatabaseError;
+
+        let pw = b"12";
+        let file = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/bitwarden/pbkdf2.json");
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert("Visa", b"password", pw).unwrap();
+
+        let report = db
+            .insert_from_bitwarden_dry_run(file, Some(b"test"), pw)
+            .unwrap();
+        assert_eq!(report.added.len(), 6);
+        assert_eq!(
+            report.conflicts,
+            vec![(String::from("Visa"), DatabaseError::AlreadyExists)]
+        );
+        assert_eq!(report.skipped, 2);
+        assert_eq!(report.unmapped.len(), 4);
+        assert!(db.insert_from_bitwarden(file, Some(b"test"), pw).is_err());
+        assert!(db.insert_from_bitwarden(file, None, pw).is_err());
+        assert!(db
+            .insert_from_bitwarden(file, Some(b"test"), b"wrong")
+            .is_err());
+        assert_eq!(db.list().unwrap(), vec!["Visa"]);
+
+        db.remove("Visa", pw).unwrap();
+        let report = db.insert_from_bitwarden(file, Some(b"test"), pw).unwrap();
+        assert_eq!(report.added.len(), 7);
+        assert_eq!(report.unmapped[0], "Personal/Mail: passkey");
+        assert!(db.contains_group("Empty"));
+
+        let mail = db.get_info("Personal/Mail").unwrap();
+        assert_eq!(mail.created(), 1672567200);
+        assert_eq!(mail.modified(), 1709287200);
+        let tags: Vec<&str> = mail.tags().iter().map(|tag| tag.as_str()).collect();
+        assert_eq!(tags, vec!["favorite"]);
+        assert_eq!(mail.history_times(), vec![1706781600, 1685613600]);
+        let entry = db.get_entry("Personal/Mail", pw).unwrap();
+        assert_eq!(entry.password.as_slice(), b"current-Pass3");
+        assert_eq!(
+            entry.field("otp").unwrap().as_str(),
+            "otpauth://totp/Mail?secret=JBSWY3DPEHPK3PXP"
+        );
+
+        assert_eq!(db.undo(pw).unwrap().as_str(), format!("import {}", file));
+        assert_eq!(db.list().unwrap().len(), 0);
+        assert!(!db.contains_group("Empty"));
+    }
 
     #[cfg(feature = "use-compression")]
     #[test]
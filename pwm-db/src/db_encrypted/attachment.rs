@@ -0,0 +1,315 @@
+#[cfg(feature = "use-compression")]
+use lz4_flex::{compress_prepend_size, decompress_size_prepended};
+use pwm_lib::{
+    encryption::{
+        default::{decrypt, encrypt},
+        EncryptionResult,
+    },
+    hash::{randomize_slice, HashResult},
+    zeroize::{Zeroize, Zeroizing},
+};
+
+use crate::db_base::error::DatabaseError;
+use crate::db_encrypted::entry::KeyCache;
+use crate::db_encrypted::tlv::{
+    read_encrypted, read_i64, read_string, Reader, UnknownFields, Writer,
+};
+use crate::db_encrypted::DatabaseEncrypted;
+use crate::timestamp::now;
+
+// Largest file that can be attached unless configured
+pub static DEFAULT_MAX_ATTACHMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+// The last component of an attachment's name. Attachments are stored and
+// extracted under it, so a name from another program can't point outside a
+// directory. None for names without one, like "" or "..".
+pub fn attachment_file_name(name: &str) -> Option<&str> {
+    match name.rsplit(['/', '\\', ':']).next() {
+        Some("" | "." | "..") | None => None,
+        Some(name) => Some(name),
+    }
+}
+
+// A file stored with an entry. The contents are encrypted with a random key
+// of their own, which is encrypted with the master password like a secret
+// field, so reading the entry's password never touches the file.
+#[derive(Debug, Clone)]
+pub struct EncryptedAttachment {
+    pub name: String,
+    // Bytes before compression
+    pub size: u64,
+    pub added: i64,
+    key: EncryptionResult,
+    data: EncryptionResult,
+    // lz4 with the size prepended, only with the use-compression feature
+    compressed: bool,
+    unknown: UnknownFields,
+}
+
+impl EncryptedAttachment {
+    pub(crate) fn encrypt(
+        name: &str,
+        contents: &[u8],
+        key: &HashResult,
+    ) -> Result<Self, DatabaseError> {
+        let name = match attachment_file_name(name) {
+            Some(name) => name,
+            None => {
+                return Err(DatabaseError::InputError(String::from(
+                    "An attachment needs a name",
+                )))
+            }
+        };
+
+        let mut salt = [0u8; 32];
+        let mut subkey = Zeroizing::new([0u8; 32]);
+        randomize_slice(&mut salt);
+        randomize_slice(subkey.as_mut_slice());
+        let attachment_key = HashResult::new_with_salt_and_hash(&salt, subkey.as_slice())?;
+
+        #[cfg(feature = "use-compression")]
+        let (data, compressed) = (
+            encrypt(
+                &Zeroizing::new(compress_prepend_size(contents)),
+                &attachment_key,
+            )?,
+            true,
+        );
+        #[cfg(not(feature = "use-compression"))]
+        let (data, compressed) = (encrypt(contents, &attachment_key)?, false);
+
+        Ok(Self {
+            name: name.to_string(),
+            size: contents.len() as u64,
+            added: now(),
+            key: encrypt(subkey.as_slice(), key)?,
+            data,
+            compressed,
+            unknown: UnknownFields::default(),
+        })
+    }
+
+    pub(crate) fn decrypt(&self, keys: &mut KeyCache) -> Result<Zeroizing<Vec<u8>>, DatabaseError> {
+        let subkey = keys.decrypt(&self.key)?;
+        let attachment_key =
+            HashResult::new_with_salt_and_hash(self.data.get_salt_slice(), &subkey)?;
+        let plaintext = decrypt(&self.data, &attachment_key)?;
+        if !self.compressed {
+            return Ok(Zeroizing::new(plaintext.as_slice().to_vec()));
+        }
+
+        #[cfg(feature = "use-compression")]
+        let contents = match decompress_size_prepended(plaintext.as_slice()) {
+            Ok(contents) => Ok(Zeroizing::new(contents)),
+            Err(error) => Err(DatabaseError::CompressionError(error.to_string())),
+        };
+        #[cfg(not(feature = "use-compression"))]
+        let contents = Err(DatabaseError::CompressionError(String::from(
+            "The attachment is compressed, pwm was built without compression",
+        )));
+
+        contents
+    }
+
+    pub(crate) fn write_tlv(&self, writer: &mut Writer) {
+        writer.string(TAG_ATTACHMENT_NAME, &self.name);
+        writer.i64(TAG_ATTACHMENT_SIZE, self.size as i64);
+        writer.i64(TAG_ATTACHMENT_ADDED, self.added);
+        writer.encrypted(TAG_ATTACHMENT_KEY, &self.key);
+        writer.encrypted(TAG_ATTACHMENT_DATA, &self.data);
+        if self.compressed {
+            writer.bytes(TAG_ATTACHMENT_COMPRESSED, &[]);
+        }
+        self.unknown.write(writer);
+    }
+
+    pub(crate) fn read_tlv(value: &[u8]) -> Result<Self, DatabaseError> {
+        let mut name = None;
+        let mut size = 0;
+        let mut added = 0;
+        let mut key = None;
+        let mut data = None;
+        let mut compressed = false;
+        let mut unknown = UnknownFields::default();
+        let mut reader = Reader::new(value);
+        while let Some((tag, value)) = reader.next()? {
+            match tag {
+                TAG_ATTACHMENT_NAME => name = Some(read_string(value)?),
+                TAG_ATTACHMENT_SIZE => size = read_i64(value)? as u64,
+                TAG_ATTACHMENT_ADDED => added = read_i64(value)?,
+                TAG_ATTACHMENT_KEY => key = Some(read_encrypted(value)?),
+                TAG_ATTACHMENT_DATA => data = Some(read_encrypted(value)?),
+                TAG_ATTACHMENT_COMPRESSED => compressed = true,
+                _ => unknown.push(tag, value),
+            }
+        }
+
+        match (name, key, data) {
+            (Some(name), Some(key), Some(data)) => Ok(Self {
+                name,
+                size,
+                added,
+                key,
+                data,
+                compressed,
+                unknown,
+            }),
+            _ => Err(DatabaseError::FailedDeserialize),
+        }
+    }
+}
+
+impl Zeroize for EncryptedAttachment {
+    fn zeroize(&mut self) {
+        self.name.zeroize();
+    }
+}
+
+// Tags of the attachment message, see FORMAT.md
+const TAG_ATTACHMENT_NAME: u32 = 1;
+const TAG_ATTACHMENT_SIZE: u32 = 2;
+const TAG_ATTACHMENT_ADDED: u32 = 3;
+const TAG_ATTACHMENT_KEY: u32 = 4;
+const TAG_ATTACHMENT_DATA: u32 = 5;
+const TAG_ATTACHMENT_COMPRESSED: u32 = 6;
+
+impl DatabaseEncrypted {
+    // Attaches a file of at most max_size bytes to the entry, AlreadyExists
+    // if it has an attachment with the name
+    pub fn add_attachment(
+        &mut self,
+        name: &str,
+        attachment: &str,
+        contents: &[u8],
+        max_size: u64,
+        password: &[u8],
+    ) -> Result<(), DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        if contents.len() as u64 > max_size {
+            return Err(DatabaseError::InputError(format!(
+                "{} is {} bytes, attachments can be at most {} bytes",
+                attachment,
+                contents.len(),
+                max_size
+            )));
+        }
+        let stored = attachment_file_name(attachment).unwrap_or(attachment);
+        if self.db.get(name)?.attachment(stored).is_some() {
+            return Err(DatabaseError::AlreadyExists);
+        }
+
+        let hash = Self::hash_password_argon2(password)?;
+        let encrypted = EncryptedAttachment::encrypt(attachment, contents, &hash)?;
+
+        let description = format!("attach {} to {}", attachment, name);
+        self.journaled(description, vec![name.to_string()], |db| {
+            db.get_mut(name)?.add_attachment(encrypted);
+            Ok(())
+        })
+    }
+
+    pub fn get_attachment(
+        &self,
+        name: &str,
+        attachment: &str,
+        password: &[u8],
+    ) -> Result<Zeroizing<Vec<u8>>, DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        match self.db.get(name)?.attachment(attachment) {
+            Some(attachment) => attachment.decrypt(&mut KeyCache::new(password)),
+            None => Err(DatabaseError::NotFound),
+        }
+    }
+
+    pub fn remove_attachment(
+        &mut self,
+        name: &str,
+        attachment: &str,
+        password: &[u8],
+    ) -> Result<(), DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        if self.db.get(name)?.attachment(attachment).is_none() {
+            return Err(DatabaseError::NotFound);
+        }
+
+        let description = format!("detach {} from {}", attachment, name);
+        self.journaled(description, vec![name.to_string()], |db| {
+            db.get_mut(name)?.remove_attachment(attachment)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::db_base::error::DatabaseError;
+    use crate::db_encrypted::{db_interface::DatabaseInterface, DatabaseEncrypted};
+
+    #[test]
+    fn test_attachments() {
+        let pw = b"test";
+        let contents = b"recovery codes ".repeat(100);
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert("mail", b"password1", pw).unwrap();
+        db.add_attachment("mail", "codes.txt", &contents, 4096, pw)
+            .unwrap();
+        db.add_attachment("mail", "empty", b"", 4096, pw).unwrap();
+        assert_eq!(
+            db.add_attachment("mail", "codes.txt", b"again", 4096, pw)
+                .unwrap_err(),
+            DatabaseError::AlreadyExists
+        );
+        assert!(db.add_attachment("mail", "big", &contents, 10, pw).is_err());
+        assert!(db.add_attachment("missing", "a", b"a", 10, pw).is_err());
+        assert!(db.add_attachment("mail", "a", b"a", 10, b"wrong").is_err());
+        for name in ["", "..", "keys/"] {
+            assert!(db.add_attachment("mail", name, b"a", 10, pw).is_err());
+        }
+        assert_eq!(
+            db.add_attachment("mail", "../codes.txt", b"a", 10, pw)
+                .unwrap_err(),
+            DatabaseError::AlreadyExists
+        );
+
+        let info = db.get_info("mail").unwrap();
+        let names: Vec<&str> = info.attachments().iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, vec!["codes.txt", "empty"]);
+        assert_eq!(info.attachments()[0].size, contents.len() as u64);
+
+        // Only the last component of a path is kept
+        db.add_attachment("mail", "../../.ssh\\id_ed25519", b"key", 10, pw)
+            .unwrap();
+        assert!(db.get_attachment("mail", "id_ed25519", pw).is_ok());
+        db.remove_attachment("mail", "id_ed25519", pw).unwrap();
+
+        // Saved with the vault and kept when the entry is edited
+        let serialized = db.serialize_encrypted(pw).unwrap();
+        let mut db = DatabaseEncrypted::new_deserialize_encrypted(&serialized, pw).unwrap();
+        let mut entry = db.get_entry("mail", pw).unwrap();
+        entry.username = String::from("someone");
+        db.replace_entry(&entry, pw).unwrap();
+        assert_eq!(
+            *db.get_attachment("mail", "codes.txt", pw).unwrap(),
+            contents
+        );
+        assert!(db.get_attachment("mail", "empty", pw).unwrap().is_empty());
+        assert!(db.get_attachment("mail", "codes.txt", b"wrong").is_err());
+
+        db.remove_attachment("mail", "codes.txt", pw).unwrap();
+        assert_eq!(
+            db.get_attachment("mail", "codes.txt", pw).unwrap_err(),
+            DatabaseError::NotFound
+        );
+        assert!(db.remove_attachment("mail", "codes.txt", pw).is_err());
+        db.undo(pw).unwrap();
+        assert_eq!(db.get_info("mail").unwrap().attachments().len(), 2);
+    }
+}
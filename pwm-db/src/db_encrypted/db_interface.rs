@@ -3,25 +3,102 @@ use log::{trace, warn};
 use lz4_flex::compress_prepend_size;
 
 use pwm_lib::{
+    atomic_file::write_atomic_with,
     encryption::{
         default::{decrypt, encrypt},
         EncryptionResult,
     },
+    progress::Progress,
     zeroize::Zeroizing,
 };
 
 use crate::db_base::error::DatabaseError;
 
-use super::DatabaseEncrypted;
+use super::{
+    bitwarden::read_bitwarden,
+    entry::{
+        normalize_tag, CustomField, EncryptedEntry, Entry, FieldValue, ImportedEntry, KeyCache,
+        PasswordHistory, BUILTIN_FIELDS, FIELD_NOTES, FIELD_PASSWORD, FIELD_TITLE, FIELD_URL,
+        FIELD_USERNAME,
+    },
+    import::ImportContents,
+    kdbx::read_kdbx,
+    parallel::parallel_map,
+    DatabaseEncrypted,
+};
 
 pub trait DatabaseInterface {
     fn new_deserialize_encrypted(
         serialized: &EncryptionResult,
         password: &[u8],
     ) -> Result<DatabaseEncrypted, DatabaseError>;
+    fn new_deserialize_encrypted_with_progress(
+        serialized: &EncryptionResult,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<DatabaseEncrypted, DatabaseError>;
     fn insert(&mut self, name: &str, data: &[u8], password: &[u8]) -> Result<(), DatabaseError>;
+    fn insert_entry(&mut self, entry: &Entry, password: &[u8]) -> Result<(), DatabaseError>;
     fn insert_from_csv(&mut self, file: &str, password: &[u8]) -> Result<(), DatabaseError>;
+    fn insert_from_csv_dry_run(
+        &self,
+        file: &str,
+        password: &[u8],
+    ) -> Result<ImportReport, DatabaseError>;
+    fn insert_from_csv_with_progress(
+        &mut self,
+        file: &str,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<(), DatabaseError>;
+    fn insert_from_kdbx(
+        &mut self,
+        file: &str,
+        kdbx_password: &[u8],
+        key_file: Option<&str>,
+        password: &[u8],
+    ) -> Result<(), DatabaseError>;
+    fn insert_from_kdbx_dry_run(
+        &self,
+        file: &str,
+        kdbx_password: &[u8],
+        key_file: Option<&str>,
+        password: &[u8],
+    ) -> Result<ImportReport, DatabaseError>;
+    fn insert_from_kdbx_with_progress(
+        &mut self,
+        file: &str,
+        kdbx_password: &[u8],
+        key_file: Option<&str>,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<(), DatabaseError>;
+    fn insert_from_bitwarden(
+        &mut self,
+        file: &str,
+        export_password: Option<&[u8]>,
+        password: &[u8],
+    ) -> Result<ImportReport, DatabaseError>;
+    fn insert_from_bitwarden_dry_run(
+        &self,
+        file: &str,
+        export_password: Option<&[u8]>,
+        password: &[u8],
+    ) -> Result<ImportReport, DatabaseError>;
+    fn insert_from_bitwarden_with_progress(
+        &mut self,
+        file: &str,
+        export_password: Option<&[u8]>,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<ImportReport, DatabaseError>;
     fn export_to_csv(&mut self, file: &str, password: &[u8]) -> Result<(), DatabaseError>;
+    fn export_to_csv_with_progress(
+        &mut self,
+        file: &str,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<(), DatabaseError>;
     fn remove(&mut self, name: &str, password: &[u8]) -> Result<(), DatabaseError>;
     fn replace(
         &mut self,
@@ -29,9 +106,61 @@ pub trait DatabaseInterface {
         new_data: &[u8],
         password: &[u8],
     ) -> Result<(), DatabaseError>;
+    fn replace_entry(&mut self, entry: &Entry, password: &[u8]) -> Result<(), DatabaseError>;
     fn rename(&mut self, name: &str, new_name: &str, password: &[u8]) -> Result<(), DatabaseError>;
+    fn create_group(&mut self, group: &str, password: &[u8]) -> Result<(), DatabaseError>;
+    fn rename_group(
+        &mut self,
+        group: &str,
+        new_group: &str,
+        password: &[u8],
+    ) -> Result<(), DatabaseError>;
+    fn remove_group(
+        &mut self,
+        group: &str,
+        recursive: bool,
+        password: &[u8],
+    ) -> Result<Vec<String>, DatabaseError>;
+    fn add_tag(&mut self, name: &str, tag: &str, password: &[u8]) -> Result<bool, DatabaseError>;
+    fn remove_tag(&mut self, name: &str, tag: &str, password: &[u8])
+        -> Result<bool, DatabaseError>;
     fn get(&self, name: &str, password: &[u8]) -> Result<EncryptionResult, DatabaseError>;
+    fn get_entry(&self, name: &str, password: &[u8]) -> Result<Entry, DatabaseError>;
+    fn get_history(
+        &self,
+        name: &str,
+        password: &[u8],
+    ) -> Result<Vec<PasswordHistory>, DatabaseError>;
+    fn restore_history(
+        &mut self,
+        name: &str,
+        index: usize,
+        password: &[u8],
+    ) -> Result<(), DatabaseError>;
+    fn undo(&mut self, password: &[u8]) -> Result<Zeroizing<String>, DatabaseError>;
+    fn redo(&mut self, password: &[u8]) -> Result<Zeroizing<String>, DatabaseError>;
+    fn get_field(
+        &self,
+        name: &str,
+        field: &str,
+        password: &[u8],
+    ) -> Result<Zeroizing<String>, DatabaseError>;
+    fn get_all_with_progress(
+        &self,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<Vec<(String, EncryptionResult)>, DatabaseError>;
+    fn get_all_entries_with_progress(
+        &self,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<Vec<Entry>, DatabaseError>;
     fn serialize_encrypted(&self, password: &[u8]) -> Result<EncryptionResult, DatabaseError>;
+    fn serialize_encrypted_with_progress(
+        &self,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<EncryptionResult, DatabaseError>;
 }
 
 impl DatabaseInterface for DatabaseEncrypted {
@@ -39,7 +168,16 @@ impl DatabaseInterface for DatabaseEncrypted {
         serialized: &EncryptionResult,
         password: &[u8],
     ) -> Result<DatabaseEncrypted, DatabaseError> {
-        let (db, _hash) = Self::new_deserialize_encrypted_internal(serialized, password)?;
+        Self::new_deserialize_encrypted_with_progress(serialized, password, &Progress::new())
+    }
+
+    fn new_deserialize_encrypted_with_progress(
+        serialized: &EncryptionResult,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<DatabaseEncrypted, DatabaseError> {
+        let (db, _hash) =
+            Self::new_deserialize_encrypted_internal(serialized, password, progress)?;
         Ok(db)
     }
 
@@ -51,88 +189,184 @@ impl DatabaseInterface for DatabaseEncrypted {
         let hash = Self::hash_password_argon2(password)?;
         let data = encrypt(data, &hash)?;
 
-        self.db.insert(name, data)?;
+        self.journaled(format!("insert {}", name), vec![name.to_string()], |db| {
+            db.insert(name, EncryptedEntry::new_with_password(data))
+        })
+    }
 
-        Ok(())
+    fn insert_entry(&mut self, entry: &Entry, password: &[u8]) -> Result<(), DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        let hash = Self::hash_password_argon2(password)?;
+        let encrypted = EncryptedEntry::encrypt(entry, &hash)?;
+
+        let name = entry.title.clone();
+        self.journaled(format!("insert {}", name), vec![name], |db| {
+            db.insert(&entry.title, encrypted)
+        })
     }
 
     fn insert_from_csv(&mut self, file: &str, password: &[u8]) -> Result<(), DatabaseError> {
+        self.insert_from_csv_with_progress(file, password, &Progress::new())
+    }
+
+    fn insert_from_csv_dry_run(
+        &self,
+        file: &str,
+        password: &[u8],
+    ) -> Result<ImportReport, DatabaseError> {
         if !self.hash_password_and_compare(password) {
             return Err(DatabaseError::InvalidPassword);
         }
 
-        let mut failed_records = Vec::new();
-        let mut rdr = csv::Reader::from_path(file)?;
-        for record in rdr.records() {
-            match record {
-                Ok(record) => {
-                    if let (Some(key), Some(data)) = (record.get(0), record.get(1)) {
-                        let hash = Self::hash_password_argon2(password)?;
-                        let data = encrypt(data.as_bytes(), &hash)?;
+        Ok(self.plan_import(&read_csv(file)?))
+    }
 
-                        match self.db.insert(key, data) {
-                            Ok(()) => (),
-                            Err(error) => {
-                                failed_records.push(String::from(key));
-                                warn!("pwm-db: Failed to import: {}", error.to_string());
-                            }
-                        };
-                    }
-                    trace!(
-                        "pwm-db: attempted to import record: {:?}, {:?}",
-                        record.get(0),
-                        record.get(1)
-                    );
-                }
-                Err(_) => {}
-            };
+    fn insert_from_csv_with_progress(
+        &mut self,
+        file: &str,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<(), DatabaseError> {
+        if !self.hash_password_and_compare_cancellable(password, progress)? {
+            return Err(DatabaseError::InvalidPassword);
         }
 
-        if failed_records.len() == 0 {
-            Ok(())
-        } else {
-            let mut error_msg = String::new();
-            for (index, record) in failed_records.iter().enumerate() {
-                if index != failed_records.len() - 1 {
-                    let record = record.clone();
-                    error_msg.push_str((record + ", ").as_str());
-                } else {
-                    error_msg.push_str(record.as_str());
-                }
-            }
-            Err(DatabaseError::ImportError(error_msg))
-        }
+        self.import(file, &read_csv(file)?, password, progress)?;
+        Ok(())
     }
 
-    fn export_to_csv(&mut self, file: &str, password: &[u8]) -> Result<(), DatabaseError> {
+    fn insert_from_kdbx(
+        &mut self,
+        file: &str,
+        kdbx_password: &[u8],
+        key_file: Option<&str>,
+        password: &[u8],
+    ) -> Result<(), DatabaseError> {
+        self.insert_from_kdbx_with_progress(
+            file,
+            kdbx_password,
+            key_file,
+            password,
+            &Progress::new(),
+        )
+    }
+
+    fn insert_from_kdbx_dry_run(
+        &self,
+        file: &str,
+        kdbx_password: &[u8],
+        key_file: Option<&str>,
+        password: &[u8],
+    ) -> Result<ImportReport, DatabaseError> {
         if !self.hash_password_and_compare(password) {
             return Err(DatabaseError::InvalidPassword);
         }
-        let mut writer = csv::Writer::from_path(file)?;
 
-        writer.write_record([b"Username", b"Password"])?;
-        for name in self.db.list()? {
-            let ciphertext = self.db.get(name.as_str())?;
-            let hash = Self::hash_password_argon2_with_salt(password, ciphertext.get_salt_slice())?;
-
-            let result = decrypt(ciphertext, &hash)?;
+        Ok(self.plan_import(&read_kdbx(file, kdbx_password, key_file)?))
+    }
 
-            writer.write_record([name.as_bytes(), result.as_slice()])?;
+    // Like a csv import, groups without entries are created too and entries
+    // keep their history, tags, attachments and times. The recycle bin isn't
+    // imported.
+    fn insert_from_kdbx_with_progress(
+        &mut self,
+        file: &str,
+        kdbx_password: &[u8],
+        key_file: Option<&str>,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<(), DatabaseError> {
+        if !self.hash_password_and_compare_cancellable(password, progress)? {
+            return Err(DatabaseError::InvalidPassword);
         }
 
+        let contents = read_kdbx(file, kdbx_password, key_file)?;
+        self.import(file, &contents, password, progress)?;
         Ok(())
     }
 
-    fn remove(&mut self, name: &str, password: &[u8]) -> Result<(), DatabaseError> {
+    fn insert_from_bitwarden(
+        &mut self,
+        file: &str,
+        export_password: Option<&[u8]>,
+        password: &[u8],
+    ) -> Result<ImportReport, DatabaseError> {
+        self.insert_from_bitwarden_with_progress(file, export_password, password, &Progress::new())
+    }
+
+    fn insert_from_bitwarden_dry_run(
+        &self,
+        file: &str,
+        export_password: Option<&[u8]>,
+        password: &[u8],
+    ) -> Result<ImportReport, DatabaseError> {
         if !self.hash_password_and_compare(password) {
             return Err(DatabaseError::InvalidPassword);
         }
 
-        self.db.remove(name)?;
+        Ok(self.plan_import(&read_bitwarden(file, export_password)?))
+    }
+
+    // The export password is only needed for password protected exports.
+    // The report lists what the entries leave out, like passkeys.
+    fn insert_from_bitwarden_with_progress(
+        &mut self,
+        file: &str,
+        export_password: Option<&[u8]>,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<ImportReport, DatabaseError> {
+        if !self.hash_password_and_compare_cancellable(password, progress)? {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        let contents = read_bitwarden(file, export_password)?;
+        self.import(file, &contents, password, progress)
+    }
+
+    fn export_to_csv(&mut self, file: &str, password: &[u8]) -> Result<(), DatabaseError> {
+        self.export_to_csv_with_progress(file, password, &Progress::new())
+    }
+
+    // One step of progress per record, everything is decrypted in parallel
+    // before the file is created so a failed export never leaves a partial file
+    fn export_to_csv_with_progress(
+        &mut self,
+        file: &str,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<(), DatabaseError> {
+        let entries = self.get_all_entries_with_progress(password, progress)?;
+        let columns = CsvColumns::for_entries(&entries);
+
+        // No backups of an older export, it is plaintext
+        write_atomic_with(file, 0, |file| {
+            let mut writer = csv::Writer::from_writer(file);
+
+            writer.write_record(columns.headers())?;
+            for entry in entries.iter() {
+                writer.write_record(columns.record(entry).iter().map(|value| value.as_slice()))?;
+            }
+
+            writer.flush()
+        })?;
 
         Ok(())
     }
 
+    fn remove(&mut self, name: &str, password: &[u8]) -> Result<(), DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        self.journaled(format!("remove {}", name), vec![name.to_string()], |db| {
+            db.remove(name)
+        })
+    }
+
     fn replace(
         &mut self,
         name: &str,
@@ -146,9 +380,28 @@ impl DatabaseInterface for DatabaseEncrypted {
         let hash = Self::hash_password_argon2(password)?;
         let data = encrypt(new_data, &hash)?;
 
-        self.db.replace(name, data)?;
+        let mut entry = self.db.get(name)?.clone();
+        entry.set_password(data);
+        self.journaled(format!("edit {}", name), vec![name.to_string()], |db| {
+            db.replace(name, entry)
+        })
+    }
 
-        Ok(())
+    fn replace_entry(&mut self, entry: &Entry, password: &[u8]) -> Result<(), DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        let hash = Self::hash_password_argon2(password)?;
+        let encrypted = self
+            .db
+            .get(&entry.title)?
+            .encrypt_update(entry, &hash, password)?;
+
+        let name = entry.title.clone();
+        self.journaled(format!("edit {}", name), vec![name], |db| {
+            db.replace(&entry.title, encrypted)
+        })
     }
 
     fn rename(&mut self, name: &str, new_name: &str, password: &[u8]) -> Result<(), DatabaseError> {
@@ -156,9 +409,94 @@ impl DatabaseInterface for DatabaseEncrypted {
             return Err(DatabaseError::InvalidPassword);
         }
 
-        self.db.rename(name, new_name)?;
+        let names = vec![name.to_string(), new_name.to_string()];
+        self.journaled(format!("rename {} to {}", name, new_name), names, |db| {
+            db.rename(name, new_name)?;
+            db.get_mut(new_name)?.set_modified();
+            Ok(())
+        })
+    }
 
-        Ok(())
+    fn create_group(&mut self, group: &str, password: &[u8]) -> Result<(), DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        self.journaled(format!("create group {}", group), Vec::new(), |db| {
+            db.create_group(group)
+        })
+    }
+
+    // Also moves the group, with everything in it
+    fn rename_group(
+        &mut self,
+        group: &str,
+        new_group: &str,
+        password: &[u8],
+    ) -> Result<(), DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        let names = self.group_entries(group, Some(new_group));
+        let description = format!("rename group {} to {}", group, new_group);
+        self.journaled(description, names, |db| db.rename_group(group, new_group))
+    }
+
+    // Fails with NotEmpty unless recursive, returns the removed entries
+    fn remove_group(
+        &mut self,
+        group: &str,
+        recursive: bool,
+        password: &[u8],
+    ) -> Result<Vec<String>, DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        let names = self.group_entries(group, None);
+        self.journaled(format!("remove group {}", group), names, |db| {
+            db.remove_group(group, recursive)
+        })
+    }
+
+    // False if the entry already had the tag
+    fn add_tag(&mut self, name: &str, tag: &str, password: &[u8]) -> Result<bool, DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        let tag = normalize_tag(tag)?;
+        if self.db.get(name)?.tags().contains(&tag) {
+            return Ok(false);
+        }
+
+        let description = format!("tag {} with {}", name, tag);
+        self.journaled(description, vec![name.to_string()], |db| {
+            db.get_mut(name)?.add_tag(&tag)
+        })
+    }
+
+    // False if the entry didn't have the tag
+    fn remove_tag(
+        &mut self,
+        name: &str,
+        tag: &str,
+        password: &[u8],
+    ) -> Result<bool, DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        let tag = tag.trim().to_lowercase();
+        if !self.db.get(name)?.tags().contains(&tag) {
+            return Ok(false);
+        }
+
+        let description = format!("remove tag {} from {}", tag, name);
+        self.journaled(description, vec![name.to_string()], |db| {
+            Ok(db.get_mut(name)?.remove_tag(&tag))
+        })
     }
 
     fn get(&self, name: &str, password: &[u8]) -> Result<EncryptionResult, DatabaseError> {
@@ -166,7 +504,7 @@ impl DatabaseInterface for DatabaseEncrypted {
             return Err(DatabaseError::InvalidPassword);
         }
 
-        let ciphertext = self.db.get(name)?;
+        let ciphertext = self.db.get(name)?.password();
         let hash = Self::hash_password_argon2_with_salt(password, ciphertext.get_salt_slice())?;
 
         let result = decrypt(ciphertext, &hash)?;
@@ -174,19 +512,443 @@ impl DatabaseInterface for DatabaseEncrypted {
         Ok(result)
     }
 
+    fn get_entry(&self, name: &str, password: &[u8]) -> Result<Entry, DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        self.db.get(name)?.decrypt(name, password)
+    }
+
+    // Previous passwords, newest first
+    fn get_history(
+        &self,
+        name: &str,
+        password: &[u8],
+    ) -> Result<Vec<PasswordHistory>, DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        self.db.get(name)?.decrypt_history(password)
+    }
+
+    // Index 0 is the password replaced last
+    fn restore_history(
+        &mut self,
+        name: &str,
+        index: usize,
+        password: &[u8],
+    ) -> Result<(), DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        self.journaled(format!("restore {}", name), vec![name.to_string()], |db| {
+            db.get_mut(name)?.restore_history(index)
+        })
+    }
+
+    // Returns a description of what was undone
+    fn undo(&mut self, password: &[u8]) -> Result<Zeroizing<String>, DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        self.journal.undo(&mut self.db)
+    }
+
+    fn redo(&mut self, password: &[u8]) -> Result<Zeroizing<String>, DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        self.journal.redo(&mut self.db)
+    }
+
+    // Only decrypts when the field is the password or a secret field
+    fn get_field(
+        &self,
+        name: &str,
+        field: &str,
+        password: &[u8],
+    ) -> Result<Zeroizing<String>, DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        let entry = self.db.get(name)?;
+        if let Some(value) = entry.plain_field(name, field) {
+            return Ok(Zeroizing::new(value));
+        }
+
+        let ciphertext = if field.eq_ignore_ascii_case(FIELD_PASSWORD) {
+            entry.password()
+        } else {
+            match entry.field(field) {
+                Some(FieldValue::Secret(ciphertext)) => ciphertext,
+                _ => return Err(DatabaseError::NotFound),
+            }
+        };
+
+        let plaintext = KeyCache::new(password).decrypt(ciphertext)?;
+        match String::from_utf8(plaintext.to_vec()) {
+            Ok(value) => Ok(Zeroizing::new(value)),
+            Err(_error) => Err(DatabaseError::InputError(format!(
+                "The {} field is not valid UTF-8",
+                field
+            ))),
+        }
+    }
+
+    // Decrypts every entry in parallel, one step of progress per entry,
+    // returned in the same order as list
+    fn get_all_with_progress(
+        &self,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<Vec<(String, EncryptionResult)>, DatabaseError> {
+        if !self.hash_password_and_compare_cancellable(password, progress)? {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        let names = self.db.list()?;
+        let db = &self.db;
+        let decrypted = parallel_map(&names, progress, |name| {
+            let ciphertext = db.get(name.as_str())?.password();
+            let hash = Self::hash_password_argon2_with_salt(password, ciphertext.get_salt_slice())?;
+
+            Ok(decrypt(ciphertext, &hash)?)
+        })?;
+
+        let mut records = Vec::with_capacity(decrypted.len());
+        for (name, result) in names.into_iter().zip(decrypted) {
+            records.push((name, result?));
+        }
+
+        Ok(records)
+    }
+
+    // Same as get_all_with_progress with every field decrypted
+    fn get_all_entries_with_progress(
+        &self,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<Vec<Entry>, DatabaseError> {
+        if !self.hash_password_and_compare_cancellable(password, progress)? {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        let names = self.db.list()?;
+        let db = &self.db;
+        let decrypted = parallel_map(&names, progress, |name| {
+            db.get(name.as_str())?.decrypt(name, password)
+        })?;
+
+        decrypted.into_iter().collect()
+    }
+
     fn serialize_encrypted(&self, password: &[u8]) -> Result<EncryptionResult, DatabaseError> {
+        self.serialize_encrypted_with_progress(password, &Progress::new())
+    }
+
+    // Two steps of progress, one for the confirmation hash and one for encrypting.
+    // Half done transactions aren't saved.
+    fn serialize_encrypted_with_progress(
+        &self,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<EncryptionResult, DatabaseError> {
+        if self.in_transaction() {
+            return Err(DatabaseError::TransactionInProgress);
+        }
         let data = self.serialize()?;
 
-        if !self.hash_password_and_compare(password) {
+        if !self.hash_password_and_compare_cancellable(password, progress)? {
             return Err(DatabaseError::InvalidPassword);
         }
 
         #[cfg(feature = "use-compression")]
         let data = Zeroizing::new(compress_prepend_size(data.as_slice()));
 
-        let hash = Self::hash_password_argon2(password)?;
+        progress.add_total(1);
+        let hash = Self::hash_password_argon2_cancellable(password, progress)?;
         let ciphertext = encrypt(data.as_slice(), &hash)?;
 
         Ok(ciphertext)
     }
 }
+
+// What importing a file would change, found without changing anything
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    // Entries that would be added, in file order
+    pub added: Vec<String>,
+    // Entries that can't be added and why, nothing is imported if any can't
+    pub conflicts: Vec<(String, DatabaseError)>,
+    // Records without a title or that couldn't be read
+    pub skipped: usize,
+    // What the added entries leave out, one line each
+    pub unmapped: Vec<String>,
+}
+
+impl DatabaseEncrypted {
+    // Tries every insert on the names alone, so records conflicting with
+    // earlier records in the same file are found too. Groups that already
+    // exist are left as they are.
+    fn plan_import(&self, contents: &ImportContents) -> ImportReport {
+        let mut names = self.db.names_only();
+        let mut report = ImportReport {
+            skipped: contents.skipped,
+            unmapped: contents.unmapped.clone(),
+            ..Default::default()
+        };
+        for imported in contents.entries.iter() {
+            let title = &imported.entry.title;
+            match names.insert(title, ()) {
+                Ok(()) => report.added.push(title.clone()),
+                Err(error) => report.conflicts.push((title.clone(), error)),
+            }
+        }
+        for group in contents.groups.iter() {
+            if names.contains_group(group) {
+                continue;
+            }
+            if let Err(error) = names.create_group(group) {
+                report.conflicts.push((group.clone(), error));
+            }
+        }
+        report
+    }
+
+    // One step of progress per record, records are encrypted in parallel and
+    // all of them are encrypted before any are inserted so a cancelled import
+    // leaves the database untouched. Insertion happens in file order, either
+    // every record is imported or none are. Returns what was planned.
+    fn import(
+        &mut self,
+        file: &str,
+        contents: &ImportContents,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<ImportReport, DatabaseError> {
+        let records = &contents.entries;
+        // Records that can't be inserted are found before anything is encrypted
+        let report = self.plan_import(contents);
+        if !report.conflicts.is_empty() {
+            for (name, error) in report.conflicts.iter() {
+                warn!("pwm-db: Failed to import {}: {}", name, error);
+            }
+            let names: Vec<&str> = report
+                .conflicts
+                .iter()
+                .map(|(name, _error)| name.as_str())
+                .collect();
+            return Err(DatabaseError::ImportError(names.join(", ")));
+        }
+
+        let encrypted = parallel_map(records, progress, |imported| {
+            let hash = Self::hash_password_argon2(password)?;
+            EncryptedEntry::encrypt_imported(imported, &hash)
+        })?;
+
+        // The first failure in file order, not whichever thread failed first
+        let mut ciphertexts = Vec::with_capacity(encrypted.len());
+        for (imported, result) in records.iter().zip(encrypted) {
+            ciphertexts.push((&imported.entry.title, result?));
+        }
+
+        // The whole import is undone at once
+        let description = format!("import {}", file);
+        let names = records
+            .iter()
+            .map(|imported| imported.entry.title.clone())
+            .collect();
+        self.transaction(&description, |db| {
+            db.journaled(description.clone(), names, |db| {
+                for (key, data) in ciphertexts {
+                    db.insert(key, data)?;
+                }
+                for group in contents.groups.iter() {
+                    if !db.contains_group(group) {
+                        db.create_group(group)?;
+                    }
+                }
+                Ok(())
+            })
+        })?;
+        Ok(report)
+    }
+}
+
+// Every record with a title, the others are skipped
+fn read_csv(file: &str) -> Result<ImportContents, DatabaseError> {
+    let mut rdr = csv::Reader::from_path(file)?;
+    let columns = CsvColumns::new(rdr.headers()?);
+    let mut contents = ImportContents::default();
+    for record in rdr.records() {
+        match record {
+            Ok(record) => {
+                trace!("pwm-db: attempted to import record: {:?}", record.get(0));
+                match columns.entry(&record) {
+                    Some(entry) => contents.entries.push(ImportedEntry {
+                        entry,
+                        ..Default::default()
+                    }),
+                    None => contents.skipped += 1,
+                }
+            }
+            Err(_) => contents.skipped += 1,
+        };
+    }
+    Ok(contents)
+}
+
+// Column prefixes of custom fields in csv files
+static CSV_FIELD_PREFIX: &str = "Field:";
+static CSV_SECRET_PREFIX: &str = "Secret:";
+
+enum CsvColumn {
+    Builtin(&'static str),
+    Custom(String, bool),
+    Ignored,
+}
+
+// Files with a Title column are read by their headers, anything else is the
+// older "name,password" layout
+struct CsvColumns {
+    columns: Vec<CsvColumn>,
+}
+
+impl CsvColumns {
+    fn new(headers: &csv::StringRecord) -> Self {
+        let structured = headers
+            .iter()
+            .any(|header| header.trim().eq_ignore_ascii_case(FIELD_TITLE));
+
+        let columns = if structured {
+            headers
+                .iter()
+                .map(|header| Self::column(header.trim()))
+                .collect()
+        } else {
+            vec![
+                CsvColumn::Builtin(FIELD_TITLE),
+                CsvColumn::Builtin(FIELD_PASSWORD),
+            ]
+        };
+
+        Self { columns }
+    }
+
+    fn column(header: &str) -> CsvColumn {
+        for builtin in BUILTIN_FIELDS {
+            if header.eq_ignore_ascii_case(builtin) {
+                return CsvColumn::Builtin(builtin);
+            }
+        }
+
+        if let Some(name) = header.strip_prefix(CSV_FIELD_PREFIX) {
+            return CsvColumn::Custom(name.to_string(), false);
+        }
+        if let Some(name) = header.strip_prefix(CSV_SECRET_PREFIX) {
+            return CsvColumn::Custom(name.to_string(), true);
+        }
+
+        CsvColumn::Ignored
+    }
+
+    // Every custom field in use, in the order they are first seen
+    fn for_entries(entries: &[Entry]) -> Self {
+        let mut columns = vec![
+            CsvColumn::Builtin(FIELD_TITLE),
+            CsvColumn::Builtin(FIELD_USERNAME),
+            CsvColumn::Builtin(FIELD_PASSWORD),
+            CsvColumn::Builtin(FIELD_URL),
+            CsvColumn::Builtin(FIELD_NOTES),
+        ];
+
+        for field in entries.iter().flat_map(|entry| entry.fields.iter()) {
+            let exists = columns.iter().any(|column| match column {
+                CsvColumn::Custom(name, secret) => *name == field.name && *secret == field.secret,
+                _ => false,
+            });
+            if !exists {
+                columns.push(CsvColumn::Custom(field.name.clone(), field.secret));
+            }
+        }
+
+        Self { columns }
+    }
+
+    fn headers(&self) -> Vec<String> {
+        self.columns
+            .iter()
+            .map(|column| match column {
+                CsvColumn::Builtin(FIELD_URL) => String::from("URL"),
+                CsvColumn::Builtin(name) => {
+                    let (first, rest) = name.split_at(1);
+                    first.to_ascii_uppercase() + rest
+                }
+                CsvColumn::Custom(name, false) => format!("{}{}", CSV_FIELD_PREFIX, name),
+                CsvColumn::Custom(name, true) => format!("{}{}", CSV_SECRET_PREFIX, name),
+                CsvColumn::Ignored => String::new(),
+            })
+            .collect()
+    }
+
+    // URLs are one per line, empty custom fields are left out
+    fn entry(&self, record: &csv::StringRecord) -> Option<Entry> {
+        let mut entry = Entry::default();
+        for (column, value) in self.columns.iter().zip(record.iter()) {
+            match column {
+                CsvColumn::Builtin(FIELD_TITLE) => entry.title = value.to_string(),
+                CsvColumn::Builtin(FIELD_USERNAME) => entry.username = value.to_string(),
+                CsvColumn::Builtin(FIELD_PASSWORD) => {
+                    entry.password = Zeroizing::new(value.as_bytes().to_vec())
+                }
+                CsvColumn::Builtin(FIELD_URL) => {
+                    entry.urls = value
+                        .lines()
+                        .map(|url| url.trim())
+                        .filter(|url| !url.is_empty())
+                        .map(String::from)
+                        .collect()
+                }
+                CsvColumn::Builtin(_name) => entry.notes = value.to_string(),
+                CsvColumn::Custom(name, secret) if !value.is_empty() => {
+                    entry.fields.push(CustomField::new(name, value, *secret))
+                }
+                CsvColumn::Custom(..) | CsvColumn::Ignored => (),
+            }
+        }
+
+        if entry.title.is_empty() {
+            return None;
+        }
+        Some(entry)
+    }
+
+    fn record(&self, entry: &Entry) -> Vec<Zeroizing<Vec<u8>>> {
+        self.columns
+            .iter()
+            .map(|column| {
+                let value = match column {
+                    CsvColumn::Builtin(FIELD_PASSWORD) => return entry.password.clone(),
+                    CsvColumn::Builtin(name) => entry.field(name),
+                    CsvColumn::Custom(name, secret) => entry
+                        .fields
+                        .iter()
+                        .find(|field| field.name == *name && field.secret == *secret)
+                        .map(|field| field.value.clone()),
+                    CsvColumn::Ignored => None,
+                };
+
+                match value {
+                    Some(value) => Zeroizing::new(value.as_bytes().to_vec()),
+                    None => Zeroizing::new(Vec::new()),
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,321 @@
+use pwm_lib::{encryption::EncryptionResult, zeroize::Zeroizing};
+
+use crate::db_base::error::DatabaseError;
+use crate::db_encrypted::entry::{
+    EncryptedEntry, FieldValue, KeyCache, FIELD_NOTES, FIELD_PASSWORD, FIELD_URL, FIELD_USERNAME,
+};
+use crate::db_encrypted::DatabaseEncrypted;
+
+// How a field changed, None is a secret value that wasn't revealed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldChange {
+    Added(Option<Zeroizing<String>>),
+    Removed(Option<Zeroizing<String>>),
+    Changed(Option<Zeroizing<String>>, Option<Zeroizing<String>>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub name: String,
+    pub change: FieldChange,
+}
+
+// An entry in both vaults, renamed_from is set when it was stored under
+// another name before
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryDiff {
+    pub name: String,
+    pub renamed_from: Option<String>,
+    pub fields: Vec<FieldDiff>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VaultDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<EntryDiff>,
+    pub groups_added: Vec<String>,
+    pub groups_removed: Vec<String>,
+}
+
+impl VaultDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.groups_added.is_empty()
+            && self.groups_removed.is_empty()
+    }
+}
+
+// The value compared and the value shown, secrets are compared decrypted
+// and only shown when they are revealed
+type Value = (Zeroizing<Vec<u8>>, Option<Zeroizing<String>>);
+
+impl DatabaseEncrypted {
+    // What changed from older to this vault, both need the same master
+    // password. Secret values are only shown as changed unless reveal is
+    // set. Renamed entries are found by their creation time and password.
+    pub fn diff(
+        &self,
+        older: &DatabaseEncrypted,
+        password: &[u8],
+        reveal: bool,
+    ) -> Result<VaultDiff, DatabaseError> {
+        if !self.hash_password_and_compare(password) || !older.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        let mut keys = KeyCache::new(password);
+        let mut diff = VaultDiff::default();
+        let mut removed: Vec<&String> = older
+            .db
+            .iter()
+            .filter(|(name, _entry)| self.db.get(name).is_err())
+            .map(|(name, _entry)| name)
+            .collect();
+
+        for (name, entry) in self.db.iter() {
+            let (old_name, old_entry) = match older.db.get(name) {
+                Ok(old_entry) => (name, old_entry),
+                Err(_error) => match find_renamed(&removed, older, entry, &mut keys)? {
+                    Some(index) => {
+                        let old_name = removed.remove(index);
+                        (old_name, older.db.get(old_name)?)
+                    }
+                    None => {
+                        diff.added.push(name.clone());
+                        continue;
+                    }
+                },
+            };
+
+            let fields = match old_entry.same_content(entry) {
+                true => Vec::new(),
+                false => diff_fields(
+                    values(old_entry, &mut keys, reveal)?,
+                    values(entry, &mut keys, reveal)?,
+                ),
+            };
+            let renamed_from = (old_name != name).then(|| old_name.clone());
+            if renamed_from.is_some() || !fields.is_empty() {
+                diff.changed.push(EntryDiff {
+                    name: name.clone(),
+                    renamed_from,
+                    fields,
+                });
+            }
+        }
+        diff.removed = removed.into_iter().cloned().collect();
+
+        let (groups, old_groups) = (self.db.groups(), older.db.groups());
+        diff.groups_added = groups
+            .iter()
+            .filter(|group| !old_groups.contains(group))
+            .cloned()
+            .collect();
+        diff.groups_removed = old_groups
+            .into_iter()
+            .filter(|group| !groups.contains(group))
+            .collect();
+
+        Ok(diff)
+    }
+}
+
+// The removed entry that was created at the same time with the same password
+fn find_renamed(
+    removed: &[&String],
+    older: &DatabaseEncrypted,
+    entry: &EncryptedEntry,
+    keys: &mut KeyCache,
+) -> Result<Option<usize>, DatabaseError> {
+    for (index, name) in removed.iter().enumerate() {
+        let old_entry = older.db.get(name)?;
+        if old_entry.created() != entry.created() {
+            continue;
+        }
+        if old_entry.password().as_slice() == entry.password().as_slice()
+            || keys.decrypt(old_entry.password())? == keys.decrypt(entry.password())?
+        {
+            return Ok(Some(index));
+        }
+    }
+    Ok(None)
+}
+
+fn plain(value: &str) -> Value {
+    (
+        Zeroizing::new(value.as_bytes().to_vec()),
+        Some(Zeroizing::new(value.to_string())),
+    )
+}
+
+fn secret(
+    ciphertext: &EncryptionResult,
+    keys: &mut KeyCache,
+    reveal: bool,
+) -> Result<Value, DatabaseError> {
+    let value = keys.decrypt(ciphertext)?;
+    let shown = reveal.then(|| Zeroizing::new(String::from_utf8_lossy(&value).to_string()));
+    Ok((value, shown))
+}
+
+// Every field that is set, built in fields first
+fn values(
+    entry: &EncryptedEntry,
+    keys: &mut KeyCache,
+    reveal: bool,
+) -> Result<Vec<(String, Value)>, DatabaseError> {
+    let mut values = Vec::new();
+    if !entry.username.is_empty() {
+        values.push((String::from(FIELD_USERNAME), plain(&entry.username)));
+    }
+    values.push((
+        String::from(FIELD_PASSWORD),
+        secret(entry.password(), keys, reveal)?,
+    ));
+    if !entry.urls.is_empty() {
+        values.push((String::from(FIELD_URL), plain(&entry.urls.join(", "))));
+    }
+    if !entry.notes.is_empty() {
+        values.push((String::from(FIELD_NOTES), plain(&entry.notes)));
+    }
+    if !entry.tags().is_empty() {
+        let tags: Vec<&str> = entry.tags().iter().map(|tag| tag.as_str()).collect();
+        values.push((String::from("tags"), plain(&tags.join(", "))));
+    }
+    if !entry.attachments().is_empty() {
+        let attachments: Vec<String> = entry
+            .attachments()
+            .iter()
+            .map(|attachment| format!("{} ({} bytes)", attachment.name, attachment.size))
+            .collect();
+        values.push((String::from("attachments"), plain(&attachments.join(", "))));
+    }
+
+    for field in entry.field_names() {
+        let value = match entry.field(field) {
+            Some(FieldValue::Text(value)) => plain(value),
+            Some(FieldValue::Secret(ciphertext)) => secret(ciphertext, keys, reveal)?,
+            None => continue,
+        };
+        values.push((field.to_string(), value));
+    }
+
+    Ok(values)
+}
+
+fn diff_fields(old: Vec<(String, Value)>, mut new: Vec<(String, Value)>) -> Vec<FieldDiff> {
+    let mut fields = Vec::new();
+    for (name, (old_value, old_shown)) in old {
+        let change = match new.iter().position(|(other, _value)| *other == name) {
+            Some(index) => {
+                let (_name, (new_value, new_shown)) = new.remove(index);
+                if old_value == new_value {
+                    continue;
+                }
+                FieldChange::Changed(old_shown, new_shown)
+            }
+            None => FieldChange::Removed(old_shown),
+        };
+        fields.push(FieldDiff { name, change });
+    }
+    for (name, (_value, shown)) in new {
+        fields.push(FieldDiff {
+            name,
+            change: FieldChange::Added(shown),
+        });
+    }
+    fields
+}
+
+#[cfg(test)]
+mod test {
+    use pwm_lib::zeroize::Zeroizing;
+
+    use crate::db_base::error::DatabaseError;
+    use crate::db_encrypted::{
+        db_interface::DatabaseInterface, entry::CustomField, DatabaseEncrypted,
+    };
+
+    use super::{EntryDiff, FieldChange, FieldDiff};
+
+    fn shown(value: &str) -> Option<Zeroizing<String>> {
+        Some(Zeroizing::new(value.to_string()))
+    }
+
+    #[test]
+    fn test_diff() {
+        let pw = b"test";
+        let mut old = DatabaseEncrypted::new(pw).unwrap();
+        old.insert("mail", b"password1", pw).unwrap();
+        old.insert("bank", b"password2", pw).unwrap();
+        old.insert("chat", b"password3", pw).unwrap();
+        old.create_group("old", pw).unwrap();
+        let mut entry = old.get_entry("mail", pw).unwrap();
+        entry.username = String::from("old");
+        old.replace_entry(&entry, pw).unwrap();
+        let serialized = old.serialize_encrypted(pw).unwrap();
+        let mut new = DatabaseEncrypted::new_deserialize_encrypted(&serialized, pw).unwrap();
+        assert!(new.diff(&old, pw, false).unwrap().is_empty());
+
+        new.remove("bank", pw).unwrap();
+        new.insert("new", b"password4", pw).unwrap();
+        new.rename("chat", "work/chat", pw).unwrap();
+        new.remove_group("old", false, pw).unwrap();
+        // Every secret is encrypted again, only the changed ones are listed
+        entry.username = String::from("new");
+        entry.password = Zeroizing::new(b"changed".to_vec());
+        entry.fields.push(CustomField::new("pin", "1234", true));
+        new.replace_entry(&entry, pw).unwrap();
+
+        let diff = new.diff(&old, pw, false).unwrap();
+        assert_eq!(diff.added, vec!["new"]);
+        assert_eq!(diff.removed, vec!["bank"]);
+        assert_eq!(diff.groups_added, vec!["work"]);
+        assert_eq!(diff.groups_removed, vec!["old"]);
+        assert_eq!(
+            diff.changed,
+            vec![
+                EntryDiff {
+                    name: String::from("mail"),
+                    renamed_from: None,
+                    fields: vec![
+                        FieldDiff {
+                            name: String::from("username"),
+                            change: FieldChange::Changed(shown("old"), shown("new")),
+                        },
+                        FieldDiff {
+                            name: String::from("password"),
+                            change: FieldChange::Changed(None, None),
+                        },
+                        FieldDiff {
+                            name: String::from("pin"),
+                            change: FieldChange::Added(None),
+                        },
+                    ],
+                },
+                EntryDiff {
+                    name: String::from("work/chat"),
+                    renamed_from: Some(String::from("chat")),
+                    fields: Vec::new(),
+                },
+            ]
+        );
+
+        let diff = new.diff(&old, pw, true).unwrap();
+        assert_eq!(
+            diff.changed[0].fields[1].change,
+            FieldChange::Changed(shown("password1"), shown("changed"))
+        );
+        assert_eq!(
+            diff.changed[0].fields[2].change,
+            FieldChange::Added(shown("1234"))
+        );
+        assert_eq!(
+            new.diff(&old, b"wrong", false).unwrap_err(),
+            DatabaseError::InvalidPassword
+        );
+    }
+}
@@ -0,0 +1,750 @@
+use pwm_lib::{
+    encryption::{
+        default::{decrypt, encrypt},
+        EncryptionResult,
+    },
+    hash::{argon2_wrapper::argon2_hash_password_with_salt, HashResult},
+    zeroize::{Zeroize, Zeroizing},
+};
+use std::collections::BTreeSet;
+
+use crate::{
+    db_base::error::DatabaseError,
+    db_encrypted::attachment::EncryptedAttachment,
+    db_encrypted::tlv::{read_encrypted, read_i64, read_string, Reader, UnknownFields, Writer},
+    timestamp::now,
+};
+
+// Names that refer to the built in fields, custom fields can't use them
+pub const FIELD_TITLE: &str = "title";
+pub const FIELD_USERNAME: &str = "username";
+pub const FIELD_PASSWORD: &str = "password";
+pub const FIELD_URL: &str = "url";
+pub const FIELD_NOTES: &str = "notes";
+pub static BUILTIN_FIELDS: [&str; 5] = [
+    FIELD_TITLE,
+    FIELD_USERNAME,
+    FIELD_PASSWORD,
+    FIELD_URL,
+    FIELD_NOTES,
+];
+
+// Number of replaced passwords kept per entry
+pub static MAX_HISTORY: usize = 10;
+
+// Tags are stored lower case and can't contain whitespace or commas
+pub fn normalize_tag(tag: &str) -> Result<String, DatabaseError> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() || tag.contains(|c: char| c.is_whitespace() || c == ',') {
+        return Err(DatabaseError::InputError(format!(
+            "Invalid tag \"{}\"",
+            tag
+        )));
+    }
+    Ok(tag)
+}
+
+// A decrypted entry, the title is the name it is stored under
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub title: String,
+    pub username: String,
+    pub password: Zeroizing<Vec<u8>>,
+    pub urls: Vec<String>,
+    pub notes: String,
+    pub fields: Vec<CustomField>,
+}
+
+// An entry read from another password manager, with what Entry can't hold
+#[derive(Debug, Default, Clone)]
+pub struct ImportedEntry {
+    pub entry: Entry,
+    pub tags: Vec<String>,
+    // Replaced passwords, newest first
+    pub history: Vec<PasswordHistory>,
+    // File names and contents
+    pub attachments: Vec<(String, Zeroizing<Vec<u8>>)>,
+    // 0 when the other program didn't store the time
+    pub created: i64,
+    pub modified: i64,
+    pub accessed: i64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CustomField {
+    pub name: String,
+    pub value: Zeroizing<String>,
+    // Secret fields are encrypted like the password
+    pub secret: bool,
+}
+
+impl CustomField {
+    pub fn new(name: &str, value: &str, secret: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            value: Zeroizing::new(value.to_string()),
+            secret,
+        }
+    }
+}
+
+impl Entry {
+    pub fn new(title: &str, password: &[u8]) -> Self {
+        Self {
+            title: title.to_string(),
+            password: Zeroizing::new(password.to_vec()),
+            ..Self::default()
+        }
+    }
+
+    pub fn is_builtin_field(name: &str) -> bool {
+        BUILTIN_FIELDS
+            .iter()
+            .any(|field| field.eq_ignore_ascii_case(name))
+    }
+
+    // Built in fields are matched ignoring case, custom fields exactly. URLs
+    // are returned one per line.
+    pub fn field(&self, name: &str) -> Option<Zeroizing<String>> {
+        let value = if name.eq_ignore_ascii_case(FIELD_TITLE) {
+            self.title.clone()
+        } else if name.eq_ignore_ascii_case(FIELD_USERNAME) {
+            self.username.clone()
+        } else if name.eq_ignore_ascii_case(FIELD_PASSWORD) {
+            String::from_utf8(self.password.to_vec()).ok()?
+        } else if name.eq_ignore_ascii_case(FIELD_URL) {
+            self.urls.join("\n")
+        } else if name.eq_ignore_ascii_case(FIELD_NOTES) {
+            self.notes.clone()
+        } else {
+            let field = self.fields.iter().find(|field| field.name == name)?;
+            field.value.to_string()
+        };
+
+        Some(Zeroizing::new(value))
+    }
+
+    pub fn validate(&self) -> Result<(), DatabaseError> {
+        if self.title.is_empty() {
+            return Err(DatabaseError::InputError(String::from(
+                "An entry needs a title",
+            )));
+        }
+
+        for (index, field) in self.fields.iter().enumerate() {
+            if field.name.is_empty() {
+                return Err(DatabaseError::InputError(String::from(
+                    "Custom fields need a name",
+                )));
+            }
+            if Self::is_builtin_field(&field.name) {
+                return Err(DatabaseError::InputError(format!(
+                    "\"{}\" is a built in field",
+                    field.name
+                )));
+            }
+            if self.fields[..index]
+                .iter()
+                .any(|other| other.name == field.name)
+            {
+                return Err(DatabaseError::InputError(format!(
+                    "Duplicate field \"{}\"",
+                    field.name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// The stored form of an entry. Everything but the password and the secret
+// fields is kept in plain text so it can be listed without the master
+// password, the vault file as a whole is still encrypted.
+#[derive(Debug, Clone)]
+pub struct EncryptedEntry {
+    pub username: String,
+    pub urls: Vec<String>,
+    pub notes: String,
+    password: EncryptionResult,
+    fields: Vec<EncryptedField>,
+    // Seconds since the unix epoch, 0 when unknown
+    created: i64,
+    modified: i64,
+    accessed: i64,
+    // Replaced passwords, newest first
+    history: Vec<HistoryItem>,
+    tags: BTreeSet<String>,
+    attachments: Vec<EncryptedAttachment>,
+    // Written back when saving, see FORMAT.md
+    unknown: UnknownFields,
+}
+
+#[derive(Debug, Clone)]
+struct HistoryItem {
+    password: EncryptionResult,
+    replaced: i64,
+    unknown: UnknownFields,
+}
+
+// A decrypted previous password and when it was replaced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordHistory {
+    pub password: Zeroizing<Vec<u8>>,
+    pub replaced: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct EncryptedField {
+    pub name: String,
+    pub value: FieldValue,
+    unknown: UnknownFields,
+}
+
+#[derive(Debug, Clone)]
+pub enum FieldValue {
+    Text(String),
+    Secret(EncryptionResult),
+}
+
+impl EncryptedEntry {
+    // An entry holding nothing but the password, which is how entries of
+    // older vaults are read
+    pub fn from_password(password: EncryptionResult) -> Self {
+        Self {
+            username: String::new(),
+            urls: Vec::new(),
+            notes: String::new(),
+            password,
+            fields: Vec::new(),
+            created: 0,
+            modified: 0,
+            accessed: 0,
+            history: Vec::new(),
+            tags: BTreeSet::new(),
+            attachments: Vec::new(),
+            unknown: UnknownFields::default(),
+        }
+    }
+
+    // A new entry created now
+    pub fn new_with_password(password: EncryptionResult) -> Self {
+        let time = now();
+        Self {
+            created: time,
+            modified: time,
+            ..Self::from_password(password)
+        }
+    }
+
+    // The password and every secret field share the key, each has its own
+    // nonce
+    pub fn encrypt(entry: &Entry, key: &HashResult) -> Result<Self, DatabaseError> {
+        entry.validate()?;
+
+        let mut fields = Vec::with_capacity(entry.fields.len());
+        for field in entry.fields.iter() {
+            let value = if field.secret {
+                FieldValue::Secret(encrypt(field.value.as_bytes(), key)?)
+            } else {
+                FieldValue::Text(field.value.to_string())
+            };
+            fields.push(EncryptedField {
+                name: field.name.clone(),
+                value,
+                unknown: UnknownFields::default(),
+            });
+        }
+
+        let time = now();
+        Ok(Self {
+            username: entry.username.clone(),
+            urls: entry.urls.clone(),
+            notes: entry.notes.clone(),
+            password: encrypt(entry.password.as_slice(), key)?,
+            fields,
+            created: time,
+            modified: time,
+            accessed: 0,
+            history: Vec::new(),
+            tags: BTreeSet::new(),
+            attachments: Vec::new(),
+            unknown: UnknownFields::default(),
+        })
+    }
+
+    // Keeps the times the other program stored, where it stored them
+    pub(crate) fn encrypt_imported(
+        imported: &ImportedEntry,
+        key: &HashResult,
+    ) -> Result<Self, DatabaseError> {
+        let mut encrypted = Self::encrypt(&imported.entry, key)?;
+        for tag in imported.tags.iter() {
+            encrypted.tags.insert(normalize_tag(tag)?);
+        }
+        for item in imported.history.iter().rev() {
+            encrypted.push_history(encrypt(item.password.as_slice(), key)?, item.replaced);
+        }
+        for (name, contents) in imported.attachments.iter() {
+            encrypted
+                .attachments
+                .push(EncryptedAttachment::encrypt(name, contents, key)?);
+        }
+
+        if imported.created != 0 {
+            encrypted.created = imported.created;
+        }
+        if imported.modified != 0 {
+            encrypted.modified = imported.modified;
+        }
+        encrypted.accessed = imported.accessed;
+        Ok(encrypted)
+    }
+
+    // Encrypts the new version of this entry, keeping its timestamps and
+    // history. The old password is only added to the history if it changed.
+    pub fn encrypt_update(
+        &self,
+        entry: &Entry,
+        key: &HashResult,
+        password: &[u8],
+    ) -> Result<Self, DatabaseError> {
+        let mut updated = Self::encrypt(entry, key)?;
+        updated.created = self.created;
+        updated.accessed = self.accessed;
+        updated.history = self.history.clone();
+        updated.tags = self.tags.clone();
+        updated.attachments = self.attachments.clone();
+        updated.unknown = self.unknown.clone();
+        for field in updated.fields.iter_mut() {
+            if let Some(old) = self.fields.iter().find(|old| old.name == field.name) {
+                field.unknown = old.unknown.clone();
+            }
+        }
+
+        let old = KeyCache::new(password).decrypt(&self.password)?;
+        if old.as_slice() != entry.password.as_slice() {
+            updated.push_history(self.password.clone(), updated.modified);
+        }
+
+        Ok(updated)
+    }
+
+    pub fn decrypt(&self, title: &str, password: &[u8]) -> Result<Entry, DatabaseError> {
+        let mut keys = KeyCache::new(password);
+
+        let mut fields = Vec::with_capacity(self.fields.len());
+        for field in self.fields.iter() {
+            let (value, secret) = match &field.value {
+                FieldValue::Text(value) => (Zeroizing::new(value.clone()), false),
+                FieldValue::Secret(value) => (keys.decrypt_string(value)?, true),
+            };
+            fields.push(CustomField {
+                name: field.name.clone(),
+                value,
+                secret,
+            });
+        }
+
+        Ok(Entry {
+            title: title.to_string(),
+            username: self.username.clone(),
+            password: keys.decrypt(&self.password)?,
+            urls: self.urls.clone(),
+            notes: self.notes.clone(),
+            fields,
+        })
+    }
+
+    pub fn password(&self) -> &EncryptionResult {
+        &self.password
+    }
+
+    // Replaces only the password, secret fields keep their own key. The old
+    // password goes into the history.
+    pub fn set_password(&mut self, password: EncryptionResult) {
+        let time = now();
+        let old = std::mem::replace(&mut self.password, password);
+        self.push_history(old, time);
+        self.modified = time;
+    }
+
+    fn push_history(&mut self, password: EncryptionResult, replaced: i64) {
+        self.history.insert(
+            0,
+            HistoryItem {
+                password,
+                replaced,
+                unknown: UnknownFields::default(),
+            },
+        );
+        self.history.truncate(MAX_HISTORY);
+    }
+
+    pub fn created(&self) -> i64 {
+        self.created
+    }
+
+    pub fn modified(&self) -> i64 {
+        self.modified
+    }
+
+    pub fn accessed(&self) -> i64 {
+        self.accessed
+    }
+
+    pub fn set_modified(&mut self) {
+        self.modified = now();
+    }
+
+    pub fn set_accessed(&mut self) {
+        self.accessed = now();
+    }
+
+    // When each previous password was replaced, newest first
+    pub fn history_times(&self) -> Vec<i64> {
+        self.history.iter().map(|item| item.replaced).collect()
+    }
+
+    pub fn decrypt_history(&self, password: &[u8]) -> Result<Vec<PasswordHistory>, DatabaseError> {
+        let mut keys = KeyCache::new(password);
+        let mut history = Vec::with_capacity(self.history.len());
+        for item in self.history.iter() {
+            history.push(PasswordHistory {
+                password: keys.decrypt(&item.password)?,
+                replaced: item.replaced,
+            });
+        }
+        Ok(history)
+    }
+
+    // Makes a previous password current again, index 0 being the newest. The
+    // current one goes into the history so a restore can be undone.
+    pub fn restore_history(&mut self, index: usize) -> Result<(), DatabaseError> {
+        if index >= self.history.len() {
+            return Err(DatabaseError::NotFound);
+        }
+
+        let item = self.history.remove(index);
+        self.set_password(item.password);
+        Ok(())
+    }
+
+    pub fn tags(&self) -> &BTreeSet<String> {
+        &self.tags
+    }
+
+    // False if the entry already had the tag
+    pub fn add_tag(&mut self, tag: &str) -> Result<bool, DatabaseError> {
+        let added = self.tags.insert(normalize_tag(tag)?);
+        if added {
+            self.set_modified();
+        }
+        Ok(added)
+    }
+
+    // False if the entry didn't have the tag
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        let removed = self.tags.remove(&tag.trim().to_lowercase());
+        if removed {
+            self.set_modified();
+        }
+        removed
+    }
+
+    // True if the entry has every one of the tags
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter()
+            .all(|tag| self.tags.contains(&tag.trim().to_lowercase()))
+    }
+
+    pub fn attachments(&self) -> &[EncryptedAttachment] {
+        &self.attachments
+    }
+
+    pub fn attachment(&self, name: &str) -> Option<&EncryptedAttachment> {
+        self.attachments
+            .iter()
+            .find(|attachment| attachment.name == name)
+    }
+
+    pub(crate) fn add_attachment(&mut self, attachment: EncryptedAttachment) {
+        self.attachments.push(attachment);
+        self.set_modified();
+    }
+
+    pub(crate) fn remove_attachment(&mut self, name: &str) -> Result<(), DatabaseError> {
+        match self
+            .attachments
+            .iter()
+            .position(|attachment| attachment.name == name)
+        {
+            Some(index) => {
+                self.attachments.remove(index).zeroize();
+                self.set_modified();
+                Ok(())
+            }
+            None => Err(DatabaseError::NotFound),
+        }
+    }
+
+    pub fn field_names(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(|field| field.name.as_str())
+    }
+
+    pub fn field(&self, name: &str) -> Option<&FieldValue> {
+        self.fields
+            .iter()
+            .find(|field| field.name == name)
+            .map(|field| &field.value)
+    }
+
+    // The value of a field that can be read without decrypting anything
+    pub fn plain_field(&self, title: &str, name: &str) -> Option<String> {
+        if name.eq_ignore_ascii_case(FIELD_TITLE) {
+            Some(title.to_string())
+        } else if name.eq_ignore_ascii_case(FIELD_USERNAME) {
+            Some(self.username.clone())
+        } else if name.eq_ignore_ascii_case(FIELD_URL) {
+            Some(self.urls.join("\n"))
+        } else if name.eq_ignore_ascii_case(FIELD_NOTES) {
+            Some(self.notes.clone())
+        } else {
+            match self.field(name)? {
+                FieldValue::Text(value) => Some(value.clone()),
+                FieldValue::Secret(_value) => None,
+            }
+        }
+    }
+}
+
+// Tags of the entry message, see FORMAT.md
+const TAG_NAME: u32 = 1;
+const TAG_USERNAME: u32 = 2;
+const TAG_URL: u32 = 3;
+const TAG_NOTES: u32 = 4;
+const TAG_PASSWORD: u32 = 5;
+const TAG_FIELD: u32 = 6;
+const TAG_CREATED: u32 = 7;
+const TAG_MODIFIED: u32 = 8;
+const TAG_ACCESSED: u32 = 9;
+const TAG_HISTORY: u32 = 10;
+const TAG_TAG: u32 = 11;
+const TAG_ATTACHMENT: u32 = 12;
+
+const TAG_FIELD_NAME: u32 = 1;
+const TAG_FIELD_TEXT: u32 = 2;
+const TAG_FIELD_SECRET: u32 = 3;
+
+const TAG_HISTORY_PASSWORD: u32 = 1;
+const TAG_HISTORY_REPLACED: u32 = 2;
+
+impl EncryptedEntry {
+    // Whether two copies hold the same entry, reading one doesn't count as
+    // a change
+    pub(crate) fn same_content(&self, other: &Self) -> bool {
+        let mut other = other.clone();
+        other.accessed = self.accessed;
+
+        let mut ours = Writer::new();
+        self.write_tlv("", &mut ours);
+        let mut theirs = Writer::new();
+        other.write_tlv("", &mut theirs);
+        other.zeroize();
+        *ours.into_inner() == *theirs.into_inner()
+    }
+
+    pub(crate) fn write_tlv(&self, name: &str, writer: &mut Writer) {
+        writer.string(TAG_NAME, name);
+        writer.string_if_set(TAG_USERNAME, &self.username);
+        for url in self.urls.iter() {
+            writer.string(TAG_URL, url);
+        }
+        writer.string_if_set(TAG_NOTES, &self.notes);
+        writer.encrypted(TAG_PASSWORD, &self.password);
+        for field in self.fields.iter() {
+            writer.message(TAG_FIELD, |writer| {
+                writer.string(TAG_FIELD_NAME, &field.name);
+                match &field.value {
+                    FieldValue::Text(value) => writer.string(TAG_FIELD_TEXT, value),
+                    FieldValue::Secret(value) => writer.encrypted(TAG_FIELD_SECRET, value),
+                }
+                field.unknown.write(writer);
+            });
+        }
+        writer.i64(TAG_CREATED, self.created);
+        writer.i64(TAG_MODIFIED, self.modified);
+        writer.i64(TAG_ACCESSED, self.accessed);
+        for item in self.history.iter() {
+            writer.message(TAG_HISTORY, |writer| {
+                writer.encrypted(TAG_HISTORY_PASSWORD, &item.password);
+                writer.i64(TAG_HISTORY_REPLACED, item.replaced);
+                item.unknown.write(writer);
+            });
+        }
+        for tag in self.tags.iter() {
+            writer.string(TAG_TAG, tag);
+        }
+        for attachment in self.attachments.iter() {
+            writer.message(TAG_ATTACHMENT, |writer| attachment.write_tlv(writer));
+        }
+        self.unknown.write(writer);
+    }
+
+    // The name and the entry, unknown fields are kept to be written back.
+    // Only the name and the password are required.
+    pub(crate) fn read_tlv(value: &[u8]) -> Result<(String, Self), DatabaseError> {
+        let mut name = None;
+        let mut password = None;
+        let mut entry = Self::from_password(EncryptionResult::new(Vec::new())?);
+
+        let mut reader = Reader::new(value);
+        while let Some((tag, value)) = reader.next()? {
+            match tag {
+                TAG_NAME => name = Some(read_string(value)?),
+                TAG_USERNAME => entry.username = read_string(value)?,
+                TAG_URL => entry.urls.push(read_string(value)?),
+                TAG_NOTES => entry.notes = read_string(value)?,
+                TAG_PASSWORD => password = Some(read_encrypted(value)?),
+                TAG_FIELD => entry.fields.push(Self::read_field(value)?),
+                TAG_CREATED => entry.created = read_i64(value)?,
+                TAG_MODIFIED => entry.modified = read_i64(value)?,
+                TAG_ACCESSED => entry.accessed = read_i64(value)?,
+                TAG_HISTORY => entry.history.push(Self::read_history(value)?),
+                TAG_TAG => {
+                    entry.tags.insert(read_string(value)?);
+                }
+                TAG_ATTACHMENT => entry
+                    .attachments
+                    .push(EncryptedAttachment::read_tlv(value)?),
+                _ => entry.unknown.push(tag, value),
+            }
+        }
+
+        match (name, password) {
+            (Some(name), Some(password)) => {
+                entry.password = password;
+                Ok((name, entry))
+            }
+            _ => Err(DatabaseError::FailedDeserialize),
+        }
+    }
+
+    fn read_field(value: &[u8]) -> Result<EncryptedField, DatabaseError> {
+        let mut name = None;
+        let mut field_value = FieldValue::Text(String::new());
+        let mut unknown = UnknownFields::default();
+        let mut reader = Reader::new(value);
+        while let Some((tag, value)) = reader.next()? {
+            match tag {
+                TAG_FIELD_NAME => name = Some(read_string(value)?),
+                TAG_FIELD_TEXT => field_value = FieldValue::Text(read_string(value)?),
+                TAG_FIELD_SECRET => field_value = FieldValue::Secret(read_encrypted(value)?),
+                _ => unknown.push(tag, value),
+            }
+        }
+
+        match name {
+            Some(name) => Ok(EncryptedField {
+                name,
+                value: field_value,
+                unknown,
+            }),
+            None => Err(DatabaseError::FailedDeserialize),
+        }
+    }
+
+    fn read_history(value: &[u8]) -> Result<HistoryItem, DatabaseError> {
+        let mut password = None;
+        let mut replaced = 0;
+        let mut unknown = UnknownFields::default();
+        let mut reader = Reader::new(value);
+        while let Some((tag, value)) = reader.next()? {
+            match tag {
+                TAG_HISTORY_PASSWORD => password = Some(read_encrypted(value)?),
+                TAG_HISTORY_REPLACED => replaced = read_i64(value)?,
+                _ => unknown.push(tag, value),
+            }
+        }
+
+        match password {
+            Some(password) => Ok(HistoryItem {
+                password,
+                replaced,
+                unknown,
+            }),
+            None => Err(DatabaseError::FailedDeserialize),
+        }
+    }
+}
+
+// Ciphertexts zeroize themselves when dropped, this clears the plain text
+impl Zeroize for EncryptedEntry {
+    fn zeroize(&mut self) {
+        self.username.zeroize();
+        self.urls.zeroize();
+        self.notes.zeroize();
+        for field in self.fields.iter_mut() {
+            field.name.zeroize();
+            if let FieldValue::Text(value) = &mut field.value {
+                value.zeroize();
+            }
+        }
+        for mut tag in std::mem::take(&mut self.tags) {
+            tag.zeroize();
+        }
+        for attachment in self.attachments.iter_mut() {
+            attachment.zeroize();
+        }
+    }
+}
+
+// Derives every distinct salt only once, a password changed with replace has
+// a different salt than the secret fields
+pub(crate) struct KeyCache<'a> {
+    password: &'a [u8],
+    keys: Vec<HashResult>,
+}
+
+impl<'a> KeyCache<'a> {
+    pub(crate) fn new(password: &'a [u8]) -> Self {
+        Self {
+            password,
+            keys: Vec::new(),
+        }
+    }
+
+    pub(crate) fn decrypt(
+        &mut self,
+        ciphertext: &EncryptionResult,
+    ) -> Result<Zeroizing<Vec<u8>>, DatabaseError> {
+        let salt = ciphertext.get_salt_slice();
+        let key = match self.keys.iter().position(|key| key.get_salt() == salt) {
+            Some(index) => &self.keys[index],
+            None => {
+                let key = match argon2_hash_password_with_salt(self.password, salt) {
+                    Ok(key) => key,
+                    Err(error) => return Err(DatabaseError::FailedHash(error.to_string())),
+                };
+                self.keys.push(key);
+                &self.keys[self.keys.len() - 1]
+            }
+        };
+
+        let plaintext = decrypt(ciphertext, key)?;
+        Ok(Zeroizing::new(plaintext.as_slice().to_vec()))
+    }
+
+    fn decrypt_string(
+        &mut self,
+        ciphertext: &EncryptionResult,
+    ) -> Result<Zeroizing<String>, DatabaseError> {
+        let plaintext = self.decrypt(ciphertext)?;
+        match String::from_utf8(plaintext.to_vec()) {
+            Ok(value) => Ok(Zeroizing::new(value)),
+            Err(_error) => Err(DatabaseError::FailedEncryption(String::from(
+                "Secret field is not valid UTF-8",
+            ))),
+        }
+    }
+}
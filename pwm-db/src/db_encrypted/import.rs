@@ -0,0 +1,119 @@
+use std::collections::BTreeSet;
+
+use aes::Aes256;
+use cbc::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use pwm_lib::zeroize::Zeroizing;
+
+use crate::db_base::{error::DatabaseError, GROUP_SEPARATOR};
+use crate::db_encrypted::entry::ImportedEntry;
+
+// Title of entries that have none, numbered if there are several
+pub(crate) static UNTITLED: &str = "Untitled";
+
+// What a file from another password manager holds that can be imported
+#[derive(Default)]
+pub(crate) struct ImportContents {
+    // Named by their group path, in the order of the file
+    pub entries: Vec<ImportedEntry>,
+    // Groups without entries, which would be lost otherwise
+    pub groups: Vec<String>,
+    // Records that aren't imported at all
+    pub skipped: usize,
+    // What was left out of the imported entries, one line each
+    pub unmapped: Vec<String>,
+}
+
+// Group and entry names can't contain the group separator or be empty
+pub(crate) fn part_name(name: &str, default: &str) -> String {
+    let name = name.trim().replace(GROUP_SEPARATOR, "-");
+    if name.is_empty() {
+        default.to_string()
+    } else {
+        name
+    }
+}
+
+// Every entry and group name an import has handed out. Other programs let
+// entries share a title, later ones get a number like "Mail (2)".
+#[derive(Default)]
+pub(crate) struct ImportNames {
+    names: BTreeSet<String>,
+}
+
+impl ImportNames {
+    // Groups are shared by their entries, so taking one twice is fine
+    pub fn take(&mut self, name: &str) {
+        self.names.insert(name.to_string());
+    }
+
+    pub fn unique(&mut self, name: String) -> String {
+        let mut unique = name.clone();
+        let mut number = 2;
+        while self.names.contains(&unique) {
+            unique = format!("{} ({})", name, number);
+            number += 1;
+        }
+        self.names.insert(unique.clone());
+        unique
+    }
+}
+
+// PKCS#7 padded, padding that doesn't check out fails to decrypt
+pub(crate) fn decrypt_aes256_cbc(
+    key: &[u8],
+    iv: &[u8],
+    data: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, DatabaseError> {
+    if iv.len() != 16 {
+        return Err(damaged());
+    }
+    let decryptor = match cbc::Decryptor::<Aes256>::new_from_slices(key, iv) {
+        Ok(decryptor) => decryptor,
+        Err(_error) => return Err(DatabaseError::ImportError(String::from("Invalid key"))),
+    };
+
+    let mut plaintext = Zeroizing::new(data.to_vec());
+    let length = match decryptor.decrypt_padded_mut::<Pkcs7>(&mut plaintext) {
+        Ok(decrypted) => decrypted.len(),
+        Err(_error) => {
+            return Err(DatabaseError::FailedEncryption(String::from(
+                "Failed to decrypt, the padding is invalid",
+            )))
+        }
+    };
+    plaintext.truncate(length);
+    Ok(plaintext)
+}
+
+fn damaged() -> DatabaseError {
+    DatabaseError::ImportError(String::from("The file is damaged"))
+}
+
+#[cfg(test)]
+mod test {
+    use aes::Aes256;
+    use cbc::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+
+    use super::decrypt_aes256_cbc;
+
+    #[test]
+    fn test_decrypt_aes256_cbc() {
+        let (key, iv) = ([7u8; 32], [9u8; 16]);
+        let mut buffer = [0u8; 32];
+        buffer[..6].copy_from_slice(b"secret");
+        let encrypted = cbc::Encryptor::<Aes256>::new(&key.into(), &iv.into())
+            .encrypt_padded_mut::<Pkcs7>(&mut buffer, 6)
+            .unwrap()
+            .to_vec();
+        let decrypted = decrypt_aes256_cbc(&key, &iv, &encrypted).unwrap();
+        assert_eq!(decrypted.as_slice(), b"secret");
+
+        // Only the last byte of the padding is right
+        let mut buffer = *b"secret\x02\x02\x02\x02\x02\x02\x02\x02\x02\x0a";
+        let mut encryptor = cbc::Encryptor::<Aes256>::new(&key.into(), &iv.into());
+        encryptor.encrypt_block_mut((&mut buffer).into());
+        assert!(decrypt_aes256_cbc(&key, &iv, &buffer).is_err());
+        assert!(decrypt_aes256_cbc(&key, &iv, &encrypted[..15]).is_err());
+        assert!(decrypt_aes256_cbc(&key, &iv[..8], &encrypted).is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::BTreeSet;
+
+use pwm_lib::zeroize::{Zeroize, Zeroizing};
+
+use crate::db_base::{error::DatabaseError, Database, Trashed};
+use crate::db_encrypted::entry::EncryptedEntry;
+
+// Number of changes that can be undone, the oldest are dropped first
+pub static MAX_UNDO: usize = 100;
+
+// The entries a change touched, the stored groups and the recycle bin, as
+// they were at one point. Missing entries are None.
+struct Snapshot {
+    entries: Vec<(String, Option<Zeroizing<EncryptedEntry>>)>,
+    groups: BTreeSet<String>,
+    trash: Vec<Trashed<EncryptedEntry>>,
+}
+
+impl Snapshot {
+    fn take(db: &Database<EncryptedEntry>, names: &[String]) -> Self {
+        let entries = names
+            .iter()
+            .map(|name| {
+                let value = db.get(name).ok().cloned().map(Zeroizing::new);
+                (name.clone(), value)
+            })
+            .collect();
+
+        Self {
+            entries,
+            groups: db.stored_groups().clone(),
+            trash: db.trash().to_vec(),
+        }
+    }
+
+    // Adds the entries of a later snapshot that aren't in this one yet, the
+    // earlier state of an entry is kept
+    fn merge(&mut self, mut later: Snapshot) {
+        for (name, value) in std::mem::take(&mut later.entries) {
+            if !self.entries.iter().any(|(known, _value)| *known == name) {
+                self.entries.push((name, value));
+            }
+        }
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|(name, _value)| name.clone())
+            .collect()
+    }
+
+    fn restore(&self, db: &mut Database<EncryptedEntry>) {
+        for (name, value) in self.entries.iter() {
+            let value = value.as_ref().map(|value| (**value).clone());
+            db.restore(name, value);
+        }
+        db.restore_groups(self.groups.clone());
+        db.restore_trash(self.trash.clone());
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        for (name, _value) in self.entries.iter_mut() {
+            name.zeroize();
+        }
+        for mut group in std::mem::take(&mut self.groups) {
+            group.zeroize();
+        }
+        for trashed in self.trash.iter_mut() {
+            trashed.name.zeroize();
+            trashed.value.zeroize();
+        }
+    }
+}
+
+struct Change {
+    description: Zeroizing<String>,
+    before: Snapshot,
+    after: Snapshot,
+}
+
+// The state before a transaction, every entry changed in it is added the
+// first time it is changed
+struct Transaction {
+    before: Snapshot,
+    changed: bool,
+}
+
+// In memory undo and redo stacks, nothing of it is saved. Changes are undone
+// in the reverse order they were made, so restoring the snapshots of one is
+// always restoring the state it was made in.
+#[derive(Default)]
+pub(crate) struct Journal {
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+    // Open transactions, the innermost last
+    transactions: Vec<Transaction>,
+}
+
+impl Journal {
+    pub(crate) fn begin(db: &Database<EncryptedEntry>, names: &[String]) -> PendingChange {
+        PendingChange {
+            before: Snapshot::take(db, names),
+            revision: db.revision(),
+        }
+    }
+
+    // A new change can't be redone over, so the redo stack is cleared
+    pub(crate) fn record(
+        &mut self,
+        db: &Database<EncryptedEntry>,
+        pending: PendingChange,
+        names: &[String],
+        description: String,
+    ) {
+        // Failed operations that didn't change anything aren't recorded
+        if db.revision() == pending.revision {
+            return;
+        }
+
+        // Changes in a transaction are recorded when it is committed
+        if let Some(transaction) = self.transactions.last_mut() {
+            transaction.before.merge(pending.before);
+            transaction.changed = true;
+            return;
+        }
+
+        self.push(Change {
+            description: Zeroizing::new(description),
+            before: pending.before,
+            after: Snapshot::take(db, names),
+        });
+    }
+
+    fn push(&mut self, change: Change) {
+        self.undo.push(change);
+        if self.undo.len() > MAX_UNDO {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    pub(crate) fn in_transaction(&self) -> bool {
+        !self.transactions.is_empty()
+    }
+
+    pub(crate) fn begin_transaction(&mut self, db: &Database<EncryptedEntry>) {
+        self.transactions.push(Transaction {
+            before: Snapshot::take(db, &[]),
+            changed: false,
+        });
+    }
+
+    // A nested transaction becomes part of the one around it, the outermost
+    // is recorded as a single change
+    pub(crate) fn commit_transaction(
+        &mut self,
+        db: &Database<EncryptedEntry>,
+        description: String,
+    ) -> Result<(), DatabaseError> {
+        let transaction = match self.transactions.pop() {
+            Some(transaction) => transaction,
+            None => return Err(DatabaseError::NoTransaction),
+        };
+        if !transaction.changed {
+            return Ok(());
+        }
+
+        match self.transactions.last_mut() {
+            Some(outer) => {
+                outer.before.merge(transaction.before);
+                outer.changed = true;
+            }
+            None => {
+                let names = transaction.before.names();
+                self.push(Change {
+                    description: Zeroizing::new(description),
+                    before: transaction.before,
+                    after: Snapshot::take(db, &names),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Puts back every entry and group changed since the transaction began
+    pub(crate) fn rollback_transaction(
+        &mut self,
+        db: &mut Database<EncryptedEntry>,
+    ) -> Result<(), DatabaseError> {
+        let transaction = match self.transactions.pop() {
+            Some(transaction) => transaction,
+            None => return Err(DatabaseError::NoTransaction),
+        };
+        if transaction.changed {
+            transaction.before.restore(db);
+        }
+        Ok(())
+    }
+
+    // Returns what was undone
+    pub(crate) fn undo(
+        &mut self,
+        db: &mut Database<EncryptedEntry>,
+    ) -> Result<Zeroizing<String>, DatabaseError> {
+        if self.in_transaction() {
+            return Err(DatabaseError::TransactionInProgress);
+        }
+        let change = match self.undo.pop() {
+            Some(change) => change,
+            None => return Err(DatabaseError::NothingToUndo),
+        };
+
+        change.before.restore(db);
+        let description = change.description.clone();
+        self.redo.push(change);
+        Ok(description)
+    }
+
+    // Returns what was redone
+    pub(crate) fn redo(
+        &mut self,
+        db: &mut Database<EncryptedEntry>,
+    ) -> Result<Zeroizing<String>, DatabaseError> {
+        if self.in_transaction() {
+            return Err(DatabaseError::TransactionInProgress);
+        }
+        let change = match self.redo.pop() {
+            Some(change) => change,
+            None => return Err(DatabaseError::NothingToRedo),
+        };
+
+        change.after.restore(db);
+        let description = change.description.clone();
+        self.undo.push(change);
+        Ok(description)
+    }
+}
+
+// The state before an operation, recorded once it is done
+pub(crate) struct PendingChange {
+    before: Snapshot,
+    revision: u64,
+}
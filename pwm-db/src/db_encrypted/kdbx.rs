@@ -0,0 +1,443 @@
+use std::io::Read;
+
+use base64ct::{Base64, Encoding};
+use log::warn;
+use pwm_lib::{hash::sha_wrapper::sha256_hash, zeroize::Zeroizing};
+
+use crate::db_base::{error::DatabaseError, join_group};
+
+use super::{
+    entry::{CustomField, Entry, ImportedEntry, PasswordHistory},
+    import::{part_name, ImportContents, ImportNames, UNTITLED},
+};
+
+mod container;
+mod xml;
+
+use xml::Node;
+
+// Seconds between 0001-01-01, where KeePass counts from, and the Unix epoch
+static KDBX_EPOCH_OFFSET: i64 = 62135596800;
+
+// Only checks the signature, not the version or the key
+pub fn is_kdbx_file(path: &str) -> bool {
+    let mut signature = [0u8; 8];
+    match std::fs::File::open(path) {
+        Ok(mut file) => file.read_exact(&mut signature).is_ok() && container::is_kdbx(&signature),
+        Err(_error) => false,
+    }
+}
+
+pub(crate) fn read_kdbx(
+    file: &str,
+    password: &[u8],
+    key_file: Option<&str>,
+) -> Result<ImportContents, DatabaseError> {
+    let data = std::fs::read(file)?;
+    if !container::is_kdbx(&data) {
+        return Err(DatabaseError::ImportError(format!(
+            "{} isn't a KeePass database",
+            file
+        )));
+    }
+
+    let key = composite_key(password, key_file)?;
+    let payload = container::decrypt(&data, key.as_slice())?;
+    let document = xml::parse(&payload.xml, &payload.stream_key)?;
+
+    let root = match document.child("Root").and_then(|root| root.child("Group")) {
+        Some(root) => root,
+        None => {
+            return Err(DatabaseError::ImportError(String::from(
+                "The database has no groups",
+            )))
+        }
+    };
+
+    // The recycle bin is only used if it is enabled
+    let meta = document.child("Meta");
+    let recycle_bin = match meta {
+        Some(meta)
+            if !meta
+                .child_text("RecycleBinEnabled")
+                .eq_ignore_ascii_case("false") =>
+        {
+            meta.child_text("RecycleBinUUID").trim().to_string()
+        }
+        _ => String::new(),
+    };
+
+    let mut reader = KdbxReader {
+        binaries: &payload.binaries,
+        recycle_bin,
+        names: ImportNames::default(),
+        contents: ImportContents::default(),
+    };
+    // The root group is the database itself, its name isn't a group. Entries
+    // in the recycle bin are counted as skipped.
+    reader.read_group(root, "")?;
+    Ok(reader.contents)
+}
+
+// SHA-256 of the hashed password followed by the key from the key file. A
+// key file alone leaves the password out, like KeePass does.
+fn composite_key(
+    password: &[u8],
+    key_file: Option<&str>,
+) -> Result<Zeroizing<[u8; 32]>, DatabaseError> {
+    let mut keys = Zeroizing::new(Vec::with_capacity(64));
+    if !password.is_empty() || key_file.is_none() {
+        let mut hash = Zeroizing::new([0u8; 32]);
+        sha256_hash(password, hash.as_mut_slice())?;
+        keys.extend_from_slice(hash.as_slice());
+    }
+    if let Some(key_file) = key_file {
+        let data = Zeroizing::new(std::fs::read(key_file)?);
+        keys.extend_from_slice(key_file_key(&data)?.as_slice());
+    }
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    sha256_hash(&keys, key.as_mut_slice())?;
+    Ok(key)
+}
+
+// XML key files hold the key in hex (version 2.0) or base64 (version 1.0).
+// Other files are used as they are if they are 32 bytes or 64 hex digits,
+// anything else is hashed.
+fn key_file_key(data: &[u8]) -> Result<Zeroizing<Vec<u8>>, DatabaseError> {
+    let text = String::from_utf8_lossy(data);
+    if text.trim_start().starts_with('<') {
+        // Key files have no protected values, so no stream key is needed
+        let document = xml::parse(data, &[])?;
+        let key = document.child("Key");
+        let value = Zeroizing::new(match key.and_then(|key| key.child("Data")) {
+            Some(value) => value.text.split_whitespace().collect::<String>(),
+            None => return Err(key_file_error()),
+        });
+        let version = match document.child("Meta") {
+            Some(meta) => meta.child_text("Version").trim().to_string(),
+            None => String::new(),
+        };
+        let key = if version.starts_with("2.") {
+            decode_hex(&value)
+        } else {
+            Base64::decode_vec(&value).ok().map(Zeroizing::new)
+        };
+        return match key {
+            Some(key) => Ok(key),
+            None => Err(key_file_error()),
+        };
+    }
+
+    if data.len() == 32 {
+        return Ok(Zeroizing::new(data.to_vec()));
+    }
+    if data.len() == 64 {
+        if let Some(key) = decode_hex(&text) {
+            return Ok(key);
+        }
+    }
+    let mut key = Zeroizing::new(vec![0u8; 32]);
+    sha256_hash(data, key.as_mut_slice())?;
+    Ok(key)
+}
+
+fn decode_hex(text: &str) -> Option<Zeroizing<Vec<u8>>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    let mut bytes = Zeroizing::new(Vec::with_capacity(text.len() / 2));
+    for index in (0..text.len()).step_by(2) {
+        bytes.push(u8::from_str_radix(&text[index..index + 2], 16).ok()?);
+    }
+    Some(bytes)
+}
+
+fn key_file_error() -> DatabaseError {
+    DatabaseError::ImportError(String::from("The key file isn't a KeePass key file"))
+}
+
+struct KdbxReader<'a> {
+    binaries: &'a [Zeroizing<Vec<u8>>],
+    recycle_bin: String,
+    names: ImportNames,
+    contents: ImportContents,
+}
+
+impl KdbxReader<'_> {
+    // Subgroups are named before the entries, so an entry named like a
+    // group is the one that gets a number
+    fn read_group(&mut self, group: &Node, path: &str) -> Result<(), DatabaseError> {
+        let mut subgroups = Vec::new();
+        for subgroup in group.children("Group") {
+            if !self.recycle_bin.is_empty()
+                && subgroup.child_text("UUID").trim() == self.recycle_bin
+            {
+                self.contents.skipped += count_entries(subgroup);
+                continue;
+            }
+            let name = join_group(path, &part_name(subgroup.child_text("Name"), "Group"));
+            self.names.take(&name);
+            subgroups.push((subgroup, name));
+        }
+
+        let mut empty = subgroups.is_empty();
+        for entry in group.children("Entry") {
+            let imported = self.read_entry(entry, path)?;
+            self.contents.entries.push(imported);
+            empty = false;
+        }
+        if empty && !path.is_empty() {
+            self.contents.groups.push(path.to_string());
+        }
+
+        for (subgroup, name) in subgroups {
+            self.read_group(subgroup, &name)?;
+        }
+        Ok(())
+    }
+
+    fn read_entry(&mut self, node: &Node, path: &str) -> Result<ImportedEntry, DatabaseError> {
+        let mut entry = read_strings(node);
+        let title = join_group(path, &part_name(&entry.title, UNTITLED));
+        entry.title = self.names.unique(title);
+
+        let mut attachments = Vec::new();
+        for binary in node.children("Binary") {
+            let name = binary.child_text("Key");
+            let contents = binary
+                .child("Value")
+                .and_then(|value| value.reference)
+                .and_then(|reference| self.binaries.get(reference));
+            match contents {
+                Some(contents) => attachments.push((name.to_string(), contents.clone())),
+                None => warn!("pwm-db: Attachment {} of {} is missing", name, entry.title),
+            }
+        }
+
+        let tags = node
+            .child_text("Tags")
+            .split([';', ','])
+            .map(|tag| tag.split_whitespace().collect::<Vec<&str>>().join("-"))
+            .filter(|tag| !tag.is_empty())
+            .collect();
+
+        // Older versions come first, each one's password was replaced when
+        // the next one was saved
+        let mut versions: Vec<&Node> = match node.child("History") {
+            Some(history) => history.children("Entry").collect(),
+            None => Vec::new(),
+        };
+        versions.push(node);
+        let mut history = Vec::new();
+        for pair in versions.windows(2) {
+            let old = read_strings(pair[0]).password;
+            let new = read_strings(pair[1]).password;
+            if old != new {
+                history.insert(
+                    0,
+                    PasswordHistory {
+                        password: old,
+                        replaced: time(pair[1], "LastModificationTime"),
+                    },
+                );
+            }
+        }
+
+        Ok(ImportedEntry {
+            entry,
+            tags,
+            history,
+            attachments,
+            created: time(node, "CreationTime"),
+            modified: time(node, "LastModificationTime"),
+            accessed: time(node, "LastAccessTime"),
+        })
+    }
+}
+
+// The standard strings become the built in fields, the rest custom fields
+fn read_strings(node: &Node) -> Entry {
+    let mut entry = Entry::default();
+    for string in node.children("String") {
+        let key = string.child_text("Key");
+        let value = match string.child("Value") {
+            Some(value) => value,
+            None => continue,
+        };
+        match key {
+            "Title" => entry.title = value.text.to_string(),
+            "UserName" => entry.username = value.text.to_string(),
+            "Password" => entry.password = Zeroizing::new(value.text.as_bytes().to_vec()),
+            "URL" => {
+                if !value.text.is_empty() {
+                    entry.urls.push(value.text.to_string());
+                }
+            }
+            "Notes" => entry.notes = value.text.to_string(),
+            "" => (),
+            _ => {
+                // Names are case sensitive in KeePass, so "url" is a custom field there
+                let name = if Entry::is_builtin_field(key) {
+                    format!("{} (KeePass)", key)
+                } else {
+                    key.to_string()
+                };
+                entry
+                    .fields
+                    .push(CustomField::new(&name, &value.text, value.protected));
+            }
+        }
+    }
+    entry
+}
+
+fn count_entries(group: &Node) -> usize {
+    group.children("Entry").count() + group.children("Group").map(count_entries).sum::<usize>()
+}
+
+// KDBX 4 stores times as base64 of little endian seconds since year 1, 0 if
+// the time is missing or in another format
+fn time(node: &Node, name: &str) -> i64 {
+    let value = match node.child("Times") {
+        Some(times) => times.child_text(name).trim(),
+        None => return 0,
+    };
+    match Base64::decode_vec(value) {
+        Ok(bytes) if bytes.len() == 8 => {
+            let mut seconds = [0u8; 8];
+            seconds.copy_from_slice(&bytes);
+            (i64::from_le_bytes(seconds) - KDBX_EPOCH_OFFSET).max(0)
+        }
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    static FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/kdbx/");
+
+    fn fixture(name: &str) -> String {
+        format!("{}{}", FIXTURES, name)
+    }
+
+    fn titles(contents: &ImportContents) -> Vec<&str> {
+        contents
+            .entries
+            .iter()
+            .map(|imported| imported.entry.title.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_read_kdbx() {
+        assert!(is_kdbx_file(&fixture("aes-argon2d.kdbx")));
+        assert!(!is_kdbx_file(&fixture("keyfile.keyx")));
+
+        let key_file = fixture("keyfile.keyx");
+        for (name, key_file) in [
+            ("aes-argon2d.kdbx", None),
+            ("aes-aeskdf.kdbx", None),
+            ("chacha20-argon2id-keyfile.kdbx", Some(key_file.as_str())),
+        ] {
+            let contents = read_kdbx(&fixture(name), b"test", key_file).unwrap();
+            assert_eq!(
+                titles(&contents),
+                vec!["Mail", "Mail (2)", "Work/Server", "Work/Untitled"]
+            );
+            assert_eq!(contents.groups, vec![String::from("Work/Empty")]);
+            assert_eq!(contents.skipped, 1);
+
+            let mail = &contents.entries[0];
+            assert_eq!(mail.entry.username, "someone@example.com");
+            assert_eq!(mail.entry.password.as_slice(), b"current-Pass3");
+            assert_eq!(
+                mail.entry.urls,
+                vec![String::from("https://mail.example.com")]
+            );
+            assert_eq!(mail.entry.notes, "Recovery codes are attached");
+            assert_eq!(
+                mail.entry.fields,
+                vec![
+                    CustomField::new("Security question", "blue", false),
+                    CustomField::new("otp", "otpauth://totp/Mail?secret=JBSWY3DPEHPK3PXP", true),
+                ]
+            );
+            assert_eq!(
+                mail.tags,
+                vec![String::from("personal"), String::from("Important")]
+            );
+            assert_eq!(mail.created, 1600000000);
+            assert_eq!(mail.modified, 1700000000);
+            assert_eq!(
+                mail.history,
+                vec![
+                    PasswordHistory {
+                        password: Zeroizing::new(b"second-Pass2".to_vec()),
+                        replaced: 1700000000,
+                    },
+                    PasswordHistory {
+                        password: Zeroizing::new(b"first-Pass1".to_vec()),
+                        replaced: 1650000000,
+                    },
+                ]
+            );
+
+            let attachments: Vec<(&str, &[u8])> = mail
+                .attachments
+                .iter()
+                .map(|(name, contents)| (name.as_str(), contents.as_slice()))
+                .collect();
+            let photo: Vec<u8> = (0..4).flat_map(|_| 0..=255u8).collect();
+            assert_eq!(
+                attachments,
+                vec![
+                    ("codes.txt", b"1111-2222\n3333-4444\n".as_slice()),
+                    ("photo.bin", photo.as_slice()),
+                ]
+            );
+
+            assert_eq!(contents.entries[2].entry.password.as_slice(), b"Srv&<pass>");
+            assert_eq!(contents.entries[3].entry.password.as_slice(), b"no-title");
+        }
+    }
+
+    #[test]
+    fn test_read_kdbx_wrong_key() {
+        assert!(read_kdbx(&fixture("aes-argon2d.kdbx"), b"wrong", None).is_err());
+        assert!(read_kdbx(&fixture("chacha20-argon2id-keyfile.kdbx"), b"test", None).is_err());
+        assert!(read_kdbx(&fixture("keyfile.keyx"), b"test", None).is_err());
+    }
+
+    #[test]
+    fn test_key_file_key() {
+        let raw = [7u8; 32];
+        assert_eq!(key_file_key(&raw).unwrap().as_slice(), raw.as_slice());
+
+        let hex = "07".repeat(32);
+        assert_eq!(
+            key_file_key(hex.as_bytes()).unwrap().as_slice(),
+            raw.as_slice()
+        );
+
+        let xml = "<KeyFile><Meta><Version>2.0</Version></Meta><Key>\
+                   <Data Hash=\"0\">0707 0707 0707 0707\n0707070707070707\
+                   0707070707070707 0707070707070707</Data></Key></KeyFile>";
+        assert_eq!(
+            key_file_key(xml.as_bytes()).unwrap().as_slice(),
+            raw.as_slice()
+        );
+
+        let xml = "<KeyFile><Meta><Version>1.00</Version></Meta><Key>\
+                   <Data>BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=</Data></Key></KeyFile>";
+        assert_eq!(
+            key_file_key(xml.as_bytes()).unwrap().as_slice(),
+            raw.as_slice()
+        );
+
+        // Anything else is hashed
+        assert_eq!(key_file_key(b"some file").unwrap().len(), 32);
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use aes::{
+    cipher::{BlockEncrypt, KeyInit, KeyIvInit, StreamCipher},
+    Aes256,
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20::ChaCha20;
+use flate2::read::GzDecoder;
+use pwm_lib::{
+    hash::{
+        compare_hash,
+        sha_wrapper::{hmac_sha256, sha256_hash, sha512_hash},
+    },
+    zeroize::Zeroizing,
+};
+
+use crate::db_base::error::DatabaseError;
+use crate::db_encrypted::import::decrypt_aes256_cbc;
+
+static SIGNATURE_1: u32 = 0x9AA2D903;
+static SIGNATURE_2: u32 = 0xB54BFB67;
+
+static CIPHER_AES256: [u8; 16] = uuid(0x31c1f2e6bf714350be5805216afc5aff);
+static CIPHER_CHACHA20: [u8; 16] = uuid(0xd6038a2b8b6f4cb5a524339a31dbb59a);
+static KDF_AES: [u8; 16] = uuid(0xc9d9f39a628a4460bf740d08c18a4fea);
+static KDF_ARGON2D: [u8; 16] = uuid(0xef636ddf8c29444b91f7a9a403e30a0c);
+static KDF_ARGON2ID: [u8; 16] = uuid(0x9e298b1956db4773b23dfc3ef6f0a1e6);
+
+// Outer header fields
+const HEADER_END: u8 = 0;
+const HEADER_CIPHER: u8 = 2;
+const HEADER_COMPRESSION: u8 = 3;
+const HEADER_MASTER_SEED: u8 = 4;
+const HEADER_IV: u8 = 7;
+const HEADER_KDF: u8 = 11;
+
+// Inner header fields
+const INNER_END: u8 = 0;
+const INNER_STREAM_ID: u8 = 1;
+const INNER_STREAM_KEY: u8 = 2;
+const INNER_BINARY: u8 = 3;
+
+// The only inner stream KDBX 4 writers use
+const STREAM_CHACHA20: u32 = 3;
+
+const fn uuid(value: u128) -> [u8; 16] {
+    value.to_be_bytes()
+}
+
+// The decrypted inner part of a file
+pub(crate) struct Payload {
+    pub xml: Zeroizing<Vec<u8>>,
+    // Attachments, referenced by index from the XML
+    pub binaries: Vec<Zeroizing<Vec<u8>>>,
+    // Key of the stream that protected values are XORed with
+    pub stream_key: Zeroizing<Vec<u8>>,
+}
+
+pub(crate) fn is_kdbx(data: &[u8]) -> bool {
+    let mut input = Input::new(data);
+    match (input.u32(), input.u32()) {
+        (Ok(first), Ok(second)) => first == SIGNATURE_1 && second == SIGNATURE_2,
+        _ => false,
+    }
+}
+
+// composite_key is SHA-256 over the hashed password and key file
+pub(crate) fn decrypt(data: &[u8], composite_key: &[u8]) -> Result<Payload, DatabaseError> {
+    let mut input = Input::new(data);
+    if !is_kdbx(data) {
+        return Err(import_error("Not a KeePass database"));
+    }
+    input.take(8)?;
+    let minor = input.u16()?;
+    let major = input.u16()?;
+    if major != 4 {
+        return Err(DatabaseError::ImportError(format!(
+            "KDBX {}.{} isn't supported, save the database as KDBX 4 first",
+            major, minor
+        )));
+    }
+
+    let mut cipher = None;
+    let mut compressed = false;
+    let mut master_seed = None;
+    let mut iv = None;
+    let mut kdf = None;
+    loop {
+        let id = input.u8()?;
+        let size = input.u32()? as usize;
+        let value = input.take(size)?;
+        match id {
+            HEADER_END => break,
+            HEADER_CIPHER => cipher = Some(value),
+            HEADER_COMPRESSION => compressed = Input::new(value).u32()? == 1,
+            HEADER_MASTER_SEED => master_seed = Some(value),
+            HEADER_IV => iv = Some(value),
+            HEADER_KDF => kdf = Some(read_variant_dictionary(value)?),
+            _ => (),
+        }
+    }
+    let header = &data[..input.position];
+
+    let (cipher, master_seed, iv, kdf) = match (cipher, master_seed, iv, kdf) {
+        (Some(cipher), Some(master_seed), Some(iv), Some(kdf)) => (cipher, master_seed, iv, kdf),
+        _ => return Err(import_error("The header is incomplete")),
+    };
+
+    let mut hash = [0u8; 32];
+    sha256_hash(header, &mut hash)?;
+    if !compare_hash(&hash, input.take(32)?) {
+        return Err(import_error("The header is damaged"));
+    }
+
+    let transformed = transform_key(composite_key, &kdf)?;
+    let mut seeded = Zeroizing::new(Vec::with_capacity(65));
+    seeded.extend_from_slice(master_seed);
+    seeded.extend_from_slice(transformed.as_slice());
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    sha256_hash(&seeded, key.as_mut_slice())?;
+    seeded.push(1);
+    let mut hmac_key = Zeroizing::new([0u8; 64]);
+    sha512_hash(&seeded, hmac_key.as_mut_slice())?;
+
+    // A wrong password or key file is only noticed here
+    let mut mac = [0u8; 32];
+    hmac_sha256(
+        block_key(hmac_key.as_slice(), u64::MAX)?.as_slice(),
+        header,
+        &mut mac,
+    )?;
+    if !compare_hash(&mac, input.take(32)?) {
+        return Err(import_error("Wrong KeePass password or key file"));
+    }
+
+    let mut encrypted = Vec::new();
+    for index in 0u64.. {
+        let stored_mac = input.take(32)?;
+        let size_bytes = input.take(4)?;
+        let size = Input::new(size_bytes).u32()? as usize;
+        let block = input.take(size)?;
+
+        let mut message = Vec::with_capacity(12 + size);
+        message.extend_from_slice(&index.to_le_bytes());
+        message.extend_from_slice(size_bytes);
+        message.extend_from_slice(block);
+        hmac_sha256(
+            block_key(hmac_key.as_slice(), index)?.as_slice(),
+            &message,
+            &mut mac,
+        )?;
+        if !compare_hash(&mac, stored_mac) {
+            return Err(import_error("The database is damaged"));
+        }
+
+        if size == 0 {
+            break;
+        }
+        encrypted.extend_from_slice(block);
+    }
+
+    let decrypted = if cipher == CIPHER_AES256 {
+        decrypt_aes256_cbc(key.as_slice(), iv, &encrypted)?
+    } else if cipher == CIPHER_CHACHA20 {
+        if iv.len() != 12 {
+            return Err(import_error("The header is damaged"));
+        }
+        let mut data = Zeroizing::new(encrypted);
+        ChaCha20::new(key.as_slice().into(), iv.into()).apply_keystream(&mut data);
+        data
+    } else {
+        return Err(import_error(
+            "The database uses a cipher pwm doesn't support, use AES or ChaCha20",
+        ));
+    };
+
+    let inner = if compressed {
+        let mut inner = Zeroizing::new(Vec::new());
+        if let Err(error) = GzDecoder::new(decrypted.as_slice()).read_to_end(&mut inner) {
+            return Err(DatabaseError::CompressionError(error.to_string()));
+        }
+        inner
+    } else {
+        decrypted
+    };
+
+    read_inner(&inner)
+}
+
+fn read_inner(inner: &[u8]) -> Result<Payload, DatabaseError> {
+    let mut input = Input::new(inner);
+    let mut stream_key = None;
+    let mut binaries = Vec::new();
+    loop {
+        let id = input.u8()?;
+        let size = input.u32()? as usize;
+        let value = input.take(size)?;
+        match id {
+            INNER_END => break,
+            INNER_STREAM_ID if Input::new(value).u32()? != STREAM_CHACHA20 => {
+                return Err(import_error("Unsupported inner stream, expected ChaCha20"));
+            }
+            INNER_STREAM_KEY => stream_key = Some(Zeroizing::new(value.to_vec())),
+            // The first byte only says whether KeePass keeps it protected in
+            // memory
+            INNER_BINARY if !value.is_empty() => binaries.push(Zeroizing::new(value[1..].to_vec())),
+            _ => (),
+        }
+    }
+
+    match stream_key {
+        Some(stream_key) => Ok(Payload {
+            xml: Zeroizing::new(inner[input.position..].to_vec()),
+            binaries,
+            stream_key,
+        }),
+        None => Err(import_error("The inner header is incomplete")),
+    }
+}
+
+fn transform_key(
+    composite_key: &[u8],
+    kdf: &BTreeMap<String, Vec<u8>>,
+) -> Result<Zeroizing<[u8; 32]>, DatabaseError> {
+    let id = dictionary_bytes(kdf, "$UUID")?;
+    let salt = dictionary_bytes(kdf, "S")?;
+    let mut transformed = Zeroizing::new([0u8; 32]);
+
+    if id == KDF_AES {
+        let rounds = dictionary_u64(kdf, "R")?;
+        let cipher = match Aes256::new_from_slice(salt) {
+            Ok(cipher) => cipher,
+            Err(_error) => return Err(import_error("Invalid AES-KDF seed")),
+        };
+        transformed.copy_from_slice(composite_key);
+        for _ in 0..rounds {
+            for block in transformed.chunks_exact_mut(16) {
+                cipher.encrypt_block(block.into());
+            }
+        }
+        let rounded = Zeroizing::new(*transformed);
+        sha256_hash(rounded.as_slice(), transformed.as_mut_slice())?;
+        return Ok(transformed);
+    }
+
+    let algorithm = if id == KDF_ARGON2D {
+        Algorithm::Argon2d
+    } else if id == KDF_ARGON2ID {
+        Algorithm::Argon2id
+    } else {
+        return Err(import_error("Unsupported key derivation function"));
+    };
+    let version = match dictionary_u64(kdf, "V")? {
+        0x10 => Version::V0x10,
+        0x13 => Version::V0x13,
+        _ => return Err(import_error("Unsupported Argon2 version")),
+    };
+    let memory = dictionary_u64(kdf, "M")? / 1024;
+    let iterations = dictionary_u64(kdf, "I")?;
+    let parallelism = dictionary_u64(kdf, "P")?;
+
+    let params = match Params::new(
+        memory as u32,
+        iterations as u32,
+        parallelism as u32,
+        Some(32),
+    ) {
+        Ok(params) => params,
+        Err(error) => return Err(DatabaseError::ImportError(error.to_string())),
+    };
+    if let Err(error) = Argon2::new(algorithm, version, params).hash_password_into(
+        composite_key,
+        salt,
+        transformed.as_mut_slice(),
+    ) {
+        return Err(DatabaseError::FailedHash(error.to_string()));
+    }
+
+    Ok(transformed)
+}
+
+fn block_key(hmac_key: &[u8], index: u64) -> Result<Zeroizing<[u8; 64]>, DatabaseError> {
+    let mut data = Zeroizing::new(Vec::with_capacity(72));
+    data.extend_from_slice(&index.to_le_bytes());
+    data.extend_from_slice(hmac_key);
+    let mut key = Zeroizing::new([0u8; 64]);
+    sha512_hash(&data, key.as_mut_slice())?;
+    Ok(key)
+}
+
+// Keys and raw values, the KDF parameters only use fixed size numbers and
+// byte arrays
+fn read_variant_dictionary(data: &[u8]) -> Result<BTreeMap<String, Vec<u8>>, DatabaseError> {
+    let mut input = Input::new(data);
+    if input.u16()? >> 8 != 1 {
+        return Err(import_error("Unsupported KDF parameters"));
+    }
+
+    let mut dictionary = BTreeMap::new();
+    loop {
+        let kind = input.u8()?;
+        if kind == 0 {
+            break;
+        }
+        let size = input.u32()? as usize;
+        let key = String::from_utf8_lossy(input.take(size)?).to_string();
+        let size = input.u32()? as usize;
+        dictionary.insert(key, input.take(size)?.to_vec());
+    }
+
+    Ok(dictionary)
+}
+
+fn dictionary_bytes<'a>(
+    dictionary: &'a BTreeMap<String, Vec<u8>>,
+    key: &str,
+) -> Result<&'a [u8], DatabaseError> {
+    match dictionary.get(key) {
+        Some(value) => Ok(value),
+        None => Err(DatabaseError::ImportError(format!(
+            "The KDF parameter {} is missing",
+            key
+        ))),
+    }
+}
+
+fn dictionary_u64(dictionary: &BTreeMap<String, Vec<u8>>, key: &str) -> Result<u64, DatabaseError> {
+    let value = dictionary_bytes(dictionary, key)?;
+    let mut input = Input::new(value);
+    match value.len() {
+        4 => Ok(input.u32()? as u64),
+        8 => input.u64(),
+        _ => Err(DatabaseError::ImportError(format!(
+            "The KDF parameter {} is invalid",
+            key
+        ))),
+    }
+}
+
+fn import_error(message: &str) -> DatabaseError {
+    DatabaseError::ImportError(String::from(message))
+}
+
+// Little endian reads that fail at the end of the data instead of panicking
+struct Input<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Input<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], DatabaseError> {
+        match self
+            .data
+            .get(self.position..self.position.saturating_add(count))
+        {
+            Some(value) => {
+                self.position += count;
+                Ok(value)
+            }
+            None => Err(import_error("The database is cut off")),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, DatabaseError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DatabaseError> {
+        let mut bytes = [0u8; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    fn u32(&mut self) -> Result<u32, DatabaseError> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, DatabaseError> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
@@ -0,0 +1,152 @@
+use base64ct::{Base64, Encoding};
+use chacha20::{
+    cipher::{KeyIvInit, StreamCipher},
+    ChaCha20,
+};
+use pwm_lib::{hash::sha_wrapper::sha512_hash, zeroize::Zeroizing};
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+
+use crate::db_base::error::DatabaseError;
+
+// An element of the decrypted XML, protected values are already in plain text
+#[derive(Default)]
+pub(crate) struct Node {
+    pub name: String,
+    pub text: Zeroizing<String>,
+    pub protected: bool,
+    // Index into the binaries of the inner header
+    pub reference: Option<usize>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    pub fn child(&self, name: &str) -> Option<&Node> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    pub fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Node> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    // Empty if there is no such child
+    pub fn child_text(&self, name: &str) -> &str {
+        match self.child(name) {
+            Some(child) => child.text.as_str(),
+            None => "",
+        }
+    }
+}
+
+// Values marked Protected="True" are XORed with one ChaCha20 stream in
+// document order, so they are decrypted while reading
+pub(crate) fn parse(xml: &[u8], stream_key: &[u8]) -> Result<Node, DatabaseError> {
+    let xml = match std::str::from_utf8(xml) {
+        Ok(xml) => xml,
+        Err(_error) => return Err(xml_error("The XML isn't valid UTF-8")),
+    };
+
+    let mut digest = Zeroizing::new([0u8; 64]);
+    sha512_hash(stream_key, digest.as_mut_slice())?;
+    let mut stream = ChaCha20::new(digest[..32].into(), digest[32..44].into());
+
+    let mut reader = Reader::from_str(xml);
+    let mut stack: Vec<Node> = Vec::new();
+    loop {
+        let event = match reader.read_event() {
+            Ok(event) => event,
+            Err(error) => return Err(xml_error(&error.to_string())),
+        };
+        match event {
+            Event::Start(element) => stack.push(start_node(&element)?),
+            Event::Empty(element) => {
+                let node = start_node(&element)?;
+                if let Some(root) = finish_node(&mut stack, node, &mut stream)? {
+                    return Ok(root);
+                }
+            }
+            Event::Text(text) => {
+                if let Some(node) = stack.last_mut() {
+                    match text.unescape() {
+                        Ok(text) => node.text.push_str(&text),
+                        Err(error) => return Err(xml_error(&error.to_string())),
+                    }
+                }
+            }
+            Event::CData(text) => {
+                if let Some(node) = stack.last_mut() {
+                    node.text.push_str(&String::from_utf8_lossy(&text));
+                }
+            }
+            Event::End(_element) => {
+                let node = match stack.pop() {
+                    Some(node) => node,
+                    None => return Err(xml_error("Unexpected end of an element")),
+                };
+                if let Some(root) = finish_node(&mut stack, node, &mut stream)? {
+                    return Ok(root);
+                }
+            }
+            Event::Eof => return Err(xml_error("The XML is cut off")),
+            _ => (),
+        }
+    }
+}
+
+fn start_node(element: &BytesStart) -> Result<Node, DatabaseError> {
+    let mut node = Node {
+        name: String::from_utf8_lossy(element.name().as_ref()).to_string(),
+        ..Node::default()
+    };
+
+    for attribute in element.attributes() {
+        let attribute = match attribute {
+            Ok(attribute) => attribute,
+            Err(error) => return Err(xml_error(&error.to_string())),
+        };
+        let value = match attribute.unescape_value() {
+            Ok(value) => value,
+            Err(error) => return Err(xml_error(&error.to_string())),
+        };
+        match attribute.key.as_ref() {
+            b"Protected" => node.protected = value.eq_ignore_ascii_case("true"),
+            b"Ref" => node.reference = value.parse().ok(),
+            _ => (),
+        }
+    }
+
+    Ok(node)
+}
+
+// Adds the node to its parent, the root is returned once it is complete
+fn finish_node(
+    stack: &mut [Node],
+    mut node: Node,
+    stream: &mut ChaCha20,
+) -> Result<Option<Node>, DatabaseError> {
+    if node.protected {
+        let mut value = match Base64::decode_vec(node.text.trim()) {
+            Ok(value) => Zeroizing::new(value),
+            Err(_error) => return Err(xml_error("A protected value isn't base64")),
+        };
+        stream.apply_keystream(&mut value);
+        node.text = match String::from_utf8(value.to_vec()) {
+            Ok(text) => Zeroizing::new(text),
+            Err(_error) => return Err(xml_error("A protected value isn't valid UTF-8")),
+        };
+    }
+
+    match stack.last_mut() {
+        Some(parent) => {
+            parent.children.push(node);
+            Ok(None)
+        }
+        None => Ok(Some(node)),
+    }
+}
+
+fn xml_error(message: &str) -> DatabaseError {
+    DatabaseError::ImportError(format!("Invalid XML: {}", message))
+}
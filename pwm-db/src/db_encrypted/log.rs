@@ -0,0 +1,758 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+#[cfg(feature = "use-compression")]
+use lz4_flex::{compress_prepend_size, decompress_size_prepended};
+use pwm_lib::{
+    atomic_file::write_atomic_with,
+    encryption::{
+        default::{decrypt_with_aad, encrypt_with_aad},
+        EncryptionResult,
+    },
+    hash::{
+        argon2_wrapper::{
+            argon2_hash_password_cancellable, argon2_hash_password_with_salt_cancellable,
+        },
+        sha_wrapper::sha256_hash,
+        HashResult,
+    },
+    progress::Progress,
+    zeroize::Zeroizing,
+};
+
+use crate::db_base::{error::DatabaseError, Trashed};
+use crate::db_encrypted::entry::EncryptedEntry;
+use crate::db_encrypted::migration::{read_trashed, write_trashed};
+use crate::db_encrypted::tlv::{read_string, Reader, Writer};
+use crate::db_encrypted::DatabaseEncrypted;
+
+// Log files start with this, the log version and the salt of the key every
+// record is encrypted with. See FORMAT.md.
+static LOG_MAGIC: &[u8; 4] = b"PWML";
+const LOG_VERSION: u8 = 1;
+const SALT_LENGTH: usize = 32;
+const HEADER_LENGTH: usize = LOG_MAGIC.len() + 1 + SALT_LENGTH;
+
+// Two copies of the head follow the header, a save overwrites the older one
+// so a torn write still leaves the previous head
+const SLOT_LENGTH: usize = 160;
+const FRAMES_START: usize = HEADER_LENGTH + 2 * SLOT_LENGTH;
+
+// Records appended since the last snapshot before a save compacts the log
+pub static COMPACT_AFTER: usize = 1000;
+
+// Frames shorter than the salt and nonce of the ciphertext are broken
+const MIN_FRAME: usize = SALT_LENGTH + 24;
+
+// Tags of a record, every record is one of them
+const TAG_SNAPSHOT: u32 = 1;
+const TAG_PUT: u32 = 2;
+const TAG_DELETE: u32 = 3;
+const TAG_GROUPS: u32 = 4;
+const TAG_COMMIT: u32 = 5;
+const TAG_TRASH: u32 = 6;
+
+const TAG_GROUP: u32 = 1;
+const TAG_TRASHED: u32 = 1;
+
+// How opening a log went
+#[derive(Debug, PartialEq, Eq)]
+pub struct LogReport {
+    // Records since the last snapshot
+    pub records: usize,
+    // A broken or unfinished record was found, everything after the last
+    // complete save before it is dropped and rewritten by the next save
+    pub recovered: bool,
+}
+
+// The frames of every complete save, kept encrypted in the header so frames
+// that were cut off or changed are noticed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Head {
+    frames: u64,
+    // The length of the file up to the last complete save
+    length: u64,
+    // The digest chained over every frame, see chain
+    digest: [u8; 32],
+}
+
+// What the log on disk holds, so a save only appends what changed
+pub(crate) struct LogState {
+    path: PathBuf,
+    key: HashResult,
+    head: Head,
+    // The slot holding the head
+    slot: usize,
+    records: usize,
+    digests: BTreeMap<String, [u8; 32]>,
+    groups: BTreeSet<String>,
+    trash: [u8; 32],
+}
+
+enum Record {
+    Snapshot(Zeroizing<Vec<u8>>),
+    Put(String, Box<EncryptedEntry>),
+    Delete(String),
+    Groups(BTreeSet<String>),
+    Trash(Vec<Trashed<EncryptedEntry>>),
+    Commit,
+}
+
+impl DatabaseEncrypted {
+    pub fn is_log_file(contents: &[u8]) -> bool {
+        contents.starts_with(LOG_MAGIC)
+    }
+
+    // Whether saves append to a log, see save_log
+    pub fn uses_log(&self) -> bool {
+        self.log.is_some()
+    }
+
+    // Later saves with save_log write a new log instead of appending
+    pub fn stop_log(&mut self) {
+        self.log = None;
+    }
+
+    // Two steps of progress, one for the key and one for the confirmation
+    // hash. Replays every complete save, a frame that is missing or can't be
+    // read drops it and everything after it.
+    pub fn open_log(
+        path: impl AsRef<Path>,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<(Self, LogReport), DatabaseError> {
+        let path = path.as_ref();
+        let contents = std::fs::read(path)?;
+        let salt = match contents.strip_prefix(LOG_MAGIC) {
+            Some([version, data @ ..])
+                if *version == LOG_VERSION && contents.len() >= FRAMES_START =>
+            {
+                &data[..SALT_LENGTH]
+            }
+            Some([version, ..]) if *version > LOG_VERSION => {
+                return Err(DatabaseError::UnsupportedVersion(*version))
+            }
+            _ => return Err(DatabaseError::FailedDeserialize),
+        };
+
+        progress.add_total(2);
+        let key = argon2_hash_password_with_salt_cancellable(password, salt, progress)?;
+
+        let header = &contents[..HEADER_LENGTH];
+        let mut slots = [None, None];
+        for (slot, head) in slots.iter_mut().enumerate() {
+            let offset = HEADER_LENGTH + slot * SLOT_LENGTH;
+            *head = read_head(&contents[offset..offset + SLOT_LENGTH], header, slot, &key);
+        }
+        let (slot, head) = match slots {
+            [Some(first), Some(second)] if second.frames > first.frames => (1, second),
+            [Some(head), _] => (0, head),
+            [None, Some(head)] => (1, head),
+            // Neither head can be read with the wrong key
+            [None, None] => return Err(DatabaseError::InvalidPassword),
+        };
+
+        let mut db: Option<Self> = None;
+        let mut pending = Vec::new();
+        let mut records = 0;
+        let mut frames = &contents[FRAMES_START..];
+        let mut index = 0;
+        let mut chained = digest(header)?;
+        let mut offset = FRAMES_START;
+        // The frames up to the last commit that was read
+        let mut last = Head {
+            frames: 0,
+            length: FRAMES_START as u64,
+            digest: chained,
+        };
+        // Frames after the head are an unfinished save
+        while index < head.frames {
+            let frame = match next_frame(&mut frames) {
+                Some(frame) => frame,
+                None => break,
+            };
+            let record = match read_record(frame, &frame_aad(index, &chained), &key) {
+                Ok(record) => record,
+                Err(_error) => break,
+            };
+            offset += 4 + frame.len();
+            index += 1;
+            chained = chain(&chained, frame)?;
+
+            match record {
+                Some(Record::Commit) => {
+                    records += pending.len() + 1;
+                    for record in pending.drain(..) {
+                        match (record, &mut db) {
+                            (Record::Snapshot(serialized), _) => {
+                                db = Some(Self::new_deserialize(&serialized, password, progress)?);
+                                records = 0;
+                            }
+                            (Record::Put(name, entry), Some(db)) => {
+                                db.db.restore(&name, Some(*entry))
+                            }
+                            (Record::Delete(name), Some(db)) => db.db.restore(&name, None),
+                            (Record::Groups(groups), Some(db)) => db.db.restore_groups(groups),
+                            (Record::Trash(trash), Some(db)) => db.db.restore_trash(trash),
+                            _ => return Err(DatabaseError::FailedDeserialize),
+                        }
+                    }
+                    last = Head {
+                        frames: index,
+                        length: offset as u64,
+                        digest: chained,
+                    };
+                }
+                Some(record) => pending.push(record),
+                None => (),
+            }
+        }
+
+        let mut db = match db {
+            Some(db) => db,
+            None => return Err(DatabaseError::FailedDeserialize),
+        };
+        // Missing or changed frames, bytes after the head, or a second head
+        // that can't be read next to one that can are all torn or broken saves
+        let recovered =
+            last != head || last.length as usize != contents.len() || slots.contains(&None);
+        if recovered {
+            warn!(
+                "pwm-db: log {} was recovered, {} bytes after the last complete save are dropped",
+                path.display(),
+                contents.len() - last.length as usize
+            );
+        }
+
+        db.log = Some(LogState {
+            path: path.to_path_buf(),
+            key,
+            head: last,
+            slot,
+            // The other slot may hold a head with more frames, so a recovered
+            // log is rewritten instead of appended to
+            records: if recovered { COMPACT_AFTER } else { records },
+            digests: db.digests()?,
+            groups: db.db.stored_groups().clone(),
+            trash: digest(&trash_record(db.db.trash()))?,
+        });
+        Ok((db, LogReport { records, recovered }))
+    }
+
+    // Appends a record for every entry changed since the log was opened or
+    // saved, followed by a commit. A new log with a snapshot is written
+    // instead when the vault isn't from this log, the file was changed by
+    // something else or COMPACT_AFTER records were appended.
+    pub fn save_log(
+        &mut self,
+        path: impl AsRef<Path>,
+        password: &[u8],
+        backups: usize,
+        progress: &Progress,
+    ) -> Result<(), DatabaseError> {
+        if self.in_transaction() {
+            return Err(DatabaseError::TransactionInProgress);
+        }
+        if !self.hash_password_and_compare_cancellable(password, progress)? {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        let path = path.as_ref();
+        let length = std::fs::metadata(path).map(|metadata| metadata.len()).ok();
+        let state = match &mut self.log {
+            Some(state)
+                if state.path == path
+                    && Some(state.head.length) == length
+                    && state.records < COMPACT_AFTER =>
+            {
+                state
+            }
+            _ => return self.write_snapshot(path, password, backups, progress),
+        };
+
+        let mut records = Vec::new();
+        let mut digests = BTreeMap::new();
+        for (name, entry) in self.db.iter() {
+            let message = entry_message(name, entry);
+            let digest = digest(&message)?;
+            if state.digests.get(name) != Some(&digest) {
+                records.push(record(TAG_PUT, &message));
+            }
+            digests.insert(name.clone(), digest);
+        }
+        for name in state.digests.keys() {
+            if !digests.contains_key(name) {
+                records.push(record(TAG_DELETE, name.as_bytes()));
+            }
+        }
+        if *self.db.stored_groups() != state.groups {
+            records.push(groups_record(self.db.stored_groups()));
+        }
+        let trash = trash_record(self.db.trash());
+        let trash_digest = digest(&trash)?;
+        if trash_digest != state.trash {
+            records.push(trash);
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+        records.push(record(TAG_COMMIT, &[]));
+
+        // The frames are on disk before the head pointing past them
+        let (frames, head) = append_frames(&records, &state.head, &state.key)?;
+        let slot = 1 - state.slot;
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.seek(SeekFrom::Start(state.head.length))?;
+        file.write_all(&frames)?;
+        file.sync_data()?;
+        let bytes = write_head(&head, &log_header(&state.key), slot, &state.key)?;
+        file.seek(SeekFrom::Start((HEADER_LENGTH + slot * SLOT_LENGTH) as u64))?;
+        file.write_all(&bytes)?;
+        file.sync_data()?;
+
+        state.head = head;
+        state.slot = slot;
+        state.records += records.len();
+        state.digests = digests;
+        state.groups = self.db.stored_groups().clone();
+        state.trash = trash_digest;
+        Ok(())
+    }
+
+    // Replaces the log with a single snapshot, same progress as save_log
+    pub fn compact_log(
+        &mut self,
+        path: impl AsRef<Path>,
+        password: &[u8],
+        backups: usize,
+        progress: &Progress,
+    ) -> Result<(), DatabaseError> {
+        if self.in_transaction() {
+            return Err(DatabaseError::TransactionInProgress);
+        }
+        if !self.hash_password_and_compare_cancellable(password, progress)? {
+            return Err(DatabaseError::InvalidPassword);
+        }
+        self.write_snapshot(path.as_ref(), password, backups, progress)
+    }
+
+    // A new key is derived for every snapshot
+    fn write_snapshot(
+        &mut self,
+        path: &Path,
+        password: &[u8],
+        backups: usize,
+        progress: &Progress,
+    ) -> Result<(), DatabaseError> {
+        progress.add_total(1);
+        let key = argon2_hash_password_cancellable(password, progress)?;
+
+        let serialized = self.serialize()?;
+        #[cfg(feature = "use-compression")]
+        let serialized = Zeroizing::new(compress_prepend_size(serialized.as_slice()));
+
+        let mut contents = log_header(&key);
+        let start = Head {
+            frames: 0,
+            length: FRAMES_START as u64,
+            digest: digest(&contents)?,
+        };
+        let records = [record(TAG_SNAPSHOT, &serialized), record(TAG_COMMIT, &[])];
+        let (frames, head) = append_frames(&records, &start, &key)?;
+        for slot in 0..2 {
+            let head = write_head(&head, &log_header(&key), slot, &key)?;
+            contents.extend_from_slice(&head);
+        }
+        contents.extend_from_slice(&frames);
+        write_atomic_with(path, backups, |file| file.write_all(&contents))?;
+
+        self.log = Some(LogState {
+            path: path.to_path_buf(),
+            key,
+            head,
+            slot: 0,
+            records: 0,
+            digests: self.digests()?,
+            groups: self.db.stored_groups().clone(),
+            trash: digest(&trash_record(self.db.trash()))?,
+        });
+        Ok(())
+    }
+
+    fn digests(&self) -> Result<BTreeMap<String, [u8; 32]>, DatabaseError> {
+        let mut digests = BTreeMap::new();
+        for (name, entry) in self.db.iter() {
+            digests.insert(name.clone(), digest(&entry_message(name, entry))?);
+        }
+        Ok(digests)
+    }
+}
+
+// The frame's bytes, None at the end or when the last frame was cut off
+fn next_frame<'a>(frames: &mut &'a [u8]) -> Option<&'a [u8]> {
+    if frames.len() < 4 {
+        return None;
+    }
+    let (length, rest) = frames.split_at(4);
+    let length = u32::from_le_bytes([length[0], length[1], length[2], length[3]]) as usize;
+    if length > rest.len() {
+        return None;
+    }
+
+    let (frame, rest) = rest.split_at(length);
+    *frames = rest;
+    Some(frame)
+}
+
+fn frame(record: &[u8], aad: &[u8], key: &HashResult) -> Result<Vec<u8>, DatabaseError> {
+    let ciphertext = encrypt_with_aad(record, aad, key)?;
+    let mut frame = (ciphertext.as_slice().len() as u32).to_le_bytes().to_vec();
+    frame.extend_from_slice(ciphertext.as_slice());
+    Ok(frame)
+}
+
+// The frames of the records after head and the head including them
+fn append_frames(
+    records: &[Zeroizing<Vec<u8>>],
+    head: &Head,
+    key: &HashResult,
+) -> Result<(Vec<u8>, Head), DatabaseError> {
+    let mut frames = Vec::new();
+    let mut head = *head;
+    for record in records {
+        let frame = frame(record, &frame_aad(head.frames, &head.digest), key)?;
+        head.frames += 1;
+        head.digest = chain(&head.digest, &frame[4..])?;
+        frames.extend_from_slice(&frame);
+    }
+    head.length += frames.len() as u64;
+    Ok((frames, head))
+}
+
+// Every frame is bound to its index and the frames before it, so frames
+// can't be dropped, reordered or copied from another log with the same key
+fn frame_aad(index: u64, previous: &[u8; 32]) -> Vec<u8> {
+    let mut aad = index.to_le_bytes().to_vec();
+    aad.extend_from_slice(previous);
+    aad
+}
+
+fn chain(previous: &[u8; 32], frame: &[u8]) -> Result<[u8; 32], DatabaseError> {
+    let mut message = previous.to_vec();
+    message.extend_from_slice(frame);
+    digest(&message)
+}
+
+fn log_header(key: &HashResult) -> Vec<u8> {
+    let mut header = LOG_MAGIC.to_vec();
+    header.push(LOG_VERSION);
+    header.extend_from_slice(key.get_salt());
+    header
+}
+
+fn head_aad(header: &[u8], slot: usize) -> Vec<u8> {
+    let mut aad = header.to_vec();
+    aad.push(slot as u8);
+    aad
+}
+
+// A 4 byte little endian length and the encrypted head, padded with zeros
+fn write_head(
+    head: &Head,
+    header: &[u8],
+    slot: usize,
+    key: &HashResult,
+) -> Result<Vec<u8>, DatabaseError> {
+    let mut plaintext = head.frames.to_le_bytes().to_vec();
+    plaintext.extend_from_slice(&head.length.to_le_bytes());
+    plaintext.extend_from_slice(&head.digest);
+    let ciphertext = encrypt_with_aad(&plaintext, &head_aad(header, slot), key)?;
+
+    let mut bytes = (ciphertext.as_slice().len() as u32).to_le_bytes().to_vec();
+    bytes.extend_from_slice(ciphertext.as_slice());
+    if bytes.len() > SLOT_LENGTH {
+        return Err(DatabaseError::FailedSerialize);
+    }
+    bytes.resize(SLOT_LENGTH, 0);
+    Ok(bytes)
+}
+
+// None when the head was torn, changed or the key is wrong
+fn read_head(bytes: &[u8], header: &[u8], slot: usize, key: &HashResult) -> Option<Head> {
+    let mut bytes = bytes;
+    let ciphertext = next_frame(&mut bytes)?;
+    if ciphertext.len() < MIN_FRAME {
+        return None;
+    }
+    let ciphertext = EncryptionResult::new(ciphertext.to_vec()).ok()?;
+    let plaintext = decrypt_with_aad(&ciphertext, &head_aad(header, slot), key).ok()?;
+    let plaintext = plaintext.as_slice();
+    if plaintext.len() != 48 {
+        return None;
+    }
+
+    let mut frames = [0; 8];
+    let mut length = [0; 8];
+    let mut digest = [0; 32];
+    frames.copy_from_slice(&plaintext[..8]);
+    length.copy_from_slice(&plaintext[8..16]);
+    digest.copy_from_slice(&plaintext[16..]);
+    Some(Head {
+        frames: u64::from_le_bytes(frames),
+        length: u64::from_le_bytes(length),
+        digest,
+    })
+}
+
+// Records of kinds this version doesn't know are None
+fn read_record(
+    frame: &[u8],
+    aad: &[u8],
+    key: &HashResult,
+) -> Result<Option<Record>, DatabaseError> {
+    if frame.len() < MIN_FRAME {
+        return Err(DatabaseError::FailedDeserialize);
+    }
+    let plaintext = decrypt_with_aad(&EncryptionResult::new(frame.to_vec())?, aad, key)?;
+
+    let mut reader = Reader::new(plaintext.as_slice());
+    let (tag, value) = match reader.next()? {
+        Some(field) => field,
+        None => return Err(DatabaseError::FailedDeserialize),
+    };
+    let record = match tag {
+        TAG_SNAPSHOT => {
+            #[cfg(feature = "use-compression")]
+            let value = match decompress_size_prepended(value) {
+                Ok(value) => Zeroizing::new(value),
+                Err(error) => return Err(DatabaseError::CompressionError(error.to_string())),
+            };
+            #[cfg(not(feature = "use-compression"))]
+            let value = Zeroizing::new(value.to_vec());
+            Record::Snapshot(value)
+        }
+        TAG_PUT => {
+            let (name, entry) = EncryptedEntry::read_tlv(value)?;
+            Record::Put(name, Box::new(entry))
+        }
+        TAG_DELETE => Record::Delete(read_string(value)?),
+        TAG_GROUPS => {
+            let mut groups = BTreeSet::new();
+            let mut reader = Reader::new(value);
+            while let Some((tag, value)) = reader.next()? {
+                if tag == TAG_GROUP {
+                    groups.insert(read_string(value)?);
+                }
+            }
+            Record::Groups(groups)
+        }
+        TAG_TRASH => {
+            let mut trash = Vec::new();
+            let mut reader = Reader::new(value);
+            while let Some((tag, value)) = reader.next()? {
+                if tag == TAG_TRASHED {
+                    trash.push(read_trashed(value)?);
+                }
+            }
+            Record::Trash(trash)
+        }
+        TAG_COMMIT => Record::Commit,
+        _ => return Ok(None),
+    };
+    Ok(Some(record))
+}
+
+fn record(tag: u32, value: &[u8]) -> Zeroizing<Vec<u8>> {
+    let mut writer = Writer::new();
+    writer.bytes(tag, value);
+    writer.into_inner()
+}
+
+fn groups_record(groups: &BTreeSet<String>) -> Zeroizing<Vec<u8>> {
+    let mut writer = Writer::new();
+    writer.message(TAG_GROUPS, |writer| {
+        for group in groups {
+            writer.string(TAG_GROUP, group);
+        }
+    });
+    writer.into_inner()
+}
+
+// The whole recycle bin, written again whenever it changed
+fn trash_record(trash: &[Trashed<EncryptedEntry>]) -> Zeroizing<Vec<u8>> {
+    let mut writer = Writer::new();
+    writer.message(TAG_TRASH, |writer| {
+        for trashed in trash {
+            writer.message(TAG_TRASHED, |writer| write_trashed(trashed, writer));
+        }
+    });
+    writer.into_inner()
+}
+
+fn entry_message(name: &str, entry: &EncryptedEntry) -> Zeroizing<Vec<u8>> {
+    let mut writer = Writer::new();
+    entry.write_tlv(name, &mut writer);
+    writer.into_inner()
+}
+
+fn digest(message: &[u8]) -> Result<[u8; 32], DatabaseError> {
+    let mut digest = [0; 32];
+    sha256_hash(message, &mut digest)?;
+    Ok(digest)
+}
+
+#[cfg(test)]
+mod test {
+    use pwm_lib::progress::Progress;
+
+    use crate::db_base::error::DatabaseError;
+    use crate::db_encrypted::{db_interface::DatabaseInterface, DatabaseEncrypted};
+
+    use super::{COMPACT_AFTER, FRAMES_START, HEADER_LENGTH};
+
+    fn open(path: &str, pw: &[u8]) -> (DatabaseEncrypted, super::LogReport) {
+        DatabaseEncrypted::open_log(path, pw, &Progress::new()).unwrap()
+    }
+
+    #[test]
+    fn test_log() {
+        let pw = b"test";
+        let path = "db_encrypted_log_test00000000.log";
+        let progress = Progress::new();
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert("mail", b"password1", pw).unwrap();
+        db.insert("bank", b"password2", pw).unwrap();
+        db.create_group("empty", pw).unwrap();
+        db.save_log(path, pw, 0, &progress).unwrap();
+        assert!(DatabaseEncrypted::is_log_file(
+            &std::fs::read(path).unwrap()
+        ));
+
+        let (mut db, report) = open(path, pw);
+        assert!(!report.recovered);
+        assert_eq!(db.list().unwrap(), vec!["bank", "mail"]);
+        assert!(db.contains_group("empty"));
+
+        // Only changes are appended
+        let length = std::fs::metadata(path).unwrap().len();
+        db.insert("chat", b"password3", pw).unwrap();
+        db.remove("bank", pw).unwrap();
+        db.remove_group("empty", false, pw).unwrap();
+        db.save_log(path, pw, 0, &progress).unwrap();
+        let appended = std::fs::metadata(path).unwrap().len();
+        assert!(appended > length);
+        db.save_log(path, pw, 0, &progress).unwrap();
+        assert_eq!(std::fs::metadata(path).unwrap().len(), appended);
+
+        // The removed entry is in the recycle bin
+        let (mut db, report) = open(path, pw);
+        assert_eq!(report.records, 5);
+        assert_eq!(db.list().unwrap(), vec!["chat", "mail"]);
+        assert_eq!(db.trash()[0].name, "bank");
+        assert_eq!(db.get("chat", pw).unwrap().as_slice(), b"password3");
+        assert!(!db.contains_group("empty"));
+
+        db.compact_log(path, pw, 0, &progress).unwrap();
+        let (db, report) = open(path, pw);
+        assert_eq!(report.records, 0);
+        assert_eq!(db.list().unwrap(), vec!["chat", "mail"]);
+
+        assert!(matches!(
+            DatabaseEncrypted::open_log(path, b"wrong", &progress),
+            Err(DatabaseError::InvalidPassword)
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_log_compaction() {
+        let pw = b"test";
+        let path = "db_encrypted_log_test00000001.log";
+        let progress = Progress::new();
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.save_log(path, pw, 0, &progress).unwrap();
+
+        // A put and a commit
+        db.insert("mail", b"password", pw).unwrap();
+        db.save_log(path, pw, 0, &progress).unwrap();
+        assert_eq!(open(path, pw).1.records, 2);
+
+        db.log.as_mut().unwrap().records = COMPACT_AFTER;
+        db.insert("chat", b"password", pw).unwrap();
+        db.save_log(path, pw, 0, &progress).unwrap();
+        let (db, report) = open(path, pw);
+        assert_eq!(report.records, 0);
+        assert_eq!(db.list().unwrap(), vec!["chat", "mail"]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_log_recovery() {
+        let pw = b"test";
+        let path = "db_encrypted_log_test00000002.log";
+        let progress = Progress::new();
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert("mail", b"password1", pw).unwrap();
+        db.save_log(path, pw, 0, &progress).unwrap();
+        db.insert("chat", b"password2", pw).unwrap();
+        db.save_log(path, pw, 0, &progress).unwrap();
+        let saved = std::fs::read(path).unwrap();
+        db.insert("bank", b"password3", pw).unwrap();
+        db.save_log(path, pw, 0, &progress).unwrap();
+        let contents = std::fs::read(path).unwrap();
+
+        // A save that was cut off before its head was written
+        let unfinished = [&saved[..], &contents[saved.len()..contents.len() - 10]].concat();
+        std::fs::write(path, &unfinished).unwrap();
+        let (mut db, report) = open(path, pw);
+        assert!(report.recovered);
+        assert_eq!(db.list().unwrap(), vec!["chat", "mail"]);
+
+        // The next save replaces the broken tail
+        db.insert("bank", b"password3", pw).unwrap();
+        db.save_log(path, pw, 0, &progress).unwrap();
+        let (db, report) = open(path, pw);
+        assert!(!report.recovered);
+        assert_eq!(db.list().unwrap(), vec!["bank", "chat", "mail"]);
+
+        // A head that was torn, the last save wrote the first slot
+        let mut torn = contents.clone();
+        torn[HEADER_LENGTH + 10] ^= 1;
+        std::fs::write(path, &torn).unwrap();
+        let (db, report) = open(path, pw);
+        assert!(report.recovered);
+        assert_eq!(db.list().unwrap(), vec!["chat", "mail"]);
+
+        // A record that was changed, the saves before it are kept
+        let mut corrupted = contents.clone();
+        corrupted[saved.len() + 10] ^= 1;
+        std::fs::write(path, &corrupted).unwrap();
+        let (mut db, report) = open(path, pw);
+        assert!(report.recovered);
+        assert_eq!(db.list().unwrap(), vec!["chat", "mail"]);
+
+        // The next save writes a new log, the old head has more frames
+        db.insert("bank", b"password4", pw).unwrap();
+        db.save_log(path, pw, 0, &progress).unwrap();
+        let (db, report) = open(path, pw);
+        assert!(!report.recovered);
+        assert_eq!(db.get("bank", pw).unwrap().as_slice(), b"password4");
+
+        // Complete saves that were cut off
+        std::fs::write(path, &contents[..saved.len()]).unwrap();
+        let (db, report) = open(path, pw);
+        assert!(report.recovered);
+        assert_eq!(db.list().unwrap(), vec!["chat", "mail"]);
+
+        // Nothing is left without the first snapshot
+        std::fs::write(path, &contents[..FRAMES_START + 10]).unwrap();
+        assert!(matches!(
+            DatabaseEncrypted::open_log(path, pw, &progress),
+            Err(DatabaseError::FailedDeserialize)
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::BTreeSet;
+
+use crate::db_base::{error::DatabaseError, Trashed};
+use crate::db_encrypted::entry::EncryptedEntry;
+use crate::db_encrypted::DatabaseEncrypted;
+
+// Which copy of an entry a conflict keeps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeSide {
+    Ours,
+    Theirs,
+}
+
+// An entry both copies changed in different ways since the base, None is a
+// copy that removed it
+#[derive(Debug, Clone)]
+pub struct MergeConflict {
+    pub name: String,
+    pub ours: Option<EncryptedEntry>,
+    pub theirs: Option<EncryptedEntry>,
+}
+
+impl MergeConflict {
+    // The copy that was modified last, a removal is older than any change
+    pub fn newer(&self) -> MergeSide {
+        let modified =
+            |entry: &Option<EncryptedEntry>| entry.as_ref().map(|entry| entry.modified());
+        if modified(&self.theirs) > modified(&self.ours) {
+            MergeSide::Theirs
+        } else {
+            MergeSide::Ours
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    // Entries taken from the other copy because only it changed them
+    pub merged: Vec<String>,
+    // Entries left as they are in this vault until they are resolved
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl DatabaseEncrypted {
+    // Three-way merge of another copy of this vault, base is the copy both
+    // were made from. Changes only one side made are combined, entries both
+    // sides changed are returned as conflicts. All three copies need the
+    // same master password. Undone as one change.
+    pub fn merge(
+        &mut self,
+        base: &DatabaseEncrypted,
+        theirs: &DatabaseEncrypted,
+        password: &[u8],
+    ) -> Result<MergeReport, DatabaseError> {
+        if !self.hash_password_and_compare(password)
+            || !base.hash_password_and_compare(password)
+            || !theirs.hash_password_and_compare(password)
+        {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        let mut names = BTreeSet::new();
+        for db in [&*self, base, theirs] {
+            names.extend(db.db.iter().map(|(name, _entry)| name.clone()));
+        }
+
+        let mut report = MergeReport::default();
+        for name in names {
+            let ours = self.db.get(&name).ok();
+            let theirs = theirs.db.get(&name).ok();
+            let base = base.db.get(&name).ok();
+
+            if same(ours, theirs) || same(base, theirs) {
+                continue;
+            }
+            if same(base, ours) {
+                report.merged.push(name);
+                continue;
+            }
+            report.conflicts.push(MergeConflict {
+                name,
+                ours: ours.cloned(),
+                theirs: theirs.cloned(),
+            });
+        }
+
+        // A group is kept if both copies have it or one of them added it
+        let (ours, base_groups) = (self.db.stored_groups(), base.db.stored_groups());
+        let groups = ours
+            .union(theirs.db.stored_groups())
+            .filter(|group| {
+                let added = !base_groups.contains(*group);
+                added || (ours.contains(*group) && theirs.db.stored_groups().contains(*group))
+            })
+            .cloned()
+            .collect::<BTreeSet<String>>();
+
+        // An entry the other copy removed goes to the recycle bin, unless
+        // it is already in theirs and comes from there
+        let trashed_by_them = |name: &str| {
+            theirs
+                .db
+                .trash()
+                .iter()
+                .any(|trashed| trashed.name == name && !contains(base.db.trash(), trashed))
+        };
+        let merged = report.merged.clone();
+        self.journaled(String::from("merge"), merged.clone(), |db| {
+            for name in merged.iter() {
+                match theirs.db.get(name) {
+                    Ok(entry) => db.restore(name, Some(entry.clone())),
+                    Err(_error) if trashed_by_them(name) => db.restore(name, None),
+                    Err(_error) => db.remove(name)?,
+                }
+            }
+            if *db.stored_groups() != groups {
+                db.restore_groups(groups);
+            }
+
+            // Like groups, a removed entry is kept if both copies have it or
+            // one of them added it
+            let ours = db.trash();
+            let mut trash: Vec<Trashed<EncryptedEntry>> = ours
+                .iter()
+                .chain(
+                    theirs
+                        .db
+                        .trash()
+                        .iter()
+                        .filter(|trashed| !contains(ours, trashed)),
+                )
+                .filter(|trashed| {
+                    let added = !contains(base.db.trash(), trashed);
+                    added || (contains(ours, trashed) && contains(theirs.db.trash(), trashed))
+                })
+                .cloned()
+                .collect();
+            trash.sort_by_key(|trashed| trashed.removed);
+            let unchanged = trash.len() == ours.len()
+                && trash.iter().zip(ours).all(|(trashed, ours)| {
+                    trashed.name == ours.name && trashed.removed == ours.removed
+                });
+            if !unchanged {
+                db.restore_trash(trash);
+            }
+            Ok(())
+        })?;
+
+        Ok(report)
+    }
+
+    // Keeps one copy of a conflicting entry, the conflict must come from
+    // merge on this vault
+    pub fn resolve_conflict(
+        &mut self,
+        conflict: &MergeConflict,
+        side: MergeSide,
+    ) -> Result<(), DatabaseError> {
+        let value = match side {
+            MergeSide::Ours => &conflict.ours,
+            MergeSide::Theirs => &conflict.theirs,
+        };
+        let name = conflict.name.clone();
+        self.journaled(format!("merge {}", name), vec![name.clone()], |db| {
+            match value {
+                Some(entry) => db.restore(&name, Some(entry.clone())),
+                None if db.get(&name).is_ok() => db.remove(&name)?,
+                None => (),
+            }
+            Ok(())
+        })
+    }
+}
+
+// The same removal, by the entry's name and when it was removed
+fn contains(trash: &[Trashed<EncryptedEntry>], trashed: &Trashed<EncryptedEntry>) -> bool {
+    trash
+        .iter()
+        .any(|other| other.name == trashed.name && other.removed == trashed.removed)
+}
+
+fn same(a: Option<&EncryptedEntry>, b: Option<&EncryptedEntry>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.same_content(b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::db_encrypted::{db_interface::DatabaseInterface, DatabaseEncrypted};
+
+    use super::MergeSide;
+
+    fn copy(db: &DatabaseEncrypted, pw: &[u8]) -> DatabaseEncrypted {
+        let serialized = db.serialize_encrypted(pw).unwrap();
+        DatabaseEncrypted::new_deserialize_encrypted(&serialized, pw).unwrap()
+    }
+
+    #[test]
+    fn test_merge() {
+        let pw = b"test";
+        let mut base = DatabaseEncrypted::new(pw).unwrap();
+        base.insert("mail", b"password1", pw).unwrap();
+        base.insert("bank", b"password2", pw).unwrap();
+        base.insert("chat", b"password3", pw).unwrap();
+        base.create_group("old", pw).unwrap();
+        let mut ours = copy(&base, pw);
+        let mut theirs = copy(&base, pw);
+
+        ours.replace("mail", b"ours", pw).unwrap();
+        ours.create_group("ours", pw).unwrap();
+        ours.mark_accessed("chat").unwrap();
+        theirs.remove("bank", pw).unwrap();
+        theirs.insert("new", b"password4", pw).unwrap();
+        theirs.replace("chat", b"theirs", pw).unwrap();
+        theirs.remove_group("old", false, pw).unwrap();
+
+        let report = ours.merge(&base, &theirs, pw).unwrap();
+        assert_eq!(report.merged, vec!["bank", "chat", "new"]);
+        assert!(report.conflicts.is_empty());
+        assert_eq!(ours.list().unwrap(), vec!["chat", "mail", "new"]);
+        assert_eq!(ours.get("mail", pw).unwrap().as_slice(), b"ours");
+        assert_eq!(ours.get("chat", pw).unwrap().as_slice(), b"theirs");
+        assert_eq!(ours.groups(), vec!["ours"]);
+        assert_eq!(ours.trash().len(), 1);
+        assert_eq!(ours.trash()[0].removed, theirs.trash()[0].removed);
+
+        // Merging again changes nothing
+        let report = ours.merge(&base, &theirs, pw).unwrap();
+        assert!(report.merged.is_empty());
+        ours.undo(pw).unwrap();
+        assert_eq!(ours.list().unwrap(), vec!["bank", "chat", "mail"]);
+        assert!(ours.contains_group("old"));
+
+        assert!(ours.merge(&base, &theirs, b"wrong").is_err());
+    }
+
+    #[test]
+    fn test_merge_trash() {
+        let pw = b"test";
+        let mut base = DatabaseEncrypted::new(pw).unwrap();
+        base.insert("mail", b"password1", pw).unwrap();
+        base.insert("bank", b"password2", pw).unwrap();
+        base.insert("chat", b"password3", pw).unwrap();
+        base.insert("old", b"password4", pw).unwrap();
+        base.remove("old", pw).unwrap();
+        let mut ours = copy(&base, pw);
+        let mut theirs = copy(&base, pw);
+
+        ours.purge_trash(None, pw).unwrap();
+        ours.remove("chat", pw).unwrap();
+        theirs.remove("bank", pw).unwrap();
+        theirs.purge_trash(None, pw).unwrap();
+        theirs.remove("mail", pw).unwrap();
+
+        // Their removal of bank still lands in the recycle bin, the purged
+        // old stays purged
+        let report = ours.merge(&base, &theirs, pw).unwrap();
+        assert_eq!(report.merged, vec!["bank", "mail"]);
+        assert!(ours.list().unwrap().is_empty());
+        let mut trash: Vec<&str> = ours
+            .trash()
+            .iter()
+            .map(|trashed| trashed.name.as_str())
+            .collect();
+        trash.sort();
+        assert_eq!(trash, vec!["bank", "chat", "mail"]);
+        let mail = ours.trash().iter().find(|trashed| trashed.name == "mail");
+        assert_eq!(mail.unwrap().removed, theirs.trash()[0].removed);
+
+        ours.restore_trashed("bank", pw).unwrap();
+        assert_eq!(ours.get("bank", pw).unwrap().as_slice(), b"password2");
+        ours.undo(pw).unwrap();
+        ours.undo(pw).unwrap();
+        assert_eq!(ours.trash().len(), 1);
+        assert_eq!(ours.trash()[0].name, "chat");
+    }
+
+    #[test]
+    fn test_merge_conflicts() {
+        let pw = b"test";
+        let mut base = DatabaseEncrypted::new(pw).unwrap();
+        base.insert("mail", b"password1", pw).unwrap();
+        base.insert("bank", b"password2", pw).unwrap();
+        let mut ours = copy(&base, pw);
+        let mut theirs = copy(&base, pw);
+
+        ours.replace("mail", b"ours", pw).unwrap();
+        theirs.replace("mail", b"theirs", pw).unwrap();
+        ours.remove("bank", pw).unwrap();
+        theirs.replace("bank", b"theirs", pw).unwrap();
+
+        let report = ours.merge(&base, &theirs, pw).unwrap();
+        assert!(report.merged.is_empty());
+        let conflicts = report.conflicts;
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(conflicts[0].name, "bank");
+        assert!(conflicts[0].ours.is_none());
+        assert_eq!(conflicts[0].newer(), MergeSide::Theirs);
+
+        ours.resolve_conflict(&conflicts[0], MergeSide::Theirs)
+            .unwrap();
+        ours.resolve_conflict(&conflicts[1], MergeSide::Ours)
+            .unwrap();
+        assert_eq!(ours.get("bank", pw).unwrap().as_slice(), b"theirs");
+        assert_eq!(ours.get("mail", pw).unwrap().as_slice(), b"ours");
+    }
+}
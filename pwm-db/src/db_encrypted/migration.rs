@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use pwm_lib::{encryption::EncryptionResult, zeroize::Zeroizing};
+use serde::de::DeserializeOwned;
+
+use crate::db_base::{error::DatabaseError, Database, DatabaseV0, Trashed};
+use crate::db_encrypted::entry::EncryptedEntry;
+use crate::db_encrypted::tlv::{read_i64, read_string, Reader, UnknownFields, Writer};
+
+// Serialized vaults start with this and the format version, vaults from
+// before versioning are only the database without a header (version 0).
+// Fields can be added without a new version, see FORMAT.md.
+pub(crate) static FORMAT_MAGIC: &[u8; 4] = b"PWMD";
+pub(crate) const FORMAT_VERSION: u8 = 1;
+
+// Tags of the vault message
+const TAG_ENTRY: u32 = 1;
+const TAG_GROUP: u32 = 2;
+const TAG_TRASH: u32 = 3;
+
+// Tags of a removed entry
+const TAG_TRASH_ENTRY: u32 = 1;
+const TAG_TRASH_REMOVED: u32 = 2;
+
+// A vault in the layout of the version it was saved with
+enum Layout {
+    // The released bincode layout, every value is only the encrypted password
+    V0(DatabaseV0<EncryptionResult>),
+    // Tagged fields instead of bincode
+    V1(Database<EncryptedEntry>),
+}
+
+impl Layout {
+    fn read(serialized: &[u8]) -> Result<Self, DatabaseError> {
+        let (version, data) = match serialized.strip_prefix(FORMAT_MAGIC) {
+            Some(data) => match data.split_first() {
+                Some((version, data)) => (*version, data),
+                None => return Err(DatabaseError::FailedDeserialize),
+            },
+            None => (0, serialized),
+        };
+
+        match version {
+            0 => Ok(Self::V0(decode(data)?)),
+            1 => Ok(Self::V1(read_vault(data)?)),
+            version => Err(DatabaseError::UnsupportedVersion(version)),
+        }
+    }
+
+    // One version up, each migration only knows the layout it reads and the
+    // one it writes
+    fn migrate(self) -> Self {
+        match self {
+            Self::V0(db) => Self::V1(Database::from(db.map(EncryptedEntry::from_password))),
+            Self::V1(db) => Self::V1(db),
+        }
+    }
+}
+
+fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, DatabaseError> {
+    match bincode::deserialize(data) {
+        Ok(value) => Ok(value),
+        Err(_error) => Err(DatabaseError::FailedDeserialize),
+    }
+}
+
+// Reads a vault of any version up to FORMAT_VERSION and migrates it to the
+// current layout
+pub(crate) fn deserialize(serialized: &[u8]) -> Result<Database<EncryptedEntry>, DatabaseError> {
+    let mut layout = Layout::read(serialized)?;
+    loop {
+        layout = match layout {
+            Layout::V1(db) => return Ok(db),
+            older => older.migrate(),
+        };
+    }
+}
+
+// Unknown fields are kept to be written back, a name saved twice means the
+// file is broken
+fn read_vault(data: &[u8]) -> Result<Database<EncryptedEntry>, DatabaseError> {
+    let mut entries = BTreeMap::new();
+    let mut groups = BTreeSet::new();
+    let mut trash = Vec::new();
+    let mut unknown = UnknownFields::default();
+    let mut reader = Reader::new(data);
+    while let Some((tag, value)) = reader.next()? {
+        match tag {
+            TAG_ENTRY => {
+                let (name, entry) = EncryptedEntry::read_tlv(value)?;
+                if entries.insert(name, entry).is_some() {
+                    return Err(DatabaseError::FailedDeserialize);
+                }
+            }
+            TAG_GROUP => {
+                groups.insert(read_string(value)?);
+            }
+            TAG_TRASH => trash.push(read_trashed(value)?),
+            _ => unknown.push(tag, value),
+        }
+    }
+    Ok(Database::from_parts(entries, groups, trash, unknown))
+}
+
+pub(crate) fn read_trashed(data: &[u8]) -> Result<Trashed<EncryptedEntry>, DatabaseError> {
+    let mut entry = None;
+    let mut removed = 0;
+    let mut unknown = UnknownFields::default();
+    let mut reader = Reader::new(data);
+    while let Some((tag, value)) = reader.next()? {
+        match tag {
+            TAG_TRASH_ENTRY => entry = Some(EncryptedEntry::read_tlv(value)?),
+            TAG_TRASH_REMOVED => removed = read_i64(value)?,
+            _ => unknown.push(tag, value),
+        }
+    }
+
+    match entry {
+        Some((name, value)) => Ok(Trashed {
+            name,
+            removed,
+            value,
+            unknown,
+        }),
+        None => Err(DatabaseError::FailedDeserialize),
+    }
+}
+
+pub(crate) fn write_trashed(trashed: &Trashed<EncryptedEntry>, writer: &mut Writer) {
+    writer.message(TAG_TRASH_ENTRY, |writer| {
+        trashed.value.write_tlv(&trashed.name, writer)
+    });
+    writer.i64(TAG_TRASH_REMOVED, trashed.removed);
+    trashed.unknown.write(writer);
+}
+
+// The header and the current layout, only entries, stored groups, the
+// recycle bin and the fields this version doesn't know are written
+pub(crate) fn serialize(db: &Database<EncryptedEntry>) -> Zeroizing<Vec<u8>> {
+    let mut writer = Writer::new();
+    for (name, entry) in db.iter() {
+        writer.message(TAG_ENTRY, |writer| entry.write_tlv(name, writer));
+    }
+    for group in db.stored_groups() {
+        writer.string(TAG_GROUP, group);
+    }
+    for trashed in db.trash() {
+        writer.message(TAG_TRASH, |writer| write_trashed(trashed, writer));
+    }
+    db.unknown_fields().write(&mut writer);
+
+    let mut data = Zeroizing::new(FORMAT_MAGIC.to_vec());
+    data.push(FORMAT_VERSION);
+    data.extend_from_slice(&writer.into_inner());
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use crate::db_base::error::DatabaseError;
+    use crate::db_encrypted::{db_interface::DatabaseInterface, DatabaseEncrypted};
+    use pwm_lib::encryption::EncryptionResult;
+
+    use super::{serialize, FORMAT_MAGIC, FORMAT_VERSION, TAG_ENTRY, TAG_TRASH};
+
+    // Vaults saved in older formats, see tests/vaults/README.md
+    fn open(version: u8) -> DatabaseEncrypted {
+        let file = format!("tests/vaults/v{}.vault", version);
+        let contents = EncryptionResult::new(std::fs::read(file).unwrap()).unwrap();
+        DatabaseEncrypted::new_deserialize_encrypted(&contents, b"test").unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "use-compression")]
+    fn test_fixtures() {
+        let pw = b"test";
+        let db = open(0);
+        assert_eq!(db.list().unwrap(), vec!["mail", "work/chat"]);
+        assert_eq!(db.get("work/chat", pw).unwrap().as_slice(), b"password2");
+        assert_eq!(db.get_info("mail").unwrap().created(), 0);
+
+        // Saving writes the current format
+        let serialized = db.serialize_encrypted(pw).unwrap();
+        let db = DatabaseEncrypted::new_deserialize_encrypted(&serialized, pw).unwrap();
+        assert_eq!(db.get("mail", pw).unwrap().as_slice(), b"password1");
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        use crate::db_encrypted::tlv::{Reader, Writer};
+
+        let pw = b"test";
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert("mail", b"password", pw).unwrap();
+        db.add_tag("mail", "prod", pw).unwrap();
+        db.create_group("empty", pw).unwrap();
+        db.insert("old", b"password", pw).unwrap();
+        db.remove("old", pw).unwrap();
+
+        // Fields a newer version could add to the vault, an entry and an
+        // entry in the recycle bin, after the ones this version writes
+        let saved = serialize(&db.db);
+        let mut writer = Writer::new();
+        let mut reader = Reader::new(&saved[FORMAT_MAGIC.len() + 1..]);
+        while let Some((tag, value)) = reader.next().unwrap() {
+            match tag {
+                TAG_ENTRY | TAG_TRASH => writer.message(tag, |writer| {
+                    let mut fields = Reader::new(value);
+                    while let Some((tag, value)) = fields.next().unwrap() {
+                        writer.bytes(tag, value);
+                    }
+                    writer.message(100, |writer| writer.i64(1, 5));
+                }),
+                tag => writer.bytes(tag, value),
+            }
+        }
+        writer.string(99, "new");
+        let mut serialized = FORMAT_MAGIC.to_vec();
+        serialized.push(FORMAT_VERSION);
+        serialized.extend_from_slice(&writer.into_inner());
+
+        let read = DatabaseEncrypted::deserialize_database(&serialized).unwrap();
+        assert_eq!(read.list().unwrap(), vec!["mail"]);
+        assert!(read.get("mail").unwrap().tags().contains("prod"));
+        assert!(read.contains_group("empty"));
+        assert_eq!(read.trash().len(), 1);
+        assert_eq!(*serialize(&read), serialized);
+
+        // Changing the entry keeps the fields it doesn't know
+        db.db = read;
+        db.replace("mail", b"other", pw).unwrap();
+        let saved = serialize(&db.db);
+        let mut reader = Reader::new(&saved[FORMAT_MAGIC.len() + 1..]);
+        let (tag, entry) = reader.next().unwrap().unwrap();
+        assert_eq!(tag, TAG_ENTRY);
+        let mut fields = Reader::new(entry);
+        let mut unknown = 0;
+        while let Some((tag, _value)) = fields.next().unwrap() {
+            unknown += (tag == 100) as usize;
+        }
+        assert_eq!(unknown, 1);
+    }
+
+    #[test]
+    fn test_newer_version() {
+        let mut serialized = FORMAT_MAGIC.to_vec();
+        serialized.push(FORMAT_VERSION + 1);
+        serialized.extend_from_slice(&[0; 16]);
+
+        assert_eq!(
+            DatabaseEncrypted::deserialize_database(&serialized).unwrap_err(),
+            DatabaseError::UnsupportedVersion(FORMAT_VERSION + 1)
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+};
+
+use pwm_lib::progress::Progress;
+
+use crate::db_base::error::DatabaseError;
+
+fn worker_count(items: usize) -> usize {
+    let threads = match std::thread::available_parallelism() {
+        Ok(threads) => threads.get(),
+        Err(_error) => 1,
+    };
+
+    threads.min(items).max(1)
+}
+
+// Runs job over every item on a pool of worker threads, one step of progress
+// per item. Results come back in the same order as items no matter which
+// worker finished first, so callers can report errors deterministically.
+pub(crate) fn parallel_map<T, R, F>(
+    items: &[T],
+    progress: &Progress,
+    job: F,
+) -> Result<Vec<Result<R, DatabaseError>>, DatabaseError>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> Result<R, DatabaseError> + Sync,
+{
+    progress.add_total(items.len());
+
+    let next = AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<Result<R, DatabaseError>>>> =
+        items.iter().map(|_| Mutex::new(None)).collect();
+
+    std::thread::scope(|scope| {
+        for _ in 0..worker_count(items.len()) {
+            scope.spawn(|| loop {
+                if progress.is_cancelled() {
+                    return;
+                }
+
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                if index >= items.len() {
+                    return;
+                }
+
+                let result = job(&items[index]);
+                if let Ok(mut slot) = results[index].lock() {
+                    *slot = Some(result);
+                }
+                progress.increment();
+            });
+        }
+    });
+
+    if progress.is_cancelled() {
+        return Err(DatabaseError::Cancelled);
+    }
+
+    let mut ordered = Vec::with_capacity(results.len());
+    for result in results {
+        match result.into_inner() {
+            Ok(Some(result)) => ordered.push(result),
+            // Every item got a result, a worker that panicked was re-raised by
+            // the scope before this point
+            Ok(None) | Err(_) => return Err(DatabaseError::LockError),
+        }
+    }
+
+    Ok(ordered)
+}
+
+#[cfg(test)]
+mod test {
+    use pwm_lib::progress::Progress;
+
+    use super::parallel_map;
+    use crate::db_base::error::DatabaseError;
+
+    #[test]
+    fn test_parallel_map_ordered() {
+        let items: Vec<usize> = (0..64).collect();
+        let progress = Progress::new();
+
+        let results = parallel_map(&items, &progress, |item| {
+            if item % 10 == 3 {
+                Err(DatabaseError::InputError(format!("{}", item)))
+            } else {
+                Ok(item * 2)
+            }
+        })
+        .unwrap();
+
+        assert_eq!(progress.completed(), 64);
+        for (index, result) in results.iter().enumerate() {
+            if index % 10 == 3 {
+                assert_eq!(
+                    result,
+                    &Err(DatabaseError::InputError(format!("{}", index)))
+                );
+            } else {
+                assert_eq!(result, &Ok(index * 2));
+            }
+        }
+    }
+
+    #[test]
+    fn test_parallel_map_cancelled() {
+        let items: Vec<usize> = (0..8).collect();
+        let progress = Progress::new();
+        progress.cancel();
+
+        let result = parallel_map(&items, &progress, |item| Ok(*item));
+        assert_eq!(result.unwrap_err(), DatabaseError::Cancelled);
+    }
+}
@@ -0,0 +1,421 @@
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+
+use crate::db_base::{is_in_group, Database, GROUP_SEPARATOR};
+use crate::db_encrypted::entry::{EncryptedEntry, FieldValue};
+
+// Searches over everything that isn't secret:
+//   word         fuzzy matches the name, or is contained in any plain field
+//   "a phrase"   is contained in the name or any plain field
+//   tag:prod     has the tag
+//   url:github   a URL contains it, likewise user:, name:, notes: and group:
+//   -term, NOT term, a OR b, a AND b, (a b)
+// Terms next to each other are joined with AND, which binds tighter than OR.
+// Everything is matched ignoring case. Parsing never fails so a half typed
+// query still gives results, unclosed quotes and brackets end with the query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    // The empty query
+    All,
+    Term(Term),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    Word(String),
+    Phrase(String),
+    Tag(String),
+    Url(String),
+    User(String),
+    Name(String),
+    Notes(String),
+    Group(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    Not,
+    And,
+    Or,
+    Term(Term),
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Self {
+        let tokens = tokenize(query);
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+        };
+
+        // A stray ")" only ends the part before it
+        let mut parts = Vec::new();
+        loop {
+            match parser.parse_or() {
+                Expr::All => (),
+                expr => parts.push(expr),
+            }
+            if parser.next().is_none() {
+                break;
+            }
+        }
+
+        let expr = match parts.len() {
+            0 => Expr::All,
+            1 => parts.remove(0),
+            _ => Expr::And(parts),
+        };
+        Self { expr }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.expr == Expr::All
+    }
+
+    fn matches(&self, entry: &IndexedEntry, matcher: &SkimMatcherV2) -> bool {
+        self.expr.matches(entry, matcher)
+    }
+
+    // Better fuzzy matches of the words on the name come first
+    fn score(&self, entry: &IndexedEntry, matcher: &SkimMatcherV2) -> i64 {
+        self.expr.score(entry, matcher)
+    }
+}
+
+impl Expr {
+    fn matches(&self, entry: &IndexedEntry, matcher: &SkimMatcherV2) -> bool {
+        match self {
+            Expr::All => true,
+            Expr::Term(term) => term.matches(entry, matcher),
+            Expr::Not(expr) => !expr.matches(entry, matcher),
+            Expr::And(exprs) => exprs.iter().all(|expr| expr.matches(entry, matcher)),
+            Expr::Or(exprs) => exprs.iter().any(|expr| expr.matches(entry, matcher)),
+        }
+    }
+
+    fn score(&self, entry: &IndexedEntry, matcher: &SkimMatcherV2) -> i64 {
+        match self {
+            Expr::Term(Term::Word(word)) => matcher.fuzzy_match(&entry.name, word).unwrap_or(0),
+            Expr::And(exprs) | Expr::Or(exprs) => {
+                exprs.iter().map(|expr| expr.score(entry, matcher)).sum()
+            }
+            _ => 0,
+        }
+    }
+}
+
+impl Term {
+    fn matches(&self, entry: &IndexedEntry, matcher: &SkimMatcherV2) -> bool {
+        match self {
+            Term::Word(word) => {
+                matcher.fuzzy_match(&entry.name, word).is_some() || entry.fields_contain(word)
+            }
+            Term::Phrase(phrase) => entry.name.contains(phrase) || entry.fields_contain(phrase),
+            Term::Tag(tag) => entry.tags.contains(tag),
+            Term::Url(url) => entry.urls.iter().any(|other| other.contains(url)),
+            Term::User(user) => entry.username.contains(user),
+            Term::Name(name) => entry.name.contains(name),
+            Term::Notes(notes) => entry.notes.contains(notes),
+            Term::Group(group) => is_in_group(&entry.name, group.trim_matches(GROUP_SEPARATOR)),
+        }
+    }
+
+    // Prefixes that aren't known stay part of the word, so "https://..."
+    // is still a word
+    fn new(word: &str) -> Self {
+        let word = word.to_lowercase();
+        if let Some((key, value)) = word.split_once(':') {
+            let value = value.to_string();
+            if !value.is_empty() {
+                match key {
+                    "tag" => return Term::Tag(value),
+                    "url" => return Term::Url(value),
+                    "user" | "username" => return Term::User(value),
+                    "name" | "title" => return Term::Name(value),
+                    "notes" => return Term::Notes(value),
+                    "group" => return Term::Group(value),
+                    _ => (),
+                }
+            }
+        }
+        Term::Word(word)
+    }
+}
+
+fn tokenize(query: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '-' => {
+                chars.next();
+                match chars.peek() {
+                    Some(&next) if !next.is_whitespace() => tokens.push(Token::Not),
+                    // A lone "-" is a word
+                    _ => tokens.push(Token::Term(Term::Word(String::from("-")))),
+                }
+            }
+            '"' => {
+                chars.next();
+                let phrase: String = chars.by_ref().take_while(|&c| c != '"').collect();
+                if !phrase.is_empty() {
+                    tokens.push(Token::Term(Term::Phrase(phrase.to_lowercase())));
+                }
+            }
+            _ => {
+                // Quotes inside a word only group spaces, as in url:"a b"
+                let mut word = String::new();
+                let mut quoted = false;
+                while let Some(&c) = chars.peek() {
+                    if !quoted && (c.is_whitespace() || c == '(' || c == ')') {
+                        break;
+                    }
+                    chars.next();
+                    match c {
+                        '"' => quoted = !quoted,
+                        c => word.push(c),
+                    }
+                }
+
+                match word.as_str() {
+                    "AND" | "&&" => tokens.push(Token::And),
+                    "OR" | "||" => tokens.push(Token::Or),
+                    "NOT" => tokens.push(Token::Not),
+                    _ => tokens.push(Token::Term(Term::new(&word))),
+                }
+            }
+        }
+    }
+
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Expr {
+        let mut exprs = vec![self.parse_and()];
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            exprs.push(self.parse_and());
+        }
+
+        exprs.retain(|expr| *expr != Expr::All);
+        match exprs.len() {
+            0 => Expr::All,
+            1 => exprs.remove(0),
+            _ => Expr::Or(exprs),
+        }
+    }
+
+    fn parse_and(&mut self) -> Expr {
+        let mut exprs = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(Token::Close) | Some(Token::Or) => break,
+                Some(Token::And) => {
+                    self.next();
+                }
+                Some(_) => match self.parse_not() {
+                    Expr::All => (),
+                    expr => exprs.push(expr),
+                },
+            }
+        }
+
+        match exprs.len() {
+            0 => Expr::All,
+            1 => exprs.remove(0),
+            _ => Expr::And(exprs),
+        }
+    }
+
+    fn parse_not(&mut self) -> Expr {
+        match self.next() {
+            Some(Token::Not) => match self.peek() {
+                None | Some(Token::Close) | Some(Token::Or) | Some(Token::And) => Expr::All,
+                Some(_) => match self.parse_not() {
+                    Expr::All => Expr::All,
+                    expr => Expr::Not(Box::new(expr)),
+                },
+            },
+            Some(Token::Open) => {
+                let expr = self.parse_or();
+                if self.peek() == Some(&Token::Close) {
+                    self.next();
+                }
+                expr
+            }
+            Some(Token::Term(term)) => Expr::Term(term.clone()),
+            _ => Expr::All,
+        }
+    }
+}
+
+// The plain text of an entry, lower case
+struct IndexedEntry {
+    // As stored, search results use it
+    original_name: String,
+    name: String,
+    username: String,
+    urls: Vec<String>,
+    notes: String,
+    tags: Vec<String>,
+    fields: Vec<String>,
+}
+
+impl IndexedEntry {
+    fn new(name: &str, entry: &EncryptedEntry) -> Self {
+        let fields = entry
+            .field_names()
+            .filter_map(|field| match entry.field(field)? {
+                FieldValue::Text(value) => Some(value.to_lowercase()),
+                FieldValue::Secret(_value) => None,
+            })
+            .collect();
+
+        Self {
+            original_name: name.to_string(),
+            name: name.to_lowercase(),
+            username: entry.username.to_lowercase(),
+            urls: entry.urls.iter().map(|url| url.to_lowercase()).collect(),
+            notes: entry.notes.to_lowercase(),
+            tags: entry.tags().iter().cloned().collect(),
+            fields,
+        }
+    }
+
+    fn fields_contain(&self, value: &str) -> bool {
+        self.username.contains(value)
+            || self.urls.iter().any(|url| url.contains(value))
+            || self.notes.contains(value)
+            || self.tags.iter().any(|tag| tag.contains(value))
+            || self.fields.iter().any(|field| field.contains(value))
+    }
+}
+
+// Rebuilt only when the database changed since the last search, the last
+// result is kept since the GUI searches again every frame
+#[derive(Default)]
+pub(crate) struct SearchIndex {
+    revision: Option<u64>,
+    entries: Vec<IndexedEntry>,
+    prev_query: String,
+    prev_result: Vec<String>,
+}
+
+impl SearchIndex {
+    pub(crate) fn search(&mut self, db: &Database<EncryptedEntry>, query: &str) -> &Vec<String> {
+        if self.revision == Some(db.revision()) && self.prev_query == query {
+            return &self.prev_result;
+        }
+
+        if self.revision != Some(db.revision()) {
+            self.entries = db
+                .iter()
+                .map(|(name, entry)| IndexedEntry::new(name, entry))
+                .collect();
+            self.revision = Some(db.revision());
+        }
+
+        let parsed = Query::parse(query);
+        let matcher = SkimMatcherV2::default();
+        let mut results: Vec<(i64, &IndexedEntry)> = self
+            .entries
+            .iter()
+            .filter(|entry| parsed.matches(entry, &matcher))
+            .map(|entry| (parsed.score(entry, &matcher), entry))
+            .collect();
+        // Stable, equal scores stay sorted by name
+        results.sort_by_key(|(score, _entry)| std::cmp::Reverse(*score));
+
+        self.prev_result = results
+            .into_iter()
+            .map(|(_score, entry)| entry.original_name.clone())
+            .collect();
+        self.prev_query = query.to_string();
+        &self.prev_result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Expr, Query, Term};
+
+    fn word(word: &str) -> Expr {
+        Expr::Term(Term::Word(word.to_string()))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(Query::parse("").is_empty());
+        assert!(Query::parse("  ( ) NOT").is_empty());
+        assert_eq!(Query::parse("Mail").expr, word("mail"));
+        assert_eq!(
+            Query::parse("tag:prod url:GitHub.com -tag:old \"exact phrase\"").expr,
+            Expr::And(vec![
+                Expr::Term(Term::Tag(String::from("prod"))),
+                Expr::Term(Term::Url(String::from("github.com"))),
+                Expr::Not(Box::new(Expr::Term(Term::Tag(String::from("old"))))),
+                Expr::Term(Term::Phrase(String::from("exact phrase"))),
+            ])
+        );
+        assert_eq!(
+            Query::parse("a b OR c AND NOT d").expr,
+            Expr::Or(vec![
+                Expr::And(vec![word("a"), word("b")]),
+                Expr::And(vec![word("c"), Expr::Not(Box::new(word("d")))]),
+            ])
+        );
+        assert_eq!(
+            Query::parse("a (b OR c").expr,
+            Expr::And(vec![word("a"), Expr::Or(vec![word("b"), word("c")])])
+        );
+        assert_eq!(
+            Query::parse("user:\"a b\" https://x.org unknown:key").expr,
+            Expr::And(vec![
+                Expr::Term(Term::User(String::from("a b"))),
+                word("https://x.org"),
+                word("unknown:key"),
+            ])
+        );
+        assert_eq!(
+            Query::parse("a) b").expr,
+            Expr::And(vec![word("a"), word("b")])
+        );
+    }
+}
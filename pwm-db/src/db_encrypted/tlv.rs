@@ -0,0 +1,177 @@
+use pwm_lib::{encryption::EncryptionResult, zeroize::Zeroizing};
+
+use crate::db_base::error::DatabaseError;
+
+// Tag, length, value encoding of the vault, see FORMAT.md. Tags and lengths
+// are LEB128 varints and every value is length delimited, so fields a reader
+// doesn't know can be skipped.
+
+pub(crate) struct Writer {
+    data: Zeroizing<Vec<u8>>,
+}
+
+impl Writer {
+    pub(crate) fn new() -> Self {
+        Self {
+            data: Zeroizing::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn into_inner(self) -> Zeroizing<Vec<u8>> {
+        self.data
+    }
+
+    pub(crate) fn bytes(&mut self, tag: u32, value: &[u8]) {
+        write_varint(&mut self.data, tag as u64);
+        write_varint(&mut self.data, value.len() as u64);
+        self.data.extend_from_slice(value);
+    }
+
+    pub(crate) fn string(&mut self, tag: u32, value: &str) {
+        self.bytes(tag, value.as_bytes());
+    }
+
+    // Left out when empty, readers default to an empty string
+    pub(crate) fn string_if_set(&mut self, tag: u32, value: &str) {
+        if !value.is_empty() {
+            self.string(tag, value);
+        }
+    }
+
+    pub(crate) fn i64(&mut self, tag: u32, value: i64) {
+        self.bytes(tag, &value.to_le_bytes());
+    }
+
+    pub(crate) fn encrypted(&mut self, tag: u32, value: &EncryptionResult) {
+        self.bytes(tag, value.as_slice());
+    }
+
+    // A nested message, written by the closure
+    pub(crate) fn message(&mut self, tag: u32, write: impl FnOnce(&mut Writer)) {
+        let mut nested = Writer::new();
+        write(&mut nested);
+        self.bytes(tag, &nested.data);
+    }
+}
+
+// Fields a reader doesn't know, kept in the order they were read so saving
+// writes them back and a newer version doesn't lose them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct UnknownFields(Vec<(u32, Zeroizing<Vec<u8>>)>);
+
+impl UnknownFields {
+    pub(crate) fn push(&mut self, tag: u32, value: &[u8]) {
+        self.0.push((tag, Zeroizing::new(value.to_vec())));
+    }
+
+    pub(crate) fn write(&self, writer: &mut Writer) {
+        for (tag, value) in self.0.iter() {
+            writer.bytes(*tag, value);
+        }
+    }
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        data.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+// Reads the fields of one message in order
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    // The next tag and its value, None at the end of the message
+    pub(crate) fn next(&mut self) -> Result<Option<(u32, &'a [u8])>, DatabaseError> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+
+        let tag = match u32::try_from(self.varint()?) {
+            Ok(tag) => tag,
+            Err(_error) => return Err(DatabaseError::FailedDeserialize),
+        };
+        let length = match usize::try_from(self.varint()?) {
+            Ok(length) if length <= self.data.len() => length,
+            _ => return Err(DatabaseError::FailedDeserialize),
+        };
+
+        let (value, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(Some((tag, value)))
+    }
+
+    fn varint(&mut self) -> Result<u64, DatabaseError> {
+        let mut value = 0u64;
+        for (index, byte) in self.data.iter().enumerate().take(10) {
+            value |= ((byte & 0x7f) as u64) << (7 * index);
+            if byte & 0x80 == 0 {
+                self.data = &self.data[index + 1..];
+                return Ok(value);
+            }
+        }
+        Err(DatabaseError::FailedDeserialize)
+    }
+}
+
+pub(crate) fn read_string(value: &[u8]) -> Result<String, DatabaseError> {
+    match String::from_utf8(value.to_vec()) {
+        Ok(value) => Ok(value),
+        Err(_error) => Err(DatabaseError::FailedDeserialize),
+    }
+}
+
+pub(crate) fn read_i64(value: &[u8]) -> Result<i64, DatabaseError> {
+    match <[u8; 8]>::try_from(value) {
+        Ok(bytes) => Ok(i64::from_le_bytes(bytes)),
+        Err(_error) => Err(DatabaseError::FailedDeserialize),
+    }
+}
+
+pub(crate) fn read_encrypted(value: &[u8]) -> Result<EncryptionResult, DatabaseError> {
+    Ok(EncryptionResult::new(value.to_vec())?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_i64, read_string, Reader, Writer};
+
+    #[test]
+    fn test_tlv() {
+        let mut writer = Writer::new();
+        writer.string(1, "name");
+        writer.i64(300, -5);
+        writer.message(2, |writer| {
+            writer.bytes(1, &[0; 200]);
+            writer.string_if_set(2, "");
+        });
+        let data = writer.into_inner();
+
+        let mut reader = Reader::new(&data);
+        let (tag, value) = reader.next().unwrap().unwrap();
+        assert_eq!((tag, read_string(value).unwrap().as_str()), (1, "name"));
+        let (tag, value) = reader.next().unwrap().unwrap();
+        assert_eq!((tag, read_i64(value).unwrap()), (300, -5));
+
+        let (tag, value) = reader.next().unwrap().unwrap();
+        assert_eq!(tag, 2);
+        let mut nested = Reader::new(value);
+        assert_eq!(nested.next().unwrap().unwrap().1.len(), 200);
+        assert!(nested.next().unwrap().is_none());
+        assert!(reader.next().unwrap().is_none());
+
+        // A length past the end of the data
+        let mut reader = Reader::new(&data[..data.len() - 1]);
+        reader.next().unwrap();
+        reader.next().unwrap();
+        assert!(reader.next().is_err());
+    }
+}
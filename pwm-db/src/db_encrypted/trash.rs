@@ -0,0 +1,90 @@
+use crate::db_base::{error::DatabaseError, Trashed};
+use crate::db_encrypted::entry::EncryptedEntry;
+use crate::db_encrypted::DatabaseEncrypted;
+use crate::timestamp::now;
+
+// Days removed entries stay in the recycle bin unless configured, 0 keeps
+// them until the bin is purged
+pub static DEFAULT_TRASH_DAYS: u32 = 30;
+
+impl DatabaseEncrypted {
+    // Removed entries, oldest first. Removing an entry or group moves the
+    // entries here, they are saved with the vault.
+    pub fn trash(&self) -> &[Trashed<EncryptedEntry>] {
+        self.db.trash()
+    }
+
+    // Puts back the entry removed last under the name, AlreadyExists if the
+    // name is taken again
+    pub fn restore_trashed(&mut self, name: &str, password: &[u8]) -> Result<(), DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        self.journaled(format!("restore {}", name), vec![name.to_string()], |db| {
+            db.restore_trashed(name)
+        })
+    }
+
+    // Removes entries from the recycle bin for good, every one of them
+    // without days. Returns their names, undone as one change.
+    pub fn purge_trash(
+        &mut self,
+        days: Option<u32>,
+        password: &[u8],
+    ) -> Result<Vec<String>, DatabaseError> {
+        if !self.hash_password_and_compare(password) {
+            return Err(DatabaseError::InvalidPassword);
+        }
+
+        let before = days.map(|days| now() - days as i64 * 86400);
+        self.journaled(String::from("purge"), Vec::new(), |db| {
+            Ok(db.purge_trash(before))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::db_base::error::DatabaseError;
+    use crate::db_encrypted::{db_interface::DatabaseInterface, DatabaseEncrypted};
+
+    #[test]
+    fn test_trash() {
+        let pw = b"test";
+        let mut db = DatabaseEncrypted::new(pw).unwrap();
+        db.insert("mail", b"password1", pw).unwrap();
+        db.insert("work/chat", b"password2", pw).unwrap();
+        db.insert("work/bank", b"password3", pw).unwrap();
+        db.remove("mail", pw).unwrap();
+        db.remove_group("work", true, pw).unwrap();
+        let names: Vec<&str> = db.trash().iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["mail", "work/bank", "work/chat"]);
+
+        // The bin is saved with the vault
+        let serialized = db.serialize_encrypted(pw).unwrap();
+        let mut db = DatabaseEncrypted::new_deserialize_encrypted(&serialized, pw).unwrap();
+        assert_eq!(db.trash().len(), 3);
+
+        db.insert("mail", b"new", pw).unwrap();
+        assert_eq!(
+            db.restore_trashed("mail", pw).unwrap_err(),
+            DatabaseError::AlreadyExists
+        );
+        db.restore_trashed("work/chat", pw).unwrap();
+        assert_eq!(db.get("work/chat", pw).unwrap().as_slice(), b"password2");
+        assert_eq!(db.trash().len(), 2);
+        db.undo(pw).unwrap();
+        assert!(db.get("work/chat", pw).is_err());
+        assert_eq!(db.trash().len(), 3);
+        assert!(db.restore_trashed("unknown", pw).is_err());
+
+        // Only entries removed longer ago than the days are purged
+        assert!(db.purge_trash(Some(1), pw).unwrap().is_empty());
+        assert_eq!(db.purge_trash(None, pw).unwrap().len(), 3);
+        assert!(db.trash().is_empty());
+        db.undo(pw).unwrap();
+        assert_eq!(db.trash().len(), 3);
+        assert!(db.purge_trash(None, b"wrong").is_err());
+    }
+}
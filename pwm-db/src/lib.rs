@@ -1,2 +1,5 @@
+pub mod audit;
 pub mod db_base;
 pub mod db_encrypted;
+pub mod lock;
+pub mod timestamp;
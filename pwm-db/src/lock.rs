@@ -0,0 +1,461 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use pwm_lib::hash::{randomize_slice, sha_wrapper::sha256_hash};
+use pwm_lib::random::token::encode_hex;
+
+use crate::db_base::error::DatabaseError;
+
+// The process holding a vault lock
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockOwner {
+    pub pid: u32,
+    pub host: String,
+}
+
+impl LockOwner {
+    fn current() -> LockOwner {
+        LockOwner {
+            pid: std::process::id(),
+            host: host_name(),
+        }
+    }
+
+    fn parse(contents: &str) -> Option<LockOwner> {
+        let mut lines = contents.lines();
+        let pid = lines.next()?.parse::<u32>().ok()?;
+        let host = lines.next()?.to_string();
+        Some(LockOwner { pid, host })
+    }
+
+    // Processes on other hosts can't be checked and are assumed to be running
+    fn is_stale(&self) -> bool {
+        self.host == host_name() && !process_running(self.pid)
+    }
+}
+
+impl std::fmt::Display for LockOwner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(std::format_args!("process {} on {}", self.pid, self.host))
+    }
+}
+
+// Advisory lock on a vault file, a "<file>.lock" file next to it holding the
+// owner. Other programs only respect it if they check for it. A process can
+// take its own lock again, it only has one vault open for writing at a time.
+#[derive(Debug)]
+pub struct VaultLock {
+    path: PathBuf,
+    contents: String,
+}
+
+impl VaultLock {
+    // Locked if another running process holds the lock, a lock left behind
+    // by a process that exited is taken over. A lock that can't be read is
+    // held, it is never removed.
+    pub fn acquire(vault: impl AsRef<Path>) -> Result<VaultLock, DatabaseError> {
+        let path = lock_path(vault.as_ref());
+        let owner = LockOwner::current();
+        let mut token = [0u8; 8];
+        randomize_slice(&mut token);
+        let token = encode_hex(&token);
+        let contents = format!("{}\n{}\n{}\n", owner.pid, owner.host, token.as_str());
+
+        // The owner is written to a file of its own and linked into place,
+        // so the lock never exists without it
+        let temp = with_suffix(&path, token.as_str());
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?;
+        let written = file
+            .write_all(contents.as_bytes())
+            .and_then(|()| file.sync_all());
+        drop(file);
+        let result = match written {
+            Ok(()) => take(&temp, &path, &owner, token.as_str()),
+            Err(error) => Err(error.into()),
+        };
+        let _ = std::fs::remove_file(&temp);
+
+        result?;
+        Ok(VaultLock { path, contents })
+    }
+
+    pub fn is_for(&self, vault: impl AsRef<Path>) -> bool {
+        self.path == lock_path(vault.as_ref())
+    }
+}
+
+impl Drop for VaultLock {
+    fn drop(&mut self) {
+        // Left alone if this process took the lock again since
+        if let Ok(contents) = std::fs::read_to_string(&self.path) {
+            if contents == self.contents {
+                let _ = std::fs::remove_file(&self.path);
+            }
+        }
+    }
+}
+
+// What a vault file looked like when it was loaded or saved
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: [u8; 32],
+}
+
+impl FileStamp {
+    fn new(path: &Path, contents: &[u8]) -> Result<FileStamp, DatabaseError> {
+        let metadata = std::fs::metadata(path)?;
+        let mut hash = [0u8; 32];
+        sha256_hash(contents, &mut hash)?;
+        Ok(FileStamp {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            hash,
+        })
+    }
+
+    fn read(path: &Path) -> Result<FileStamp, DatabaseError> {
+        let contents = std::fs::read(path)?;
+        FileStamp::new(path, &contents)
+    }
+
+    // The contents are only hashed again if the modification time or size
+    // differ, a file that was touched but not changed is still the same
+    fn changed(&self, path: &Path) -> Result<bool, DatabaseError> {
+        let metadata = match std::fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+            Err(error) => return Err(error.into()),
+        };
+        if metadata.modified().ok() == self.modified && metadata.len() == self.len {
+            return Ok(false);
+        }
+        Ok(FileStamp::read(path)?.hash != self.hash)
+    }
+}
+
+// The file a vault was opened from or saved to and the lock held on it, so
+// that two programs don't silently overwrite each other's changes
+#[derive(Debug, Default)]
+pub struct VaultFile {
+    path: Option<PathBuf>,
+    lock: Option<VaultLock>,
+    stamp: Option<FileStamp>,
+}
+
+impl VaultFile {
+    // A vault that wasn't saved yet
+    pub fn new() -> VaultFile {
+        VaultFile::default()
+    }
+
+    // Reads the vault and locks it, Locked if another process has it open.
+    // A read only vault isn't locked until it is saved.
+    pub fn open(
+        path: impl AsRef<Path>,
+        read_only: bool,
+    ) -> Result<(VaultFile, Vec<u8>), DatabaseError> {
+        let path = path.as_ref();
+        let lock = match read_only {
+            true => None,
+            false => Some(VaultLock::acquire(path)?),
+        };
+        let contents = std::fs::read(path)?;
+        let stamp = FileStamp::new(path, &contents)?;
+
+        let file = VaultFile {
+            path: Some(path.to_path_buf()),
+            lock,
+            stamp: Some(stamp),
+        };
+        Ok((file, contents))
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.path.is_some() && self.lock.is_none()
+    }
+
+    // Whether path is this vault's file and another program wrote to it
+    // since it was loaded or saved
+    pub fn changed_on_disk(&self, path: impl AsRef<Path>) -> Result<bool, DatabaseError> {
+        match (&self.path, &self.stamp) {
+            (Some(own), Some(stamp)) if own == path.as_ref() => stamp.changed(own),
+            _ => Ok(false),
+        }
+    }
+
+    // Saves the vault to path with write, taking the lock on path first
+    // unless this vault holds it already. Afterwards the vault belongs to
+    // path. Overwriting changes made on disk has to be confirmed before.
+    pub fn save<T>(
+        &mut self,
+        path: impl AsRef<Path>,
+        write: impl FnOnce() -> Result<T, DatabaseError>,
+    ) -> Result<T, DatabaseError> {
+        let path = path.as_ref();
+        let lock = match &self.lock {
+            Some(lock) if lock.is_for(path) => None,
+            _ => Some(VaultLock::acquire(path)?),
+        };
+
+        let result = write()?;
+
+        if lock.is_some() {
+            self.lock = lock;
+        }
+        self.path = Some(path.to_path_buf());
+        self.stamp = Some(FileStamp::read(path)?);
+        Ok(result)
+    }
+}
+
+pub fn lock_path(vault: &Path) -> PathBuf {
+    let mut path = vault.as_os_str().to_os_string();
+    path.push(".lock");
+    PathBuf::from(path)
+}
+
+// Links temp holding owner to the lock at path, see VaultLock::acquire
+fn take(temp: &Path, path: &Path, owner: &LockOwner, token: &str) -> Result<(), DatabaseError> {
+    // Tried once more after moving a stale lock away
+    for _ in 0..2 {
+        match link(temp, path) {
+            Ok(()) => return Ok(()),
+            Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => (),
+            Err(error) => return Err(error.into()),
+        }
+
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(error) => return Err(error.into()),
+        };
+        match LockOwner::parse(&contents) {
+            // The lock of this process is replaced in one step
+            Some(held) if held == *owner => {
+                std::fs::rename(temp, path)?;
+                return Ok(());
+            }
+            Some(held) if held.is_stale() => take_over(path, &contents, token)?,
+            Some(held) => return Err(DatabaseError::Locked(held.to_string())),
+            None => return Err(DatabaseError::Locked(String::from("another process"))),
+        }
+    }
+
+    match read_owner(path)? {
+        Some(held) => Err(DatabaseError::Locked(held.to_string())),
+        None => Err(DatabaseError::Locked(String::from("another process"))),
+    }
+}
+
+// Fails if the lock exists. File systems without hard links, like FAT, get
+// the lock written in place, it can't be read until it is complete.
+fn link(temp: &Path, path: &Path) -> std::io::Result<()> {
+    match std::fs::hard_link(temp, path) {
+        Err(error)
+            if matches!(
+                error.kind(),
+                std::io::ErrorKind::Unsupported | std::io::ErrorKind::PermissionDenied
+            ) =>
+        {
+            let contents = std::fs::read(temp)?;
+            let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+            file.write_all(&contents)?;
+            file.sync_all()
+        }
+        result => result,
+    }
+}
+
+// Moves the stale lock out of the way. Only one process can move it, the
+// one that moved another lock taken meanwhile puts it back.
+fn take_over(path: &Path, stale: &str, token: &str) -> Result<(), DatabaseError> {
+    let aside = with_suffix(path, &format!("{}.stale", token));
+    match std::fs::rename(path, &aside) {
+        Ok(()) => (),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error.into()),
+    }
+
+    let moved = std::fs::read_to_string(&aside);
+    if matches!(&moved, Ok(moved) if moved == stale) {
+        return remove_if_exists(&aside);
+    }
+    let restored = link(&aside, path);
+    remove_if_exists(&aside)?;
+    match restored {
+        Err(error) if error.kind() != std::io::ErrorKind::AlreadyExists => return Err(error.into()),
+        _ => (),
+    }
+    match moved.ok().as_deref().and_then(LockOwner::parse) {
+        Some(held) => Err(DatabaseError::Locked(held.to_string())),
+        None => Err(DatabaseError::Locked(String::from("another process"))),
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(".");
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn read_owner(path: &Path) -> Result<Option<LockOwner>, DatabaseError> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(LockOwner::parse(&contents)),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), DatabaseError> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn host_name() -> String {
+    for file in ["/proc/sys/kernel/hostname", "/etc/hostname"] {
+        if let Ok(name) = std::fs::read_to_string(file) {
+            if !name.trim().is_empty() {
+                return name.trim().to_string();
+            }
+        }
+    }
+    for variable in ["COMPUTERNAME", "HOSTNAME"] {
+        if let Ok(name) = std::env::var(variable) {
+            if !name.is_empty() {
+                return name;
+            }
+        }
+    }
+    String::from("unknown")
+}
+
+// Without /proc a process can't be checked and is assumed to be running
+fn process_running(pid: u32) -> bool {
+    let proc = Path::new("/proc");
+    !proc.is_dir() || proc.join(pid.to_string()).exists()
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::db_base::error::DatabaseError;
+
+    use super::{host_name, lock_path, VaultFile, VaultLock};
+
+    fn vault_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("pwm-lock-{}-{}", name, std::process::id()));
+        std::fs::write(&path, b"vault").unwrap();
+        path
+    }
+
+    #[test]
+    fn test_lock() {
+        let path = vault_path("lock");
+        let lock = VaultLock::acquire(&path).unwrap();
+        assert!(lock_path(&path).exists());
+        assert!(lock.is_for(&path));
+
+        // Taking it again in the same process replaces the lock, dropping
+        // the old one leaves the new one in place
+        let again = VaultLock::acquire(&path).unwrap();
+        drop(lock);
+        assert!(lock_path(&path).exists());
+        drop(again);
+        assert!(!lock_path(&path).exists());
+
+        // Locks that can't be read are left alone
+        for contents in ["", "garbage"] {
+            std::fs::write(lock_path(&path), contents).unwrap();
+            assert_eq!(
+                VaultLock::acquire(&path).unwrap_err(),
+                DatabaseError::Locked(String::from("another process"))
+            );
+            assert_eq!(std::fs::read_to_string(lock_path(&path)).unwrap(), contents);
+        }
+
+        std::fs::write(lock_path(&path), "1\nsome-other-host\n0011\n").unwrap();
+        assert_eq!(
+            VaultLock::acquire(&path).unwrap_err(),
+            DatabaseError::Locked(String::from("process 1 on some-other-host"))
+        );
+        let error = VaultFile::open(&path, false).unwrap_err();
+        assert!(matches!(error, DatabaseError::Locked(_)));
+        let (mut file, contents) = VaultFile::open(&path, true).unwrap();
+        assert_eq!(contents, b"vault");
+        assert!(file.is_read_only());
+        assert!(file.save(&path, || Ok(())).is_err());
+
+        // A process on this host that isn't running anymore
+        std::fs::write(
+            lock_path(&path),
+            format!("{}\n{}\n0011\n", u32::MAX, host_name()),
+        )
+        .unwrap();
+        file.save(&path, || Ok(())).unwrap();
+        assert!(!file.is_read_only());
+        drop(file);
+        assert!(!lock_path(&path).exists());
+
+        // No temporary files are left behind
+        let prefix = lock_path(&path)
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        let left = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().starts_with(&prefix)
+            })
+            .count();
+        assert_eq!(left, 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_changed_on_disk() {
+        let path = vault_path("changed");
+        let other = vault_path("other");
+        let (mut file, _contents) = VaultFile::open(&path, false).unwrap();
+        assert!(!file.changed_on_disk(&path).unwrap());
+        assert!(!file.changed_on_disk(&other).unwrap());
+
+        // Written again with the same contents
+        std::fs::write(&path, b"vault").unwrap();
+        assert!(!file.changed_on_disk(&path).unwrap());
+        std::fs::write(&path, b"changed").unwrap();
+        assert!(file.changed_on_disk(&path).unwrap());
+
+        file.save(&path, || Ok(std::fs::write(&path, b"saved")?))
+            .unwrap();
+        assert!(!file.changed_on_disk(&path).unwrap());
+
+        // Saving somewhere else moves the lock
+        file.save(&other, || Ok(())).unwrap();
+        assert_eq!(file.path(), Some(other.as_path()));
+        assert!(!lock_path(&path).exists());
+        assert!(lock_path(&other).exists());
+        drop(file);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&other).unwrap();
+    }
+}
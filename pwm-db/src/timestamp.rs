@@ -0,0 +1,150 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Seconds since the unix epoch, 0 means unknown
+pub fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(_error) => 0,
+    }
+}
+
+// "YYYY-MM-DD HH:MM:SS UTC"
+pub fn format_timestamp(timestamp: i64) -> String {
+    if timestamp == 0 {
+        return String::from("unknown");
+    }
+
+    let days = timestamp.div_euclid(86400);
+    let seconds = timestamp.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+// RFC 3339 like "2024-01-31T12:34:56.789Z", fractions of a second are
+// dropped. None if it doesn't parse.
+pub fn parse_timestamp(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = text.get(range)?;
+        match digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            true => digits.parse().ok(),
+            false => None,
+        }
+    };
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    if separators
+        .iter()
+        .any(|(index, separator)| text.as_bytes().get(*index) != Some(separator))
+        || !matches!(text.as_bytes().get(10), Some(b'T' | b't' | b' '))
+    {
+        return None;
+    }
+
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+
+    let mut rest = &text[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction
+            .bytes()
+            .take_while(|byte| byte.is_ascii_digit())
+            .count();
+        rest = &fraction[digits..];
+    }
+    let offset = match rest {
+        "Z" | "z" | "" => 0,
+        _ => {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let offset = rest.get(1..)?;
+            if offset.len() != 5 || offset.as_bytes()[2] != b':' {
+                return None;
+            }
+            let hours: i64 = offset[..2].parse().ok()?;
+            let minutes: i64 = offset[3..].parse().ok()?;
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+
+    let days = days_from_civil(year, month, day);
+    Some(days * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+// The inverse of civil_from_days
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// Days since 1970-01-01 to a proleptic Gregorian date, see
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "unknown");
+        assert_eq!(format_timestamp(1), "1970-01-01 00:00:01 UTC");
+        assert_eq!(format_timestamp(951782400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_timestamp(1792320000), "2026-10-18 10:40:00 UTC");
+        assert_eq!(format_timestamp(-86400), "1969-12-31 00:00:00 UTC");
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:01Z"), Some(1));
+        assert_eq!(parse_timestamp("2000-02-29T00:00:00.000Z"), Some(951782400));
+        assert_eq!(
+            parse_timestamp("2026-10-18T10:40:00.1234567Z"),
+            Some(1792320000)
+        );
+        assert_eq!(
+            parse_timestamp("2026-10-18T12:40:00+02:00"),
+            Some(1792320000)
+        );
+        assert_eq!(parse_timestamp("1969-12-31 00:00:00"), Some(-86400));
+        assert_eq!(parse_timestamp("2026-13-18T10:40:00Z"), None);
+        assert_eq!(parse_timestamp("2026-10-18"), None);
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+}
@@ -0,0 +1,535 @@
+#!/usr/bin/env python3
+# Writes the KDBX 4 fixtures in this directory, see README.md. Needs the
+# cryptography package, Argon2d isn't in it so Argon2 is implemented here and
+# checked against its Argon2id first.
+
+import base64
+import gzip
+import hashlib
+import hmac
+import os
+import struct
+
+from cryptography.hazmat.primitives import padding
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
+from cryptography.hazmat.primitives.kdf.argon2 import Argon2id
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+MASK = (1 << 64) - 1
+
+CIPHER_AES256 = bytes.fromhex("31c1f2e6bf714350be5805216afc5aff")
+CIPHER_CHACHA20 = bytes.fromhex("d6038a2b8b6f4cb5a524339a31dbb59a")
+KDF_AES = bytes.fromhex("c9d9f39a628a4460bf740d08c18a4fea")
+KDF_ARGON2D = bytes.fromhex("ef636ddf8c29444b91f7a9a403e30a0c")
+KDF_ARGON2ID = bytes.fromhex("9e298b1956db4773b23dfc3ef6f0a1e6")
+
+# Seconds between 0001-01-01 and 1970-01-01
+EPOCH_OFFSET = 62135596800
+
+
+# Argon2 version 0x13, RFC 9106
+
+
+def blake2b_long(data, length):
+    prefix = struct.pack("<I", length)
+    if length <= 64:
+        return hashlib.blake2b(prefix + data, digest_size=length).digest()
+    out = b""
+    v = hashlib.blake2b(prefix + data).digest()
+    out += v[:32]
+    while length - len(out) > 64:
+        v = hashlib.blake2b(v).digest()
+        out += v[:32]
+    v = hashlib.blake2b(v, digest_size=length - len(out)).digest()
+    return out + v
+
+
+def rotr(x, n):
+    return ((x >> n) | (x << (64 - n))) & MASK
+
+
+def gb(v, a, b, c, d):
+    va, vb, vc, vd = v[a], v[b], v[c], v[d]
+    va = (va + vb + 2 * (va & 0xFFFFFFFF) * (vb & 0xFFFFFFFF)) & MASK
+    vd = rotr(vd ^ va, 32)
+    vc = (vc + vd + 2 * (vc & 0xFFFFFFFF) * (vd & 0xFFFFFFFF)) & MASK
+    vb = rotr(vb ^ vc, 24)
+    va = (va + vb + 2 * (va & 0xFFFFFFFF) * (vb & 0xFFFFFFFF)) & MASK
+    vd = rotr(vd ^ va, 16)
+    vc = (vc + vd + 2 * (vc & 0xFFFFFFFF) * (vd & 0xFFFFFFFF)) & MASK
+    vb = rotr(vb ^ vc, 63)
+    v[a], v[b], v[c], v[d] = va, vb, vc, vd
+
+
+def permute(v):
+    gb(v, 0, 4, 8, 12)
+    gb(v, 1, 5, 9, 13)
+    gb(v, 2, 6, 10, 14)
+    gb(v, 3, 7, 11, 15)
+    gb(v, 0, 5, 10, 15)
+    gb(v, 1, 6, 11, 12)
+    gb(v, 2, 7, 8, 13)
+    gb(v, 3, 4, 9, 14)
+
+
+def compress(x, y):
+    r = [a ^ b for a, b in zip(x, y)]
+    z = list(r)
+    for row in range(8):
+        v = z[16 * row : 16 * row + 16]
+        permute(v)
+        z[16 * row : 16 * row + 16] = v
+    for column in range(8):
+        indices = []
+        for row in range(8):
+            indices += [16 * row + 2 * column, 16 * row + 2 * column + 1]
+        v = [z[i] for i in indices]
+        permute(v)
+        for i, value in zip(indices, v):
+            z[i] = value
+    return [a ^ b for a, b in zip(z, r)]
+
+
+def to_words(data):
+    return list(struct.unpack("<128Q", data))
+
+
+def argon2(kind, password, salt, iterations, memory_kib, lanes, length):
+    h0 = hashlib.blake2b(
+        struct.pack("<IIIIII", lanes, length, memory_kib, iterations, 0x13, kind)
+        + struct.pack("<I", len(password))
+        + password
+        + struct.pack("<I", len(salt))
+        + salt
+        + struct.pack("<I", 0)
+        + struct.pack("<I", 0)
+    ).digest()
+
+    blocks_total = 4 * lanes * (memory_kib // (4 * lanes))
+    columns = blocks_total // lanes
+    segment = columns // 4
+    memory = [[None] * columns for _ in range(lanes)]
+    for lane in range(lanes):
+        for i in range(2):
+            memory[lane][i] = to_words(
+                blake2b_long(h0 + struct.pack("<II", i, lane), 1024)
+            )
+
+    zero = [0] * 128
+    for r in range(iterations):
+        for s in range(4):
+            for lane in range(lanes):
+                independent = kind == 1 or (kind == 2 and r == 0 and s < 2)
+                start = 2 if r == 0 and s == 0 else 0
+                counter = 0
+                addresses = None
+
+                def next_addresses():
+                    nonlocal counter
+                    counter += 1
+                    z = [r, lane, s, blocks_total, iterations, kind, counter] + [0] * 121
+                    return compress(zero, compress(zero, z))
+
+                if independent and start == 2:
+                    addresses = next_addresses()
+                for index in range(start, segment):
+                    column = s * segment + index
+                    previous = memory[lane][column - 1 if column > 0 else columns - 1]
+                    if independent:
+                        if index % 128 == 0:
+                            addresses = next_addresses()
+                        pseudo = addresses[index % 128]
+                    else:
+                        pseudo = previous[0]
+                    j1 = pseudo & 0xFFFFFFFF
+                    j2 = pseudo >> 32
+
+                    ref_lane = lane if r == 0 and s == 0 else j2 % lanes
+                    same_lane = ref_lane == lane
+                    if r == 0:
+                        if same_lane:
+                            area = s * segment + index - 1
+                        else:
+                            area = s * segment - (1 if index == 0 else 0)
+                    else:
+                        if same_lane:
+                            area = columns - segment + index - 1
+                        else:
+                            area = columns - segment - (1 if index == 0 else 0)
+                    x = (j1 * j1) >> 32
+                    y = (area * x) >> 32
+                    relative = area - 1 - y
+                    first = 0 if r == 0 or s == 3 else (s + 1) * segment
+                    reference = memory[ref_lane][(first + relative) % columns]
+
+                    block = compress(previous, reference)
+                    if r > 0:
+                        block = [a ^ b for a, b in zip(block, memory[lane][column])]
+                    memory[lane][column] = block
+
+    final = list(memory[0][columns - 1])
+    for lane in range(1, lanes):
+        final = [a ^ b for a, b in zip(final, memory[lane][columns - 1])]
+    return blake2b_long(struct.pack("<128Q", *final), length)
+
+
+def check_argon2():
+    password, salt = b"password", b"somesaltsomesalt"
+    expected = Argon2id(
+        salt=salt, length=32, iterations=2, lanes=2, memory_cost=256
+    ).derive(password)
+    assert argon2(2, password, salt, 2, 256, 2, 32) == expected, "Argon2 is broken"
+
+
+# KDBX 4
+
+
+def variant_dictionary(items):
+    out = struct.pack("<H", 0x0100)
+    for kind, key, value in items:
+        key = key.encode()
+        if kind == 0x04:
+            value = struct.pack("<I", value)
+        elif kind == 0x05:
+            value = struct.pack("<Q", value)
+        out += struct.pack("<B", kind) + struct.pack("<i", len(key)) + key
+        out += struct.pack("<i", len(value)) + value
+    return out + b"\x00"
+
+
+def field(kind, data):
+    return struct.pack("<BI", kind, len(data)) + data
+
+
+def block_key(base, index):
+    return hashlib.sha512(struct.pack("<Q", index) + base).digest()
+
+
+def chacha20(key, nonce, data):
+    cipher = Cipher(algorithms.ChaCha20(key, b"\x00" * 4 + nonce), mode=None)
+    return cipher.encryptor().update(data)
+
+
+def kdbx_time(unix):
+    return base64.b64encode(struct.pack("<q", unix + EPOCH_OFFSET)).decode()
+
+
+class Protector:
+    # The inner ChaCha20 stream, protected values use it in document order
+    def __init__(self, key):
+        digest = hashlib.sha512(key).digest()
+        self.key, self.nonce = digest[:32], digest[32:44]
+        self.position = 0
+
+    def protect(self, value):
+        data = value.encode()
+        stream = chacha20(self.key, self.nonce, b"\x00" * (self.position + len(data)))
+        stream = stream[self.position :]
+        self.position += len(data)
+        return base64.b64encode(bytes(a ^ b for a, b in zip(data, stream))).decode()
+
+
+def times_xml(indent, created, modified):
+    pad = "\t" * indent
+    return (
+        f"{pad}<Times>\n"
+        f"{pad}\t<LastModificationTime>{kdbx_time(modified)}</LastModificationTime>\n"
+        f"{pad}\t<CreationTime>{kdbx_time(created)}</CreationTime>\n"
+        f"{pad}\t<LastAccessTime>{kdbx_time(modified)}</LastAccessTime>\n"
+        f"{pad}\t<ExpiryTime>{kdbx_time(created)}</ExpiryTime>\n"
+        f"{pad}\t<Expires>False</Expires>\n"
+        f"{pad}\t<UsageCount>0</UsageCount>\n"
+        f"{pad}\t<LocationChanged>{kdbx_time(created)}</LocationChanged>\n"
+        f"{pad}</Times>\n"
+    )
+
+
+def escape(text):
+    return text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;")
+
+
+def uuid(name):
+    return base64.b64encode(hashlib.md5(name.encode()).digest()).decode()
+
+
+def entry_xml(entry, protector, indent, history=True):
+    pad = "\t" * indent
+    out = f"{pad}<Entry>\n"
+    out += f"{pad}\t<UUID>{uuid(entry['Title'] + str(entry.get('modified')))}</UUID>\n"
+    out += f"{pad}\t<IconID>0</IconID>\n"
+    out += f"{pad}\t<ForegroundColor/>\n{pad}\t<BackgroundColor/>\n{pad}\t<OverrideURL/>\n"
+    out += f"{pad}\t<Tags>{escape(entry.get('tags', ''))}</Tags>\n"
+    out += times_xml(indent + 1, entry["created"], entry["modified"])
+    strings = dict(entry["strings"])
+    strings.setdefault("Notes", "")
+    strings["Title"] = entry["Title"]
+    for key in sorted(strings):
+        value = strings[key]
+        protected = key == "Password" or key in entry.get("protected", [])
+        out += f"{pad}\t<String>\n{pad}\t\t<Key>{escape(key)}</Key>\n"
+        if protected:
+            out += f'{pad}\t\t<Value Protected="True">{protector.protect(value)}</Value>\n'
+        elif value:
+            out += f"{pad}\t\t<Value>{escape(value)}</Value>\n"
+        else:
+            out += f"{pad}\t\t<Value/>\n"
+        out += f"{pad}\t</String>\n"
+    for name, ref in entry.get("binaries", []):
+        out += f'{pad}\t<Binary>\n{pad}\t\t<Key>{escape(name)}</Key>\n{pad}\t\t<Value Ref="{ref}"/>\n{pad}\t</Binary>\n'
+    out += (
+        f"{pad}\t<AutoType>\n{pad}\t\t<Enabled>True</Enabled>\n"
+        f"{pad}\t\t<DataTransferObfuscation>0</DataTransferObfuscation>\n"
+        f"{pad}\t\t<Association>\n{pad}\t\t\t<Window>Login*</Window>\n"
+        f"{pad}\t\t\t<KeystrokeSequence/>\n{pad}\t\t</Association>\n{pad}\t</AutoType>\n"
+    )
+    if history and entry.get("history"):
+        out += f"{pad}\t<History>\n"
+        for old in entry["history"]:
+            out += entry_xml(old, protector, indent + 2, False)
+        out += f"{pad}\t</History>\n"
+    return out + f"{pad}</Entry>\n"
+
+
+def group_xml(group, protector, indent):
+    pad = "\t" * indent
+    out = f"{pad}<Group>\n"
+    out += f"{pad}\t<UUID>{uuid('group ' + group['name'])}</UUID>\n"
+    out += f"{pad}\t<Name>{escape(group['name'])}</Name>\n"
+    out += f"{pad}\t<Notes/>\n{pad}\t<IconID>{group.get('icon', 48)}</IconID>\n"
+    out += times_xml(indent + 1, 1700000000, 1700000000)
+    out += f"{pad}\t<IsExpanded>True</IsExpanded>\n{pad}\t<DefaultAutoTypeSequence/>\n"
+    out += f"{pad}\t<EnableAutoType>null</EnableAutoType>\n{pad}\t<EnableSearching>null</EnableSearching>\n"
+    out += f"{pad}\t<LastTopVisibleEntry>AAAAAAAAAAAAAAAAAAAAAA==</LastTopVisibleEntry>\n"
+    for entry in group.get("entries", []):
+        out += entry_xml(entry, protector, indent + 1)
+    for child in group.get("groups", []):
+        out += group_xml(child, protector, indent + 1)
+    return out + f"{pad}</Group>\n"
+
+
+def database_xml(root, protector):
+    recycle_bin = uuid("group Recycle Bin")
+    return (
+        '<?xml version="1.0" encoding="UTF-8" standalone="yes"?>\n'
+        "<KeePassFile>\n"
+        "\t<Meta>\n"
+        "\t\t<Generator>KeePassXC</Generator>\n"
+        "\t\t<DatabaseName>Passwords</DatabaseName>\n"
+        f"\t\t<DatabaseNameChanged>{kdbx_time(1700000000)}</DatabaseNameChanged>\n"
+        "\t\t<DatabaseDescription/>\n"
+        "\t\t<DefaultUserName/>\n"
+        "\t\t<MaintenanceHistoryDays>365</MaintenanceHistoryDays>\n"
+        "\t\t<Color/>\n"
+        "\t\t<MemoryProtection>\n"
+        "\t\t\t<ProtectTitle>False</ProtectTitle>\n"
+        "\t\t\t<ProtectUserName>False</ProtectUserName>\n"
+        "\t\t\t<ProtectPassword>True</ProtectPassword>\n"
+        "\t\t\t<ProtectURL>False</ProtectURL>\n"
+        "\t\t\t<ProtectNotes>False</ProtectNotes>\n"
+        "\t\t</MemoryProtection>\n"
+        "\t\t<RecycleBinEnabled>True</RecycleBinEnabled>\n"
+        f"\t\t<RecycleBinUUID>{recycle_bin}</RecycleBinUUID>\n"
+        "\t\t<HistoryMaxItems>10</HistoryMaxItems>\n"
+        "\t\t<HistoryMaxSize>6291456</HistoryMaxSize>\n"
+        "\t</Meta>\n"
+        "\t<Root>\n"
+        + group_xml(root, protector, 2)
+        + "\t\t<DeletedObjects/>\n"
+        "\t</Root>\n"
+        "</KeePassFile>\n"
+    )
+
+
+def composite_key(password, key_file):
+    parts = hashlib.sha256(password.encode()).digest()
+    if key_file is not None:
+        parts += key_file
+    return hashlib.sha256(parts).digest()
+
+
+def write_kdbx(path, root, binaries, password, key_file, cipher, kdf, block_size):
+    master_seed = os.urandom(32)
+    salt = os.urandom(32)
+    if kdf == KDF_AES:
+        rounds = 1000
+        kdf_parameters = variant_dictionary(
+            [(0x42, "$UUID", KDF_AES), (0x05, "R", rounds), (0x42, "S", salt)]
+        )
+        transformed = composite_key(password, key_file)
+        aes = Cipher(algorithms.AES(salt), modes.ECB()).encryptor()
+        for _ in range(rounds):
+            transformed = aes.update(transformed)
+        transformed = hashlib.sha256(transformed).digest()
+    else:
+        iterations, memory_kib, lanes = 2, 1024, 2
+        kdf_parameters = variant_dictionary(
+            [
+                (0x42, "$UUID", kdf),
+                (0x05, "I", iterations),
+                (0x05, "M", memory_kib * 1024),
+                (0x04, "P", lanes),
+                (0x42, "S", salt),
+                (0x04, "V", 0x13),
+            ]
+        )
+        kind = 0 if kdf == KDF_ARGON2D else 2
+        transformed = argon2(
+            kind, composite_key(password, key_file), salt, iterations, memory_kib, lanes, 32
+        )
+
+    iv = os.urandom(16 if cipher == CIPHER_AES256 else 12)
+    header = struct.pack("<IIHH", 0x9AA2D903, 0xB54BFB67, 1, 4)
+    header += field(2, cipher)
+    header += field(3, struct.pack("<I", 1))
+    header += field(4, master_seed)
+    header += field(7, iv)
+    header += field(11, kdf_parameters)
+    header += field(0, b"\r\n\r\n")
+
+    key = hashlib.sha256(master_seed + transformed).digest()
+    hmac_base = hashlib.sha512(master_seed + transformed + b"\x01").digest()
+
+    inner_key = os.urandom(64)
+    protector = Protector(inner_key)
+    xml = database_xml(root, protector).encode()
+    inner = field(1, struct.pack("<I", 3)) + field(2, inner_key)
+    for data, protected in binaries:
+        inner += field(3, (b"\x01" if protected else b"\x00") + data)
+    inner += field(0, b"")
+    payload = gzip.compress(inner + xml)
+
+    if cipher == CIPHER_AES256:
+        padder = padding.PKCS7(128).padder()
+        padded = padder.update(payload) + padder.finalize()
+        encrypted = Cipher(algorithms.AES(key), modes.CBC(iv)).encryptor().update(padded)
+    else:
+        encrypted = chacha20(key, iv, payload)
+
+    out = header + hashlib.sha256(header).digest()
+    out += hmac.new(block_key(hmac_base, 2**64 - 1), header, hashlib.sha256).digest()
+    chunks = [encrypted[i : i + block_size] for i in range(0, len(encrypted), block_size)]
+    for index, chunk in enumerate(chunks + [b""]):
+        size = struct.pack("<i", len(chunk))
+        mac = hmac.new(
+            block_key(hmac_base, index), struct.pack("<Q", index) + size + chunk, hashlib.sha256
+        ).digest()
+        out += mac + size + chunk
+
+    with open(os.path.join(HERE, path), "wb") as file:
+        file.write(out)
+
+
+def key_file_xml(data):
+    hex_data = data.hex().upper()
+    checksum = hashlib.sha256(data).digest()[:4].hex().upper()
+    groups = " ".join(hex_data[i : i + 8] for i in range(0, len(hex_data), 8))
+    return (
+        '<?xml version="1.0" encoding="UTF-8"?>\n'
+        "<KeyFile>\n"
+        "    <Meta>\n"
+        "        <Version>2.0</Version>\n"
+        "    </Meta>\n"
+        "    <Key>\n"
+        f'        <Data Hash="{checksum}">\n'
+        f"            {groups[:35]}\n"
+        f"            {groups[36:]}\n"
+        "        </Data>\n"
+        "    </Key>\n"
+        "</KeyFile>\n"
+    )
+
+
+def sample_root():
+    mail_history = [
+        {
+            "Title": "Mail",
+            "created": 1600000000,
+            "modified": 1600000000,
+            "strings": {"UserName": "someone@example.com", "Password": "first-Pass1"},
+        },
+        {
+            "Title": "Mail",
+            "created": 1600000000,
+            "modified": 1650000000,
+            "strings": {"UserName": "someone@example.com", "Password": "second-Pass2"},
+        },
+    ]
+    mail = {
+        "Title": "Mail",
+        "created": 1600000000,
+        "modified": 1700000000,
+        "tags": "personal;Important",
+        "strings": {
+            "UserName": "someone@example.com",
+            "Password": "current-Pass3",
+            "URL": "https://mail.example.com",
+            "Notes": "Recovery codes are attached",
+            "otp": "otpauth://totp/Mail?secret=JBSWY3DPEHPK3PXP",
+            "Security question": "blue",
+        },
+        "protected": ["otp"],
+        "binaries": [("codes.txt", 0), ("photo.bin", 1)],
+        "history": mail_history,
+    }
+    duplicate = {
+        "Title": "Mail",
+        "created": 1700000000,
+        "modified": 1700000000,
+        "strings": {"UserName": "other@example.com", "Password": "dup-Pass4"},
+    }
+    server = {
+        "Title": "Server",
+        "created": 1690000000,
+        "modified": 1690000000,
+        "strings": {"UserName": "root", "Password": "Srv&<pass>", "URL": "ssh://host"},
+    }
+    untitled = {
+        "Title": "",
+        "created": 1690000000,
+        "modified": 1690000000,
+        "strings": {"Password": "no-title"},
+    }
+    deleted = {
+        "Title": "Old",
+        "created": 1690000000,
+        "modified": 1690000000,
+        "strings": {"Password": "deleted"},
+    }
+    return {
+        "name": "Passwords",
+        "entries": [mail, duplicate],
+        "groups": [
+            {"name": "Work", "entries": [server, untitled], "groups": [{"name": "Empty"}]},
+            {"name": "Recycle Bin", "icon": 43, "entries": [deleted]},
+        ],
+    }
+
+
+BINARIES = [(b"1111-2222\n3333-4444\n", True), (bytes(range(256)) * 4, False)]
+
+
+def main():
+    check_argon2()
+    key_data = bytes.fromhex(
+        "6D5F8B2C1A0E9D4F3B7C6A5E4D3C2B1A0F9E8D7C6B5A49382716050403020100"
+    )
+    with open(os.path.join(HERE, "keyfile.keyx"), "w") as file:
+        file.write(key_file_xml(key_data))
+
+    root = sample_root()
+    write_kdbx("aes-argon2d.kdbx", root, BINARIES, "test", None, CIPHER_AES256, KDF_ARGON2D, 1 << 20)
+    write_kdbx(
+        "chacha20-argon2id-keyfile.kdbx",
+        root,
+        BINARIES,
+        "test",
+        key_data,
+        CIPHER_CHACHA20,
+        KDF_ARGON2ID,
+        512,
+    )
+    write_kdbx("aes-aeskdf.kdbx", root, BINARIES, "test", None, CIPHER_AES256, KDF_AES, 1 << 20)
+
+
+if __name__ == "__main__":
+    main()
@@ -2,6 +2,8 @@ use std::env::VarError;
 
 use json::JsonValue;
 use log::{info, warn};
+use pwm_db::db_encrypted::{attachment::DEFAULT_MAX_ATTACHMENT_SIZE, trash::DEFAULT_TRASH_DAYS};
+use pwm_lib::atomic_file::{write_atomic, DEFAULT_BACKUPS};
 
 pub fn default_config() -> JsonValue {
     json::object! {
@@ -10,6 +12,9 @@ pub fn default_config() -> JsonValue {
         prev_vaults: [],
         prev_vaults_max: 8,
         password_length: 32,
+        backups: DEFAULT_BACKUPS,
+        trash_days: DEFAULT_TRASH_DAYS,
+        max_attachment_size: DEFAULT_MAX_ATTACHMENT_SIZE,
     }
 }
 
@@ -83,7 +88,7 @@ pub fn write_config(config: JsonValue) {
 
     info!("writing to file \"{}\"", file.display().to_string());
 
-    match std::fs::write(file, config.to_string()) {
+    match write_atomic(file, config.to_string().as_bytes(), 0) {
         Ok(()) => (),
         Err(error) => {
             warn!("failed to get config: {}", error.to_string());
@@ -1,26 +1,40 @@
+pub mod entry_editor;
 pub mod error;
 pub mod message;
+pub mod progress;
 pub mod prompt;
 
 use crate::config::{default_config, write_config};
+use crate::gui::entry_editor::EditorAction;
 use crate::state::State;
 use crate::vault::Vault;
 use crate::{config::get_config, gui::error::GuiError};
+use pwm_db::db_base::{base_name, parent_group};
+use pwm_db::db_encrypted::{
+    attachment::DEFAULT_MAX_ATTACHMENT_SIZE,
+    diff::{FieldChange, VaultDiff},
+    entry::EncryptedEntry,
+    merge::MergeSide,
+    trash::DEFAULT_TRASH_DAYS,
+};
+use pwm_db::{audit::report::DEFAULT_MAX_AGE_DAYS, timestamp::format_timestamp};
 
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::path::Component;
 use std::{path::PathBuf, sync::Arc};
 
 use eframe::egui::{
-    self, Button, Color32, Key, Label, Layout, Modifiers, Rounding, Sense, Stroke, Style, Vec2,
+    self, Button, Color32, Key, Label, Layout, Link, Modifiers, Rounding, Sense, Stroke, Style,
+    Vec2,
 };
 use eframe::CreationContext;
 use egui_extras::{Column, TableBuilder};
 use log::{debug, error, info, warn};
 
 use pwm_lib::{
+    atomic_file::DEFAULT_BACKUPS,
     crypt_file::{decrypt_file, encrypt_file},
-    random::random_password,
+    random::{random_password, token::TokenKind},
     zeroize::{Zeroize, Zeroizing},
 };
 
@@ -32,6 +46,9 @@ pub struct Gui {
 
     // Copies the original value and is modified by the text box in options
     prev_vaults_max_length_text: String,
+    backups_text: String,
+    trash_days_text: String,
+    max_attachment_size_text: String,
 
     // Exit confirmation if a vault was modified
     show_exit_confirmation_dialog: bool,
@@ -73,6 +90,14 @@ impl Gui {
 
         let pass_len = config["password_length"].as_usize().unwrap_or(32);
 
+        let backups = config["backups"].as_usize().unwrap_or(DEFAULT_BACKUPS);
+
+        let trash_days = config["trash_days"].as_u32().unwrap_or(DEFAULT_TRASH_DAYS);
+
+        let max_attachment_size = config["max_attachment_size"]
+            .as_u64()
+            .unwrap_or(DEFAULT_MAX_ATTACHMENT_SIZE);
+
         Self {
             scale: config["scale"].as_f32().unwrap_or(1.85),
             update_scale: true,
@@ -80,6 +105,9 @@ impl Gui {
             darkmode: config["dark"].as_bool().unwrap_or(true),
 
             prev_vaults_max_length_text: format!("{max_len}"),
+            backups_text: format!("{backups}"),
+            trash_days_text: format!("{trash_days}"),
+            max_attachment_size_text: format!("{max_attachment_size}"),
 
             show_exit_confirmation_dialog: false,
             allowed_to_close: false,
@@ -97,6 +125,9 @@ impl Gui {
                 prev_vaults,
                 max_len,
                 pass_len,
+                backups,
+                trash_days,
+                max_attachment_size,
             )),
         }
     }
@@ -207,6 +238,10 @@ impl eframe::App for Gui {
                 GuiError::display_error_or_print(self.state.clone(), error);
             }
 
+            if let Err(error) = Gui::display_progress(self.state.clone(), ui) {
+                GuiError::display_error_or_print(self.state.clone(), error);
+            }
+
             if let Err(error) = Gui::display_messages(self.state.clone(), ui) {
                 GuiError::display_error_or_print(self.state.clone(), error);
             }
@@ -214,11 +249,82 @@ impl eframe::App for Gui {
             if let Err(error) = self.display_vault(ui) {
                 GuiError::display_error_or_print(self.state.clone(), error);
             }
+
+            if let Err(error) = Gui::display_entry_editor(self.state.clone(), ctx) {
+                GuiError::display_error_or_print(self.state.clone(), error);
+            }
+
+            if let Err(error) = Gui::display_merge_conflicts(self.state.clone(), ctx) {
+                GuiError::display_error_or_print(self.state.clone(), error);
+            }
+
+            if let Err(error) = Gui::display_unsaved_changes(self.state.clone(), ctx) {
+                GuiError::display_error_or_print(self.state.clone(), error);
+            }
+
+            if let Err(error) = Gui::display_trash(self.state.clone(), ctx) {
+                GuiError::display_error_or_print(self.state.clone(), error);
+            }
+
+            if let Err(error) = Gui::display_audit(self.state.clone(), ctx) {
+                GuiError::display_error_or_print(self.state.clone(), error);
+            }
         });
     }
 }
 
 impl Gui {
+    fn update_backups(&mut self) {
+        let backups = match self.backups_text.parse() {
+            Ok(backups) => backups,
+            Err(error) => {
+                GuiError::display_error_or_print(self.state.clone(), GuiError::from(error));
+                return;
+            }
+        };
+
+        match self.state.backups.write() {
+            Ok(mut state_backups) => *state_backups = backups,
+            Err(error) => {
+                GuiError::display_error_or_print(self.state.clone(), error.into());
+            }
+        }
+    }
+
+    fn update_trash_days(&mut self) {
+        let trash_days = match self.trash_days_text.parse() {
+            Ok(trash_days) => trash_days,
+            Err(error) => {
+                GuiError::display_error_or_print(self.state.clone(), GuiError::from(error));
+                return;
+            }
+        };
+
+        match self.state.trash_days.write() {
+            Ok(mut state_trash_days) => *state_trash_days = trash_days,
+            Err(error) => {
+                GuiError::display_error_or_print(self.state.clone(), error.into());
+            }
+        }
+    }
+
+    fn update_max_attachment_size(&mut self) {
+        let max_attachment_size = match self.max_attachment_size_text.parse() {
+            Ok(max_attachment_size) => max_attachment_size,
+            Err(error) => {
+                GuiError::display_error_or_print(self.state.clone(), GuiError::from(error));
+                return;
+            }
+        };
+
+        match self.state.max_attachment_size.write() {
+            Ok(mut state_max_attachment_size) => *state_max_attachment_size = max_attachment_size,
+            Err(error) => {
+                GuiError::display_error_or_print(self.state.clone(), error.into());
+            }
+        }
+    }
+
     fn reset_settings(&mut self) {
         let defaults = default_config();
 
@@ -254,6 +360,15 @@ impl Gui {
                 GuiError::display_error_or_print(self.state.clone(), error.into());
             }
         }
+
+        self.backups_text = defaults["backups"].to_string();
+        self.update_backups();
+
+        self.trash_days_text = defaults["trash_days"].to_string();
+        self.update_trash_days();
+
+        self.max_attachment_size_text = defaults["max_attachment_size"].to_string();
+        self.update_max_attachment_size();
     }
 
     pub fn open_file_dialog(state: Arc<State>) -> Option<PathBuf> {
@@ -275,6 +390,43 @@ impl Gui {
         file
     }
 
+    // Same as open_file_dialog, the title says which file to pick
+    pub fn open_file_dialog_with_title(state: Arc<State>, title: &str) -> Option<PathBuf> {
+        let mut dialog = rfd::FileDialog::new().set_title(title);
+
+        match std::env::current_dir() {
+            Ok(path) => {
+                dialog = dialog.set_directory(path);
+            }
+            Err(error) => {
+                GuiError::display_error_or_print(
+                    state.clone(),
+                    format!("Could not open current directory: {}", error).into(),
+                );
+            }
+        };
+
+        dialog.pick_file()
+    }
+
+    pub fn open_folder_dialog(state: Arc<State>) -> Option<PathBuf> {
+        let mut dialog = rfd::FileDialog::new();
+
+        match std::env::current_dir() {
+            Ok(path) => {
+                dialog = dialog.set_directory(path);
+            }
+            Err(error) => {
+                GuiError::display_error_or_print(
+                    state.clone(),
+                    format!("Could not open current directory: {}", error).into(),
+                );
+            }
+        };
+
+        dialog.pick_folder()
+    }
+
     pub fn save_file_dialog(state: Arc<State>) -> Option<PathBuf> {
         let dialog = rfd::FileDialog::new();
 
@@ -520,6 +672,18 @@ impl Gui {
         }
     }
 
+    async fn encrypt_folder(state: Arc<State>) {
+        if let Err(error) = State::encrypt_folder(state.clone()).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn decrypt_folder(state: Arc<State>) {
+        if let Err(error) = State::decrypt_folder(state.clone()).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
     async fn random_password(state: Arc<State>) {
         let mut clipboard = match state.clipboard_string.write() {
             Ok(clipboard) => clipboard,
@@ -556,6 +720,32 @@ impl Gui {
         }
     }
 
+    async fn random_token(state: Arc<State>) {
+        let token = match State::generate_token(state.clone()) {
+            Ok(token) => token,
+            Err(error) => {
+                GuiError::display_error_or_print(state.clone(), error);
+                return;
+            }
+        };
+
+        match state.clipboard_string.write() {
+            Ok(mut clipboard) => {
+                *clipboard = Some(token);
+                state.egui_ctx.request_repaint();
+            }
+            Err(error) => {
+                GuiError::display_error_or_print(state.clone(), error.into());
+            }
+        }
+    }
+
+    async fn store_token(state: Arc<State>) {
+        if let Err(error) = State::store_token(state.clone()).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
     async fn clear_clipboard(state: Arc<State>) {
         let mut clipboard = match state.clipboard_string.write() {
             Ok(clipboard) => clipboard,
@@ -580,12 +770,72 @@ impl Gui {
         }
     }
 
+    async fn insert_from_csv_dry_run(state: Arc<State>) {
+        if let Err(error) = State::insert_from_csv_dry_run(state.clone()).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn insert_from_kdbx(state: Arc<State>, with_key_file: bool) {
+        if let Err(error) = State::insert_from_kdbx(state.clone(), with_key_file).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn insert_from_kdbx_dry_run(state: Arc<State>, with_key_file: bool) {
+        if let Err(error) = State::insert_from_kdbx_dry_run(state.clone(), with_key_file).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn insert_from_bitwarden(state: Arc<State>) {
+        if let Err(error) = State::insert_from_bitwarden(state.clone()).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn insert_from_bitwarden_dry_run(state: Arc<State>) {
+        if let Err(error) = State::insert_from_bitwarden_dry_run(state.clone()).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn merge(state: Arc<State>) {
+        if let Err(error) = State::merge(state.clone()).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn resolve_conflict(state: Arc<State>, conflict: Option<(String, MergeSide)>) {
+        if let Err(error) = State::resolve_conflict(state.clone(), conflict).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn show_unsaved_changes(state: Arc<State>, reveal: bool) {
+        if let Err(error) = State::show_unsaved_changes(state.clone(), reveal).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
     async fn export_to_csv(state: Arc<State>) {
         if let Err(error) = State::export_to_csv(state.clone()).await {
             GuiError::display_error_or_print(state.clone(), error);
         }
     }
 
+    async fn audit(state: Arc<State>) {
+        if let Err(error) = State::audit(state.clone()).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn check_pwned(state: Arc<State>, directory: bool) {
+        if let Err(error) = State::check_pwned(state.clone(), directory).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
     async fn rename(state: Arc<State>, name: String) {
         if let Err(error) = State::rename(state.clone(), name).await {
             GuiError::display_error_or_print(state.clone(), error);
@@ -598,12 +848,108 @@ impl Gui {
         }
     }
 
+    async fn show_history(state: Arc<State>, name: String) {
+        if let Err(error) = State::show_history(state.clone(), name).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn restore_history(state: Arc<State>, name: String) {
+        if let Err(error) = State::restore_history(state.clone(), name).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn add_attachment(state: Arc<State>, name: String) {
+        if let Err(error) = State::add_attachment(state.clone(), name).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn extract_attachment(state: Arc<State>, name: String, attachment: String) {
+        if let Err(error) = State::extract_attachment(state.clone(), name, attachment).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn remove_attachment(state: Arc<State>, name: String, attachment: String) {
+        if let Err(error) = State::remove_attachment(state.clone(), name, attachment).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn restore_trashed(state: Arc<State>, name: String) {
+        if let Err(error) = State::restore_trashed(state.clone(), name).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn purge_trash(state: Arc<State>) {
+        if let Err(error) = State::purge_trash(state.clone()).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn edit_entry(state: Arc<State>, name: String) {
+        if let Err(error) = State::edit_entry(state.clone(), name).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn save_entry(state: Arc<State>) {
+        if let Err(error) = State::save_entry(state.clone()).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
     async fn remove(state: Arc<State>, name: String) {
         if let Err(error) = State::remove(state.clone(), name).await {
             GuiError::display_error_or_print(state.clone(), error);
         }
     }
 
+    async fn move_entry(state: Arc<State>, name: String) {
+        if let Err(error) = State::move_entry(state.clone(), name).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn add_tag(state: Arc<State>, name: String) {
+        if let Err(error) = State::add_tag(state.clone(), name).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn remove_tag(state: Arc<State>, name: String, tag: String) {
+        if let Err(error) = State::remove_tag(state.clone(), name, tag).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn undo(state: Arc<State>, redo: bool) {
+        if let Err(error) = State::undo(state.clone(), redo).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn create_group(state: Arc<State>, parent: String) {
+        if let Err(error) = State::create_group(state.clone(), parent).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn rename_group(state: Arc<State>, group: String) {
+        if let Err(error) = State::rename_group(state.clone(), group).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
+    async fn remove_group(state: Arc<State>, group: String, recursive: bool) {
+        if let Err(error) = State::remove_group(state.clone(), group, recursive).await {
+            GuiError::display_error_or_print(state.clone(), error);
+        }
+    }
+
     async fn get(state: Arc<State>, name: String) {
         if let Err(error) = State::get(state.clone(), name).await {
             GuiError::display_error_or_print(state.clone(), error);
@@ -787,6 +1133,31 @@ impl Gui {
                     tokio::spawn(Gui::file_save_as(self.state.clone()));
                     ui.close_menu();
                 }
+                if ui.add_enabled(vault_locked, Button::new("Merge")).clicked() && vault_locked {
+                    tokio::spawn(Gui::merge(self.state.clone()));
+                    ui.close_menu();
+                }
+                if ui
+                    .add_enabled(vault_locked, Button::new("Show Unsaved Changes"))
+                    .clicked()
+                    && vault_locked
+                {
+                    tokio::spawn(Gui::show_unsaved_changes(self.state.clone(), false));
+                    ui.close_menu();
+                }
+                if ui
+                    .add_enabled(vault_locked, Button::new("Recycle Bin"))
+                    .clicked()
+                    && vault_locked
+                {
+                    match self.state.show_trash.write() {
+                        Ok(mut show_trash) => *show_trash = true,
+                        Err(error) => {
+                            GuiError::display_error_or_print(self.state.clone(), error.into());
+                        }
+                    }
+                    ui.close_menu();
+                }
             });
 
             ui.menu_button("Options", |ui| {
@@ -825,6 +1196,43 @@ impl Gui {
                     }
                 });
 
+                ui.horizontal(|ui| {
+                    ui.label("Backups Kept");
+                    let response = ui.add_sized(
+                        [30.0, 20.0],
+                        egui::TextEdit::singleline(&mut self.backups_text),
+                    );
+                    if response.changed() {
+                        self.update_backups();
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Recycle Bin Days");
+                    let response = ui
+                        .add_sized(
+                            [30.0, 20.0],
+                            egui::TextEdit::singleline(&mut self.trash_days_text),
+                        )
+                        .on_hover_text(
+                            "Removed entries older than this are purged when saving, 0 keeps them",
+                        );
+                    if response.changed() {
+                        self.update_trash_days();
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Max Attachment Bytes");
+                    let response = ui.add_sized(
+                        [80.0, 20.0],
+                        egui::TextEdit::singleline(&mut self.max_attachment_size_text),
+                    );
+                    if response.changed() {
+                        self.update_max_attachment_size();
+                    }
+                });
+
                 if ui.button("Reset to Defaults").clicked() {
                     self.reset_settings();
                 };
@@ -839,8 +1247,16 @@ impl Gui {
                     tokio::spawn(Gui::decrypt_file(self.state.clone()));
                     ui.close_menu();
                 }
-                match self.state.password_length.write() {
-                    Ok(mut password_length) => {
+                if ui.button("Encrypt Folder").clicked() {
+                    tokio::spawn(Gui::encrypt_folder(self.state.clone()));
+                    ui.close_menu();
+                }
+                if ui.button("Decrypt Folder").clicked() {
+                    tokio::spawn(Gui::decrypt_folder(self.state.clone()));
+                    ui.close_menu();
+                }
+                match self.state.password_length.write() {
+                    Ok(mut password_length) => {
                         ui.menu_button("Password Generation", |ui| {
                             ui.label("Password Length");
                             ui.horizontal(|ui| {
@@ -865,6 +1281,11 @@ impl Gui {
                         GuiError::display_error_or_print(self.state.clone(), error.into());
                     }
                 }
+                ui.menu_button("Token Generation", |ui| {
+                    if let Err(error) = self.display_token_menu(ui, vault_locked) {
+                        GuiError::display_error_or_print(self.state.clone(), error);
+                    }
+                });
             });
 
             if ui.button("Clear Clipboard").clicked() {
@@ -876,6 +1297,56 @@ impl Gui {
         Ok(())
     }
 
+    fn display_token_menu(&mut self, ui: &mut egui::Ui, vault_locked: bool) -> Result<(), GuiError> {
+        let mut kind = self.state.token_kind.write()?;
+        egui::ComboBox::from_label("Kind")
+            .selected_text(kind.as_str())
+            .show_ui(ui, |ui| {
+                for name in TokenKind::names() {
+                    ui.selectable_value(&mut *kind, String::from(name), name);
+                }
+            });
+
+        if kind.as_str() != "uuid" {
+            ui.label(if kind.as_str() == "apikey" {
+                "Length"
+            } else {
+                "Bytes"
+            });
+            ui.add_sized(
+                [40.0, 20.0],
+                egui::TextEdit::singleline(&mut *self.state.token_length.write()?),
+            );
+        }
+
+        if kind.as_str() == "apikey" {
+            ui.label("Prefix");
+            ui.add_sized(
+                [80.0, 20.0],
+                egui::TextEdit::singleline(&mut *self.state.token_prefix.write()?),
+            );
+        }
+        drop(kind);
+
+        ui.horizontal(|ui| {
+            if ui.button("Generate").clicked() {
+                tokio::spawn(Gui::random_token(self.state.clone()));
+                ui.close_menu();
+            }
+            // vault_locked is true when a vault is open and not busy
+            if ui
+                .add_enabled(vault_locked, Button::new("Store as Entry"))
+                .clicked()
+                && vault_locked
+            {
+                tokio::spawn(Gui::store_token(self.state.clone()));
+                ui.close_menu();
+            }
+        });
+
+        Ok(())
+    }
+
     fn display_prompts(state: Arc<State>, ui: &mut egui::Ui) -> Result<(), GuiError> {
         let mut prompts = state.prompts.write()?;
         let mut count = 0;
@@ -908,6 +1379,22 @@ impl Gui {
         Ok(())
     }
 
+    fn display_progress(state: Arc<State>, ui: &mut egui::Ui) -> Result<(), GuiError> {
+        let progress = state.progress.read()?;
+
+        if progress.is_empty() {
+            return Ok(());
+        }
+
+        for indicator in progress.iter() {
+            indicator.display(ui);
+        }
+
+        ui.separator();
+
+        Ok(())
+    }
+
     fn display_messages(state: Arc<State>, ui: &mut egui::Ui) -> Result<(), GuiError> {
         let mut messages = state.messages.write()?;
         let mut count = 0;
@@ -935,6 +1422,317 @@ impl Gui {
         Ok(())
     }
 
+    fn display_entry_editor(state: Arc<State>, ctx: &egui::Context) -> Result<(), GuiError> {
+        // Skipped for a frame while an edit is being saved
+        let mut editor = match state.entry_editor.try_write() {
+            Ok(editor) => editor,
+            Err(_error) => return Ok(()),
+        };
+        let entry_editor = match &mut *editor {
+            Some(editor) => editor,
+            None => return Ok(()),
+        };
+
+        match entry_editor.ui(ctx, State::is_vault_locked(state.clone())) {
+            EditorAction::None => (),
+            EditorAction::Save => {
+                info!("Saving entry \"{}\"", entry_editor.title());
+                tokio::spawn(Gui::save_entry(state.clone()));
+            }
+            EditorAction::Cancel => *editor = None,
+        }
+
+        Ok(())
+    }
+
+    // One row per conflict of the last merge, until every one is resolved
+    fn display_merge_conflicts(state: Arc<State>, ctx: &egui::Context) -> Result<(), GuiError> {
+        // Skipped for a frame while a conflict is being resolved
+        let conflicts = match state.merge_conflicts.try_read() {
+            Ok(conflicts) => conflicts,
+            Err(_error) => return Ok(()),
+        };
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+
+        let enabled = State::is_vault_locked(state.clone());
+        let describe = |entry: &Option<EncryptedEntry>| match entry {
+            Some(entry) => format!("modified {}", format_timestamp(entry.modified())),
+            None => String::from("removed"),
+        };
+        egui::Window::new("Merge Conflicts")
+            .collapsible(false)
+            .resizable(true)
+            .show(ctx, |ui| {
+                egui::Grid::new("merge_conflicts")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("Entry");
+                        ui.label("This Vault");
+                        ui.label("Other Copy");
+                        ui.end_row();
+
+                        for conflict in conflicts.iter() {
+                            ui.label(&conflict.name);
+                            if ui
+                                .add_enabled(enabled, Button::new(describe(&conflict.ours)))
+                                .on_hover_text("Keep this vault's copy")
+                                .clicked()
+                                && enabled
+                            {
+                                tokio::spawn(Gui::resolve_conflict(
+                                    state.clone(),
+                                    Some((conflict.name.clone(), MergeSide::Ours)),
+                                ));
+                            }
+                            if ui
+                                .add_enabled(enabled, Button::new(describe(&conflict.theirs)))
+                                .on_hover_text("Keep the other copy")
+                                .clicked()
+                                && enabled
+                            {
+                                tokio::spawn(Gui::resolve_conflict(
+                                    state.clone(),
+                                    Some((conflict.name.clone(), MergeSide::Theirs)),
+                                ));
+                            }
+                            ui.end_row();
+                        }
+                    });
+
+                if ui
+                    .add_enabled(enabled, Button::new("Keep Newer"))
+                    .on_hover_text("Keep the copy modified last for every conflict")
+                    .clicked()
+                    && enabled
+                {
+                    tokio::spawn(Gui::resolve_conflict(state.clone(), None));
+                }
+            });
+
+        Ok(())
+    }
+
+    // What changed since the vault was saved, until it is closed
+    fn display_unsaved_changes(state: Arc<State>, ctx: &egui::Context) -> Result<(), GuiError> {
+        let lines = match &*state.unsaved_changes.read()? {
+            Some(diff) => describe_diff(diff),
+            None => return Ok(()),
+        };
+
+        let enabled = State::is_vault_locked(state.clone());
+        let mut close = false;
+        egui::Window::new("Unsaved Changes")
+            .collapsible(false)
+            .resizable(true)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical()
+                    .max_height(400.0)
+                    .show(ui, |ui| {
+                        for line in lines.iter() {
+                            ui.label(line.as_str());
+                        }
+                    });
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(enabled, Button::new("Reveal Secrets"))
+                        .on_hover_text("Show the old and new values of changed secrets")
+                        .clicked()
+                        && enabled
+                    {
+                        tokio::spawn(Gui::show_unsaved_changes(state.clone(), true));
+                    }
+                    if ui.button("Close").clicked() {
+                        close = true;
+                    }
+                });
+            });
+
+        if close {
+            *state.unsaved_changes.write()? = None;
+        }
+        Ok(())
+    }
+
+    // The newest removal of a name is restored first
+    fn display_trash(state: Arc<State>, ctx: &egui::Context) -> Result<(), GuiError> {
+        if !*state.show_trash.read()? {
+            return Ok(());
+        }
+
+        let trashed: Vec<(String, String)> = match &*state.vault.read()? {
+            Some(vault) => vault
+                .trash()
+                .iter()
+                .rev()
+                .map(|trashed| (trashed.name.clone(), format_timestamp(trashed.removed)))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let enabled = State::is_vault_locked(state.clone());
+        let mut close = false;
+        egui::Window::new("Recycle Bin")
+            .collapsible(false)
+            .resizable(true)
+            .show(ctx, |ui| {
+                if trashed.is_empty() {
+                    ui.label("The recycle bin is empty");
+                }
+
+                egui::ScrollArea::vertical()
+                    .max_height(400.0)
+                    .show(ui, |ui| {
+                        for (name, removed) in trashed.iter() {
+                            ui.horizontal(|ui| {
+                                ui.label(name.as_str());
+                                ui.label(format!("removed {}", removed));
+                                if ui.add_enabled(enabled, Button::new("Restore")).clicked()
+                                    && enabled
+                                {
+                                    tokio::spawn(Gui::restore_trashed(state.clone(), name.clone()));
+                                }
+                            });
+                        }
+                    });
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(enabled && !trashed.is_empty(), Button::new("Purge All"))
+                        .on_hover_text("Remove every entry in the recycle bin for good")
+                        .clicked()
+                        && enabled
+                    {
+                        tokio::spawn(Gui::purge_trash(state.clone()));
+                    }
+                    if ui.button("Close").clicked() {
+                        close = true;
+                    }
+                });
+            });
+
+        if close {
+            *state.show_trash.write()? = false;
+        }
+        Ok(())
+    }
+
+    // Every entry name links to its editor
+    fn display_audit(state: Arc<State>, ctx: &egui::Context) -> Result<(), GuiError> {
+        let report = match &*state.audit.read()? {
+            Some(report) => report.clone(),
+            None => return Ok(()),
+        };
+
+        let weak: Vec<(String, String)> = report
+            .weak
+            .iter()
+            .map(|entry| (entry.name.clone(), format!("score {}", entry.score)))
+            .collect();
+        let reused: Vec<(String, String)> = report
+            .reused
+            .iter()
+            .flat_map(|names| {
+                names.iter().map(|name| {
+                    let others: Vec<&str> = names
+                        .iter()
+                        .filter(|other| *other != name)
+                        .map(|other| other.as_str())
+                        .collect();
+                    (name.clone(), format!("same as {}", others.join(", ")))
+                })
+            })
+            .collect();
+        let old: Vec<(String, String)> = report
+            .old
+            .iter()
+            .map(|entry| {
+                let modified = format!("modified {}", format_timestamp(entry.modified));
+                (entry.name.clone(), modified)
+            })
+            .collect();
+        let empty: Vec<(String, String)> = report
+            .empty
+            .iter()
+            .map(|entry| (entry.name.clone(), entry.fields.join(", ")))
+            .collect();
+        let missing_second_factor: Vec<(String, String)> = report
+            .missing_second_factor
+            .iter()
+            .map(|name| (name.clone(), String::new()))
+            .collect();
+        let sections = [
+            (String::from("Weak Passwords"), weak),
+            (String::from("Reused Passwords"), reused),
+            (format!("Not Changed in {} Days", DEFAULT_MAX_AGE_DAYS), old),
+            (String::from("Empty Values"), empty),
+            (String::from("Logins Without 2FA"), missing_second_factor),
+        ];
+
+        let enabled = State::is_vault_locked(state.clone());
+        let mut close = false;
+        egui::Window::new("Vault Health")
+            .collapsible(false)
+            .resizable(true)
+            .show(ctx, |ui| {
+                if report.is_clean() {
+                    ui.label(format!(
+                        "Audited {} entries, no issues were found",
+                        report.entries
+                    ));
+                } else {
+                    ui.label(format!(
+                        "Audited {} entries, {} need attention",
+                        report.entries,
+                        report.affected()
+                    ));
+                }
+
+                egui::ScrollArea::vertical()
+                    .max_height(400.0)
+                    .show(ui, |ui| {
+                        for (title, rows) in sections.iter() {
+                            if rows.is_empty() {
+                                continue;
+                            }
+                            egui::CollapsingHeader::new(format!("{} ({})", title, rows.len()))
+                                .default_open(true)
+                                .show(ui, |ui| {
+                                    for (name, detail) in rows.iter() {
+                                        ui.horizontal(|ui| {
+                                            if ui.add_enabled(enabled, Link::new(name)).clicked()
+                                                && enabled
+                                            {
+                                                tokio::spawn(Gui::edit_entry(
+                                                    state.clone(),
+                                                    name.clone(),
+                                                ));
+                                            }
+                                            ui.label(detail.as_str());
+                                        });
+                                    }
+                                });
+                        }
+                    });
+
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(enabled, Button::new("Run Again")).clicked() && enabled {
+                        tokio::spawn(Gui::audit(state.clone()));
+                    }
+                    if ui.button("Close").clicked() {
+                        close = true;
+                    }
+                });
+            });
+
+        if close {
+            *state.audit.write()? = None;
+        }
+        Ok(())
+    }
+
     fn display_recent_vaults_loop(
         state: Arc<State>,
         ui: &mut egui::Ui,
@@ -1069,8 +1867,13 @@ impl Gui {
                         return ();
                     }
                 };
-                ui.add_sized([100.0, 20.0], egui::TextEdit::singleline(&mut *buffer))
-                    .request_focus();
+                // Searched with the query language of pwm_db::db_encrypted::query
+                ui.add_sized(
+                    [200.0, 20.0],
+                    egui::TextEdit::singleline(&mut *buffer).hint_text("tag:prod -tag:old user:alice"),
+                )
+                .on_hover_text("Words match names and fields that aren't secret. Also tag:, url:, user:, name:, notes:, group:, \"phrases\", -term, AND, OR, NOT and (...)")
+                .request_focus();
                 if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
                     ui.memory_mut(|mem| mem.close_popup());
                 }
@@ -1127,6 +1930,15 @@ impl Gui {
                         ui.close_menu();
                     }
 
+                    if ui
+                        .add_enabled(vault_locked, Button::new("Import (Dry Run)"))
+                        .clicked()
+                        && vault_locked
+                    {
+                        tokio::spawn(Gui::insert_from_csv_dry_run(self.state.clone()));
+                        ui.close_menu();
+                    }
+
                     if ui
                         .add_enabled(vault_locked, Button::new("Export"))
                         .clicked()
@@ -1136,8 +1948,87 @@ impl Gui {
                         ui.close_menu();
                     }
                 });
+                ui.menu_button("KeePass", |ui| {
+                    for (label, with_key_file) in
+                        [("Import", false), ("Import With Key File", true)]
+                    {
+                        if ui
+                            .add_enabled(vault_locked, Button::new(label))
+                            .clicked()
+                            && vault_locked
+                        {
+                            tokio::spawn(Gui::insert_from_kdbx(self.state.clone(), with_key_file));
+                            ui.close_menu();
+                        }
+                    }
+
+                    for (label, with_key_file) in [
+                        ("Import (Dry Run)", false),
+                        ("Import With Key File (Dry Run)", true),
+                    ] {
+                        if ui
+                            .add_enabled(vault_locked, Button::new(label))
+                            .clicked()
+                            && vault_locked
+                        {
+                            tokio::spawn(Gui::insert_from_kdbx_dry_run(
+                                self.state.clone(),
+                                with_key_file,
+                            ));
+                            ui.close_menu();
+                        }
+                    }
+                });
+                ui.menu_button("Bitwarden", |ui| {
+                    if ui
+                        .add_enabled(vault_locked, Button::new("Import"))
+                        .clicked()
+                        && vault_locked
+                    {
+                        tokio::spawn(Gui::insert_from_bitwarden(self.state.clone()));
+                        ui.close_menu();
+                    }
+
+                    if ui
+                        .add_enabled(vault_locked, Button::new("Import (Dry Run)"))
+                        .clicked()
+                        && vault_locked
+                    {
+                        tokio::spawn(Gui::insert_from_bitwarden_dry_run(self.state.clone()));
+                        ui.close_menu();
+                    }
+                });
+                ui.menu_button("Audit", |ui| {
+                    if ui
+                        .add_enabled(vault_locked, Button::new("Health Report"))
+                        .clicked()
+                        && vault_locked
+                    {
+                        tokio::spawn(Gui::audit(self.state.clone()));
+                        ui.close_menu();
+                    }
+
+                    if ui
+                        .add_enabled(vault_locked, Button::new("Breaches (Pwned Passwords File)"))
+                        .clicked()
+                        && vault_locked
+                    {
+                        tokio::spawn(Gui::check_pwned(self.state.clone(), false));
+                        ui.close_menu();
+                    }
+
+                    if ui
+                        .add_enabled(vault_locked, Button::new("Breaches (Range Directory)"))
+                        .clicked()
+                        && vault_locked
+                    {
+                        tokio::spawn(Gui::check_pwned(self.state.clone(), true));
+                        ui.close_menu();
+                    }
+                });
             } else {
                 ui.add_enabled(vault_locked, Button::new("Csv"));
+                ui.add_enabled(vault_locked, Button::new("Audit"));
             }
 
             if ui.add_enabled(vault_locked, Button::new("Close")).clicked() && vault_locked {
@@ -1155,10 +2046,104 @@ impl Gui {
 
         let vault_list_buffer_state_id = ui.id().with("prev_vault_list_buffer");
 
+        let vault_pwned_buffer_state_id = ui.id().with("prev_vault_pwned_buffer");
+
+        let pwned = if let Some(vault) = vault.as_ref() {
+            ui.data_mut(|d| d.insert_temp(vault_pwned_buffer_state_id, vault.pwned.clone()));
+            vault.pwned.clone()
+        } else {
+            ui.data_mut(|d| {
+                d.get_temp::<HashMap<String, u64>>(vault_pwned_buffer_state_id)
+                    .unwrap_or_default()
+            })
+        };
+
+        let vault_groups_buffer_state_id = ui.id().with("prev_vault_groups_buffer");
+
+        let groups = if let Some(vault) = vault.as_ref() {
+            let groups = vault.groups();
+            ui.data_mut(|d| d.insert_temp(vault_groups_buffer_state_id, groups.clone()));
+            groups
+        } else {
+            ui.data_mut(|d| {
+                d.get_temp::<Vec<String>>(vault_groups_buffer_state_id)
+                    .unwrap_or_default()
+            })
+        };
+
+        let selected_group = {
+            let mut selected_group = self.state.selected_group.write()?;
+            // The group may have been removed or renamed elsewhere
+            if let Some(vault) = vault.as_ref() {
+                if !vault.contains_group(&selected_group) {
+                    selected_group.clear();
+                }
+            }
+            selected_group.clone()
+        };
+
+        let vault_tags_buffer_state_id = ui.id().with("prev_vault_tags_buffer");
+
+        let tags = if let Some(vault) = vault.as_ref() {
+            let tags = vault.tags()?;
+            ui.data_mut(|d| d.insert_temp(vault_tags_buffer_state_id, tags.clone()));
+            tags
+        } else {
+            ui.data_mut(|d| {
+                d.get_temp::<BTreeMap<String, usize>>(vault_tags_buffer_state_id)
+                    .unwrap_or_default()
+            })
+        };
+
+        let tag_filter = {
+            let mut tag_filter = self.state.tag_filter.write()?;
+            // Tags removed from every entry can't be unselected anymore
+            tag_filter.retain(|tag| tags.contains_key(tag));
+            tag_filter.clone()
+        };
+
+        egui::SidePanel::left("group_tree")
+            .resizable(true)
+            .default_width(150.0)
+            .show_inside(ui, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    self.display_group_tree(ui, &groups, "", &selected_group, vault_locked);
+
+                    if !tags.is_empty() {
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.strong("Tags");
+                            if !tag_filter.is_empty() && ui.small_button("Clear").clicked() {
+                                match self.state.tag_filter.write() {
+                                    Ok(mut tag_filter) => tag_filter.clear(),
+                                    Err(error) => GuiError::display_error_or_print(
+                                        self.state.clone(),
+                                        error.into(),
+                                    ),
+                                }
+                            }
+                        });
+                        for (tag, count) in tags.iter() {
+                            let selected = tag_filter.contains(tag);
+                            if ui
+                                .selectable_label(selected, format!("{} ({})", tag, count))
+                                .clicked()
+                            {
+                                self.toggle_tag_filter(tag);
+                            }
+                        }
+                    }
+                });
+            });
+
         let list = if let Some(vault) = vault.as_mut() {
-            let list = vault.list_fuzzy_match(self.state.search_string.write()?.as_str())?;
+            let list = vault.list_group_fuzzy_match(
+                &selected_group,
+                self.state.search_string.write()?.as_str(),
+                &tag_filter,
+            )?;
             ui.data_mut(|d| d.insert_temp(vault_list_buffer_state_id, list.clone()));
-            list.clone()
+            list
         } else {
             let list = ui.data_mut(|d| {
                 d.get_temp::<Vec<String>>(vault_list_buffer_state_id)
@@ -1167,20 +2152,60 @@ impl Gui {
             list
         };
 
+        // Username, first URL, tags and attachments of every listed entry
+        let vault_info_buffer_state_id = ui.id().with("prev_vault_info_buffer");
+
+        let info = if let Some(vault) = vault.as_ref() {
+            let info: Vec<(String, String, Vec<String>, Vec<String>)> = list
+                .iter()
+                .map(|name| match vault.get_info(name) {
+                    Ok(info) => (
+                        info.username.clone(),
+                        info.urls.first().cloned().unwrap_or_default(),
+                        info.tags().iter().cloned().collect(),
+                        info.attachments()
+                            .iter()
+                            .map(|attachment| attachment.name.clone())
+                            .collect(),
+                    ),
+                    Err(_error) => (String::new(), String::new(), Vec::new(), Vec::new()),
+                })
+                .collect();
+            ui.data_mut(|d| d.insert_temp(vault_info_buffer_state_id, info.clone()));
+            info
+        } else {
+            ui.data_mut(|d| {
+                d.get_temp::<Vec<(String, String, Vec<String>, Vec<String>)>>(
+                    vault_info_buffer_state_id,
+                )
+                .unwrap_or_default()
+            })
+        };
+
         let builder = TableBuilder::new(ui)
             .striped(true)
             .resizable(true)
             .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
             .column(Column::remainder())
+            .column(Column::remainder())
+            .column(Column::remainder())
             .column(Column::auto())
             .min_scrolled_height(0.0);
 
         builder
             .header(20.0, |mut header| {
+                header.col(|ui| {
+                    ui.add_space(3.0);
+                    ui.strong("Name");
+                });
                 header.col(|ui| {
                     ui.add_space(3.0);
                     ui.strong("Username");
                 });
+                header.col(|ui| {
+                    ui.add_space(3.0);
+                    ui.strong("URL");
+                });
                 header.col(|ui| {
                     ui.add_space(6.0);
                     ui.strong("Password");
@@ -1191,9 +2216,56 @@ impl Gui {
                     let row_height = 30.0;
                     body.row(row_height, |mut row| {
                         let name = &list[row_index];
+                        let (username, url, entry_tags, attachments) = match info.get(row_index) {
+                            Some((username, url, tags, attachments)) => (
+                                username.as_str(),
+                                url.as_str(),
+                                tags.as_slice(),
+                                attachments.as_slice(),
+                            ),
+                            None => ("", "", &[][..], &[][..]),
+                        };
                         row.col(|ui| {
                             ui.add_space(3.0);
-                            ui.label(format!("{}", name.clone()));
+                            // Relative to the selected group
+                            let display_name = match selected_group.is_empty() {
+                                true => name.as_str(),
+                                false => &name[selected_group.len() + 1..],
+                            };
+                            ui.label(display_name);
+                            if let Some(count) = pwned.get(name) {
+                                ui.add_space(6.0);
+                                ui.colored_label(ui.visuals().warn_fg_color, "Breached")
+                                    .on_hover_text(format!("Seen {} times in breaches", count));
+                            }
+                            // Clicking a chip filters by it
+                            for tag in entry_tags {
+                                let response =
+                                    ui.small_button(tag).on_hover_text("Filter by this tag");
+                                if response.clicked() {
+                                    self.toggle_tag_filter(tag);
+                                }
+                                if vault_locked {
+                                    response.context_menu(|ui| {
+                                        if ui.button("Remove Tag").clicked() {
+                                            tokio::spawn(Gui::remove_tag(
+                                                self.state.clone(),
+                                                name.clone(),
+                                                tag.clone(),
+                                            ));
+                                            ui.close_menu();
+                                        }
+                                    });
+                                }
+                            }
+                        });
+                        row.col(|ui| {
+                            ui.add_space(3.0);
+                            ui.add(Label::new(username).truncate());
+                        });
+                        row.col(|ui| {
+                            ui.add_space(3.0);
+                            ui.add(Label::new(url).truncate());
                         });
                         row.col(|ui| {
                             ui.add_space(6.0);
@@ -1222,6 +2294,88 @@ impl Gui {
                                         ));
                                         ui.close_menu();
                                     }
+                                    if ui
+                                        .add_enabled(vault_locked, Button::new("Edit Fields"))
+                                        .clicked()
+                                        && vault_locked
+                                    {
+                                        tokio::spawn(Gui::edit_entry(
+                                            self.state.clone(),
+                                            name.clone(),
+                                        ));
+                                        ui.close_menu();
+                                    }
+                                    if ui
+                                        .add_enabled(vault_locked, Button::new("History"))
+                                        .clicked()
+                                        && vault_locked
+                                    {
+                                        tokio::spawn(Gui::show_history(
+                                            self.state.clone(),
+                                            name.clone(),
+                                        ));
+                                        ui.close_menu();
+                                    }
+                                    if ui
+                                        .add_enabled(vault_locked, Button::new("Restore Previous"))
+                                        .clicked()
+                                        && vault_locked
+                                    {
+                                        tokio::spawn(Gui::restore_history(
+                                            self.state.clone(),
+                                            name.clone(),
+                                        ));
+                                        ui.close_menu();
+                                    }
+                                    if ui
+                                        .add_enabled(vault_locked, Button::new("Add Tag"))
+                                        .clicked()
+                                        && vault_locked
+                                    {
+                                        tokio::spawn(Gui::add_tag(
+                                            self.state.clone(),
+                                            name.clone(),
+                                        ));
+                                        ui.close_menu();
+                                    }
+                                    ui.menu_button("Attachments", |ui| {
+                                        if ui.button("Add Attachment").clicked() {
+                                            tokio::spawn(Gui::add_attachment(
+                                                self.state.clone(),
+                                                name.clone(),
+                                            ));
+                                            ui.close_menu();
+                                        }
+                                        for attachment in attachments {
+                                            ui.menu_button(attachment, |ui| {
+                                                if ui.button("Extract").clicked() {
+                                                    tokio::spawn(Gui::extract_attachment(
+                                                        self.state.clone(),
+                                                        name.clone(),
+                                                        attachment.clone(),
+                                                    ));
+                                                    ui.close_menu();
+                                                }
+                                                if ui.button("Remove").clicked() {
+                                                    tokio::spawn(Gui::remove_attachment(
+                                                        self.state.clone(),
+                                                        name.clone(),
+                                                        attachment.clone(),
+                                                    ));
+                                                    ui.close_menu();
+                                                }
+                                            });
+                                        }
+                                    });
+                                    if ui.add_enabled(vault_locked, Button::new("Move")).clicked()
+                                        && vault_locked
+                                    {
+                                        tokio::spawn(Gui::move_entry(
+                                            self.state.clone(),
+                                            name.clone(),
+                                        ));
+                                        ui.close_menu();
+                                    }
                                     if ui
                                         .add_enabled(vault_locked, Button::new("Delete"))
                                         .clicked()
@@ -1243,6 +2397,100 @@ impl Gui {
         Ok(())
     }
 
+    fn toggle_tag_filter(&self, tag: &str) {
+        match self.state.tag_filter.write() {
+            Ok(mut tag_filter) => match tag_filter.iter().position(|other| other == tag) {
+                Some(index) => {
+                    tag_filter.remove(index);
+                }
+                None => tag_filter.push(tag.to_string()),
+            },
+            Err(error) => GuiError::display_error_or_print(self.state.clone(), error.into()),
+        }
+    }
+
+    // Collapsible tree of the direct subgroups of parent, the root is shown as "All"
+    fn display_group_tree(
+        &self,
+        ui: &mut egui::Ui,
+        groups: &[String],
+        parent: &str,
+        selected_group: &str,
+        vault_locked: bool,
+    ) {
+        let children: Vec<&String> = groups
+            .iter()
+            .filter(|group| parent_group(group) == parent)
+            .collect();
+        let label = match parent.is_empty() {
+            true => "All",
+            false => base_name(parent),
+        };
+
+        let id = ui.make_persistent_id(("group_tree", parent));
+        let header = egui::collapsing_header::CollapsingState::load_with_default_open(
+            ui.ctx(),
+            id,
+            parent.is_empty(),
+        );
+
+        let show_label = |ui: &mut egui::Ui| {
+            let response = ui.selectable_label(selected_group == parent, label);
+            if response.clicked() {
+                match self.state.selected_group.write() {
+                    Ok(mut selected_group) => *selected_group = parent.to_string(),
+                    Err(error) => {
+                        GuiError::display_error_or_print(self.state.clone(), error.into())
+                    }
+                }
+            }
+            if vault_locked {
+                response.context_menu(|ui| {
+                    if ui.button("New Group").clicked() {
+                        tokio::spawn(Gui::create_group(self.state.clone(), parent.to_string()));
+                        ui.close_menu();
+                    }
+                    if !parent.is_empty() {
+                        if ui.button("Rename/Move").clicked() {
+                            tokio::spawn(Gui::rename_group(self.state.clone(), parent.to_string()));
+                            ui.close_menu();
+                        }
+                        if ui.button("Delete").clicked() {
+                            tokio::spawn(Gui::remove_group(
+                                self.state.clone(),
+                                parent.to_string(),
+                                false,
+                            ));
+                            ui.close_menu();
+                        }
+                        if ui.button("Delete With Entries").clicked() {
+                            tokio::spawn(Gui::remove_group(
+                                self.state.clone(),
+                                parent.to_string(),
+                                true,
+                            ));
+                            ui.close_menu();
+                        }
+                    }
+                });
+            }
+        };
+
+        if children.is_empty() {
+            ui.horizontal(|ui| {
+                // Line up with the labels that have a collapse button
+                ui.add_space(ui.spacing().icon_width + ui.spacing().item_spacing.x);
+                show_label(ui);
+            });
+        } else {
+            header.show_header(ui, show_label).body(|ui| {
+                for child in children {
+                    self.display_group_tree(ui, groups, child, selected_group, vault_locked);
+                }
+            });
+        }
+    }
+
     fn handle_keybinds(&mut self, ctx: &egui::Context) -> Result<(), GuiError> {
         if ctx.input(|i| i.modifiers.matches_exact(Modifiers::CTRL) && i.key_pressed(Key::N)) {
             self.file_new(self.state.clone());
@@ -1264,6 +2512,21 @@ impl Gui {
             tokio::spawn(Gui::file_save_as(self.state.clone()));
             info!("File Save as");
         }
+        if ctx.input(|i| i.modifiers.matches_exact(Modifiers::CTRL) && i.key_pressed(Key::Z))
+            && State::is_vault_locked(self.state.clone())
+        {
+            tokio::spawn(Gui::undo(self.state.clone(), false));
+            info!("Undo");
+        }
+        if ctx.input(|i| {
+            i.modifiers
+                .matches_exact(Modifiers::CTRL | Modifiers::SHIFT)
+                && i.key_pressed(Key::Z)
+        }) && State::is_vault_locked(self.state.clone())
+        {
+            tokio::spawn(Gui::undo(self.state.clone(), true));
+            info!("Redo");
+        }
         if ctx.input(|i| i.modifiers.matches_exact(Modifiers::CTRL) && i.key_pressed(Key::E)) {
             tokio::spawn(Gui::encrypt_file(self.state.clone()));
             info!("Encrypt File");
@@ -1334,12 +2597,42 @@ impl Drop for Gui {
                     }
                 };
 
+                let backups = match self.state.backups.read() {
+                    Ok(backups) => *backups,
+                    Err(error) => {
+                        warn!("State::backups was unable to be unlocked defaulting to {}: {}", DEFAULT_BACKUPS, error);
+                        DEFAULT_BACKUPS
+                    }
+                };
+
+                let trash_days = match self.state.trash_days.read() {
+                    Ok(trash_days) => *trash_days,
+                    Err(error) => {
+                        warn!("State::trash_days was unable to be unlocked defaulting to {}: {}", DEFAULT_TRASH_DAYS, error);
+                        DEFAULT_TRASH_DAYS
+                    }
+                };
+
+                let max_attachment_size = match self.state.max_attachment_size.read() {
+                    Ok(max_attachment_size) => *max_attachment_size,
+                    Err(error) => {
+                        warn!(
+                            "State::max_attachment_size was unable to be unlocked defaulting to {}: {}",
+                            DEFAULT_MAX_ATTACHMENT_SIZE, error
+                        );
+                        DEFAULT_MAX_ATTACHMENT_SIZE
+                    }
+                };
+
                 let config = json::object! {
                     dark: self.darkmode,
                     scale: self.scale,
                     prev_vaults: prev_vaults_vec[0..slice_len],
                     prev_vaults_max: max_length,
                     password_length: password_len,
+                    backups: backups,
+                    trash_days: trash_days,
+                    max_attachment_size: max_attachment_size,
                 };
 
                 write_config(config);
@@ -1377,3 +2670,48 @@ pub fn get_file_path_back_count(path: PathBuf, back_count: usize) -> String {
 
     result.display().to_string()
 }
+
+// One line per change, the fields of a changed entry are indented
+pub fn describe_diff(diff: &VaultDiff) -> Vec<Zeroizing<String>> {
+    if diff.is_empty() {
+        return vec![Zeroizing::new(String::from("No changes"))];
+    }
+
+    let mut lines = Vec::new();
+    for name in diff.added.iter() {
+        lines.push(Zeroizing::new(format!("Added {}", name)));
+    }
+    for name in diff.removed.iter() {
+        lines.push(Zeroizing::new(format!("Removed {}", name)));
+    }
+    for entry in diff.changed.iter() {
+        lines.push(Zeroizing::new(match &entry.renamed_from {
+            Some(old_name) => format!("Renamed {} to {}", old_name, entry.name),
+            None => format!("Changed {}", entry.name),
+        }));
+        for field in entry.fields.iter() {
+            let change = Zeroizing::new(match &field.change {
+                FieldChange::Added(Some(value)) => format!("added {}", value.as_str()),
+                FieldChange::Added(None) => String::from("added"),
+                FieldChange::Removed(_value) => String::from("removed"),
+                FieldChange::Changed(Some(old), Some(new)) => {
+                    format!("{} -> {}", old.as_str(), new.as_str())
+                }
+                FieldChange::Changed(_old, _new) => String::from("changed"),
+            });
+            lines.push(Zeroizing::new(format!(
+                "    {}: {}",
+                field.name,
+                change.as_str()
+            )));
+        }
+    }
+    for group in diff.groups_added.iter() {
+        lines.push(Zeroizing::new(format!("Added group {}", group)));
+    }
+    for group in diff.groups_removed.iter() {
+        lines.push(Zeroizing::new(format!("Removed group {}", group)));
+    }
+
+    lines
+}
@@ -0,0 +1,151 @@
+use eframe::egui;
+use pwm_db::db_encrypted::entry::{CustomField, Entry};
+use pwm_lib::zeroize::Zeroizing;
+
+use crate::gui::error::GuiError;
+
+#[derive(PartialEq, Eq)]
+pub enum EditorAction {
+    None,
+    Save,
+    Cancel,
+}
+
+// Window for editing every field of a decrypted entry. The master password is
+// kept until the window closes so saving doesn't ask for it again.
+pub struct EntryEditor {
+    entry: Entry,
+    password: Zeroizing<String>,
+    // One URL per line
+    urls: String,
+    master_password: Zeroizing<String>,
+    show_secrets: bool,
+}
+
+impl EntryEditor {
+    pub fn new(entry: Entry, master_password: Zeroizing<String>) -> Result<Self, GuiError> {
+        let password = match String::from_utf8(entry.password.to_vec()) {
+            Ok(password) => Zeroizing::new(password),
+            Err(error) => {
+                return Err(GuiError::Utf8Fail(format!(
+                    "Invalid UTF-8 sequence: {}",
+                    error
+                )))
+            }
+        };
+        let urls = entry.urls.join("\n");
+
+        Ok(Self {
+            entry,
+            password,
+            urls,
+            master_password,
+            show_secrets: false,
+        })
+    }
+
+    pub fn title(&self) -> &str {
+        &self.entry.title
+    }
+
+    pub fn master_password(&self) -> &[u8] {
+        self.master_password.as_bytes()
+    }
+
+    pub fn to_entry(&self) -> Entry {
+        let mut entry = self.entry.clone();
+        entry.password = Zeroizing::new(self.password.as_bytes().to_vec());
+        entry.urls = self
+            .urls
+            .lines()
+            .map(|url| url.trim())
+            .filter(|url| !url.is_empty())
+            .map(String::from)
+            .collect();
+        entry
+    }
+
+    pub fn ui(&mut self, ctx: &egui::Context, enabled: bool) -> EditorAction {
+        let mut action = EditorAction::None;
+        let mut open = true;
+
+        egui::Window::new(format!("Edit {}", self.entry.title))
+            .id(egui::Id::new("entry_editor"))
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(true)
+            .show(ctx, |ui| {
+                egui::Grid::new("entry_editor_builtin")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label("Username");
+                        ui.text_edit_singleline(&mut self.entry.username);
+                        ui.end_row();
+
+                        ui.label("Password");
+                        ui.add(
+                            egui::TextEdit::singleline(&mut *self.password)
+                                .password(!self.show_secrets),
+                        );
+                        ui.end_row();
+
+                        ui.label("URLs");
+                        ui.add(egui::TextEdit::multiline(&mut self.urls).desired_rows(2));
+                        ui.end_row();
+
+                        ui.label("Notes");
+                        ui.add(egui::TextEdit::multiline(&mut self.entry.notes).desired_rows(3));
+                        ui.end_row();
+                    });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.strong("Custom Fields");
+                    ui.add_space(6.0);
+                    ui.checkbox(&mut self.show_secrets, "Show secrets");
+                });
+
+                let mut remove = None;
+                egui::Grid::new("entry_editor_custom")
+                    .num_columns(4)
+                    .show(ui, |ui| {
+                        for (index, field) in self.entry.fields.iter_mut().enumerate() {
+                            ui.add(egui::TextEdit::singleline(&mut field.name).hint_text("Name"));
+                            ui.add(
+                                egui::TextEdit::singleline(&mut *field.value)
+                                    .hint_text("Value")
+                                    .password(field.secret && !self.show_secrets),
+                            );
+                            ui.checkbox(&mut field.secret, "Secret");
+                            if ui.button("Remove").clicked() {
+                                remove = Some(index);
+                            }
+                            ui.end_row();
+                        }
+                    });
+                if let Some(index) = remove {
+                    self.entry.fields.remove(index);
+                }
+
+                if ui.button("Add Field").clicked() {
+                    self.entry.fields.push(CustomField::default());
+                }
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(enabled, egui::Button::new("Save")).clicked() {
+                        action = EditorAction::Save;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        action = EditorAction::Cancel;
+                    }
+                });
+            });
+
+        if !open {
+            action = EditorAction::Cancel;
+        }
+
+        action
+    }
+}
@@ -20,6 +20,7 @@ pub enum GuiError {
     PasswordNotSame,
     Utf8Fail(String),
     ParseIntError(String),
+    Cancelled,
 }
 
 impl GuiError {
@@ -34,6 +35,9 @@ impl GuiError {
             GuiError::ParseIntError(error) => {
                 warn!("{}", error);
             }
+            GuiError::Cancelled => {
+                debug!("{}", error);
+            }
             _ => {
                 if let Err(display_error) = State::add_error(state, error.to_string()) {
                     error!(
@@ -61,6 +65,7 @@ impl std::fmt::Display for GuiError {
             Self::PasswordNotSame => f.write_str("Passwords do not match"),
             Self::Utf8Fail(msg) => f.write_fmt(std::format_args!("{}", msg)),
             Self::ParseIntError(msg) => f.write_fmt(std::format_args!("{}", msg)),
+            Self::Cancelled => f.write_str("Cancelled"),
         };
     }
 }
@@ -75,7 +80,10 @@ impl<T> From<PoisonError<T>> for GuiError {
 
 impl From<DatabaseError> for GuiError {
     fn from(value: DatabaseError) -> Self {
-        Self::DatabaseError(value.to_string())
+        match value {
+            DatabaseError::Cancelled => Self::Cancelled,
+            value => Self::DatabaseError(value.to_string()),
+        }
     }
 }
 
@@ -103,6 +111,12 @@ impl<T> From<SendError<T>> for GuiError {
     }
 }
 
+impl From<&'static str> for GuiError {
+    fn from(value: &'static str) -> Self {
+        Self::StringError(value.to_string())
+    }
+}
+
 impl From<ParseIntError> for GuiError {
     fn from(value: ParseIntError) -> Self {
         Self::ParseIntError(value.to_string())
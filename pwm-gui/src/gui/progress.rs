@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use eframe::egui::{self, Ui};
+use pwm_lib::progress::Progress;
+
+pub struct ProgressIndicator {
+    label: String,
+    progress: Arc<Progress>,
+}
+
+impl ProgressIndicator {
+    pub fn new(label: String, progress: Arc<Progress>) -> Self {
+        Self { label, progress }
+    }
+
+    pub fn is(&self, progress: &Arc<Progress>) -> bool {
+        Arc::ptr_eq(&self.progress, progress)
+    }
+
+    pub fn display(&self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.add_space(3.0);
+            ui.add(egui::Spinner::new());
+            ui.add_space(3.0);
+            ui.label(self.label.as_str());
+
+            // Single key derivations only ever show the spinner
+            if self.progress.total() > 1 {
+                ui.label(format!(
+                    "{}/{}",
+                    self.progress.completed(),
+                    self.progress.total()
+                ));
+            }
+
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if self.progress.is_cancelled() {
+                    ui.add_enabled(false, egui::Button::new("Cancelling"));
+                } else if ui.button("Cancel").clicked() {
+                    self.progress.cancel();
+                }
+            });
+        });
+    }
+}
@@ -8,6 +8,8 @@ pub struct Prompt {
     pub response: Zeroizing<String>,
     pub sender: Sender<Zeroizing<String>>,
     password_prompt: bool,
+    // Answered with "y" or "n" instead of text
+    confirm_prompt: bool,
     requested_focus: bool,
 }
 
@@ -23,10 +25,40 @@ impl Prompt {
             response,
             sender,
             password_prompt,
+            confirm_prompt: false,
             requested_focus: false,
         }
     }
 
+    pub fn new_confirm(prompt: String, sender: Sender<Zeroizing<String>>) -> Self {
+        Self {
+            prompt,
+            response: Zeroizing::new(String::new()),
+            sender,
+            password_prompt: false,
+            confirm_prompt: true,
+            requested_focus: false,
+        }
+    }
+
+    fn confirm_ui_internal(&mut self, ui: &mut egui::Ui) -> (bool, egui::Response) {
+        let mut remove = false;
+
+        let result = ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+            if ui.button("No").clicked() {
+                self.sender.send(Zeroizing::new(String::from("n"))).unwrap();
+                remove = true;
+            }
+
+            if ui.button("Yes").clicked() {
+                self.sender.send(Zeroizing::new(String::from("y"))).unwrap();
+                remove = true;
+            }
+        });
+
+        (remove, result.response)
+    }
+
     fn prompt_ui_internal(&mut self, ui: &mut egui::Ui) -> (bool, egui::Response) {
         let mut remove = false;
 
@@ -113,6 +145,10 @@ impl Prompt {
     }
 
     pub fn prompt_ui(&mut self, ui: &mut egui::Ui) -> (bool, egui::Response) {
+        if self.confirm_prompt {
+            return self.confirm_ui_internal(ui);
+        }
+
         if self.password_prompt {
             return self.password_ui_internal(ui);
         } else {
@@ -1,12 +1,34 @@
 use eframe::egui;
-use pwm_lib::zeroize::Zeroizing;
-
+use pwm_db::{
+    audit::report::{AuditReport, DEFAULT_MAX_AGE_DAYS},
+    db_base::{
+        base_name, error::DatabaseError, is_in_group, join_group, parent_group, GROUP_SEPARATOR,
+    },
+    db_encrypted::{
+        attachment::attachment_file_name,
+        bitwarden::is_password_protected,
+        db_interface::ImportReport,
+        diff::VaultDiff,
+        merge::{MergeConflict, MergeSide},
+    },
+    timestamp::format_timestamp,
+};
+use pwm_lib::{
+    atomic_file::write_atomic,
+    crypt_dir::{decrypt_dir, encrypt_dir, ARCHIVE_EXTENSION},
+    progress::Progress,
+    random::token::{random_token, TokenKind, DEFAULT_API_KEY_PREFIX, DEFAULT_TOKEN_BYTES},
+    zeroize::Zeroizing,
+};
+
+use crate::gui::entry_editor::EntryEditor;
 use crate::gui::message::Message;
+use crate::gui::progress::ProgressIndicator;
 use crate::gui::{error::GuiError, get_file_name, Gui};
 use crate::vault::Vault;
 
 use std::collections::VecDeque;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{channel, Receiver};
 use std::sync::Arc;
 use std::sync::RwLock;
@@ -17,12 +39,34 @@ pub struct State {
     pub messages: RwLock<Vec<Message>>,
     // Prompt, Password, Sender
     pub prompts: RwLock<Vec<Prompt>>,
+    pub progress: RwLock<Vec<ProgressIndicator>>,
     pub vault: RwLock<Option<Vault>>,
     pub clipboard_string: RwLock<Option<Zeroizing<String>>>,
     pub search_string: RwLock<String>,
     pub password_length: RwLock<String>,
+    pub token_kind: RwLock<String>,
+    pub token_length: RwLock<String>,
+    pub token_prefix: RwLock<String>,
     pub prev_vaults: RwLock<VecDeque<String>>,
     pub prev_vaults_max_length: RwLock<usize>,
+    // Number of backups kept when saving a vault
+    pub backups: RwLock<usize>,
+    // Days removed entries stay in the recycle bin, 0 keeps them
+    pub trash_days: RwLock<u32>,
+    pub show_trash: RwLock<bool>,
+    // The last audit, shown as the health dashboard until it is closed
+    pub audit: RwLock<Option<AuditReport>>,
+    // Largest file that can be attached to an entry, in bytes
+    pub max_attachment_size: RwLock<u64>,
+    pub entry_editor: RwLock<Option<EntryEditor>>,
+    // Group shown in the table, "" is the root
+    pub selected_group: RwLock<String>,
+    // Only entries with all of these tags are listed
+    pub tag_filter: RwLock<Vec<String>>,
+    // Entries both copies changed in the last merge, not resolved yet
+    pub merge_conflicts: RwLock<Vec<MergeConflict>>,
+    // Shown until closed, secret values only if they were revealed
+    pub unsaved_changes: RwLock<Option<VaultDiff>>,
     pub egui_ctx: egui::Context,
 }
 
@@ -32,16 +76,33 @@ impl State {
         prev_vaults: VecDeque<String>,
         prev_vaults_max_length: usize,
         password_length: usize,
+        backups: usize,
+        trash_days: u32,
+        max_attachment_size: u64,
     ) -> Self {
         Self {
             messages: RwLock::new(Vec::new()),
             prompts: RwLock::new(Vec::new()),
+            progress: RwLock::new(Vec::new()),
             vault: RwLock::new(None),
             clipboard_string: RwLock::new(None),
             search_string: RwLock::new(String::new()),
             password_length: RwLock::new(format!("{}", password_length)),
+            token_kind: RwLock::new(String::from(TokenKind::names()[0])),
+            token_length: RwLock::new(format!("{}", DEFAULT_TOKEN_BYTES)),
+            token_prefix: RwLock::new(String::from(DEFAULT_API_KEY_PREFIX)),
             prev_vaults: RwLock::new(prev_vaults),
             prev_vaults_max_length: RwLock::new(prev_vaults_max_length),
+            backups: RwLock::new(backups),
+            trash_days: RwLock::new(trash_days),
+            show_trash: RwLock::new(false),
+            audit: RwLock::new(None),
+            max_attachment_size: RwLock::new(max_attachment_size),
+            entry_editor: RwLock::new(None),
+            selected_group: RwLock::new(String::new()),
+            tag_filter: RwLock::new(Vec::new()),
+            merge_conflicts: RwLock::new(Vec::new()),
+            unsaved_changes: RwLock::new(None),
             egui_ctx: ctx,
         }
     }
@@ -58,6 +119,8 @@ impl State {
             Ok(vault) => Some(vault),
             Err(error) => return Err(GuiError::DatabaseError(error.to_string())),
         };
+        state.merge_conflicts.write()?.clear();
+        *state.unsaved_changes.write()? = None;
 
         state.egui_ctx.request_repaint();
         Ok(())
@@ -66,6 +129,11 @@ impl State {
     pub async fn close_vault(state: Arc<State>) -> Result<(), GuiError> {
         let mut vault = state.vault.write()?;
         *vault = None;
+        *state.entry_editor.write()? = None;
+        state.merge_conflicts.write()?.clear();
+        *state.unsaved_changes.write()? = None;
+        *state.show_trash.write()? = false;
+        *state.audit.write()? = None;
         state.egui_ctx.request_repaint();
         Ok(())
     }
@@ -80,12 +148,51 @@ impl State {
         )?;
         let password = receiver.recv()?;
 
-        let mut vault = state.vault.write()?;
-        *vault = match Vault::new_from_file(file.as_str(), password.as_bytes()) {
-            Ok(vault) => Some(vault),
-            Err(error) => return Err(GuiError::DatabaseError(error.to_string())),
+        // Unlocked before taking the lock so the current vault stays usable
+        let open = |read_only: bool| {
+            State::with_progress(
+                state.clone(),
+                format!("Unlocking {}", get_file_name(file.clone().into())),
+                |progress| {
+                    Vault::new_from_file(file.as_str(), password.as_bytes(), read_only, progress)
+                },
+            )
+        };
+        // A vault another process has open can only be read
+        let opened = match open(false)? {
+            Err(DatabaseError::Locked(owner)) => {
+                let question = format!(
+                    "{} is in use by {}, open it read-only?",
+                    get_file_name(file.clone().into()),
+                    owner
+                );
+                if !State::add_confirm_prompt(state.clone(), question)? {
+                    return Err(GuiError::Cancelled);
+                }
+                open(true)?
+            }
+            opened => opened,
         };
 
+        let opened = opened?;
+        let read_only = opened.is_read_only();
+        *state.vault.write()? = Some(opened);
+        state.merge_conflicts.write()?.clear();
+        *state.unsaved_changes.write()? = None;
+
+        if read_only {
+            State::add_message(
+                state.clone(),
+                Message::new_default_duration(
+                    Some(String::from("Opened Read-Only")),
+                    format!(
+                        "{} can't be saved while another process has it open",
+                        get_file_name(file.clone().into())
+                    ),
+                    false,
+                ),
+            )?;
+        }
         State::append_vault_path_to_prev_vaults(state.clone(), file)?;
         state.egui_ctx.request_repaint();
         Ok(())
@@ -169,6 +276,20 @@ impl State {
         path: &str,
         password: &[u8],
     ) -> Result<(), GuiError> {
+        let changed = match &*state.vault.read()? {
+            Some(vault) => vault.changed_on_disk(path)?,
+            None => return Err(GuiError::NoVault),
+        };
+        if changed {
+            let question = format!(
+                "{} was changed on disk since it was loaded, overwrite it?",
+                get_file_name(Path::new(path).to_path_buf())
+            );
+            if !State::add_confirm_prompt(state.clone(), question)? {
+                return Err(GuiError::Cancelled);
+            }
+        }
+
         let mut vault = state.vault.write()?;
         let vault = match &mut *vault {
             Some(vault) => vault,
@@ -177,7 +298,16 @@ impl State {
 
         vault.name_buffer = get_file_name(Path::new(path).to_path_buf());
 
-        vault.serialize_to_file(&path, password)?;
+        // Entries removed longer ago than the retention are dropped for good
+        let trash_days = *state.trash_days.read()?;
+        if trash_days > 0 {
+            vault.purge_trash(Some(trash_days), password)?;
+        }
+
+        let backups = *state.backups.read()?;
+        State::with_progress(state.clone(), String::from("Saving vault"), |progress| {
+            vault.serialize_to_file(path, password, backups, progress)
+        })??;
 
         State::append_vault_path_to_prev_vaults(state.clone(), path.to_string())?;
         state.egui_ctx.request_repaint();
@@ -226,7 +356,328 @@ impl State {
             None => return Err(GuiError::NoVault),
         };
 
-        vault.insert_from_csv(file.display().to_string().as_str(), password.as_bytes())?;
+        State::with_progress(state.clone(), String::from("Importing"), |progress| {
+            vault.insert_from_csv(
+                file.display().to_string().as_str(),
+                password.as_bytes(),
+                progress,
+            )
+        })??;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn insert_from_csv_dry_run(state: Arc<State>) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let file = match Gui::open_file_dialog(state.clone()) {
+            Some(file) => file,
+            None => return Err(GuiError::NoFile),
+        };
+
+        let report = {
+            let vault = state.vault.read()?;
+            let vault = match &*vault {
+                Some(vault) => vault,
+                None => return Err(GuiError::NoVault),
+            };
+            vault
+                .insert_from_csv_dry_run(file.display().to_string().as_str(), password.as_bytes())?
+        };
+
+        State::add_message(
+            state.clone(),
+            Message::new_default_duration(
+                Some(String::from("Import Dry Run")),
+                import_report_message(&report),
+                false,
+            ),
+        )?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    // The KeePass database and its key file are picked before its password
+    // is asked for
+    fn request_kdbx(
+        state: Arc<State>,
+        with_key_file: bool,
+    ) -> Result<(PathBuf, Option<PathBuf>, Zeroizing<String>), GuiError> {
+        let file = match Gui::open_file_dialog_with_title(state.clone(), "KeePass Database") {
+            Some(file) => file,
+            None => return Err(GuiError::NoFile),
+        };
+        let key_file = match with_key_file {
+            true => match Gui::open_file_dialog_with_title(state.clone(), "KeePass Key File") {
+                Some(key_file) => Some(key_file),
+                None => return Err(GuiError::NoFile),
+            },
+            false => None,
+        };
+
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter the KeePass password"))?;
+        let kdbx_password = receiver.recv()?;
+        Ok((file, key_file, kdbx_password))
+    }
+
+    pub async fn insert_from_kdbx(state: Arc<State>, with_key_file: bool) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+        let (file, key_file, kdbx_password) = Self::request_kdbx(state.clone(), with_key_file)?;
+        let key_file = key_file.map(|key_file| key_file.display().to_string());
+
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        State::with_progress(state.clone(), String::from("Importing"), |progress| {
+            vault.insert_from_kdbx(
+                file.display().to_string().as_str(),
+                kdbx_password.as_bytes(),
+                key_file.as_deref(),
+                password.as_bytes(),
+                progress,
+            )
+        })??;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn insert_from_kdbx_dry_run(
+        state: Arc<State>,
+        with_key_file: bool,
+    ) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+        let (file, key_file, kdbx_password) = Self::request_kdbx(state.clone(), with_key_file)?;
+        let key_file = key_file.map(|key_file| key_file.display().to_string());
+
+        let report = {
+            let vault = state.vault.read()?;
+            let vault = match &*vault {
+                Some(vault) => vault,
+                None => return Err(GuiError::NoVault),
+            };
+            vault.insert_from_kdbx_dry_run(
+                file.display().to_string().as_str(),
+                kdbx_password.as_bytes(),
+                key_file.as_deref(),
+                password.as_bytes(),
+            )?
+        };
+
+        State::add_message(
+            state.clone(),
+            Message::new_default_duration(
+                Some(String::from("Import Dry Run")),
+                import_report_message(&report),
+                false,
+            ),
+        )?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    // The export password is only asked for if the export has one
+    fn request_bitwarden(
+        state: Arc<State>,
+    ) -> Result<(PathBuf, Option<Zeroizing<String>>), GuiError> {
+        let file = match Gui::open_file_dialog_with_title(state.clone(), "Bitwarden Export") {
+            Some(file) => file,
+            None => return Err(GuiError::NoFile),
+        };
+        if !is_password_protected(file.display().to_string().as_str())? {
+            return Ok((file, None));
+        }
+
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter the export password"))?;
+        let export_password = receiver.recv()?;
+        Ok((file, Some(export_password)))
+    }
+
+    pub async fn insert_from_bitwarden(state: Arc<State>) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+        let (file, export_password) = Self::request_bitwarden(state.clone())?;
+
+        let report = {
+            let mut vault = state.vault.write()?;
+            let vault = match &mut *vault {
+                Some(vault) => vault,
+                None => return Err(GuiError::NoVault),
+            };
+
+            State::with_progress(state.clone(), String::from("Importing"), |progress| {
+                vault.insert_from_bitwarden(
+                    file.display().to_string().as_str(),
+                    export_password.as_ref().map(|password| password.as_bytes()),
+                    password.as_bytes(),
+                    progress,
+                )
+            })??
+        };
+
+        // Passkeys, linked fields and the like are left out of the entries
+        if !report.unmapped.is_empty() {
+            let mut message = String::from("Some items were only imported in part");
+            for line in report.unmapped.iter() {
+                message.push_str(&format!("\nNot imported: {}", line));
+            }
+            State::add_message(
+                state.clone(),
+                Message::new_default_duration(Some(String::from("Import")), message, false),
+            )?;
+        }
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn insert_from_bitwarden_dry_run(state: Arc<State>) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+        let (file, export_password) = Self::request_bitwarden(state.clone())?;
+
+        let report = {
+            let vault = state.vault.read()?;
+            let vault = match &*vault {
+                Some(vault) => vault,
+                None => return Err(GuiError::NoVault),
+            };
+            vault.insert_from_bitwarden_dry_run(
+                file.display().to_string().as_str(),
+                export_password.as_ref().map(|password| password.as_bytes()),
+                password.as_bytes(),
+            )?
+        };
+
+        State::add_message(
+            state.clone(),
+            Message::new_default_duration(
+                Some(String::from("Import Dry Run")),
+                import_report_message(&report),
+                false,
+            ),
+        )?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    // Compares the vault with the file it was opened from or last saved to
+    pub async fn show_unsaved_changes(state: Arc<State>, reveal: bool) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let diff = {
+            let vault = state.vault.read()?;
+            let vault = match &*vault {
+                Some(vault) => vault,
+                None => return Err(GuiError::NoVault),
+            };
+            State::with_progress(
+                state.clone(),
+                String::from("Comparing with the saved vault"),
+                |progress| vault.unsaved_changes(password.as_bytes(), reveal, progress),
+            )??
+        };
+
+        *state.unsaved_changes.write()? = Some(diff);
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    // Merges another copy of the open vault, both copies and the one they
+    // were made from need the same master password
+    pub async fn merge(state: Arc<State>) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let theirs = match Gui::open_file_dialog_with_title(state.clone(), "Copy To Merge") {
+            Some(file) => file.display().to_string(),
+            None => return Err(GuiError::NoFile),
+        };
+        let base = match Gui::open_file_dialog_with_title(state.clone(), "Copy Both Were Made From")
+        {
+            Some(file) => file.display().to_string(),
+            None => return Err(GuiError::NoFile),
+        };
+
+        let mut opened = Vec::new();
+        for file in [theirs, base] {
+            let vault = State::with_progress(
+                state.clone(),
+                format!("Unlocking {}", get_file_name(file.clone().into())),
+                |progress| Vault::new_from_file(file.as_str(), password.as_bytes(), true, progress),
+            )?;
+            opened.push(vault?);
+        }
+
+        let report = {
+            let mut vault = state.vault.write()?;
+            let vault = match &mut *vault {
+                Some(vault) => vault,
+                None => return Err(GuiError::NoVault),
+            };
+            vault.merge(&opened[1], &opened[0], password.as_bytes())?
+        };
+
+        State::add_message(
+            state.clone(),
+            Message::new_default_duration(
+                Some(String::from("Merge")),
+                format!(
+                    "{} entries merged, {} conflicts",
+                    report.merged.len(),
+                    report.conflicts.len()
+                ),
+                false,
+            ),
+        )?;
+        *state.merge_conflicts.write()? = report.conflicts;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    // Keeps one copy of a conflicting entry, None keeps the newer copy of
+    // every conflict left
+    pub async fn resolve_conflict(
+        state: Arc<State>,
+        conflict: Option<(String, MergeSide)>,
+    ) -> Result<(), GuiError> {
+        let mut conflicts = state.merge_conflicts.write()?;
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        match conflict {
+            Some((name, side)) => {
+                // Already resolved by an earlier click
+                let index = match conflicts.iter().position(|conflict| conflict.name == name) {
+                    Some(index) => index,
+                    None => return Ok(()),
+                };
+                vault.resolve_conflict(&conflicts[index], side)?;
+                conflicts.remove(index);
+            }
+            None => {
+                for conflict in conflicts.drain(..) {
+                    vault.resolve_conflict(&conflict, conflict.newer())?;
+                }
+            }
+        }
         state.egui_ctx.request_repaint();
         Ok(())
     }
@@ -246,7 +697,13 @@ impl State {
             None => return Err(GuiError::NoVault),
         };
 
-        vault.export_to_csv(file.display().to_string().as_str(), password.as_bytes())?;
+        State::with_progress(state.clone(), String::from("Exporting"), |progress| {
+            vault.export_to_csv(
+                file.display().to_string().as_str(),
+                password.as_bytes(),
+                progress,
+            )
+        })??;
         state.egui_ctx.request_repaint();
         Ok(())
     }
@@ -296,6 +753,253 @@ impl State {
         Ok(())
     }
 
+    // Timestamps and when each previous password was replaced, none of which
+    // needs the master password
+    pub async fn show_history(state: Arc<State>, name: String) -> Result<(), GuiError> {
+        let message = {
+            let vault = state.vault.read()?;
+            let vault = match &*vault {
+                Some(vault) => vault,
+                None => return Err(GuiError::NoVault),
+            };
+            let info = vault.get_info(&name)?;
+
+            let mut lines = vec![
+                format!("Created: {}", format_timestamp(info.created())),
+                format!("Modified: {}", format_timestamp(info.modified())),
+                format!("Accessed: {}", format_timestamp(info.accessed())),
+            ];
+            for (index, replaced) in info.history_times().into_iter().enumerate() {
+                lines.push(format!(
+                    "Previous {}: replaced {}",
+                    index + 1,
+                    format_timestamp(replaced)
+                ));
+            }
+            lines.join("\n")
+        };
+
+        State::add_message(
+            state.clone(),
+            Message::new_default_duration(Some(format!("History of {}", name)), message, false),
+        )?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn restore_history(state: Arc<State>, name: String) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let receiver = Self::add_prompt(
+            state.clone(),
+            format!(
+                "Restore which previous password of {} (1 is the newest)",
+                name
+            ),
+        )?;
+        let index = match receiver.recv()?.trim().parse::<usize>() {
+            Ok(index) if index > 0 => index - 1,
+            _ => {
+                return Err(GuiError::StringError(String::from(
+                    "Expected the number of a previous password",
+                )))
+            }
+        };
+
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        vault.restore_history(&name, index, password.as_bytes())?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn add_attachment(state: Arc<State>, name: String) -> Result<(), GuiError> {
+        let file = match Gui::open_file_dialog_with_title(
+            state.clone(),
+            &format!("Attach a file to {}", name),
+        ) {
+            Some(file) => file,
+            None => return Err(GuiError::NoFile),
+        };
+        let contents = Zeroizing::new(std::fs::read(&file)?);
+
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let max_size = *state.max_attachment_size.read()?;
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        vault.add_attachment(
+            &name,
+            &get_file_name(file),
+            &contents,
+            max_size,
+            password.as_bytes(),
+        )?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn extract_attachment(
+        state: Arc<State>,
+        name: String,
+        attachment: String,
+    ) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let contents = {
+            let vault = state.vault.read()?;
+            let vault = match &*vault {
+                Some(vault) => vault,
+                None => return Err(GuiError::NoVault),
+            };
+            vault.get_attachment(&name, &attachment, password.as_bytes())?
+        };
+
+        // The dialog asks before replacing a file
+        let file_name = attachment_file_name(&attachment).unwrap_or_default();
+        let file = match rfd::FileDialog::new().set_file_name(file_name).save_file() {
+            Some(file) => file,
+            None => return Err(GuiError::NoFile),
+        };
+        write_atomic(&file, contents.as_slice(), 0)?;
+
+        State::add_message(
+            state.clone(),
+            Message::new_default_duration(
+                Some(String::from("Attachment")),
+                format!("Extracted {} to {}", attachment, file.display()),
+                false,
+            ),
+        )?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn remove_attachment(
+        state: Arc<State>,
+        name: String,
+        attachment: String,
+    ) -> Result<(), GuiError> {
+        let question = format!("Remove the attachment {} from {}?", attachment, name);
+        if !State::add_confirm_prompt(state.clone(), question)? {
+            return Err(GuiError::Cancelled);
+        }
+
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        vault.remove_attachment(&name, &attachment, password.as_bytes())?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn restore_trashed(state: Arc<State>, name: String) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        vault.restore_trashed(&name, password.as_bytes())?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn purge_trash(state: Arc<State>) -> Result<(), GuiError> {
+        let question = String::from("Remove every entry in the recycle bin for good?");
+        if !State::add_confirm_prompt(state.clone(), question)? {
+            return Err(GuiError::Cancelled);
+        }
+
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let purged = {
+            let mut vault = state.vault.write()?;
+            let vault = match &mut *vault {
+                Some(vault) => vault,
+                None => return Err(GuiError::NoVault),
+            };
+            vault.purge_trash(None, password.as_bytes())?
+        };
+
+        State::add_message(
+            state.clone(),
+            Message::new_default_duration(
+                Some(String::from("Recycle Bin")),
+                format!("Purged {} entries", purged.len()),
+                false,
+            ),
+        )?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn edit_entry(state: Arc<State>, name: String) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let entry = {
+            let vault = state.vault.read()?;
+            let vault = match &*vault {
+                Some(vault) => vault,
+                None => return Err(GuiError::NoVault),
+            };
+            vault.get_entry(&name, password.as_bytes())?
+        };
+
+        *state.entry_editor.write()? = Some(EntryEditor::new(entry, password)?);
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    // The editor stays open if saving fails
+    pub async fn save_entry(state: Arc<State>) -> Result<(), GuiError> {
+        let mut editor = state.entry_editor.write()?;
+        let entry_editor = match &*editor {
+            Some(editor) => editor,
+            None => return Ok(()),
+        };
+
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        vault.replace_entry(&entry_editor.to_entry(), entry_editor.master_password())?;
+        *editor = None;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
     pub async fn remove(state: Arc<State>, name: String) -> Result<(), GuiError> {
         let receiver = Self::add_password_prompt(state.clone(), format!("Enter master password"))?;
         let password = receiver.recv()?;
@@ -311,17 +1015,187 @@ impl State {
         Ok(())
     }
 
+    // Moves an entry into another group, keeping its name
+    pub async fn move_entry(state: Arc<State>, name: String) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let receiver = Self::add_prompt(
+            state.clone(),
+            format!("Move {} to group (empty for the root)", name),
+        )?;
+        let group = receiver.recv()?;
+        let group = group.trim_matches(GROUP_SEPARATOR);
+
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        let new_name = join_group(group, base_name(&name));
+        vault.rename(&name, &new_name, password.as_bytes())?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn add_tag(state: Arc<State>, name: String) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let receiver = Self::add_prompt(state.clone(), format!("Enter a tag for {}", name))?;
+        let tag = receiver.recv()?;
+
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        vault.add_tag(&name, &tag, password.as_bytes())?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn remove_tag(state: Arc<State>, name: String, tag: String) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        vault.remove_tag(&name, &tag, password.as_bytes())?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn undo(state: Arc<State>, redo: bool) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        let (title, description) = if redo {
+            ("Redo", vault.redo(password.as_bytes())?)
+        } else {
+            ("Undo", vault.undo(password.as_bytes())?)
+        };
+        State::add_message(
+            state.clone(),
+            Message::new_default_duration(
+                Some(String::from(title)),
+                description.to_string(),
+                false,
+            ),
+        )?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn create_group(state: Arc<State>, parent: String) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let receiver = Self::add_prompt(state.clone(), String::from("Enter new group name"))?;
+        let name = receiver.recv()?;
+
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        let group = join_group(&parent, name.trim_matches(GROUP_SEPARATOR));
+        vault.create_group(&group, password.as_bytes())?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    // The new name is the full path, so this also moves the group
+    pub async fn rename_group(state: Arc<State>, group: String) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let receiver = Self::add_prompt(state.clone(), format!("Enter new path for {}", group))?;
+        let new_group = receiver.recv()?;
+        let new_group = new_group.trim_matches(GROUP_SEPARATOR);
+
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        vault.rename_group(&group, new_group, password.as_bytes())?;
+
+        let mut selected = state.selected_group.write()?;
+        if *selected == group || is_in_group(&selected, &group) {
+            *selected = format!("{}{}", new_group, &selected[group.len()..]);
+        }
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn remove_group(
+        state: Arc<State>,
+        group: String,
+        recursive: bool,
+    ) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        let removed = vault.remove_group(&group, recursive, password.as_bytes())?;
+
+        let mut selected = state.selected_group.write()?;
+        if *selected == group || is_in_group(&selected, &group) {
+            *selected = parent_group(&group).to_string();
+        }
+        if !removed.is_empty() {
+            State::add_message(
+                state.clone(),
+                Message::new_default_duration(
+                    Some(String::from("Group Removed")),
+                    format!("Removed {} with {} entries", group, removed.len()),
+                    false,
+                ),
+            )?;
+        }
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
     pub async fn get(state: Arc<State>, name: String) -> Result<(), GuiError> {
         let receiver = Self::add_password_prompt(state.clone(), format!("Enter master password"))?;
         let password = receiver.recv()?;
 
-        let vault = state.vault.read()?;
-        let vault = match &*vault {
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
             Some(vault) => vault,
             None => return Err(GuiError::NoVault),
         };
 
         let result = vault.get(&name, password.as_bytes())?;
+        vault.mark_accessed(&name)?;
 
         use std::str;
         let result = match str::from_utf8(result.as_ref()) {
@@ -341,6 +1215,198 @@ impl State {
         Ok(())
     }
 
+    pub async fn audit(state: Arc<State>) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let report = {
+            let vault = state.vault.read()?;
+            let vault = match &*vault {
+                Some(vault) => vault,
+                None => return Err(GuiError::NoVault),
+            };
+
+            State::with_progress(state.clone(), String::from("Auditing"), |progress| {
+                vault.audit(DEFAULT_MAX_AGE_DAYS, password.as_bytes(), progress)
+            })??
+        };
+
+        *state.audit.write()? = Some(report);
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    // directory picks a range download instead of a single ordered file
+    pub async fn check_pwned(state: Arc<State>, directory: bool) -> Result<(), GuiError> {
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let file = if directory {
+            Gui::open_folder_dialog(state.clone())
+        } else {
+            Gui::open_file_dialog(state.clone())
+        };
+        let file = match file {
+            Some(file) => file,
+            None => return Err(GuiError::NoFile),
+        };
+
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        let found = State::with_progress(
+            state.clone(),
+            String::from("Checking breaches"),
+            |progress| {
+                vault.check_pwned(
+                    file.display().to_string().as_str(),
+                    password.as_bytes(),
+                    progress,
+                )
+            },
+        )??;
+
+        let message = if found == 0 {
+            String::from("No stored passwords were found in breaches")
+        } else {
+            format!("{} stored passwords were found in breaches", found)
+        };
+        State::add_message(
+            state.clone(),
+            Message::new_default_duration(Some(String::from("Breach Check")), message, false),
+        )?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub async fn encrypt_folder(state: Arc<State>) -> Result<(), GuiError> {
+        let folder = match Gui::open_folder_dialog(state.clone()) {
+            Some(folder) => folder,
+            None => return Err(GuiError::NoFile),
+        };
+        let name = get_file_name(folder.clone());
+
+        let password = Self::add_confirmation_password_prompt(
+            state.clone(),
+            format!("Enter password to encrypt {}", name),
+            format!("Confirm password to encrypt {}", name),
+        )?;
+
+        let mut dialog =
+            rfd::FileDialog::new().set_file_name(format!("{}.{}", name, ARCHIVE_EXTENSION));
+        if let Some(parent) = folder.parent() {
+            dialog = dialog.set_directory(parent);
+        }
+        let output = match dialog.save_file() {
+            Some(output) => output,
+            None => return Err(GuiError::NoFile),
+        };
+
+        let skipped = encrypt_dir(
+            folder.display().to_string(),
+            Some(output.display().to_string()),
+            password.as_bytes(),
+        )?;
+
+        let message = if skipped.is_empty() {
+            format!("Encrypted {}", name)
+        } else {
+            format!(
+                "Encrypted {}, skipped {} since only files and folders are encrypted",
+                name,
+                skipped.join(", ")
+            )
+        };
+        State::add_message(
+            state.clone(),
+            Message::new_default_duration(Some(String::from("Encrypt Folder")), message, false),
+        )?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    // The output is picked with a save dialog since the folder must not exist
+    pub async fn decrypt_folder(state: Arc<State>) -> Result<(), GuiError> {
+        let file = match Gui::open_file_dialog(state.clone()) {
+            Some(file) => file,
+            None => return Err(GuiError::NoFile),
+        };
+        let name = get_file_name(file.clone());
+
+        let password = Self::add_password_prompt(
+            state.clone(),
+            format!("Enter password to decrypt {}", name),
+        )?
+        .recv()?;
+
+        let mut dialog = rfd::FileDialog::new();
+        if let Some(stem) = file.file_stem() {
+            dialog = dialog.set_file_name(stem.to_string_lossy());
+        }
+        if let Some(parent) = file.parent() {
+            dialog = dialog.set_directory(parent);
+        }
+        let output = match dialog.save_file() {
+            Some(output) => output,
+            None => return Err(GuiError::NoFile),
+        };
+
+        decrypt_dir(
+            file.display().to_string(),
+            Some(output.display().to_string()),
+            password.as_bytes(),
+        )?;
+
+        State::add_message(
+            state.clone(),
+            Message::new_default_duration(
+                Some(String::from("Decrypt Folder")),
+                format!("Decrypted {} into {}", name, get_file_name(output)),
+                false,
+            ),
+        )?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
+    pub fn generate_token(state: Arc<State>) -> Result<Zeroizing<String>, GuiError> {
+        let prefix = state.token_prefix.read()?;
+        let kind = match TokenKind::from_name(state.token_kind.read()?.as_str(), Some(&prefix)) {
+            Some(kind) => kind,
+            None => return Err(GuiError::StringError(String::from("Invalid token kind"))),
+        };
+        let length: usize = state.token_length.read()?.parse()?;
+
+        Ok(random_token(&kind, length)?)
+    }
+
+    pub async fn store_token(state: Arc<State>) -> Result<(), GuiError> {
+        let token = State::generate_token(state.clone())?;
+
+        let receiver =
+            State::add_prompt(state.clone(), String::from("Enter name for the new entry"))?;
+        let name = receiver.recv()?;
+
+        let receiver =
+            Self::add_password_prompt(state.clone(), String::from("Enter master password"))?;
+        let password = receiver.recv()?;
+
+        let mut vault = state.vault.write()?;
+        let vault = match &mut *vault {
+            Some(vault) => vault,
+            None => return Err(GuiError::NoVault),
+        };
+
+        vault.insert(&name, token.as_bytes(), password.as_bytes())?;
+        state.egui_ctx.request_repaint();
+        Ok(())
+    }
+
     pub fn add_prompt(
         state: Arc<State>,
         prompt: String,
@@ -377,6 +1443,18 @@ impl State {
         Ok(receiver)
     }
 
+    // True if the question was answered with yes
+    pub fn add_confirm_prompt(state: Arc<State>, prompt: String) -> Result<bool, GuiError> {
+        let (sender, receiver) = channel();
+        state
+            .prompts
+            .write()?
+            .push(Prompt::new_confirm(prompt, sender));
+        state.egui_ctx.request_repaint();
+
+        Ok(receiver.recv()?.as_str() == "y")
+    }
+
     pub fn add_confirmation_password_prompt(
         state: Arc<State>,
         prompt: String,
@@ -392,6 +1470,33 @@ impl State {
         }
     }
 
+    // Shows a spinner with a cancel button for as long as job runs
+    pub fn with_progress<T>(
+        state: Arc<State>,
+        label: String,
+        job: impl FnOnce(&Progress) -> T,
+    ) -> Result<T, GuiError> {
+        // Repaint on every finished step so record counts update immediately
+        let ctx = state.egui_ctx.clone();
+        let progress = Arc::new(Progress::with_callback(move |_completed, _total| {
+            ctx.request_repaint();
+        }));
+        state
+            .progress
+            .write()?
+            .push(ProgressIndicator::new(label, progress.clone()));
+        state.egui_ctx.request_repaint();
+
+        let result = job(&progress);
+
+        state
+            .progress
+            .write()?
+            .retain(|indicator| !indicator.is(&progress));
+        state.egui_ctx.request_repaint();
+        Ok(result)
+    }
+
     #[allow(unused)]
     pub fn add_message(state: Arc<State>, message: Message) -> Result<(), GuiError> {
         let mut messages = state.messages.write()?;
@@ -440,3 +1545,21 @@ impl State {
         vault_locked
     }
 }
+
+// What a dry run found, in the order every import shares
+fn import_report_message(report: &ImportReport) -> String {
+    let mut message = format!("{} entries would be added", report.added.len());
+    for (name, error) in report.conflicts.iter() {
+        message.push_str(&format!("\nCan't add {}: {}", name, error));
+    }
+    for line in report.unmapped.iter() {
+        message.push_str(&format!("\nNot imported: {}", line));
+    }
+    if report.skipped > 0 {
+        message.push_str(&format!("\nSkipped records: {}", report.skipped));
+    }
+    if !report.conflicts.is_empty() {
+        message.push_str("\nNothing would be imported");
+    }
+    message
+}
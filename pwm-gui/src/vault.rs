@@ -1,11 +1,27 @@
-use std::path::Path;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
 
 use log::info;
 use pwm_db::{
-    db_base::error::DatabaseError,
-    db_encrypted::{db_interface::DatabaseInterface, DatabaseEncrypted},
+    audit::{
+        pwned::{check_pwned_with_progress, PwnedPasswords},
+        report::{audit_with_progress, AuditReport},
+    },
+    db_base::{error::DatabaseError, is_in_group, Trashed},
+    db_encrypted::{
+        db_interface::{DatabaseInterface, ImportReport},
+        diff::VaultDiff,
+        entry::{EncryptedEntry, Entry},
+        merge::{MergeConflict, MergeReport, MergeSide},
+        DatabaseEncrypted,
+    },
+    lock::VaultFile,
+};
+use pwm_lib::{
+    atomic_file::write_atomic, encryption::EncryptionResult, progress::Progress, zeroize::Zeroizing,
 };
-use pwm_lib::encryption::EncryptionResult;
 
 use crate::gui::get_file_name;
 
@@ -14,6 +30,10 @@ pub struct Vault {
     pub changed: bool,
     pub path: String,
     pub name_buffer: String,
+    // Entries found in the last breach check and how often they were seen
+    pub pwned: HashMap<String, u64>,
+    // The lock on the file the vault was opened from or saved to
+    vault_file: VaultFile,
 }
 
 impl Vault {
@@ -27,19 +47,30 @@ impl Vault {
             changed: true,
             path,
             name_buffer: String::from(name),
+            pwned: HashMap::new(),
+            vault_file: VaultFile::new(),
         })
     }
 
-    pub fn new_from_file(file: &str, password: &[u8]) -> Result<Self, DatabaseError> {
-        let contents = match std::fs::read(file) {
-            Ok(contents) => match EncryptionResult::new(contents) {
-                Ok(contents) => contents,
+    // Locked if another process has the file open, unless it is opened
+    // read only
+    pub fn new_from_file(
+        file: &str,
+        password: &[u8],
+        read_only: bool,
+        progress: &Progress,
+    ) -> Result<Self, DatabaseError> {
+        let (vault_file, contents) = match VaultFile::open(file, read_only) {
+            Ok((vault_file, contents)) => match EncryptionResult::new(contents) {
+                Ok(contents) => (vault_file, contents),
                 Err(error) => return Err(DatabaseError::InputError(error.to_string())),
             },
+            Err(DatabaseError::Locked(owner)) => return Err(DatabaseError::Locked(owner)),
             Err(error) => return Err(DatabaseError::InputError(error.to_string())),
         };
 
-        let db = DatabaseEncrypted::new_deserialize_encrypted(&contents, password)?;
+        let db =
+            DatabaseEncrypted::new_deserialize_encrypted_with_progress(&contents, password, progress)?;
 
         let path = Path::new(file);
         let name = get_file_name(path.to_path_buf());
@@ -52,6 +83,8 @@ impl Vault {
             changed: false,
             path,
             name_buffer: name,
+            pwned: HashMap::new(),
+            vault_file,
         })
     }
 
@@ -65,18 +98,156 @@ impl Vault {
         self.db.insert(name, data, password)
     }
 
-    pub fn insert_from_csv(&mut self, file: &str, password: &[u8]) -> Result<(), DatabaseError> {
+    pub fn insert_from_csv(
+        &mut self,
+        file: &str,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<(), DatabaseError> {
+        // Nothing is imported if any record fails
+        self.db.insert_from_csv_with_progress(file, password, progress)?;
+        self.changed = true;
+        Ok(())
+    }
+
+    pub fn insert_from_csv_dry_run(
+        &self,
+        file: &str,
+        password: &[u8],
+    ) -> Result<ImportReport, DatabaseError> {
+        self.db.insert_from_csv_dry_run(file, password)
+    }
+
+    pub fn insert_from_kdbx(
+        &mut self,
+        file: &str,
+        kdbx_password: &[u8],
+        key_file: Option<&str>,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<(), DatabaseError> {
+        self.db.insert_from_kdbx_with_progress(
+            file,
+            kdbx_password,
+            key_file,
+            password,
+            progress,
+        )?;
+        self.changed = true;
+        Ok(())
+    }
+
+    pub fn insert_from_kdbx_dry_run(
+        &self,
+        file: &str,
+        kdbx_password: &[u8],
+        key_file: Option<&str>,
+        password: &[u8],
+    ) -> Result<ImportReport, DatabaseError> {
+        self.db
+            .insert_from_kdbx_dry_run(file, kdbx_password, key_file, password)
+    }
+
+    pub fn insert_from_bitwarden(
+        &mut self,
+        file: &str,
+        export_password: Option<&[u8]>,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<ImportReport, DatabaseError> {
+        let report = self.db.insert_from_bitwarden_with_progress(
+            file,
+            export_password,
+            password,
+            progress,
+        )?;
         self.changed = true;
-        self.db.insert_from_csv(file, password)
+        Ok(report)
     }
 
-    pub fn export_to_csv(&mut self, file: &str, password: &[u8]) -> Result<(), DatabaseError> {
-        self.db.export_to_csv(file, password)
+    pub fn insert_from_bitwarden_dry_run(
+        &self,
+        file: &str,
+        export_password: Option<&[u8]>,
+        password: &[u8],
+    ) -> Result<ImportReport, DatabaseError> {
+        self.db
+            .insert_from_bitwarden_dry_run(file, export_password, password)
+    }
+
+    pub fn export_to_csv(
+        &mut self,
+        file: &str,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<(), DatabaseError> {
+        self.db.export_to_csv_with_progress(file, password, progress)
     }
 
     pub fn remove(&mut self, name: &str, password: &[u8]) -> Result<(), DatabaseError> {
+        self.db.remove(name, password)?;
+        self.changed = true;
+        self.pwned.remove(name);
+        Ok(())
+    }
+
+    pub fn add_attachment(
+        &mut self,
+        name: &str,
+        attachment: &str,
+        contents: &[u8],
+        max_size: u64,
+        password: &[u8],
+    ) -> Result<(), DatabaseError> {
+        self.db
+            .add_attachment(name, attachment, contents, max_size, password)?;
+        self.changed = true;
+        Ok(())
+    }
+
+    pub fn get_attachment(
+        &self,
+        name: &str,
+        attachment: &str,
+        password: &[u8],
+    ) -> Result<Zeroizing<Vec<u8>>, DatabaseError> {
+        self.db.get_attachment(name, attachment, password)
+    }
+
+    pub fn remove_attachment(
+        &mut self,
+        name: &str,
+        attachment: &str,
+        password: &[u8],
+    ) -> Result<(), DatabaseError> {
+        self.db.remove_attachment(name, attachment, password)?;
+        self.changed = true;
+        Ok(())
+    }
+
+    // Removed entries, oldest first
+    pub fn trash(&self) -> &[Trashed<EncryptedEntry>] {
+        self.db.trash()
+    }
+
+    pub fn restore_trashed(&mut self, name: &str, password: &[u8]) -> Result<(), DatabaseError> {
+        self.db.restore_trashed(name, password)?;
         self.changed = true;
-        self.db.remove(name, password)
+        Ok(())
+    }
+
+    // Every entry in the recycle bin without days, or only those removed
+    // longer ago
+    pub fn purge_trash(
+        &mut self,
+        days: Option<u32>,
+        password: &[u8],
+    ) -> Result<Vec<String>, DatabaseError> {
+        let purged = self.db.purge_trash(days, password)?;
+        if !purged.is_empty() {
+            self.changed = true;
+        }
+        Ok(purged)
     }
 
     pub fn replace(
@@ -85,8 +256,19 @@ impl Vault {
         new_data: &[u8],
         password: &[u8],
     ) -> Result<(), DatabaseError> {
+        self.db.replace(name, new_data, password)?;
+        self.changed = true;
+        // The new password hasn't been checked
+        self.pwned.remove(name);
+        Ok(())
+    }
+
+    // Keyed by the entry title, renaming is done with rename
+    pub fn replace_entry(&mut self, entry: &Entry, password: &[u8]) -> Result<(), DatabaseError> {
+        self.db.replace_entry(entry, password)?;
         self.changed = true;
-        self.db.replace(name, new_data, password)
+        self.pwned.remove(&entry.title);
+        Ok(())
     }
 
     pub fn rename(
@@ -98,26 +280,246 @@ impl Vault {
         if name == new_name {
             return Ok(())
         }
+        self.db.rename(name, new_name, password)?;
         self.changed = true;
-        self.db.rename(name, new_name, password)
+        if let Some(count) = self.pwned.remove(name) {
+            self.pwned.insert(new_name.to_string(), count);
+        }
+        Ok(())
+    }
+
+    // Returns the number of entries found
+    pub fn check_pwned(
+        &mut self,
+        file: &str,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<usize, DatabaseError> {
+        let pwned = PwnedPasswords::open(file)?;
+        let found = check_pwned_with_progress(&self.db, &pwned, password, progress)?;
+
+        self.pwned = found
+            .into_iter()
+            .map(|entry| (entry.name, entry.count))
+            .collect();
+        Ok(self.pwned.len())
+    }
+
+    pub fn audit(
+        &self,
+        max_age_days: u32,
+        password: &[u8],
+        progress: &Progress,
+    ) -> Result<AuditReport, DatabaseError> {
+        audit_with_progress(&self.db, max_age_days, password, progress)
     }
 
     pub fn get(&self, name: &str, password: &[u8]) -> Result<EncryptionResult, DatabaseError> {
         self.db.get(name, password)
     }
 
+    // Accessing an entry doesn't count as a change that needs saving
+    pub fn mark_accessed(&mut self, name: &str) -> Result<(), DatabaseError> {
+        self.db.mark_accessed(name)
+    }
+
+    pub fn restore_history(
+        &mut self,
+        name: &str,
+        index: usize,
+        password: &[u8],
+    ) -> Result<(), DatabaseError> {
+        self.db.restore_history(name, index, password)?;
+        self.changed = true;
+        self.pwned.remove(name);
+        Ok(())
+    }
+
+    pub fn get_entry(&self, name: &str, password: &[u8]) -> Result<Entry, DatabaseError> {
+        self.db.get_entry(name, password)
+    }
+
+    pub fn get_info(&self, name: &str) -> Result<&EncryptedEntry, DatabaseError> {
+        self.db.get_info(name)
+    }
+
     #[allow(unused)]
     pub fn list(&self) -> Result<Vec<String>, DatabaseError> {
         self.db.list()
     }
 
-    pub fn list_fuzzy_match(&mut self, pattern: &str) -> Result<&Vec<String>, DatabaseError> {
-        self.db.list_fuzzy_match(pattern)
+    pub fn groups(&self) -> Vec<String> {
+        self.db.groups()
+    }
+
+    pub fn contains_group(&self, group: &str) -> bool {
+        self.db.contains_group(group)
+    }
+
+    // Every entry below the group matching the pattern and tags, full names
+    pub fn list_group_fuzzy_match(
+        &mut self,
+        group: &str,
+        pattern: &str,
+        tags: &[String],
+    ) -> Result<Vec<String>, DatabaseError> {
+        self.db.list_group_fuzzy_match(group, pattern, tags)
+    }
+
+    // Every tag in use and how many entries have it
+    pub fn tags(&self) -> Result<BTreeMap<String, usize>, DatabaseError> {
+        self.db.tags()
+    }
+
+    pub fn add_tag(&mut self, name: &str, tag: &str, password: &[u8]) -> Result<(), DatabaseError> {
+        if self.db.add_tag(name, tag, password)? {
+            self.changed = true;
+        }
+        Ok(())
+    }
+
+    pub fn remove_tag(
+        &mut self,
+        name: &str,
+        tag: &str,
+        password: &[u8],
+    ) -> Result<(), DatabaseError> {
+        if !self.db.remove_tag(name, tag, password)? {
+            return Err(DatabaseError::NotFound);
+        }
+        self.changed = true;
+        Ok(())
+    }
+
+    pub fn undo(&mut self, password: &[u8]) -> Result<Zeroizing<String>, DatabaseError> {
+        let description = self.db.undo(password)?;
+        self.changed = true;
+        self.retain_pwned()?;
+        Ok(description)
+    }
+
+    pub fn redo(&mut self, password: &[u8]) -> Result<Zeroizing<String>, DatabaseError> {
+        let description = self.db.redo(password)?;
+        self.changed = true;
+        self.retain_pwned()?;
+        Ok(description)
+    }
+
+    // What changed since the vault was last saved, every entry is new if it
+    // never was
+    pub fn unsaved_changes(
+        &self,
+        password: &[u8],
+        reveal: bool,
+        progress: &Progress,
+    ) -> Result<VaultDiff, DatabaseError> {
+        let saved = match Path::new(&self.path).exists() {
+            true => Self::new_from_file(&self.path, password, true, progress)?.db,
+            false => DatabaseEncrypted::new(password)?,
+        };
+        self.db.diff(&saved, password, reveal)
+    }
+
+    // Takes the changes only the other copy made since base, the conflicts
+    // are kept as they are in this vault until they are resolved
+    pub fn merge(
+        &mut self,
+        base: &Vault,
+        theirs: &Vault,
+        password: &[u8],
+    ) -> Result<MergeReport, DatabaseError> {
+        let report = self.db.merge(&base.db, &theirs.db, password)?;
+        if !report.merged.is_empty() {
+            self.changed = true;
+            self.retain_pwned()?;
+        }
+        Ok(report)
+    }
+
+    pub fn resolve_conflict(
+        &mut self,
+        conflict: &MergeConflict,
+        side: MergeSide,
+    ) -> Result<(), DatabaseError> {
+        self.db.resolve_conflict(conflict, side)?;
+        self.changed = true;
+        self.retain_pwned()
+    }
+
+    // Drop breach results for entries that no longer exist
+    fn retain_pwned(&mut self) -> Result<(), DatabaseError> {
+        let names = self.db.list()?;
+        self.pwned.retain(|name, _| names.contains(name));
+        Ok(())
+    }
+
+    pub fn create_group(&mut self, group: &str, password: &[u8]) -> Result<(), DatabaseError> {
+        self.db.create_group(group, password)?;
+        self.changed = true;
+        Ok(())
+    }
+
+    pub fn rename_group(
+        &mut self,
+        group: &str,
+        new_group: &str,
+        password: &[u8],
+    ) -> Result<(), DatabaseError> {
+        self.db.rename_group(group, new_group, password)?;
+        self.changed = true;
+        // Keep breach results for the moved entries
+        let moved: Vec<String> = self
+            .pwned
+            .keys()
+            .filter(|name| is_in_group(name, group))
+            .cloned()
+            .collect();
+        for name in moved {
+            if let Some(count) = self.pwned.remove(&name) {
+                self.pwned
+                    .insert(format!("{}{}", new_group, &name[group.len()..]), count);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove_group(
+        &mut self,
+        group: &str,
+        recursive: bool,
+        password: &[u8],
+    ) -> Result<Vec<String>, DatabaseError> {
+        let removed = self.db.remove_group(group, recursive, password)?;
+        self.changed = true;
+        for name in removed.iter() {
+            self.pwned.remove(name);
+        }
+        Ok(removed)
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.vault_file.is_read_only()
     }
 
-    pub fn serialize_to_file(&mut self, file: &str, password: &[u8]) -> Result<(), DatabaseError> {
-        let ciphertext = self.db.serialize_encrypted(password)?;
-        std::fs::write(file, ciphertext.as_ref())?;
+    // Whether another program wrote to file since the vault was loaded from
+    // or saved to it
+    pub fn changed_on_disk(&self, file: &str) -> Result<bool, DatabaseError> {
+        self.vault_file.changed_on_disk(file)
+    }
+
+    // Locked if another process has file open
+    pub fn serialize_to_file(
+        &mut self,
+        file: &str,
+        password: &[u8],
+        backups: usize,
+        progress: &Progress,
+    ) -> Result<(), DatabaseError> {
+        let db = &self.db;
+        self.vault_file.save(file, || {
+            let ciphertext = db.serialize_encrypted_with_progress(password, progress)?;
+            Ok(write_atomic(file, ciphertext.as_ref(), backups)?)
+        })?;
         self.changed = false;
         self.path = file.to_string();
         self.name_buffer = get_file_name(Path::new(file).to_path_buf());
@@ -0,0 +1,181 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::hash::randomize_slice;
+use crate::random::token::encode_hex;
+
+// Number of "<file>.bak.N" copies kept by vault saves unless configured
+pub static DEFAULT_BACKUPS: usize = 3;
+
+// Replaces the file so that a crash or full disk leaves either the old or the
+// new contents, never a mix of both
+pub fn write_atomic(
+    path: impl AsRef<Path>,
+    contents: &[u8],
+    backups: usize,
+) -> Result<(), std::io::Error> {
+    write_atomic_with(path, backups, |file| file.write_all(contents))
+}
+
+// Same as write_atomic for contents that are streamed. The old file is kept
+// as "<file>.bak.1", moving older copies up to "<file>.bak.<backups>".
+pub fn write_atomic_with<T>(
+    path: impl AsRef<Path>,
+    backups: usize,
+    write: impl FnOnce(&mut File) -> Result<T, std::io::Error>,
+) -> Result<T, std::io::Error> {
+    // Writing through a symlink replaces its target, not the link
+    let path = match std::fs::symlink_metadata(path.as_ref()) {
+        Ok(metadata) if metadata.file_type().is_symlink() => std::fs::canonicalize(path)?,
+        _ => path.as_ref().to_path_buf(),
+    };
+    let path = path.as_path();
+    let temp = temp_path(path)?;
+
+    let result = write_temp(path, &temp, write).and_then(|result| {
+        if backups > 0 && path.exists() {
+            rotate_backups(path, backups)?;
+        }
+        std::fs::rename(&temp, path)?;
+        sync_parent(path)?;
+        Ok(result)
+    });
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+
+    result
+}
+
+pub fn backup_path(path: impl AsRef<Path>, index: usize) -> PathBuf {
+    let mut backup = path.as_ref().as_os_str().to_os_string();
+    backup.push(format!(".bak.{}", index));
+    PathBuf::from(backup)
+}
+
+// Hidden file next to the target, renaming only stays atomic on the same file
+// system
+fn temp_path(path: &Path) -> Result<PathBuf, std::io::Error> {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is not a file", path.display()),
+            ))
+        }
+    };
+
+    let mut random = [0u8; 8];
+    randomize_slice(&mut random);
+    Ok(path.with_file_name(format!(".{}.{}.tmp", name, encode_hex(&random).as_str())))
+}
+
+fn write_temp<T>(
+    path: &Path,
+    temp: &Path,
+    write: impl FnOnce(&mut File) -> Result<T, std::io::Error>,
+) -> Result<T, std::io::Error> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(temp)?;
+
+    // Replacing a file shouldn't make it readable to anyone new
+    if let Ok(metadata) = std::fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+    }
+
+    let result = write(&mut file)?;
+    file.sync_all()?;
+    Ok(result)
+}
+
+fn rotate_backups(path: &Path, backups: usize) -> Result<(), std::io::Error> {
+    for index in (1..backups).rev() {
+        let from = backup_path(path, index);
+        if from.exists() {
+            std::fs::rename(&from, backup_path(path, index + 1))?;
+        }
+    }
+
+    // A hard link keeps the old contents without a moment where the file is
+    // missing, copying is the fallback for file systems without links
+    let newest = backup_path(path, 1);
+    if newest.exists() {
+        std::fs::remove_file(&newest)?;
+    }
+    if std::fs::hard_link(path, &newest).is_err() {
+        std::fs::copy(path, &newest)?;
+    }
+
+    Ok(())
+}
+
+// The rename is only durable once the directory entry is written
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<(), std::io::Error> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
+
+// Directories can't be opened as files on Windows, rename is durable there
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<(), std::io::Error> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("pwm-atomic-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn test_write_atomic_backups() {
+        let directory = temp_dir("backups");
+        let file = directory.join("vault");
+
+        for contents in ["1", "2", "3", "4", "5"] {
+            write_atomic(&file, contents.as_bytes(), 3).unwrap();
+        }
+
+        assert_eq!(std::fs::read(&file).unwrap(), b"5");
+        assert_eq!(std::fs::read(backup_path(&file, 1)).unwrap(), b"4");
+        assert_eq!(std::fs::read(backup_path(&file, 2)).unwrap(), b"3");
+        assert_eq!(std::fs::read(backup_path(&file, 3)).unwrap(), b"2");
+        assert!(!backup_path(&file, 4).exists());
+
+        // Only the file itself and its backups
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 4);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_write_atomic_failure() {
+        let directory = temp_dir("failure");
+        let file = directory.join("vault");
+        write_atomic(&file, b"old", 0).unwrap();
+
+        let result = write_atomic_with(&file, 2, |file| {
+            file.write_all(b"partial")?;
+            Err::<(), _>(std::io::Error::other("disk full"))
+        });
+        assert!(result.is_err());
+
+        // Nothing changed and no temp file was left behind
+        assert_eq!(std::fs::read(&file).unwrap(), b"old");
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
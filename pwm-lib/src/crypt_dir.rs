@@ -0,0 +1,497 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::atomic_file::write_atomic_with;
+use crate::encryption::stream::{StreamDecryptor, StreamEncryptor};
+use crate::hash::argon2_wrapper::{argon2_hash_password, argon2_hash_password_with_salt};
+
+// Directories are packed into a small archive which is then encrypted with
+// the chunked format, so nothing has to fit in memory.
+//
+//   magic "PWMA" | version | entries | END
+//   entry: kind | path length (u32) | path | mode (u32) | mtime seconds (i64)
+//          | mtime nanoseconds (u32) | size (u64) and contents for files
+//
+// Paths are relative, '/' separated and UTF-8. Symlinks and special files are
+// not archived.
+static ARCHIVE_MAGIC: &[u8; 4] = b"PWMA";
+static ARCHIVE_VERSION: u8 = 1;
+pub static ARCHIVE_EXTENSION: &str = "pwma";
+
+const KIND_END: u8 = 0;
+const KIND_DIRECTORY: u8 = 1;
+const KIND_FILE: u8 = 2;
+
+// Paths longer than this are not produced by any real file system
+const MAX_PATH_LENGTH: usize = 64 * 1024;
+
+fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+fn other_error(msg: String) -> std::io::Error {
+    std::io::Error::other(msg)
+}
+
+// Encrypts the directory into output, "<directory>.pwma" if not given.
+// Returns the paths that were skipped because they are symlinks or special
+// files.
+pub fn encrypt_dir(
+    directory: String,
+    output: Option<String>,
+    password: &[u8],
+) -> Result<Vec<String>, std::io::Error> {
+    if !std::fs::metadata(&directory)?.is_dir() {
+        return Err(other_error(format!("{} is not a directory", directory)));
+    }
+
+    let output = match output {
+        Some(output) => output,
+        None => format!(
+            "{}.{}",
+            directory.trim_end_matches(['/', '\\']),
+            ARCHIVE_EXTENSION
+        ),
+    };
+
+    let hash = match argon2_hash_password(password) {
+        Ok(hash) => hash,
+        Err(error) => return Err(other_error(error.to_string())),
+    };
+
+    if Path::new(&output).exists() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", output),
+        ));
+    }
+
+    write_atomic_with(&output, 0, |file| {
+        let mut writer = StreamEncryptor::new(file, &hash)?;
+        let skipped = write_archive(Path::new(&directory), &mut writer)?;
+        writer.finish()?;
+        Ok(skipped)
+    })
+}
+
+// Restores the archive into output, the archive name without ".pwma" if not
+// given. The output directory must not exist yet.
+pub fn decrypt_dir(
+    file: String,
+    output: Option<String>,
+    password: &[u8],
+) -> Result<(), std::io::Error> {
+    let output = match output {
+        Some(output) => output,
+        None => match file.strip_suffix(&format!(".{}", ARCHIVE_EXTENSION)) {
+            Some(output) if !output.is_empty() => output.to_string(),
+            _ => {
+                return Err(other_error(format!(
+                    "{} doesn't end in .{}, provide an output directory",
+                    file, ARCHIVE_EXTENSION
+                )))
+            }
+        },
+    };
+
+    let mut reader = StreamDecryptor::new(File::open(&file)?, |salt| {
+        argon2_hash_password_with_salt(password, salt)
+    })?;
+
+    let output = PathBuf::from(output);
+    std::fs::create_dir(&output)?;
+
+    let result = read_archive(&mut reader, &output);
+
+    // Only whatever was authenticated got written, but a failed archive is
+    // still removed as a whole
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(&output);
+    }
+
+    result
+}
+
+struct Metadata {
+    mode: u32,
+    seconds: i64,
+    nanoseconds: u32,
+}
+
+impl Metadata {
+    fn new(metadata: &std::fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            metadata.permissions().mode() & 0o7777
+        };
+        #[cfg(not(unix))]
+        let mode = match (metadata.is_dir(), metadata.permissions().readonly()) {
+            (true, _) => 0o755,
+            (false, true) => 0o444,
+            (false, false) => 0o644,
+        };
+
+        let (seconds, nanoseconds) = match metadata.modified() {
+            Ok(modified) => match modified.duration_since(UNIX_EPOCH) {
+                Ok(duration) => (duration.as_secs() as i64, duration.subsec_nanos()),
+                Err(error) => {
+                    let duration = error.duration();
+                    (-(duration.as_secs() as i64), duration.subsec_nanos())
+                }
+            },
+            Err(_error) => (0, 0),
+        };
+
+        Self {
+            mode,
+            seconds,
+            nanoseconds,
+        }
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        let duration = Duration::new(self.seconds.unsigned_abs(), self.nanoseconds);
+        if self.seconds < 0 {
+            UNIX_EPOCH.checked_sub(duration)
+        } else {
+            UNIX_EPOCH.checked_add(duration)
+        }
+    }
+}
+
+fn write_archive(directory: &Path, writer: &mut impl Write) -> std::io::Result<Vec<String>> {
+    writer.write_all(ARCHIVE_MAGIC)?;
+    writer.write_all(&[ARCHIVE_VERSION])?;
+
+    let mut skipped = Vec::new();
+    write_directory(directory, "", writer, &mut skipped)?;
+
+    writer.write_all(&[KIND_END])?;
+    Ok(skipped)
+}
+
+// Walks the directory in name order so the same tree gives the same archive
+fn write_directory(
+    directory: &Path,
+    prefix: &str,
+    writer: &mut impl Write,
+    skipped: &mut Vec<String>,
+) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(directory)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(name) => {
+                return Err(invalid_data(
+                    format!("{} is not a valid UTF-8 name", name.to_string_lossy()).as_str(),
+                ))
+            }
+        };
+        let path = format!("{}{}", prefix, name);
+
+        // Not following symlinks, they could point anywhere
+        let metadata = std::fs::symlink_metadata(entry.path())?;
+        if metadata.is_dir() {
+            write_header(writer, KIND_DIRECTORY, &path, &Metadata::new(&metadata))?;
+            write_directory(&entry.path(), &format!("{}/", path), writer, skipped)?;
+        } else if metadata.is_file() {
+            let mut file = File::open(entry.path())?;
+            let metadata = file.metadata()?;
+            write_header(writer, KIND_FILE, &path, &Metadata::new(&metadata))?;
+
+            let size = metadata.len();
+            writer.write_all(&size.to_be_bytes())?;
+            let copied = std::io::copy(&mut (&mut file).take(size), writer)?;
+            if copied != size {
+                return Err(other_error(format!("{} changed while encrypting", path)));
+            }
+        } else {
+            skipped.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn write_header(
+    writer: &mut impl Write,
+    kind: u8,
+    path: &str,
+    metadata: &Metadata,
+) -> std::io::Result<()> {
+    writer.write_all(&[kind])?;
+    writer.write_all(&(path.len() as u32).to_be_bytes())?;
+    writer.write_all(path.as_bytes())?;
+    writer.write_all(&metadata.mode.to_be_bytes())?;
+    writer.write_all(&metadata.seconds.to_be_bytes())?;
+    writer.write_all(&metadata.nanoseconds.to_be_bytes())
+}
+
+fn read_archive(reader: &mut impl Read, output: &Path) -> std::io::Result<()> {
+    let mut magic = [0u8; 5];
+    reader.read_exact(&mut magic)?;
+    if &magic[..4] != ARCHIVE_MAGIC {
+        return Err(invalid_data("Not a directory archive"));
+    }
+    if magic[4] != ARCHIVE_VERSION {
+        return Err(invalid_data("Unsupported archive version"));
+    }
+
+    // Directory metadata is applied at the end, adding files changes the
+    // mtime and a read only directory couldn't be filled
+    let mut directories = Vec::new();
+    loop {
+        let kind = read_array::<1>(reader)?[0];
+        if kind == KIND_END {
+            break;
+        }
+
+        let path = output.join(read_path(reader)?);
+        let metadata = Metadata {
+            mode: u32::from_be_bytes(read_array(reader)?),
+            seconds: i64::from_be_bytes(read_array(reader)?),
+            nanoseconds: u32::from_be_bytes(read_array(reader)?),
+        };
+        if metadata.nanoseconds >= 1_000_000_000 {
+            return Err(invalid_data("Invalid modification time"));
+        }
+
+        match kind {
+            KIND_DIRECTORY => {
+                std::fs::create_dir_all(&path)?;
+                directories.push((path, metadata));
+            }
+            KIND_FILE => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+
+                let size = u64::from_be_bytes(read_array(reader)?);
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)?;
+                let copied = std::io::copy(&mut reader.take(size), &mut file)?;
+                if copied != size {
+                    return Err(invalid_data("Archive ends in the middle of a file"));
+                }
+
+                set_metadata(&file, &path, &metadata)?;
+            }
+            _ => return Err(invalid_data("Unknown archive entry")),
+        }
+    }
+
+    let mut rest = [0u8; 1];
+    if reader.read(&mut rest)? != 0 {
+        return Err(invalid_data("Unexpected data after the archive"));
+    }
+
+    // Children before their parents
+    for (path, metadata) in directories.iter().rev() {
+        #[cfg(unix)]
+        set_metadata(&File::open(path)?, path, metadata)?;
+        #[cfg(not(unix))]
+        let _ = (path, metadata);
+    }
+
+    Ok(())
+}
+
+fn set_metadata(file: &File, path: &Path, metadata: &Metadata) -> std::io::Result<()> {
+    if let Some(modified) = metadata.modified() {
+        file.set_modified(modified)?;
+    }
+
+    // Only the permission bits, setuid and friends are not restored
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(metadata.mode & 0o777))?;
+    }
+    #[cfg(not(unix))]
+    {
+        let mut permissions = file.metadata()?.permissions();
+        permissions.set_readonly(metadata.mode & 0o200 == 0);
+        std::fs::set_permissions(path, permissions)?;
+    }
+
+    Ok(())
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> std::io::Result<[u8; N]> {
+    let mut data = [0u8; N];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+// Every component has to be a plain name, so nothing can be written outside
+// of the output directory
+fn read_path(reader: &mut impl Read) -> std::io::Result<PathBuf> {
+    let length = u32::from_be_bytes(read_array(reader)?) as usize;
+    if length > MAX_PATH_LENGTH {
+        return Err(invalid_data("Archive path too long"));
+    }
+
+    let mut path = vec![0u8; length];
+    reader.read_exact(&mut path)?;
+    let path = match String::from_utf8(path) {
+        Ok(path) => path,
+        Err(_error) => return Err(invalid_data("Archive path is not valid UTF-8")),
+    };
+
+    let mut result = PathBuf::new();
+    for name in path.split('/') {
+        if !is_safe_name(name) {
+            return Err(invalid_data(
+                format!("Unsafe path in archive: {}", path).as_str(),
+            ));
+        }
+        result.push(name);
+    }
+
+    Ok(result)
+}
+
+fn is_safe_name(name: &str) -> bool {
+    if name.is_empty() || name.contains(['\\', '\0']) {
+        return false;
+    }
+
+    // Rejects ".", ".." and Windows drive prefixes
+    let mut components = Path::new(name).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("pwm-crypt-dir-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn test_dir_round_trip() {
+        let directory = temp_dir("round-trip");
+        let source = directory.join("source");
+        std::fs::create_dir_all(source.join("nested/empty")).unwrap();
+        std::fs::write(source.join("top.txt"), b"top").unwrap();
+        let large: Vec<u8> = (0..200_000).map(|index| index as u8).collect();
+        std::fs::write(source.join("nested/large.bin"), &large).unwrap();
+
+        let modified = UNIX_EPOCH + Duration::new(1_600_000_000, 123);
+        File::options()
+            .write(true)
+            .open(source.join("top.txt"))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(
+                source.join("top.txt"),
+                std::fs::Permissions::from_mode(0o600),
+            )
+            .unwrap();
+            std::os::unix::fs::symlink("top.txt", source.join("link")).unwrap();
+        }
+
+        let archive = directory.join("source.pwma");
+        let skipped = encrypt_dir(source.to_str().unwrap().to_string(), None, b"hunter42").unwrap();
+        #[cfg(unix)]
+        assert_eq!(skipped, vec![String::from("link")]);
+
+        let archive = archive.to_str().unwrap().to_string();
+        let restored = directory.join("restored");
+        let restored_name = Some(restored.to_str().unwrap().to_string());
+        assert!(decrypt_dir(archive.clone(), restored_name.clone(), b"hunter43").is_err());
+        assert!(!restored.exists());
+        decrypt_dir(archive, restored_name, b"hunter42").unwrap();
+
+        assert_eq!(std::fs::read(restored.join("top.txt")).unwrap(), b"top");
+        assert_eq!(
+            std::fs::read(restored.join("nested/large.bin")).unwrap(),
+            large
+        );
+        assert!(restored.join("nested/empty").is_dir());
+        assert!(!restored.join("link").exists());
+
+        let metadata = std::fs::metadata(restored.join("top.txt")).unwrap();
+        assert_eq!(metadata.modified().unwrap(), modified);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        }
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    fn archive_with_path(path: &str) -> Vec<u8> {
+        let metadata = Metadata {
+            mode: 0o644,
+            seconds: 0,
+            nanoseconds: 0,
+        };
+        let mut archive = Vec::new();
+        archive.extend_from_slice(ARCHIVE_MAGIC);
+        archive.push(ARCHIVE_VERSION);
+        write_header(&mut archive, KIND_FILE, path, &metadata).unwrap();
+        archive.extend_from_slice(&4u64.to_be_bytes());
+        archive.extend_from_slice(b"evil");
+        archive.push(KIND_END);
+        archive
+    }
+
+    #[test]
+    fn test_dir_path_traversal() {
+        let directory = temp_dir("traversal");
+        let output = directory.join("output");
+
+        for path in [
+            "../evil",
+            "a/../../evil",
+            "/etc/evil",
+            "./evil",
+            "a//evil",
+            "a\\..\\evil",
+            "",
+        ] {
+            std::fs::create_dir(&output).unwrap();
+            let archive = archive_with_path(path);
+            assert!(read_archive(&mut archive.as_slice(), &output).is_err());
+            std::fs::remove_dir_all(&output).unwrap();
+        }
+        assert!(!directory.join("evil").exists());
+
+        // Only a drive prefix on Windows, a plain name everywhere else
+        #[cfg(windows)]
+        {
+            std::fs::create_dir(&output).unwrap();
+            let archive = archive_with_path("C:evil");
+            assert!(read_archive(&mut archive.as_slice(), &output).is_err());
+            std::fs::remove_dir_all(&output).unwrap();
+        }
+
+        std::fs::create_dir(&output).unwrap();
+        let archive = archive_with_path("a/fine");
+        read_archive(&mut archive.as_slice(), &output).unwrap();
+        assert_eq!(std::fs::read(output.join("a/fine")).unwrap(), b"evil");
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
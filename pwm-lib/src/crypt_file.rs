@@ -1,3 +1,4 @@
+use crate::atomic_file::write_atomic;
 use crate::encryption::default::{decrypt, encrypt};
 use crate::encryption::EncryptionResult;
 use crate::hash::argon2_wrapper::{argon2_hash_password, argon2_hash_password_with_salt};
@@ -35,7 +36,8 @@ pub fn encrypt_file(
         None => file,
     };
 
-    std::fs::write(output, cipher_contents.as_slice())?;
+    // No backups, the old contents are usually the plaintext
+    write_atomic(output, cipher_contents.as_slice(), 0)?;
 
     Ok(())
 }
@@ -80,7 +82,7 @@ pub fn decrypt_file(
         None => file,
     };
 
-    std::fs::write(output, cipher_contents.as_slice())?;
+    write_atomic(output, cipher_contents.as_slice(), 0)?;
 
     Ok(())
 }
@@ -3,6 +3,7 @@ use zeroize::Zeroize;
 
 pub mod aes_wrapper;
 pub mod chacha20_wrapper;
+pub mod stream;
 
 pub mod default {
     use crate::hash::HashResult;
@@ -33,9 +34,37 @@ pub mod default {
         result
     }
 
+    // The associated data is authenticated but not stored, decrypting needs
+    // the same data
+    pub fn encrypt_with_aad(
+        plaintext: &[u8],
+        aad: &[u8],
+        key: &HashResult,
+    ) -> Result<EncryptionResult, EncryptionError> {
+        #[cfg(feature = "use-aes-default")]
+        let result = super::aes_wrapper::aes_encrypt_with_aad(plaintext, aad, key);
+        #[cfg(feature = "use-chacha20-default")]
+        let result = super::chacha20_wrapper::chacha20_encrypt_with_aad(plaintext, aad, key);
+
+        result
+    }
+
+    pub fn decrypt_with_aad(
+        ciphertext: &EncryptionResult,
+        aad: &[u8],
+        key: &HashResult,
+    ) -> Result<EncryptionResult, EncryptionError> {
+        #[cfg(feature = "use-aes-default")]
+        let result = super::aes_wrapper::aes_decrypt_with_aad(ciphertext, aad, key);
+        #[cfg(feature = "use-chacha20-default")]
+        let result = super::chacha20_wrapper::chacha20_decrypt_with_aad(ciphertext, aad, key);
+
+        result
+    }
+
     #[cfg(test)]
     mod test {
-        use super::{decrypt, encrypt};
+        use super::{decrypt, decrypt_with_aad, encrypt, encrypt_with_aad};
         use crate::hash::pbkdf2_wrapper::pbkdf2_hash_password;
 
         #[test]
@@ -58,6 +87,19 @@ pub mod default {
 
             assert!(matching == plaintext.len())
         }
+
+        #[test]
+        fn test_aad() {
+            let hash = pbkdf2_hash_password(b"hunter42").unwrap();
+            let ciphertext = encrypt_with_aad(b"hello", b"frame 1", &hash).unwrap();
+
+            let plaintext = decrypt_with_aad(&ciphertext, b"frame 1", &hash).unwrap();
+            assert_eq!(plaintext.as_slice(), b"hello");
+            assert!(decrypt_with_aad(&ciphertext, b"frame 2", &hash).is_err());
+            assert!(decrypt(&ciphertext, &hash).is_err());
+            let ciphertext = encrypt(b"hello", &hash).unwrap();
+            assert!(decrypt_with_aad(&ciphertext, &[], &hash).is_ok());
+        }
     }
 }
 
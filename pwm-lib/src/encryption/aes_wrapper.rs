@@ -28,6 +28,30 @@ pub fn aes_decrypt(
     Ok(result)
 }
 
+pub fn aes_encrypt_with_aad(
+    plaintext: &[u8],
+    aad: &[u8],
+    hash: &HashResult,
+) -> Result<EncryptionResult, EncryptionError> {
+    #[cfg(feature = "use-aes-gcm-siv")]
+    let result = aes_gcm_siv::aes_gcm_siv_encrypt_with_aad(plaintext, aad, hash)?;
+    #[cfg(not(feature = "use-aes-gcm-siv"))]
+    let result = aes_gcm::aes_gcm_encrypt_with_aad(plaintext, aad, hash)?;
+    Ok(result)
+}
+
+pub fn aes_decrypt_with_aad(
+    ciphertext: &EncryptionResult,
+    aad: &[u8],
+    hash: &HashResult,
+) -> Result<EncryptionResult, EncryptionError> {
+    #[cfg(feature = "use-aes-gcm-siv")]
+    let result = aes_gcm_siv::aes_gcm_siv_decrypt_with_aad(ciphertext, aad, hash)?;
+    #[cfg(not(feature = "use-aes-gcm-siv"))]
+    let result = aes_gcm::aes_gcm_decrypt_with_aad(ciphertext, aad, hash)?;
+    Ok(result)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -1,7 +1,7 @@
 use crate::encryption::{EncryptionError, EncryptionResult};
 use crate::hash::HashResult;
 use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit},
+    aead::{Aead, AeadCore, KeyInit, Payload},
     Aes256Gcm, Key,
 };
 
@@ -15,6 +15,14 @@ impl From<aes_gcm::Error> for EncryptionError {
 pub fn aes_gcm_encrypt(
     plaintext: &[u8],
     hash_result: &HashResult,
+) -> Result<EncryptionResult, EncryptionError> {
+    aes_gcm_encrypt_with_aad(plaintext, &[], hash_result)
+}
+
+pub fn aes_gcm_encrypt_with_aad(
+    plaintext: &[u8],
+    aad: &[u8],
+    hash_result: &HashResult,
 ) -> Result<EncryptionResult, EncryptionError> {
     let key = Key::<Aes256Gcm>::from_slice(hash_result.get_hash());
 
@@ -22,7 +30,13 @@ pub fn aes_gcm_encrypt(
 
     // if doing absurd number of random numbers over 4 million consider siv
     let nonce = Aes256Gcm::generate_nonce(&mut aead::OsRng); // 96-bits; unique per message
-    let mut ciphertext = cipher.encrypt(&nonce, plaintext)?;
+    let mut ciphertext = cipher.encrypt(
+        &nonce,
+        Payload {
+            msg: plaintext,
+            aad,
+        },
+    )?;
     ciphertext.extend_from_slice(nonce.as_slice());
     ciphertext.extend_from_slice(hash_result.get_salt());
 
@@ -32,6 +46,14 @@ pub fn aes_gcm_encrypt(
 pub fn aes_gcm_decrypt(
     ciphertext: &EncryptionResult,
     key: &HashResult,
+) -> Result<EncryptionResult, EncryptionError> {
+    aes_gcm_decrypt_with_aad(ciphertext, &[], key)
+}
+
+pub fn aes_gcm_decrypt_with_aad(
+    ciphertext: &EncryptionResult,
+    aad: &[u8],
+    key: &HashResult,
 ) -> Result<EncryptionResult, EncryptionError> {
     let key = Key::<Aes256Gcm>::from_slice(key.get_hash());
 
@@ -40,7 +62,13 @@ pub fn aes_gcm_decrypt(
     let cipher = Aes256Gcm::new(&key);
     let nonce = &ciphertext[ciphertext.len() - 12..];
 
-    let plaintext = cipher.decrypt(nonce.into(), &ciphertext[..ciphertext.len() - 12])?;
+    let plaintext = cipher.decrypt(
+        nonce.into(),
+        Payload {
+            msg: &ciphertext[..ciphertext.len() - 12],
+            aad,
+        },
+    )?;
 
     Ok(EncryptionResult::new(plaintext)?)
 }
@@ -3,7 +3,7 @@ use crate::{
     hash::HashResult,
 };
 use aes_gcm_siv::{
-    aead::{Aead, AeadCore, KeyInit},
+    aead::{Aead, AeadCore, KeyInit, Payload},
     Aes256GcmSiv,
 };
 
@@ -17,11 +17,25 @@ impl From<aes_gcm::Error> for EncryptionError {
 pub fn aes_gcm_siv_encrypt(
     plaintext: &[u8],
     hash_result: &HashResult,
+) -> Result<EncryptionResult, EncryptionError> {
+    aes_gcm_siv_encrypt_with_aad(plaintext, &[], hash_result)
+}
+
+pub fn aes_gcm_siv_encrypt_with_aad(
+    plaintext: &[u8],
+    aad: &[u8],
+    hash_result: &HashResult,
 ) -> Result<EncryptionResult, EncryptionError> {
     let cipher = Aes256GcmSiv::new(hash_result.get_hash().into());
     let nonce = Aes256GcmSiv::generate_nonce(&mut aead::OsRng); // 96-bits; unique per message
 
-    let mut ciphertext = cipher.encrypt(&nonce, plaintext)?;
+    let mut ciphertext = cipher.encrypt(
+        &nonce,
+        Payload {
+            msg: plaintext,
+            aad,
+        },
+    )?;
     ciphertext.extend_from_slice(nonce.as_slice());
     ciphertext.extend_from_slice(hash_result.get_salt());
 
@@ -31,12 +45,26 @@ pub fn aes_gcm_siv_encrypt(
 pub fn aes_gcm_siv_decrypt(
     ciphertext: &EncryptionResult,
     key: &HashResult,
+) -> Result<EncryptionResult, EncryptionError> {
+    aes_gcm_siv_decrypt_with_aad(ciphertext, &[], key)
+}
+
+pub fn aes_gcm_siv_decrypt_with_aad(
+    ciphertext: &EncryptionResult,
+    aad: &[u8],
+    key: &HashResult,
 ) -> Result<EncryptionResult, EncryptionError> {
     let cipher = Aes256GcmSiv::new(key.get_hash().into());
     let ciphertext = ciphertext.get_crypt_slice();
     let nonce = &ciphertext[ciphertext.len() - 12..];
 
-    let plaintext = cipher.decrypt(nonce.into(), &ciphertext[..ciphertext.len() - 12])?;
+    let plaintext = cipher.decrypt(
+        nonce.into(),
+        Payload {
+            msg: &ciphertext[..ciphertext.len() - 12],
+            aad,
+        },
+    )?;
 
     return Ok(EncryptionResult { data: plaintext });
 }
@@ -1,5 +1,5 @@
 use chacha20poly1305::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
     XChaCha20Poly1305,
 };
 
@@ -8,9 +8,23 @@ use crate::hash::HashResult;
 use super::{EncryptionError, EncryptionResult};
 
 pub fn chacha20_encrypt(plaintext: &[u8], key: &HashResult) -> Result<EncryptionResult, EncryptionError> {
+    chacha20_encrypt_with_aad(plaintext, &[], key)
+}
+
+pub fn chacha20_encrypt_with_aad(
+    plaintext: &[u8],
+    aad: &[u8],
+    key: &HashResult,
+) -> Result<EncryptionResult, EncryptionError> {
     let cipher = XChaCha20Poly1305::new(key.get_hash().into());
     let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng); // 192-bits; unique per message
-    let mut ciphertext = cipher.encrypt(&nonce, plaintext)?;
+    let mut ciphertext = cipher.encrypt(
+        &nonce,
+        Payload {
+            msg: plaintext,
+            aad,
+        },
+    )?;
     ciphertext.extend_from_slice(nonce.as_slice());
     ciphertext.extend_from_slice(key.get_salt());
 
@@ -18,13 +32,27 @@ pub fn chacha20_encrypt(plaintext: &[u8], key: &HashResult) -> Result<Encryption
 }
 
 pub fn chacha20_decrypt(ciphertext: &EncryptionResult, key: &HashResult) -> Result<EncryptionResult, EncryptionError> {
+    chacha20_decrypt_with_aad(ciphertext, &[], key)
+}
+
+pub fn chacha20_decrypt_with_aad(
+    ciphertext: &EncryptionResult,
+    aad: &[u8],
+    key: &HashResult,
+) -> Result<EncryptionResult, EncryptionError> {
     let cipher = XChaCha20Poly1305::new(key.get_hash().into());
     let ciphertext = ciphertext.get_crypt_slice();
 
     let nonce = &ciphertext[ciphertext.len() - 24..];
     let ciphertext = &ciphertext[..ciphertext.len() - 24];
 
-    let plaintext = cipher.decrypt(nonce.into(), ciphertext)?;
+    let plaintext = cipher.decrypt(
+        nonce.into(),
+        Payload {
+            msg: ciphertext,
+            aad,
+        },
+    )?;
 
     EncryptionResult::new(plaintext)
 }
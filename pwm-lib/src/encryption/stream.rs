@@ -0,0 +1,272 @@
+use std::io::{BufRead, BufReader, Read, Write};
+
+use chacha20poly1305::{
+    aead::{
+        stream::{DecryptorBE32, EncryptorBE32},
+        Payload,
+    },
+    KeyInit, XChaCha20Poly1305,
+};
+use zeroize::Zeroizing;
+
+use crate::hash::{randomize_slice, HashError, HashResult};
+
+// Chunked AEAD for data that shouldn't be held in memory all at once, using
+// the STREAM construction so chunks can't be reordered, dropped or cut off.
+//
+//   magic "PWMS" | version | salt (32) | nonce prefix (19) | chunks
+//
+// Every chunk is CHUNK_SIZE bytes of plaintext plus a tag, except the last
+// one which is shorter (maybe empty) and sealed as the last block. The header
+// is the associated data of every chunk.
+static STREAM_MAGIC: &[u8; 4] = b"PWMS";
+static STREAM_VERSION: u8 = 1;
+pub static CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+const SALT_SIZE: usize = 32;
+// XChaCha20's 24 byte nonce minus the 4 byte counter and the last block flag
+const NONCE_PREFIX_SIZE: usize = 19;
+const HEADER_SIZE: usize = 4 + 1 + SALT_SIZE + NONCE_PREFIX_SIZE;
+
+fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+pub struct StreamEncryptor<W: Write> {
+    writer: W,
+    encryptor: Option<EncryptorBE32<XChaCha20Poly1305>>,
+    header: [u8; HEADER_SIZE],
+    buffer: Zeroizing<Vec<u8>>,
+}
+
+impl<W: Write> StreamEncryptor<W> {
+    // The salt of key is stored in the header so the key can be derived again
+    pub fn new(mut writer: W, key: &HashResult) -> std::io::Result<Self> {
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+        randomize_slice(&mut nonce_prefix);
+
+        let mut header = [0u8; HEADER_SIZE];
+        header[..4].copy_from_slice(STREAM_MAGIC);
+        header[4] = STREAM_VERSION;
+        header[5..5 + SALT_SIZE].copy_from_slice(key.get_salt());
+        header[5 + SALT_SIZE..].copy_from_slice(&nonce_prefix);
+        writer.write_all(&header)?;
+
+        let cipher = XChaCha20Poly1305::new(key.get_hash().into());
+        Ok(Self {
+            writer,
+            encryptor: Some(EncryptorBE32::from_aead(cipher, (&nonce_prefix).into())),
+            header,
+            buffer: Zeroizing::new(Vec::with_capacity(CHUNK_SIZE)),
+        })
+    }
+
+    // Seals the last chunk, without this the output can't be decrypted
+    pub fn finish(mut self) -> std::io::Result<W> {
+        let encryptor = match self.encryptor.take() {
+            Some(encryptor) => encryptor,
+            None => return Err(invalid_data("Stream already finished")),
+        };
+
+        let payload = Payload {
+            msg: self.buffer.as_slice(),
+            aad: &self.header,
+        };
+        let ciphertext = match encryptor.encrypt_last(payload) {
+            Ok(ciphertext) => ciphertext,
+            Err(_error) => return Err(invalid_data("Failed to encrypt chunk")),
+        };
+        self.buffer.clear();
+
+        self.writer.write_all(&ciphertext)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_chunk(&mut self) -> std::io::Result<()> {
+        let encryptor = match self.encryptor.as_mut() {
+            Some(encryptor) => encryptor,
+            None => return Err(invalid_data("Stream already finished")),
+        };
+
+        let payload = Payload {
+            msg: self.buffer.as_slice(),
+            aad: &self.header,
+        };
+        let ciphertext = match encryptor.encrypt_next(payload) {
+            Ok(ciphertext) => ciphertext,
+            Err(_error) => return Err(invalid_data("Failed to encrypt chunk")),
+        };
+        self.buffer.clear();
+
+        self.writer.write_all(&ciphertext)
+    }
+}
+
+impl<W: Write> Write for StreamEncryptor<W> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        // A full chunk is only sealed once more data arrives, so the last
+        // chunk is always the one finish seals
+        if self.buffer.len() == CHUNK_SIZE && !data.is_empty() {
+            self.write_chunk()?;
+        }
+
+        let count = data.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..count]);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+pub struct StreamDecryptor<R: Read> {
+    reader: BufReader<R>,
+    decryptor: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    header: [u8; HEADER_SIZE],
+    buffer: Zeroizing<Vec<u8>>,
+    position: usize,
+}
+
+impl<R: Read> StreamDecryptor<R> {
+    // derive_key gets the salt from the header and returns the key
+    pub fn new(
+        reader: R,
+        derive_key: impl FnOnce(&[u8]) -> Result<HashResult, HashError>,
+    ) -> std::io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut header = [0u8; HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+
+        if &header[..4] != STREAM_MAGIC {
+            return Err(invalid_data("Not an encrypted stream"));
+        }
+        if header[4] != STREAM_VERSION {
+            return Err(invalid_data("Unsupported stream version"));
+        }
+
+        let key = match derive_key(&header[5..5 + SALT_SIZE]) {
+            Ok(key) => key,
+            Err(error) => return Err(invalid_data(error.to_string().as_str())),
+        };
+        let cipher = XChaCha20Poly1305::new(key.get_hash().into());
+        let nonce_prefix: [u8; NONCE_PREFIX_SIZE] = match header[5 + SALT_SIZE..].try_into() {
+            Ok(nonce_prefix) => nonce_prefix,
+            Err(_error) => return Err(invalid_data("Invalid header")),
+        };
+
+        Ok(Self {
+            reader,
+            decryptor: Some(DecryptorBE32::from_aead(cipher, (&nonce_prefix).into())),
+            header,
+            buffer: Zeroizing::new(Vec::new()),
+            position: 0,
+        })
+    }
+
+    fn read_chunk(&mut self) -> std::io::Result<()> {
+        let mut ciphertext = Vec::with_capacity(CHUNK_SIZE + TAG_SIZE);
+        (&mut self.reader)
+            .take((CHUNK_SIZE + TAG_SIZE) as u64)
+            .read_to_end(&mut ciphertext)?;
+        let last = self.reader.fill_buf()?.is_empty();
+
+        let payload = Payload {
+            msg: ciphertext.as_slice(),
+            aad: &self.header,
+        };
+        let plaintext = if last {
+            let decryptor = match self.decryptor.take() {
+                Some(decryptor) => decryptor,
+                None => return Err(invalid_data("Stream already finished")),
+            };
+            decryptor.decrypt_last(payload)
+        } else {
+            match self.decryptor.as_mut() {
+                Some(decryptor) => decryptor.decrypt_next(payload),
+                None => return Err(invalid_data("Stream already finished")),
+            }
+        };
+
+        // Also what a truncated stream looks like, its new last chunk wasn't
+        // sealed as the last one
+        self.buffer = match plaintext {
+            Ok(plaintext) => Zeroizing::new(plaintext),
+            Err(_error) => {
+                return Err(invalid_data(
+                    "Decryption failed, wrong password or corrupted data",
+                ))
+            }
+        };
+        self.position = 0;
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for StreamDecryptor<R> {
+    fn read(&mut self, data: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.buffer.len() {
+            if self.decryptor.is_none() {
+                return Ok(0);
+            }
+            self.read_chunk()?;
+        }
+
+        let count = data.len().min(self.buffer.len() - self.position);
+        data[..count].copy_from_slice(&self.buffer[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+
+    use super::{StreamDecryptor, StreamEncryptor, CHUNK_SIZE};
+    use crate::hash::pbkdf2_wrapper::{pbkdf2_hash_password, pbkdf2_hash_password_with_salt};
+
+    fn encrypt(plaintext: &[u8], password: &[u8]) -> Vec<u8> {
+        let key = pbkdf2_hash_password(password).unwrap();
+        let mut encryptor = StreamEncryptor::new(Vec::new(), &key).unwrap();
+        encryptor.write_all(plaintext).unwrap();
+        encryptor.finish().unwrap()
+    }
+
+    fn decrypt(ciphertext: &[u8], password: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut decryptor = StreamDecryptor::new(ciphertext, |salt| {
+            pbkdf2_hash_password_with_salt(password, salt)
+        })?;
+        let mut plaintext = Vec::new();
+        decryptor.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        // Empty, partial chunk, exactly one chunk and several chunks
+        for length in [0, 10, CHUNK_SIZE, CHUNK_SIZE * 2 + 7] {
+            let plaintext: Vec<u8> = (0..length).map(|index| index as u8).collect();
+            let ciphertext = encrypt(&plaintext, b"hunter42");
+            assert_eq!(decrypt(&ciphertext, b"hunter42").unwrap(), plaintext);
+        }
+    }
+
+    #[test]
+    fn test_stream_tampering() {
+        let plaintext = vec![7u8; CHUNK_SIZE * 2 + 7];
+        let ciphertext = encrypt(&plaintext, b"hunter42");
+
+        assert!(decrypt(&ciphertext, b"hunter43").is_err());
+
+        // Cut after the first chunk
+        let truncated = &ciphertext[..56 + CHUNK_SIZE + 16];
+        assert!(decrypt(truncated, b"hunter42").is_err());
+
+        let mut flipped = ciphertext.clone();
+        flipped[100] ^= 1;
+        assert!(decrypt(&flipped, b"hunter42").is_err());
+    }
+}
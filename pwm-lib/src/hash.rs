@@ -101,14 +101,27 @@ impl Drop for HashResult {
 #[derive(Debug)]
 pub struct HashError {
     error: String,
+    cancelled: bool,
 }
 
 impl HashError {
     pub fn new(msg: &str) -> Self {
         Self {
             error: msg.to_string(),
+            cancelled: false,
         }
     }
+
+    pub fn cancelled() -> Self {
+        Self {
+            error: String::from("Cancelled"),
+            cancelled: true,
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
 }
 
 impl std::fmt::Display for HashError {
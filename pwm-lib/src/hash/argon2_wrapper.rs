@@ -1,4 +1,6 @@
 use crate::hash::{HashError, HashResult};
+use crate::progress::{run_cancellable, Progress};
+use crate::zeroize::Zeroizing;
 use argon2::{Algorithm, Argon2, Params};
 
 // Updated April 25 of 2024
@@ -40,13 +42,60 @@ pub fn argon2_hash_password_with_salt(password: &[u8], salt: &[u8]) -> Result<Ha
     Ok(result)
 }
 
+// Hashes on a worker thread, counts as one step of progress
+fn argon2_hash_password_into_cancellable(
+    password: &[u8],
+    mut result: HashResult,
+    progress: &Progress,
+) -> Result<HashResult, HashError> {
+    let password = Zeroizing::new(password.to_vec());
+    run_cancellable(progress, move || {
+        argon2_hash_password_into(password.as_slice(), &mut result)?;
+        Ok(result)
+    })
+}
+
+pub fn argon2_hash_password_cancellable(
+    password: &[u8],
+    progress: &Progress,
+) -> Result<HashResult, HashError> {
+    argon2_hash_password_into_cancellable(password, HashResult::new(), progress)
+}
+
+pub fn argon2_hash_password_with_salt_cancellable(
+    password: &[u8],
+    salt: &[u8],
+    progress: &Progress,
+) -> Result<HashResult, HashError> {
+    argon2_hash_password_into_cancellable(password, HashResult::new_with_salt(salt)?, progress)
+}
+
 #[cfg(test)]
 mod test {
-    use super::argon2_hash_password;
+    use super::{argon2_hash_password, argon2_hash_password_with_salt_cancellable};
+    use crate::progress::Progress;
 
     #[test]
     fn test_argon2_for_crash() {
         let password = b"password123";
         let _ = argon2_hash_password(password).unwrap();
     }
+
+    #[test]
+    fn test_argon2_cancellable_matches() {
+        let password = b"password123";
+        let expected = argon2_hash_password(password).unwrap();
+
+        let progress = Progress::new();
+        let result =
+            argon2_hash_password_with_salt_cancellable(password, expected.get_salt(), &progress)
+                .unwrap();
+        assert_eq!(result.get_hash(), expected.get_hash());
+        assert_eq!(progress.completed(), 1);
+
+        progress.cancel();
+        let result =
+            argon2_hash_password_with_salt_cancellable(password, expected.get_salt(), &progress);
+        assert!(result.is_err_and(|error| error.is_cancelled()));
+    }
 }
\ No newline at end of file
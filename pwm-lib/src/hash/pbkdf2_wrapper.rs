@@ -1,7 +1,9 @@
 use pbkdf2::pbkdf2_hmac;
-use sha2::Sha512;
+use sha2::{Sha256, Sha512};
 
 use crate::hash::{HashError, HashResult};
+use crate::progress::{run_cancellable, Progress};
+use crate::zeroize::Zeroizing;
 
 // https://cheatsheetseries.owasp.org/cheatsheets/Password_Storage_Cheat_Sheet.html#pbkdf2
 // Updated April 26 of 2024
@@ -41,13 +43,56 @@ pub fn pbkdf2_hash_password_with_salt(
     Ok(result)
 }
 
+pub fn pbkdf2_hash_password_with_salt_cancellable(
+    password: &[u8],
+    salt: &[u8],
+    progress: &Progress,
+) -> Result<HashResult, HashError> {
+    let password = Zeroizing::new(password.to_vec());
+    let mut result = HashResult::new_with_salt(salt)?;
+    run_cancellable(progress, move || {
+        pbkdf2_hash_password_into(password.as_slice(), &mut result)?;
+        Ok(result)
+    })
+}
+
+// With the salt and rounds another program chose, to open its files
+pub fn pbkdf2_sha256(
+    password: &[u8],
+    salt: &[u8],
+    rounds: u32,
+    output: &mut [u8],
+) -> Result<(), HashError> {
+    if rounds == 0 {
+        return Err(HashError::new("invalid number of rounds"));
+    }
+
+    pbkdf2_hmac::<Sha256>(password, salt, rounds, output);
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
-    use super::pbkdf2_hash_password;
+    use super::{pbkdf2_hash_password, pbkdf2_sha256};
 
     #[test]
     fn test_pbkdf2_for_crash() {
         let password = b"password123";
         let _ = pbkdf2_hash_password(password).unwrap();
     }
+
+    // RFC 7914 section 11
+    #[test]
+    fn test_pbkdf2_sha256() {
+        let mut output = [0u8; 64];
+        pbkdf2_sha256(b"passwd", b"salt", 1, &mut output).unwrap();
+        assert_eq!(
+            output[..16],
+            [
+                0x55, 0xac, 0x04, 0x6e, 0x56, 0xe3, 0x08, 0x9f, 0xec, 0x16, 0x91, 0xc2, 0x25, 0x44,
+                0xb6, 0x05
+            ]
+        );
+        assert!(pbkdf2_sha256(b"passwd", b"salt", 0, &mut output).is_err());
+    }
 }
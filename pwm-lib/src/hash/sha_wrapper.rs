@@ -1,3 +1,5 @@
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
 use sha2::{Digest, Sha256, Sha512};
 
 use super::HashError;
@@ -26,3 +28,33 @@ pub fn sha512_hash(data: &[u8], output: &mut [u8]) -> Result<(), HashError> {
     Ok(())
 }
 
+
+// Only for looking up hashes in other databases like Pwned Passwords, never
+// use it to protect anything
+pub fn sha1_hash(data: &[u8], output: &mut [u8]) -> Result<(), HashError> {
+    if output.len() != 20 {
+        return Err(HashError::new("invalid output length"));
+    }
+
+    let mut hasher = Sha1::new();
+    hasher.update(data);
+    hasher.finalize_into(output.into());
+
+    Ok(())
+}
+
+// Keyed hash, lets passwords be compared without keeping them around
+pub fn hmac_sha256(key: &[u8], data: &[u8], output: &mut [u8]) -> Result<(), HashError> {
+    if output.len() != 32 {
+        return Err(HashError::new("invalid output length"));
+    }
+
+    let mut mac = match Hmac::<Sha256>::new_from_slice(key) {
+        Ok(mac) => mac,
+        Err(_error) => return Err(HashError::new("invalid key length")),
+    };
+    mac.update(data);
+    output.copy_from_slice(&mac.finalize().into_bytes());
+
+    Ok(())
+}
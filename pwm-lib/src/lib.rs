@@ -1,6 +1,10 @@
+pub mod atomic_file;
+pub mod crypt_dir;
 pub mod crypt_file;
 pub mod encryption;
 pub mod hash;
+pub mod progress;
 pub mod random;
+pub mod ssh;
 
 pub extern crate zeroize;
@@ -0,0 +1,163 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::hash::HashError;
+
+// How often a waiting caller checks if it was cancelled
+static CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Called with (completed, total) every time a step finishes
+pub type ProgressCallback = Box<dyn Fn(usize, usize) + Send + Sync>;
+
+// Shared between a long running operation and whatever is displaying it,
+// the operation counts its finished steps and the display side may cancel it
+pub struct Progress {
+    cancelled: AtomicBool,
+    completed: AtomicUsize,
+    total: AtomicUsize,
+    callback: Option<ProgressCallback>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self {
+            cancelled: AtomicBool::new(false),
+            completed: AtomicUsize::new(0),
+            total: AtomicUsize::new(0),
+            callback: None,
+        }
+    }
+
+    // The callback runs on whichever thread finished the step
+    pub fn with_callback(callback: impl Fn(usize, usize) + Send + Sync + 'static) -> Self {
+        Self {
+            callback: Some(Box::new(callback)),
+            ..Self::new()
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    // Adds steps to the total, operations that are made out of other
+    // operations each add their own share
+    pub fn add_total(&self, steps: usize) {
+        self.total.fetch_add(steps, Ordering::SeqCst);
+    }
+
+    pub fn increment(&self) {
+        let completed = self.completed.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(callback) = &self.callback {
+            callback(completed, self.total());
+        }
+    }
+
+    pub fn completed(&self) -> usize {
+        self.completed.load(Ordering::SeqCst)
+    }
+
+    pub fn total(&self) -> usize {
+        self.total.load(Ordering::SeqCst)
+    }
+
+    // None if the total isn't known yet
+    pub fn fraction(&self) -> Option<f32> {
+        let total = self.total();
+        if total == 0 {
+            return None;
+        }
+
+        Some((self.completed().min(total) as f32) / (total as f32))
+    }
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Runs job on a worker thread and waits for it, returning early if progress
+// gets cancelled. A cancelled job is left to finish on its own and its result
+// is dropped, so anything it returns should zeroize itself on drop.
+pub fn run_cancellable<T, F>(progress: &Progress, job: F) -> Result<T, HashError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, HashError> + Send + 'static,
+{
+    if progress.is_cancelled() {
+        return Err(HashError::cancelled());
+    }
+
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        // The receiver is gone if the caller was cancelled
+        let _ = sender.send(job());
+    });
+
+    loop {
+        match receiver.recv_timeout(CANCEL_POLL_INTERVAL) {
+            Ok(result) => {
+                let value = result?;
+                progress.increment();
+                return Ok(value);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if progress.is_cancelled() {
+                    return Err(HashError::cancelled());
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(HashError::new("Worker thread exited without a result"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run_cancellable, Progress};
+
+    #[test]
+    fn test_run_cancellable() {
+        let progress = Progress::new();
+        progress.add_total(1);
+
+        let value = run_cancellable(&progress, || Ok(5)).unwrap();
+        assert_eq!(value, 5);
+        assert_eq!(progress.completed(), 1);
+        assert_eq!(progress.fraction(), Some(1.0));
+    }
+
+    #[test]
+    fn test_callback() {
+        use std::sync::{Arc, Mutex};
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let calls_inner = calls.clone();
+        let progress = Progress::with_callback(move |completed, total| {
+            calls_inner.lock().unwrap().push((completed, total));
+        });
+        progress.add_total(2);
+        progress.increment();
+        progress.increment();
+
+        assert_eq!(*calls.lock().unwrap(), vec![(1, 2), (2, 2)]);
+    }
+
+    #[test]
+    fn test_run_cancelled() {
+        let progress = Progress::new();
+        progress.cancel();
+
+        let result = run_cancellable(&progress, || Ok(5));
+        assert!(result.unwrap_err().is_cancelled());
+        assert_eq!(progress.completed(), 0);
+    }
+}
@@ -1,4 +1,16 @@
-use passwords::PasswordGenerator;
+pub mod token;
+
+use passwords::{analyzer, scorer, PasswordGenerator};
+use zeroize::Zeroizing;
+
+// Score of the passwords crate, under 60 is very weak and 80 or more is good
+pub fn password_score(password: &str) -> f64 {
+    let analyzed = analyzer::analyze(password);
+    let score = scorer::score(&analyzed);
+    let _password = Zeroizing::new(analyzed.into_password());
+
+    score
+}
 
 pub fn random_password(length: usize) -> Result<String, &'static str> {
     let pg = PasswordGenerator {
@@ -0,0 +1,254 @@
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::hash::randomize_slice;
+
+static HEX_ALPHABET: &[u8; 16] = b"0123456789abcdef";
+static BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+static BASE64URL_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+static BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+static API_KEY_ALPHABET: &[u8; 62] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+pub static DEFAULT_TOKEN_BYTES: usize = 32;
+pub static DEFAULT_API_KEY_PREFIX: &str = "pwm";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Hex,
+    Base64,
+    Base64Url,
+    Base32,
+    Uuid,
+    // Holds the prefix
+    ApiKey(String),
+}
+
+impl TokenKind {
+    pub fn names() -> [&'static str; 6] {
+        ["hex", "base64", "base64url", "base32", "uuid", "apikey"]
+    }
+
+    pub fn from_name(name: &str, prefix: Option<&str>) -> Option<TokenKind> {
+        let kind = match name.to_ascii_lowercase().as_str() {
+            "hex" => TokenKind::Hex,
+            "base64" | "b64" => TokenKind::Base64,
+            "base64url" | "b64url" => TokenKind::Base64Url,
+            "base32" | "b32" => TokenKind::Base32,
+            "uuid" => TokenKind::Uuid,
+            "apikey" | "api" => {
+                TokenKind::ApiKey(String::from(prefix.unwrap_or(DEFAULT_API_KEY_PREFIX)))
+            }
+            _ => return None,
+        };
+
+        Some(kind)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenKind::Hex => "hex",
+            TokenKind::Base64 => "base64",
+            TokenKind::Base64Url => "base64url",
+            TokenKind::Base32 => "base32",
+            TokenKind::Uuid => "uuid",
+            TokenKind::ApiKey(_) => "apikey",
+        }
+    }
+}
+
+// For a uuid the length is ignored, for an api key it is the number of
+// characters after the prefix, for everything else it is the number of bytes
+pub fn random_token(kind: &TokenKind, length: usize) -> Result<Zeroizing<String>, &'static str> {
+    if length == 0 && *kind != TokenKind::Uuid {
+        return Err("length must be greater than 0");
+    }
+
+    let token = match kind {
+        TokenKind::Hex => random_hex(length),
+        TokenKind::Base64 => random_base64(length),
+        TokenKind::Base64Url => random_base64url(length),
+        TokenKind::Base32 => random_base32(length),
+        TokenKind::Uuid => random_uuid_v4(),
+        TokenKind::ApiKey(prefix) => random_api_key(prefix.as_str(), length),
+    };
+
+    Ok(token)
+}
+
+pub fn random_bytes(count: usize) -> Zeroizing<Vec<u8>> {
+    let mut bytes = Zeroizing::new(vec![0; count]);
+    randomize_slice(&mut bytes);
+    bytes
+}
+
+pub fn random_hex(count: usize) -> Zeroizing<String> {
+    encode_hex(&random_bytes(count))
+}
+
+pub fn random_base64(count: usize) -> Zeroizing<String> {
+    encode_base64(&random_bytes(count), BASE64_ALPHABET, true)
+}
+
+pub fn random_base64url(count: usize) -> Zeroizing<String> {
+    encode_base64(&random_bytes(count), BASE64URL_ALPHABET, false)
+}
+
+pub fn random_base32(count: usize) -> Zeroizing<String> {
+    encode_base32(&random_bytes(count))
+}
+
+pub fn random_uuid_v4() -> Zeroizing<String> {
+    let mut bytes = random_bytes(16);
+    // Version 4, variant RFC 4122
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = encode_hex(&bytes);
+    Zeroizing::new(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    ))
+}
+
+// prefix_ followed by length alphanumeric characters
+pub fn random_api_key(prefix: &str, length: usize) -> Zeroizing<String> {
+    let mut key = Zeroizing::new(String::with_capacity(prefix.len() + 1 + length));
+    if !prefix.is_empty() {
+        key.push_str(prefix);
+        key.push('_');
+    }
+
+    // Rejection sampling so every character is equally likely,
+    // 248 is the largest multiple of 62 that fits in a byte
+    let limit = (256 / API_KEY_ALPHABET.len() * API_KEY_ALPHABET.len()) as u8;
+    let target = key.len() + length;
+    let mut byte = [0u8; 1];
+    while key.len() < target {
+        randomize_slice(&mut byte);
+        if byte[0] < limit {
+            key.push(API_KEY_ALPHABET[(byte[0] as usize) % API_KEY_ALPHABET.len()] as char);
+        }
+    }
+    byte.zeroize();
+
+    key
+}
+
+pub fn encode_hex(data: &[u8]) -> Zeroizing<String> {
+    let mut result = Zeroizing::new(String::with_capacity(data.len() * 2));
+    for byte in data {
+        result.push(HEX_ALPHABET[(byte >> 4) as usize] as char);
+        result.push(HEX_ALPHABET[(byte & 0x0f) as usize] as char);
+    }
+
+    result
+}
+
+fn encode_base64(data: &[u8], alphabet: &[u8; 64], pad: bool) -> Zeroizing<String> {
+    let mut result = Zeroizing::new(String::with_capacity(data.len().div_ceil(3) * 4));
+    for chunk in data.chunks(3) {
+        let b0 = chunk[0] as u32;
+        let b1 = *chunk.get(1).unwrap_or(&0) as u32;
+        let b2 = *chunk.get(2).unwrap_or(&0) as u32;
+        let triple = (b0 << 16) | (b1 << 8) | b2;
+
+        let characters = chunk.len() + 1;
+        for index in 0..4 {
+            if index < characters {
+                let sextet = (triple >> (18 - index * 6)) & 0x3f;
+                result.push(alphabet[sextet as usize] as char);
+            } else if pad {
+                result.push('=');
+            }
+        }
+    }
+
+    result
+}
+
+// RFC 4648 base32 with padding
+fn encode_base32(data: &[u8]) -> Zeroizing<String> {
+    let mut result = Zeroizing::new(String::with_capacity(data.len().div_ceil(5) * 8));
+    for chunk in data.chunks(5) {
+        let mut block = [0u8; 5];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let mut value = 0u64;
+        for byte in block {
+            value = (value << 8) | byte as u64;
+        }
+        block.zeroize();
+
+        let characters = (chunk.len() * 8).div_ceil(5);
+        for index in 0..8 {
+            if index < characters {
+                let quintet = (value >> (35 - index * 5)) & 0x1f;
+                result.push(BASE32_ALPHABET[quintet as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encodings() {
+        // RFC 4648 test vectors
+        let vectors = [
+            ("", "", "", ""),
+            ("f", "66", "Zg==", "MY======"),
+            ("fo", "666f", "Zm8=", "MZXQ===="),
+            ("foo", "666f6f", "Zm9v", "MZXW6==="),
+            ("foob", "666f6f62", "Zm9vYg==", "MZXW6YQ="),
+            ("fooba", "666f6f6261", "Zm9vYmE=", "MZXW6YTB"),
+            ("foobar", "666f6f626172", "Zm9vYmFy", "MZXW6YTBOI======"),
+        ];
+
+        for (input, hex, base64, base32) in vectors {
+            assert_eq!(encode_hex(input.as_bytes()).as_str(), hex);
+            assert_eq!(
+                encode_base64(input.as_bytes(), BASE64_ALPHABET, true).as_str(),
+                base64
+            );
+            assert_eq!(encode_base32(input.as_bytes()).as_str(), base32);
+        }
+
+        assert_eq!(
+            encode_base64(&[0xfb, 0xff], BASE64URL_ALPHABET, false).as_str(),
+            "-_8"
+        );
+    }
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(random_hex(16).len(), 32);
+        assert_eq!(random_base64url(32).len(), 43);
+        assert_eq!(random_base32(20).len(), 32);
+
+        let uuid = random_uuid_v4();
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+        assert!(["8", "9", "a", "b"].contains(&&uuid[19..20]));
+
+        let key = random_api_key("test", 24);
+        assert!(key.starts_with("test_"));
+        assert_eq!(key.len(), 29);
+        assert!(key[5..].chars().all(|c| c.is_ascii_alphanumeric()));
+
+        let kind = TokenKind::from_name("apikey", Some("sk")).unwrap();
+        assert!(random_token(&kind, 8).unwrap().starts_with("sk_"));
+        assert!(random_token(&TokenKind::Hex, 0).is_err());
+        assert!(TokenKind::from_name("nope", None).is_none());
+    }
+}
@@ -0,0 +1,371 @@
+use std::io::{Read, Write};
+use std::os::unix::{
+    fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+    io::AsRawFd,
+    net::{UnixListener, UnixStream},
+};
+use std::path::Path;
+use std::sync::Arc;
+
+use super::{put_string, SshKey};
+use crate::hash::randomize_slice;
+use crate::random::token::encode_hex;
+
+// Message numbers from the ssh-agent protocol draft (draft-miller-ssh-agent)
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+
+// OpenSSH uses the same limit, anything bigger is not a real request
+const MAX_MESSAGE_LENGTH: usize = 256 * 1024;
+
+// Asked before every signature, returning false refuses the request
+pub type ConfirmCallback = Box<dyn Fn(&SshKey) -> bool + Send + Sync>;
+
+// Read only agent, keys can't be added or removed through the socket since
+// they live in the vault
+pub struct Agent {
+    keys: Vec<SshKey>,
+    confirm: Option<ConfirmCallback>,
+}
+
+impl Agent {
+    pub fn new(keys: Vec<SshKey>) -> Self {
+        Self {
+            keys,
+            confirm: None,
+        }
+    }
+
+    pub fn with_confirmation(
+        keys: Vec<SshKey>,
+        confirm: impl Fn(&SshKey) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            keys,
+            confirm: Some(Box::new(confirm)),
+        }
+    }
+
+    pub fn keys(&self) -> &[SshKey] {
+        &self.keys
+    }
+
+    // Takes a message without its length prefix and returns the response
+    // the same way, every unknown or malformed request gets a failure
+    pub fn handle_message(&self, message: &[u8]) -> Vec<u8> {
+        let response = match message.split_first() {
+            Some((&SSH_AGENTC_REQUEST_IDENTITIES, _)) => self.identities(),
+            Some((&SSH_AGENTC_SIGN_REQUEST, body)) => self.sign(body),
+            _ => None,
+        };
+
+        match response {
+            Some(response) => response,
+            None => vec![SSH_AGENT_FAILURE],
+        }
+    }
+
+    fn identities(&self) -> Option<Vec<u8>> {
+        let mut response = vec![SSH_AGENT_IDENTITIES_ANSWER];
+        let mut count = 0u32;
+        let mut identities = Vec::new();
+        for key in self.keys.iter() {
+            let blob = match key.public_key_blob() {
+                Ok(blob) => blob,
+                Err(_error) => continue,
+            };
+            put_string(&mut identities, &blob);
+            put_string(&mut identities, key.name().as_bytes());
+            count += 1;
+        }
+
+        response.extend_from_slice(&count.to_be_bytes());
+        response.extend_from_slice(&identities);
+        Some(response)
+    }
+
+    fn sign(&self, body: &[u8]) -> Option<Vec<u8>> {
+        let mut reader = MessageReader::new(body);
+        let blob = reader.read_string()?;
+        let data = reader.read_string()?;
+        let flags = reader.read_u32()?;
+
+        let key = self.keys.iter().find(|key| match key.public_key_blob() {
+            Ok(key_blob) => key_blob.as_slice() == blob,
+            Err(_error) => false,
+        })?;
+
+        if let Some(confirm) = &self.confirm {
+            if !confirm(key) {
+                return None;
+            }
+        }
+
+        let signature = key.sign(data, flags).ok()?;
+        let mut response = vec![SSH_AGENT_SIGN_RESPONSE];
+        put_string(&mut response, &signature);
+        Some(response)
+    }
+
+    fn handle_stream(&self, mut stream: UnixStream) -> std::io::Result<()> {
+        loop {
+            let mut length = [0u8; 4];
+            match stream.read_exact(&mut length) {
+                Ok(()) => (),
+                // The client hung up
+                Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(error) => return Err(error),
+            }
+
+            let length = u32::from_be_bytes(length) as usize;
+            if length > MAX_MESSAGE_LENGTH {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Message too long",
+                ));
+            }
+
+            let mut message = vec![0u8; length];
+            stream.read_exact(&mut message)?;
+
+            let response = self.handle_message(&message);
+            stream.write_all(&(response.len() as u32).to_be_bytes())?;
+            stream.write_all(&response)?;
+        }
+    }
+
+    // Answers every connection on its own thread, only returns if accepting
+    // fails. Errors of single connections go to on_error and don't stop the
+    // agent, connections from other users are closed right away.
+    pub fn serve(
+        self: Arc<Self>,
+        listener: UnixListener,
+        on_error: impl Fn(std::io::Error) + Send + Sync + 'static,
+    ) -> std::io::Result<()> {
+        let on_error = Arc::new(on_error);
+        for stream in listener.incoming() {
+            let stream = stream?;
+            match peer_uid(&stream) {
+                Ok(uid) if uid == current_uid() => (),
+                Ok(_uid) => {
+                    on_error(std::io::Error::new(
+                        std::io::ErrorKind::PermissionDenied,
+                        "Refused a connection from another user",
+                    ));
+                    continue;
+                }
+                Err(error) => {
+                    on_error(error);
+                    continue;
+                }
+            }
+
+            let agent = self.clone();
+            let on_error = on_error.clone();
+            std::thread::spawn(move || {
+                if let Err(error) = agent.handle_stream(stream) {
+                    on_error(error);
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn current_uid() -> libc::uid_t {
+    unsafe { libc::geteuid() }
+}
+
+// The user on the other end of the socket
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<libc::uid_t> {
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    match result {
+        0 => Ok(credentials.uid),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<libc::uid_t> {
+    let mut uid = 0;
+    let mut gid = 0;
+    let result = unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) };
+    match result {
+        0 => Ok(uid),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+// Binds the socket so only the current user can connect, a leftover socket
+// from an agent that is no longer running gets replaced. Anything else at
+// the path is left alone.
+pub fn bind(path: &str) -> std::io::Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "The path exists and isn't a socket",
+            ))
+        }
+        Ok(_metadata) if UnixStream::connect(path).is_ok() => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                "Another agent is listening on this socket",
+            ))
+        }
+        Ok(_metadata) => std::fs::remove_file(path)?,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => (),
+        Err(error) => return Err(error),
+    }
+
+    // The socket is created in a directory only the current user can enter
+    // and linked to the path once its permissions are set
+    let mut token = [0u8; 8];
+    randomize_slice(&mut token);
+    let parent = match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let private = parent.join(format!(".pwm-{}", encode_hex(&token).as_str()));
+    std::fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let socket = private.join("agent");
+    let listener = UnixListener::bind(&socket).and_then(|listener| {
+        std::fs::set_permissions(&socket, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::hard_link(&socket, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&socket);
+    let _ = std::fs::remove_dir(&private);
+    listener
+}
+
+struct MessageReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> MessageReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        if self.data.len() < 4 {
+            return None;
+        }
+        let (value, rest) = self.data.split_at(4);
+        self.data = rest;
+        Some(u32::from_be_bytes([value[0], value[1], value[2], value[3]]))
+    }
+
+    fn read_string(&mut self) -> Option<&'a [u8]> {
+        let length = self.read_u32()? as usize;
+        if self.data.len() < length {
+            return None;
+        }
+        let (value, rest) = self.data.split_at(length);
+        self.data = rest;
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ssh::test::TEST_KEY;
+
+    fn sign_request(blob: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut request = vec![SSH_AGENTC_SIGN_REQUEST];
+        put_string(&mut request, blob);
+        put_string(&mut request, data);
+        request.extend_from_slice(&0u32.to_be_bytes());
+        request
+    }
+
+    #[test]
+    fn test_identities() {
+        let key = SshKey::from_entry("github", TEST_KEY.as_bytes()).unwrap();
+        let blob = key.public_key_blob().unwrap();
+        let agent = Agent::new(vec![key]);
+
+        let response = agent.handle_message(&[SSH_AGENTC_REQUEST_IDENTITIES]);
+        let mut reader = MessageReader::new(&response[1..]);
+        assert_eq!(response[0], SSH_AGENT_IDENTITIES_ANSWER);
+        assert_eq!(reader.read_u32(), Some(1));
+        assert_eq!(reader.read_string(), Some(blob.as_slice()));
+        assert_eq!(reader.read_string(), Some(b"github".as_slice()));
+    }
+
+    #[test]
+    fn test_sign() {
+        let key = SshKey::from_entry("github", TEST_KEY.as_bytes()).unwrap();
+        let blob = key.public_key_blob().unwrap();
+        let agent = Agent::new(vec![key]);
+
+        let response = agent.handle_message(&sign_request(&blob, b"data"));
+        assert_eq!(response[0], SSH_AGENT_SIGN_RESPONSE);
+
+        // Unknown key and truncated request
+        let response = agent.handle_message(&sign_request(b"nope", b"data"));
+        assert_eq!(response, vec![SSH_AGENT_FAILURE]);
+        let response = agent.handle_message(&[SSH_AGENTC_SIGN_REQUEST, 0, 0]);
+        assert_eq!(response, vec![SSH_AGENT_FAILURE]);
+    }
+
+    #[test]
+    fn test_sign_refused() {
+        let key = SshKey::from_entry("github", TEST_KEY.as_bytes()).unwrap();
+        let blob = key.public_key_blob().unwrap();
+        let agent = Agent::with_confirmation(vec![key], |_key| false);
+
+        let response = agent.handle_message(&sign_request(&blob, b"data"));
+        assert_eq!(response, vec![SSH_AGENT_FAILURE]);
+    }
+
+    #[test]
+    fn test_bind() {
+        let path = std::env::temp_dir().join(format!("pwm-agent-test-{}", std::process::id()));
+        let path = path.display().to_string();
+        let listener = bind(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let _client = UnixStream::connect(&path).unwrap();
+        let (stream, _address) = listener.accept().unwrap();
+        assert_eq!(peer_uid(&stream).unwrap(), current_uid());
+        assert_eq!(
+            bind(&path).err().unwrap().kind(),
+            std::io::ErrorKind::AddrInUse
+        );
+
+        // A socket left behind is replaced, other files are not
+        drop(listener);
+        drop(bind(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::write(&path, b"not a socket").unwrap();
+        assert_eq!(
+            bind(&path).err().unwrap().kind(),
+            std::io::ErrorKind::AlreadyExists
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), b"not a socket");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,6 +1,10 @@
 extern crate getrandom;
 extern crate proc_macro;
-use std::{env, io::Read, str::FromStr};
+use std::{
+    env,
+    io::{Read, Write},
+    str::FromStr,
+};
 
 use getrandom::getrandom;
 use proc_macro::TokenStream;
@@ -21,7 +25,15 @@ pub fn random_pepper(_input: TokenStream) -> TokenStream {
         }
         Err(_error) => {
             getrandom(&mut random).unwrap();
-            std::fs::write(full_path.to_str().unwrap(), &random).unwrap();
+
+            // Losing the pepper makes every vault unreadable, so it is written
+            // to a temporary file first. pwm-lib's atomic_file can't be used
+            // since pwm-lib depends on this crate.
+            let temp_path = current_dir.join(format!("{}.tmp", PEPPER_PATH));
+            let mut file = std::fs::File::create(&temp_path).unwrap();
+            file.write_all(&random).unwrap();
+            file.sync_all().unwrap();
+            std::fs::rename(&temp_path, &full_path).unwrap();
         }
     };
 